@@ -0,0 +1,40 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mcp::tape::{Scan, Tape};
+use mcp::{analysis, basic, integrity, load, verifier};
+
+// Read arbitrary bytes as a CAS file and walk its files as `mcp list` and `mcp check` do
+fuzz_target!(|bytes: &[u8]| {
+    for scan in [Scan::Aligned, Scan::Unaligned].iter().copied() {
+        let tape = Tape::from_bytes_with(bytes, scan);
+        for file in tape.files() {
+            let _ = file.name();
+            let _ = file.payload();
+            let _ = file.text_len();
+            let _ = load::check_addresses(&file);
+            if let mcp::File::Basic(_, data) = file {
+                let _ = basic::detokenize(data);
+            }
+        }
+        let _ = tape.entries();
+        let _ = tape.stats();
+        let _ = tape.problems();
+        let _ = analysis::check(&tape);
+        let _ = integrity::check(&tape);
+        let _ = verifier::checks(&tape);
+
+        // Aligned tapes are written back as they were read, and unaligned ones are padded
+        // without losing or splitting blocks
+        let written = tape.to_bytes();
+        if scan == Scan::Aligned {
+            assert_eq!(bytes, &written[..]);
+        }
+        assert_eq!(
+            tape.blocks().len(),
+            Tape::from_bytes(&written).blocks().len()
+        );
+    }
+    let _ = Tape::parse(bytes);
+    let _ = Tape::parse_with_recovery(bytes);
+});
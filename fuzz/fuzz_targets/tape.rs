@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mcp::tape::Tape;
+
+// Serialize an arbitrary tape and check that parsing it back yields the same blocks
+fuzz_target!(|tape: Tape| {
+    let bytes: Vec<u8> = tape.blocks().iter().flat_map(|b| b.data().to_vec()).collect();
+    let read = Tape::from_bytes(&bytes);
+    assert_eq!(tape.blocks().len(), read.blocks().len());
+    for (a, b) in tape.blocks().iter().zip(read.blocks()) {
+        assert_eq!(a.data(), b.data());
+    }
+    for _ in read.files() {}
+    let _ = Tape::parse(&bytes);
+});
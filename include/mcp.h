@@ -0,0 +1,73 @@
+/*
+ * MSX CAS Packager
+ * Copyright (c) 2015 Alvaro Polo
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * C interface of the mcp library, built with the `ffi` feature
+ *
+ * See the documentation of the `ffi` module for the details of every function.
+ * Functions that may fail return -1 (or NULL) on error and 0 on success, except
+ * mcp_tape_write(), which returns a length.
+ */
+
+#ifndef MCP_H
+#define MCP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The types of the files of a tape */
+#define MCP_FILE_BIN 0
+#define MCP_FILE_BASIC 1
+#define MCP_FILE_ASCII 2
+#define MCP_FILE_CUSTOM 3
+
+/* A tape, as an opaque handle */
+typedef struct McpTape mcp_tape;
+
+/* Create an empty tape */
+mcp_tape *mcp_tape_new(void);
+
+/* Create a tape from the bytes of a CAS file, which are copied */
+mcp_tape *mcp_tape_from_bytes(const uint8_t *data, size_t len);
+
+/* Release a tape; releasing NULL does nothing */
+void mcp_tape_free(mcp_tape *tape);
+
+/* Returns the number of files of a tape */
+size_t mcp_tape_file_count(const mcp_tape *tape);
+
+/* Returns the MCP_FILE_* type of a file, or -1 if there is no such file */
+int mcp_tape_file_type(const mcp_tape *tape, size_t index);
+
+/* Returns the name of a file ("" for custom files), valid until the tape changes */
+const char *mcp_tape_file_name(const mcp_tape *tape, size_t index);
+
+/* Returns the payload of a file and stores its length, valid until the tape changes */
+const uint8_t *mcp_tape_file_payload(const mcp_tape *tape, size_t index, size_t *len);
+
+/* Stores the addresses of a binary file, or returns -1 if it is not one */
+int mcp_tape_file_addresses(const mcp_tape *tape, size_t index,
+                            uint16_t *begin, uint16_t *end, uint16_t *start);
+
+/* Appends a file of the given MCP_FILE_* type; the name is ignored for custom files */
+int mcp_tape_append(mcp_tape *tape, int file_type, const char *name,
+                    const uint8_t *data, size_t len);
+
+/* Writes the CAS file of a tape if it fits in the buffer, and returns its length (-1 on error) */
+ptrdiff_t mcp_tape_write(const mcp_tape *tape, uint8_t *buffer, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MCP_H */
@@ -0,0 +1,31 @@
+/*
+ * MSX CAS Packager
+ * Copyright (c) 2015 Alvaro Polo
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Compile check of mcp.h against the signatures of the `ffi` module
+ *
+ * Every function is assigned to a pointer of the type it has in Rust, so that
+ * `cc -fsyntax-only -Wall -Werror -Iinclude include/mcp_test.c` fails if the
+ * header declares a different one.
+ */
+
+#include "mcp.h"
+
+mcp_tape *(*check_new)(void) = mcp_tape_new;
+mcp_tape *(*check_from_bytes)(const uint8_t *, size_t) = mcp_tape_from_bytes;
+void (*check_free)(mcp_tape *) = mcp_tape_free;
+size_t (*check_file_count)(const mcp_tape *) = mcp_tape_file_count;
+int (*check_file_type)(const mcp_tape *, size_t) = mcp_tape_file_type;
+const char *(*check_file_name)(const mcp_tape *, size_t) = mcp_tape_file_name;
+const uint8_t *(*check_file_payload)(const mcp_tape *, size_t, size_t *) =
+    mcp_tape_file_payload;
+int (*check_file_addresses)(const mcp_tape *, size_t, uint16_t *, uint16_t *, uint16_t *) =
+    mcp_tape_file_addresses;
+int (*check_append)(mcp_tape *, int, const char *, const uint8_t *, size_t) = mcp_tape_append;
+ptrdiff_t (*check_write)(const mcp_tape *, uint8_t *, size_t) = mcp_tape_write;
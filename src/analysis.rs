@@ -0,0 +1,162 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Sanity checks of the memory the binary files of a tape are loaded into
+//!
+//! `load::check_addresses()` tells whether the addresses of a binary file are consistent
+//! by themselves. `check()` runs it on every binary file of a tape, and also looks at the
+//! files around them: a binary file that loads over another one, or over the Basic
+//! program that precedes it in the tape (typically the loader that runs `BLOAD`), probably
+//! does not load as intended.
+
+use crate::basic;
+use crate::load::{self, Problem, Severity};
+use crate::tape::{File, Tape};
+
+/// The address of the first byte of the Basic program text
+const BASIC_TEXT: usize = 0x8001;
+
+/// A problem found in the memory map of a tape
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    /// The index of the binary file the problem was found in
+    pub index: usize,
+    /// The index of the file it collides with, or `None` if the problem is in the
+    /// addresses of the file itself
+    pub other: Option<usize>,
+    /// The problem found
+    pub problem: Problem,
+}
+
+/// Returns the problems found in the memory map of the binary files of the given tape
+///
+/// Besides the problems of `load::check_addresses()`, binary files are checked against
+/// the binary files before them in the tape, which they would overwrite if they were
+/// loaded in the same session, and against the tokenized length of the last Basic or
+/// ASCII program before them, which is the one running when they are loaded. Collisions
+/// are reported as warnings, since a loader may overwrite itself on purpose.
+///
+pub fn check(tape: &Tape) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut bins: Vec<(usize, String, usize, usize)> = vec![];
+    let mut program: Option<(usize, String, usize)> = None;
+    for (index, file) in tape.files().enumerate() {
+        let (name, begin, end) = match &file {
+            File::Bin(name, begin, end, _, _) => (name.clone(), *begin, *end),
+            File::Basic(name, data) => {
+                program = Some((index, name.clone(), data.len()));
+                continue;
+            }
+            File::Ascii(name, _) => {
+                if let Ok(tokens) = basic::tokenize(&file.payload()) {
+                    program = Some((index, name.clone(), tokens.len()));
+                }
+                continue;
+            }
+            File::Custom(_) | File::Header(..) => continue,
+        };
+        let problems = load::check_addresses(&file);
+        let consistent = problems.iter().all(|p| p.severity != Severity::Error);
+        findings.extend(problems.into_iter().map(|problem| Finding {
+            index,
+            other: None,
+            problem,
+        }));
+        if !consistent {
+            continue;
+        }
+        if let Some((other, other_name, len)) = &program {
+            let program_end = BASIC_TEXT + len - 1;
+            if begin <= program_end && end >= BASIC_TEXT {
+                findings.push(collision(
+                    index,
+                    *other,
+                    format!(
+                        "loads over the Basic program {} (file {}) at 0x{:04x}-0x{:04x}",
+                        other_name, other, BASIC_TEXT, program_end
+                    ),
+                ));
+            }
+        }
+        for (other, other_name, other_begin, other_end) in &bins {
+            if begin <= *other_end && end >= *other_begin {
+                findings.push(collision(
+                    index,
+                    *other,
+                    format!(
+                        "loads over the binary file {} (file {}) at 0x{:04x}-0x{:04x}",
+                        other_name,
+                        other,
+                        begin.max(*other_begin),
+                        end.min(*other_end)
+                    ),
+                ));
+            }
+        }
+        bins.push((index, name, begin, end));
+    }
+    findings
+}
+
+fn collision(index: usize, other: usize, message: String) -> Finding {
+    Finding {
+        index,
+        other: Some(other),
+        problem: Problem {
+            severity: Severity::Warning,
+            message,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape;
+
+    #[test]
+    fn should_check_memory_map() {
+        let mut tape = Tape::new();
+        let (loader, _) = tape::file_name("loader");
+        tape.append_ascii(&loader, b"10 BLOAD\"CAS:\",R\r\n")
+            .unwrap();
+        for (name, begin, end) in [
+            ("a", 0x9000, 0x90ff),
+            ("b", 0x90f0, 0x9100),
+            ("c", 0x8000, 0x8010),
+            ("rom", 0x4000, 0x9000),
+        ] {
+            let (name, _) = tape::file_name(name);
+            let mut data = vec![];
+            for address in [begin, end, begin].iter() {
+                data.extend_from_slice(&u16::to_le_bytes(*address));
+            }
+            data.resize(6 + (end - begin + 1) as usize, 0);
+            tape.append_bin(&name, &data).unwrap();
+        }
+
+        let findings = check(&tape);
+        let summary: Vec<(usize, Option<usize>, Severity)> = findings
+            .iter()
+            .map(|f| (f.index, f.other, f.problem.severity))
+            .collect();
+        assert_eq!(
+            vec![
+                (2, Some(1), Severity::Warning),
+                (3, Some(0), Severity::Warning),
+                (4, None, Severity::Error),
+            ],
+            summary
+        );
+        assert_eq!(
+            "loads over the binary file a (file 1) at 0x90f0-0x90ff",
+            findings[0].problem.message
+        );
+    }
+}
@@ -6,103 +6,1332 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::env::args;
+use std::env::args_os;
+use std::ffi::OsString;
 use std::path::PathBuf;
 
-use docopt::Docopt;
-
-static USAGE: &'static str = "
-Usage: mcp -l <cas-file>
-       mcp -a <cas-file> <file>...
-       mcp -x <cas-file>
-       mcp -e <cas-file> <wav-file>
-       mcp --help
-       mcp --version
-
-Options:
-    -h, --help                  Print this message
-    -v, --version               Print the mcp version
-    -l, --list                  Lists the contents of the given CAS file
-    -a, --add                   Add new files to a given CAS file. If the CAS
-                                file does not exist, it is created.
-    -x, --extract               Extracts the contents from the given CAS file
-    -e, --export                Exports the CAS file into a WAV file
-";
+use clap::error::ErrorKind;
+use clap::{Arg, ArgAction, ArgMatches};
+
+use mcp::dir::ExtractOptions;
+use mcp::fixture::Fixture;
+use mcp::include::Dialect;
+use mcp::load::Ram;
+use mcp::machine::Machine;
+use mcp::meta::Annotation;
+use mcp::remote::Line;
+use mcp::tape::{FileType, MergePolicy, Scan};
+use mcp::text::LineEnding;
+use mcp::wav::{Cassette, Profile};
+
+use crate::report::{Format, Output, Verbosity};
 
 /// A command introduced through the command line interface
 ///
 /// An enumeration of the commands accepted by `mcp`.
 ///
 /// * `Version`, prints the `mcp` version
-/// * `List(path: PathBuf)`, lists the contents of the given CAS file
-/// * `Add(path: PathBuf, files: Vec<PathBuf>)`, adds files to the given CAS file
-/// * `Extract(path: PathBuf, item: PathBuf)`, extract the given item from the given CAS file
-/// * `Export(path: PathBuf, output: PathBuf)`, export the given CAS file into given output WAV file
+/// * `List(path: PathBuf, options: ListOptions, scan: Scan)`, lists the contents of the
+///   given CAS file
+/// * `Add(path: PathBuf, files: Vec<PathBuf>, options: AddOptions)`, adds files to the given
+///   CAS file
+/// * `Extract(path: PathBuf, names: Vec<String>, dir: Option<PathBuf>, options: ExtractOptions,
+///   scan: Scan, machine: Machine)`,
+///   extract the files of the given CAS file selected by the given names, indices (given
+///   as such or with `--index`) or glob patterns (or all of them) into the given directory (or the current one), optionally
+///   converting the line endings of ASCII files, prefixing the names with the position of
+///   the files in the tape and detokenizing Basic programs
+/// * `Cat(path: PathBuf, name: String, basic: bool, hex: bool, scan: Scan)`, prints the files
+///   of the given CAS file selected by the given name, index or glob pattern, optionally
+///   detokenizing them if they are Basic programs or as a hex dump
+/// * `Remove(path: PathBuf, names: Vec<String>)`, removes the files of the given CAS file
+///   selected by the given names, indices or glob patterns
+/// * `Rename(path: PathBuf, name: String, new_name: String)`, renames the given file of the
+///   given CAS file
+/// * `Move(path: PathBuf, name: String, to: usize)`, moves the given file of the given CAS
+///   file to the given position
+/// * `Patch(path: PathBuf, name: String, offset: usize, bytes: Patch)`, overwrites the
+///   payload of the given file of the given CAS file from the given offset on
+/// * `Browse(path: PathBuf)`, browses the files of the given CAS file interactively, to
+///   preview, extract, remove and rename them
+/// * `Merge(path: PathBuf, paths: Vec<PathBuf>, policy: MergePolicy, gzip: bool)`, writes the
+///   files of the given CAS files into a new one, optionally skipping duplicates, renaming
+///   files with the same name and compressing it with gzip
+/// * `Split(path: PathBuf, dir: Option<PathBuf>)`, writes every file of the given CAS file as
+///   a CAS file of its own into the given directory (or the current one)
+/// * `Export(path: PathBuf, output: PathBuf, profile: Profile)`, export the given CAS file into
+///   given output WAV file, encoded with the speed and sample rate of the given profile, or
+///   writes its files into the given MSX-DOS disk image if it has `.dsk` extension
+/// * `ExportAsm(path: PathBuf, name: String, dialect: Dialect)`, prints the given file of the
+///   given CAS file as Z80 assembly source in the syntax of the given assembler
+/// * `ExportC(path: PathBuf, name: String, output: Option<PathBuf>)`, writes the given file
+///   of the given CAS file as a C header to the given output file, or prints it
+/// * `Convert(path: PathBuf, output: PathBuf, profile: Profile, scan: Scan)`, converts the given tape
+///   between the CAS and TSX formats, writing the blocks of TSX files at the speeds of the given profile
+/// * `ConvertAll(paths: Vec<PathBuf>, to: String, dir: Option<PathBuf>, jobs: Option<usize>,
+///   profile: Profile, scan: Scan)`, converts the given tapes to the given format (`cas`, `tsx`,
+///   `wav` or `csw`) next to them or into the given directory, in parallel by the given number
+///   of threads (or one per CPU), writing TSX and WAV files at the speeds of the given profile
+/// * `RomLoad(rom: PathBuf, path: PathBuf)`, converts the given ROM image into a CAS file
+///   that loads and runs it with `RUN"CAS:"`
+/// * `Import(path: PathBuf, disk: PathBuf, names: Vec<String>)`, appends the given files of
+///   the given MSX-DOS disk image (all if empty) to the given CAS file
+/// * `Build(manifest: PathBuf, loader: bool)`, builds the CAS file described by the given
+///   manifest, optionally with a Basic loader before its first binary file
+/// * `Master(output: PathBuf, paths: Vec<PathBuf>, gap: u32, index: Option<PathBuf>)`,
+///   records the given CAS files one after another into the given WAV file, separated by
+///   the given seconds of silence, optionally writing the times of each one to a file
+/// * `Play(path: PathBuf, profile: Profile, remote: Option<Line>, pause: u32)`, plays the
+///   given CAS file through the sound device at the speeds of the given profile, to be
+///   loaded by a real MSX, optionally asserting a control line while every file plays and
+///   pausing the given seconds between files
+/// * `Load(wav: PathBuf, path: PathBuf, gzip: bool)`, decodes the given recording of a tape
+///   into the given CAS file, optionally compressed with gzip
+/// * `Record(dir: PathBuf, input: Option<String>, seconds: Option<u32>)`, records a tape
+///   from the given input device into the given session directory, for the given seconds
+///   or until Enter is pressed
+/// * `Remote(line: Line, seconds: Option<u32>)`, switches on the cassette remote relay
+///   driven by the given control line, for the given seconds or until Enter is pressed
+/// * `Identify(path: PathBuf)`, identifies the contents of the files in the given CAS file
+/// * `Annotate(path: PathBuf, index: usize, annotation: Annotation, clear: bool)`, sets the
+///   annotations of a file of the given CAS file, stored in its sidecar metadata file
+/// * `Info(paths: Vec<PathBuf>)`, prints statistics about the given CAS files as a whole
+/// * `Hash(paths: Vec<PathBuf>, machine: Machine)`, prints the CRC32 and SHA-1 of each of
+///   the given CAS files
+/// * `Verify(dat: PathBuf, paths: Vec<PathBuf>, strict: bool)`, verifies the given CAS files
+///   and their contents against the given DAT file, optionally failing on inconsistent
+///   addresses of binary files
+/// * `Check(paths: Vec<PathBuf>)`, checks the structure of the given CAS files and fails if
+///   any of them is damaged
+/// * `GrepBasic(pattern: String, paths: Vec<PathBuf>, ignore_case: bool)`, searches the
+///   given text in the Basic programs of the given CAS files and directories
+/// * `Which(file: PathBuf, paths: Vec<PathBuf>)`, searches the given host file in the
+///   given CAS files and directories
+/// * `CompareDir(path: PathBuf, dir: PathBuf)`, compares the files of the given CAS file
+///   with the same-named files of the given directory
+/// * `Diff(path: PathBuf, other: PathBuf, blocks: bool)`, compares the files of the given CAS
+///   files, or their blocks if `blocks` is set
+/// * `Edit(path: PathBuf, name: String)`, edits the given Basic program of the given CAS
+///   file with the editor set in `EDITOR`
+/// * `BasicShrink(path: PathBuf, name: String)`, shrinks the given Basic program of the
+///   given CAS file
+/// * `BasicConvert(path: PathBuf, name: String, to: FileType)`, converts the given Basic
+///   program of the given CAS file into a tokenized (`Basic`) or an `Ascii` file in place
+/// * `Fit(path: PathBuf, cassette: Cassette, profile: Profile)`, checks whether the given CAS
+///   file fits in one side of the given cassette when recorded at the speeds of the given profile
+/// * `LoadCheck(path: PathBuf, ram: Vec<Ram>)`, checks whether the files of the given CAS
+///   file would load in machines with the given RAM sizes
+/// * `GenFixture(path: PathBuf, fixture: Fixture, gzip: bool)`, writes a synthetic CAS file
+///   generated from the given fixture, optionally compressed with gzip
+/// * `Doctor`, checks the environment `mcp` runs in and suggests fixes for the problems found
+/// * `Preview(path: PathBuf)`, renders the screen dumps of the given CAS file as PNG images
+/// * `OpenMsxInsert(path: PathBuf, socket: Option<PathBuf>, load: Option<String>)`, inserts
+///   the given CAS file in a running openMSX, optionally typing a load command
+/// * `External(name: String, args: Vec<OsString>)`, runs the `mcp-<name>` program found in
+///   `PATH` passing it the remaining arguments
+///
+/// The commands with a `Scan` find the blocks of the given CAS file at any offset, unless
+/// `--strict` is given to find them at offsets aligned to 8 bytes only.
 ///
 #[derive(Debug, PartialEq)]
 pub enum Command {
     Version,
-    List(PathBuf),
-    Add(PathBuf, Vec<PathBuf>),
-    Extract(PathBuf),
-    Export(PathBuf, PathBuf),
+    List(PathBuf, ListOptions, Scan),
+    Add(PathBuf, Vec<PathBuf>, AddOptions),
+    Extract(
+        PathBuf,
+        Vec<String>,
+        Option<PathBuf>,
+        ExtractOptions,
+        Scan,
+        Machine,
+    ),
+    Cat(PathBuf, String, bool, bool, Scan),
+    Remove(PathBuf, Vec<String>),
+    Rename(PathBuf, String, String),
+    Move(PathBuf, String, usize),
+    Patch(PathBuf, String, usize, Patch),
+    Browse(PathBuf),
+    Merge(PathBuf, Vec<PathBuf>, MergePolicy, bool),
+    Split(PathBuf, Option<PathBuf>),
+    Export(PathBuf, PathBuf, Profile),
+    ExportAsm(PathBuf, String, Dialect),
+    ExportC(PathBuf, String, Option<PathBuf>),
+    Convert(PathBuf, PathBuf, Profile, Scan),
+    ConvertAll(
+        Vec<PathBuf>,
+        String,
+        Option<PathBuf>,
+        Option<usize>,
+        Profile,
+        Scan,
+    ),
+    RomLoad(PathBuf, PathBuf),
+    Import(PathBuf, PathBuf, Vec<String>),
+    Build(PathBuf, bool),
+    Master(PathBuf, Vec<PathBuf>, u32, Option<PathBuf>),
+    Play(PathBuf, Profile, Option<Line>, u32),
+    Load(PathBuf, PathBuf, bool),
+    Record(PathBuf, Option<String>, Option<u32>),
+    Remote(Line, Option<u32>),
+    Identify(PathBuf),
+    Annotate(PathBuf, usize, Annotation, bool),
+    Info(Vec<PathBuf>),
+    Hash(Vec<PathBuf>, Machine),
+    Verify(PathBuf, Vec<PathBuf>, bool),
+    Check(Vec<PathBuf>),
+    GrepBasic(String, Vec<PathBuf>, bool),
+    Which(PathBuf, Vec<PathBuf>),
+    CompareDir(PathBuf, PathBuf),
+    Diff(PathBuf, PathBuf, bool),
+    Edit(PathBuf, String),
+    BasicShrink(PathBuf, String),
+    BasicConvert(PathBuf, String, FileType),
+    Fit(PathBuf, Cassette, Profile),
+    LoadCheck(PathBuf, Vec<Ram>),
+    GenFixture(PathBuf, Fixture, bool),
+    Doctor,
+    Preview(PathBuf),
+    OpenMsxInsert(PathBuf, Option<PathBuf>, Option<String>),
+    External(String, Vec<OsString>),
 }
 
-/// A raw description of the arguments processed by DCOPT
+/// The options of the `list` command
+#[derive(Debug, Default, PartialEq)]
+pub struct ListOptions {
+    /// Warn about the inconsistent addresses of binary files
+    pub long: bool,
+    /// Print the files as CSV records
+    pub csv: bool,
+    /// Print the checksums of the files and the tape
+    pub hashes: bool,
+    /// Print the position of the files in the tape and their offset in the CAS file
+    pub index: bool,
+    /// The machine the CAS file is for
+    pub machine: Machine,
+}
+
+/// The bytes written by the `patch` command
+#[derive(Debug, PartialEq)]
+pub enum Patch {
+    /// The bytes given in hexadecimal in the command line
+    Bytes(Vec<u8>),
+    /// The contents of the given file
+    File(PathBuf),
+}
+
+/// The options of the `add` command
+#[derive(Debug, Default, PartialEq)]
+pub struct AddOptions {
+    /// Convert the line endings of ASCII files
+    pub eol: Option<LineEnding>,
+    /// Append a program that verifies the tape on the MSX
+    pub verifier: bool,
+    /// Write the CAS file compressed with gzip
+    pub gzip: bool,
+    /// Pack the files as raw machine code loaded at this address
+    pub begin: Option<u16>,
+    /// The start address of the raw machine code
+    pub start: Option<u16>,
+    /// Read ASCII files and Basic sources in UTF-8
+    pub utf8: bool,
+    /// Add the files as custom files, whatever their extension
+    pub custom: bool,
+    /// The name of the file in the tape, or its label if it is a custom file
+    pub name: Option<String>,
+    /// The machine the CAS file is for
+    pub machine: Machine,
+    /// Add a Basic loader before the first binary file
+    pub loader: bool,
+}
+
+/// Build the clap definition of the `mcp` command line
 ///
-/// This is not public. Use `Command` instead.
+/// Every command is a subcommand that may also be invoked with the short and long flags
+/// of previous versions (e.g. `mcp -l foo.cas` or `mcp --list foo.cas`). Any unknown
+/// subcommand is accepted as an external command.
 ///
-#[derive(Debug, Deserialize)]
-struct Args {
-    // flag_help: bool,
-    flag_version: bool,
-    flag_list: bool,
-    flag_add: bool,
-    flag_extract: bool,
-    flag_export: bool,
-    arg_cas_file: String,
-    arg_file: Vec<String>,
-    arg_wav_file: String,
+fn cli() -> clap::Command {
+    let cas_file = || {
+        Arg::new("cas-file")
+            .required(true)
+            .value_parser(clap::value_parser!(PathBuf))
+    };
+    let gzip = || {
+        Arg::new("gzip")
+            .long("gzip")
+            .action(ArgAction::SetTrue)
+            .help("Compress the CAS file with gzip")
+    };
+    let eol = || {
+        Arg::new("eol")
+            .long("eol")
+            .value_name("eol")
+            .value_parser(|s: &str| s.parse::<LineEnding>().map_err(|e| e.to_string()))
+            .help("Convert the line endings of ASCII files to `cr`, `lf` or `crlf`")
+    };
+    let strict = || {
+        Arg::new("strict")
+            .long("strict")
+            .action(ArgAction::SetTrue)
+            .help("Find blocks at offsets aligned to 8 bytes only, as emulators do")
+    };
+    let with_loader = || {
+        Arg::new("with-loader")
+        .long("with-loader")
+        .action(ArgAction::SetTrue)
+        .help("Add a Basic program before the first binary file that loads and runs it with `RUN\"CAS:\"`")
+    };
+    let machine = || {
+        Arg::new("machine")
+        .long("machine")
+        .value_name("machine")
+        .value_parser(|s: &str| s.parse::<Machine>().map_err(|e| e.to_string()))
+        .help("The machine the CAS file is for: `msx` (the default) or `svi` (Spectravideo SVI-318 and SVI-328)")
+    };
+    let baud = || {
+        Arg::new("baud")
+            .long("baud")
+            .value_name("bauds")
+            .default_value("1200")
+            .value_parser(["1200", "2400", "3600", "4800"])
+            .help("The speed of the recording (3600 and 4800 are turbo speeds not every MSX reads)")
+    };
+    let turbo_from = || {
+        Arg::new("turbo-from")
+        .long("turbo-from")
+        .value_name("index")
+        .value_parser(clap::value_parser!(usize))
+        .help("Record the files before the given position (starting at 0) at 1200 bauds, for a loader to read the rest")
+    };
+    let program = || {
+        Arg::new("program")
+            .required(true)
+            .help("The name of the program in the CAS file (e.g. `GAME` or `game.bas`)")
+    };
+
+    clap::Command::new("mcp")
+        .about("MSX CAS Packager")
+        .override_usage("mcp <COMMAND>\n       mcp --version")
+        .disable_version_flag(true)
+        .arg_required_else_help(true)
+        .allow_external_subcommands(true)
+        .arg(Arg::new("version")
+            .short('v')
+            .long("version")
+            .action(ArgAction::SetTrue)
+            .exclusive(true)
+            .help("Print the mcp version"))
+        .arg(Arg::new("format")
+            .long("format")
+            .value_name("format")
+            .global(true)
+            .default_value("text")
+            .value_parser(|s: &str| s.parse::<Format>().map_err(|e| e.to_string()))
+            .help("Print the output, errors and warnings as `text` or `json`"))
+        .arg(Arg::new("verbose")
+            .long("verbose")
+            .action(ArgAction::SetTrue)
+            .global(true)
+            .help("Detail the blocks of the tapes as they are processed"))
+        .arg(Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .action(ArgAction::SetTrue)
+            .global(true)
+            .conflicts_with("verbose")
+            .help("Print only the results, warnings and errors, with no progress messages"))
+        .subcommand(clap::Command::new("list")
+            .short_flag('l')
+            .long_flag("list")
+            .about("Lists the contents of the given CAS file")
+            .arg(Arg::new("long")
+                .long("long")
+                .action(ArgAction::SetTrue)
+                .help("Warn about binary files whose addresses would not BLOAD as expected"))
+            .arg(Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print the files as JSON records (same as `--format json`)"))
+            .arg(Arg::new("csv")
+                .long("csv")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["json", "long"])
+                .help("Print the files as CSV records, with a header line"))
+            .arg(Arg::new("hashes")
+                .long("hashes")
+                .action(ArgAction::SetTrue)
+                .help("Print the CRC32 and SHA-1 of the payload of each file and of the whole CAS file"))
+            .arg(Arg::new("index")
+                .long("index")
+                .action(ArgAction::SetTrue)
+                .help("Print the position of each file (as given to extract, remove or move) and its offset in the CAS file"))
+            .arg(machine())
+            .arg(strict())
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("add")
+            .short_flag('a')
+            .long_flag("add")
+            .about("Add new files to a given CAS file. If the CAS file does not exist, it is created.")
+            .arg(eol())
+            .arg(Arg::new("utf8")
+                .long("utf8")
+                .action(ArgAction::SetTrue)
+                .help("Read ASCII files and Basic sources as UTF-8 text, converting them to the MSX charset"))
+            .arg(Arg::new("with-verifier")
+                .long("with-verifier")
+                .action(ArgAction::SetTrue)
+                .help("Append a Basic program named VERIFY that checks the files of the tape on the MSX"))
+            .arg(with_loader())
+            .arg(gzip())
+            .arg(Arg::new("begin")
+                .long("begin")
+                .value_name("address")
+                .value_parser(parse_address)
+                .help("Add the files as binary files of raw machine code loaded at the given address (e.g. `0xc000`)"))
+            .arg(Arg::new("start")
+                .long("start")
+                .value_name("address")
+                .requires("begin")
+                .value_parser(parse_address)
+                .help("The start address of the raw machine code (the begin address if not given)"))
+            .arg(Arg::new("custom")
+                .long("custom")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("begin")
+                .help("Add the files as custom files, whatever their extension"))
+            .arg(Arg::new("name")
+                .long("name")
+                .value_name("name")
+                .help("The name of the file in the CAS file, or the label of a custom file, instead of its file name"))
+            .arg(machine().conflicts_with("with-verifier"))
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .required(true)
+                .num_args(1..)
+                .help("Files to add. The files of directories are added in alphabetical order")
+                .value_parser(clap::value_parser!(PathBuf))))
+        .subcommand(clap::Command::new("extract")
+            .short_flag('x')
+            .long_flag("extract")
+            .about("Extracts the contents from the given CAS file")
+            .arg(eol())
+            .arg(Arg::new("numbered")
+                .long("numbered")
+                .action(ArgAction::SetTrue)
+                .help("Prefix the extracted files with their position in the tape (e.g. `01_game.bin`)"))
+            .arg(Arg::new("as-text")
+                .long("as-text")
+                .action(ArgAction::SetTrue)
+                .help("Write tokenized Basic programs as text listings"))
+            .arg(Arg::new("utf8")
+                .long("utf8")
+                .action(ArgAction::SetTrue)
+                .help("Write ASCII files and listings as UTF-8 text, with `lf` line endings unless `--eol` is given"))
+            .arg(Arg::new("out")
+                .short('o')
+                .long("out")
+                .value_name("dir")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The directory to extract the files to (the current one if not given)"))
+            .arg(Arg::new("index")
+                .long("index")
+                .value_name("n")
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(usize))
+                .help("Extract the file at the given position in the CAS file (starting at 0), as shown by `list --index`"))
+            .arg(machine())
+            .arg(strict())
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .num_args(1..)
+                .help("The files to extract, by name, position in the CAS file (starting at 0) or glob pattern (e.g. `*.bin`). All of them if not given")))
+        .subcommand(clap::Command::new("cat")
+            .visible_alias("dump")
+            .about("Prints a file of the given CAS file")
+            .arg(Arg::new("basic")
+                .long("basic")
+                .action(ArgAction::SetTrue)
+                .help("Print tokenized Basic programs as text listings"))
+            .arg(Arg::new("hex")
+                .long("hex")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("basic")
+                .help("Print the file as a hex dump with the offset of each line"))
+            .arg(strict())
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .required(true)
+                .help("The files to print, by name (e.g. `GAME`, `game.bas` or `custom.000`), position in the CAS file (starting at 0) or glob pattern (e.g. `*.bin`)")))
+        .subcommand(clap::Command::new("remove")
+            .about("Removes files from the given CAS file")
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .required(true)
+                .num_args(1..)
+                .help("The files to remove, by name, position in the CAS file (starting at 0) or glob pattern (e.g. `*.bin`)")))
+        .subcommand(clap::Command::new("rename")
+            .about("Renames a file of the given CAS file")
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .required(true)
+                .help("The file to rename, by name or position in the CAS file (starting at 0)"))
+            .arg(Arg::new("new-name")
+                .required(true)
+                .help("The new name, up to 6 characters with or without extension (e.g. `GAME` or `game.bin`)")))
+        .subcommand(clap::Command::new("move")
+            .about("Moves a file of the given CAS file to another position")
+            .arg(Arg::new("to")
+                .long("to")
+                .value_name("index")
+                .required(true)
+                .value_parser(clap::value_parser!(usize))
+                .help("The new position of the file (starting at 0)"))
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .required(true)
+                .help("The file to move, by name or position in the CAS file (starting at 0)")))
+        .subcommand(clap::Command::new("patch")
+            .about("Overwrites bytes of a file of the given CAS file")
+            .arg(Arg::new("at")
+                .long("at")
+                .value_name("offset")
+                .required(true)
+                .value_parser(parse_offset)
+                .help("The offset in the payload of the file (e.g. `0x1a3`), at most its length"))
+            .arg(Arg::new("bytes")
+                .long("bytes")
+                .value_name("hex")
+                .value_parser(parse_bytes)
+                .help("The bytes to write, in hexadecimal (e.g. `\"21 00 C9\"`)"))
+            .arg(Arg::new("from-file")
+                .long("from-file")
+                .value_name("file")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write the contents of the given file"))
+            .group(clap::ArgGroup::new("patch").args(["bytes", "from-file"]).required(true))
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .required(true)
+                .help("The file to patch, by name or position in the CAS file (starting at 0)")))
+        .subcommand(clap::Command::new("browse")
+            .about("Browses the files of the given CAS file interactively to preview, extract, remove or rename them")
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("merge")
+            .about("Writes the files of several CAS files one after another into a new CAS file")
+            .arg(Arg::new("dedup")
+                .long("dedup")
+                .action(ArgAction::SetTrue)
+                .help("Skip the files with the same name and contents as a previous one"))
+            .arg(Arg::new("rename")
+                .long("rename")
+                .action(ArgAction::SetTrue)
+                .help("Rename the files with the same name as a previous one (e.g. `GAME` to `GAME1`)"))
+            .arg(gzip())
+            .arg(Arg::new("output")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The CAS file to write"))
+            .arg(Arg::new("cas-file")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The CAS files to merge, in order")))
+        .subcommand(clap::Command::new("split")
+            .about("Writes every file of the given CAS file as a CAS file of its own")
+            .arg(Arg::new("out")
+                .short('o')
+                .long("out")
+                .value_name("dir")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The directory to write the CAS files to (the current one if not given)"))
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("export")
+            .short_flag('e')
+            .long_flag("export")
+            .visible_alias("wav")
+            .about("Exports the CAS file into a WAV file (or a CSW or DSK file if <wav-file> has that extension)")
+            .arg(baud())
+            .arg(turbo_from())
+            .arg(Arg::new("sample-rate")
+                .long("sample-rate")
+                .value_name("hz")
+                .value_parser(clap::value_parser!(u32).range(9600..=192000))
+                .help("The samples per second of the WAV file [default: 43200, or 48000 at 4800 bauds]"))
+            .arg(cas_file())
+            .arg(Arg::new("wav-file").required(true).value_parser(clap::value_parser!(PathBuf))))
+        .subcommand(clap::Command::new("export-asm")
+            .about("Prints a file of the given CAS file as Z80 assembly source to include in other programs")
+            .arg(Arg::new("syntax")
+                .long("syntax")
+                .value_name("assembler")
+                .default_value("sjasmplus")
+                .value_parser(|s: &str| s.parse::<Dialect>().map_err(|e| e.to_string()))
+                .help("The assembler syntax: `sjasmplus`, `pasmo` or `tniasm`"))
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .required(true)
+                .help("The name of the file in the CAS file (e.g. `GAME`, `game.bin` or `custom.000`)")))
+        .subcommand(clap::Command::new("export-c")
+            .about("Writes a file of the given CAS file as a C header to include in other programs")
+            .arg(Arg::new("out")
+                .long("out")
+                .value_name("header")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The header file to write (printed if not given)"))
+            .arg(cas_file())
+            .arg(Arg::new("file")
+                .required(true)
+                .help("The name of the file in the CAS file (e.g. `GAME`, `game.bin` or `custom.000`)")))
+        .subcommand(clap::Command::new("convert")
+            .about("Converts a tape between the CAS and TSX formats, as given by the file extensions, or several tapes with --to")
+            .override_usage("mcp convert [OPTIONS] <input> <output>\n       mcp convert [OPTIONS] --to <format> <input>...")
+            .arg(baud().help("The speed of the blocks of TSX and WAV files"))
+            .arg(turbo_from())
+            .arg(strict())
+            .arg(Arg::new("to")
+                .long("to")
+                .value_name("format")
+                .value_parser(["cas", "tsx", "wav", "csw"])
+                .help("Convert every given tape to this format, naming the files after the tapes"))
+            .arg(Arg::new("output-dir")
+                .long("output-dir")
+                .value_name("dir")
+                .requires("to")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The directory to write the converted tapes to, instead of next to them"))
+            .arg(Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("n")
+                .requires("to")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("The number of tapes converted at once [default: the number of CPUs]"))
+            .arg(Arg::new("input")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The tape to convert and the file to write it to, or the tapes to convert with --to")))
+        .subcommand(clap::Command::new("romload")
+            .about("Converts a ROM image into a CAS file that loads and runs it with RUN\"CAS:\"")
+            .arg(Arg::new("rom-file")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The ROM image of 8KB, 16KB or 32KB"))
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("import")
+            .about("Imports files of an MSX-DOS disk image into the given CAS file. If the CAS file does not exist, it is created.")
+            .arg(cas_file())
+            .arg(Arg::new("dsk-file")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The disk image to read the files from"))
+            .arg(Arg::new("file")
+                .num_args(0..)
+                .help("The names of the files in the disk image (e.g. `GAME.BIN`). All the files if not given")))
+        .subcommand(clap::Command::new("build")
+            .about("Builds the CAS file described by a TOML manifest, replacing it if it exists")
+            .arg(with_loader())
+            .arg(Arg::new("manifest")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The manifest listing the files to pack (e.g. `tape.toml`)")))
+        .subcommand(clap::Command::new("master")
+            .about("Records several CAS files one after another into a WAV file to master a cassette side")
+            .arg(Arg::new("gap")
+                .long("gap")
+                .value_name("seconds")
+                .default_value("5")
+                .value_parser(clap::value_parser!(u32))
+                .help("The silence between programs"))
+            .arg(Arg::new("index")
+                .long("index")
+                .value_name("file")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write the times of each program to the given file (printed if not given)"))
+            .arg(Arg::new("wav-file").required(true).value_parser(clap::value_parser!(PathBuf)))
+            .arg(Arg::new("cas-file")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The CAS files, in the order they are recorded")))
+        .subcommand(clap::Command::new("play")
+            .about("Plays the CAS file through the sound device to load it in a real MSX")
+            .arg(baud())
+            .arg(turbo_from())
+            .arg(Arg::new("remote")
+                .long("remote")
+                .value_name("line")
+                .value_parser(|s: &str| s.parse::<Line>().map_err(|e| e.to_string()))
+                .help("Drive a cassette remote relay with a control line (`gpio:<number>`, `dtr:<device>` or `rts:<device>`), on while every file plays"))
+            .arg(Arg::new("pause")
+                .long("pause")
+                .value_name("seconds")
+                .default_value("3")
+                .requires("remote")
+                .value_parser(clap::value_parser!(u32))
+                .help("The seconds the remote is off between files"))
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("load")
+            .about("Decodes a WAV recording of a tape into a CAS file")
+            .arg(gzip())
+            .arg(Arg::new("wav-file").required(true).value_parser(clap::value_parser!(PathBuf)))
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("record")
+            .about("Records a tape from the sound device into a session directory, decoded and cut by file")
+            .arg(Arg::new("input")
+                .long("input")
+                .value_name("device")
+                .help("The input device to record from (e.g. `line-in`), instead of the default one"))
+            .arg(Arg::new("seconds")
+                .long("seconds")
+                .value_name("seconds")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("Stop recording after the given seconds, instead of when Enter is pressed"))
+            .arg(Arg::new("session-dir").required(true).value_parser(clap::value_parser!(PathBuf))))
+        .subcommand(clap::Command::new("remote")
+            .about("Switches on a cassette remote relay to run the cassette player")
+            .arg(Arg::new("seconds")
+                .long("seconds")
+                .value_name("seconds")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("Switch the relay off after the given seconds, instead of when Enter is pressed"))
+            .arg(Arg::new("line")
+                .required(true)
+                .value_parser(|s: &str| s.parse::<Line>().map_err(|e| e.to_string()))
+                .help("The control line of the relay: `gpio:<number>`, `dtr:<device>` or `rts:<device>`")))
+        .subcommand(clap::Command::new("identify")
+            .about("Identifies the contents of the files in the given CAS file using known signatures")
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("annotate")
+            .about("Annotates a file of the given CAS file with a title, notes, source or dump date")
+            .arg(Arg::new("title").long("title").value_name("text").help("Set the title of the file"))
+            .arg(Arg::new("notes").long("notes").value_name("text").help("Set the notes about the file"))
+            .arg(Arg::new("source").long("source").value_name("text").help("Set where the file was dumped from"))
+            .arg(Arg::new("dump-date").long("dump-date").value_name("date").help("Set when the file was dumped"))
+            .arg(Arg::new("clear")
+                .long("clear")
+                .action(ArgAction::SetTrue)
+                .help("Remove the annotations not set by this command"))
+            .arg(cas_file())
+            .arg(Arg::new("index")
+                .required(true)
+                .value_parser(clap::value_parser!(usize))
+                .help("The position of the file in the CAS file, starting at 0")))
+        .subcommand(clap::Command::new("info")
+            .about("Prints statistics about the given CAS files")
+            .arg(Arg::new("cas-file")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))))
+        .subcommand(clap::Command::new("hash")
+            .about("Prints the CRC32 and SHA-1 of the given CAS files (once decompressed)")
+            .arg(machine())
+            .arg(Arg::new("cas-file")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))))
+        .subcommand(clap::Command::new("verify")
+            .about("Verifies the given CAS files and their contents against a No-Intro/TOSEC DAT file")
+            .arg(Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .help("Fail if the addresses of a binary file would not BLOAD as expected"))
+            .arg(Arg::new("dat-file").required(true).value_parser(clap::value_parser!(PathBuf)))
+            .arg(Arg::new("cas-file")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))))
+        .subcommand(clap::Command::new("check")
+            .about("Checks the structure of the given CAS files and fails if any of them is damaged")
+            .arg(Arg::new("cas-file")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))))
+        .subcommand(clap::Command::new("grep-basic")
+            .about("Searches the given text in the Basic programs of the given CAS files and directories")
+            .arg(Arg::new("ignore-case")
+                .short('i')
+                .long("ignore-case")
+                .action(ArgAction::SetTrue)
+                .help("Ignore the case of letters"))
+            .arg(Arg::new("pattern").required(true))
+            .arg(Arg::new("path")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("CAS files, or directories searched recursively for CAS files")))
+        .subcommand(clap::Command::new("which")
+            .about("Finds the CAS files that contain the given file")
+            .arg(Arg::new("file")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The file to look for (e.g., one extracted from a CAS file)"))
+            .arg(Arg::new("path")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("CAS files, or directories searched recursively for CAS files")))
+        .subcommand(clap::Command::new("compare-dir")
+            .about("Compares the files of the given CAS file with the same-named files of a directory")
+            .arg(cas_file())
+            .arg(Arg::new("dir")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The directory with the source files")))
+        .subcommand(clap::Command::new("diff")
+            .about("Compares two CAS files and reports the files added, removed or changed")
+            .arg(Arg::new("blocks")
+                .long("blocks")
+                .action(ArgAction::SetTrue)
+                .help("Compare the blocks of the CAS files instead of their files"))
+            .arg(cas_file())
+            .arg(Arg::new("other")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The CAS file to compare with")))
+        .subcommand(clap::Command::new("edit")
+            .about("Edits a Basic program of the given CAS file with the editor set in $EDITOR")
+            .arg(cas_file())
+            .arg(program()))
+        .subcommand(clap::Command::new("basic")
+            .about("Transforms the Basic programs of a CAS file")
+            .subcommand_required(true)
+            .subcommand(clap::Command::new("shrink")
+                .about("Makes a Basic program smaller: shortens variable names, removes remarks and spaces, and merges lines")
+                .arg(cas_file())
+                .arg(program()))
+            .subcommand(clap::Command::new("convert")
+                .about("Converts a Basic program between tokenized (CLOAD) and ASCII (LOAD) in place")
+                .arg(Arg::new("to")
+                    .long("to")
+                    .value_name("format")
+                    .required(true)
+                    .value_parser(["ascii", "tokenized"])
+                    .help("The format to convert the program to"))
+                .arg(cas_file())
+                .arg(program())))
+        .subcommand(clap::Command::new("fit")
+            .about("Checks whether the given CAS file fits in one side of a cassette")
+            .arg(Arg::new("length")
+                .long("length")
+                .value_name("length")
+                .default_value("C60")
+                .value_parser(|s: &str| s.parse::<Cassette>().map_err(|e| e.to_string()))
+                .help("The length of the cassette (e.g. `C60` or `C90`)"))
+            .arg(baud())
+            .arg(turbo_from())
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("loadcheck")
+            .about("Checks whether the files of the given CAS file would load in a real MSX")
+            .arg(Arg::new("ram")
+                .long("ram")
+                .value_name("sizes")
+                .value_delimiter(',')
+                .default_value("16,32,64")
+                .value_parser(|s: &str| s.parse::<Ram>().map_err(|e| e.to_string()))
+                .help("The comma-separated RAM sizes in KB of the machines to check"))
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("gen-fixture")
+            .about("Generates a synthetic CAS file for testing. The same options always generate the same file")
+            .arg(Arg::new("files")
+                .long("files")
+                .value_name("count")
+                .default_value("5")
+                .value_parser(clap::value_parser!(usize))
+                .help("The number of files of the tape"))
+            .arg(Arg::new("types")
+                .long("types")
+                .value_name("types")
+                .value_delimiter(',')
+                .default_value("bin,basic,ascii,custom")
+                .value_parser(|s: &str| s.parse::<FileType>().map_err(|e| e.to_string()))
+                .help("The comma-separated types the files are chosen from"))
+            .arg(Arg::new("seed")
+                .long("seed")
+                .value_name("seed")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u64))
+                .help("The seed of the generator"))
+            .arg(gzip())
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("doctor")
+            .about("Checks the environment mcp runs in (editor, openMSX, external commands...)"))
+        .subcommand(clap::Command::new("preview")
+            .about("Renders the screen dumps found in the given CAS file as PNG images")
+            .arg(cas_file()))
+        .subcommand(clap::Command::new("openmsx")
+            .about("Controls a running openMSX emulator")
+            .subcommand_required(true)
+            .subcommand(clap::Command::new("insert")
+                .about("Inserts the given CAS file in the cassette player of openMSX and rewinds it")
+                .arg(Arg::new("socket")
+                    .long("socket")
+                    .value_name("path")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Connect to the given control socket instead of the most recent openMSX"))
+                .arg(Arg::new("load")
+                    .long("load")
+                    .value_name("command")
+                    .help("Type the given command after inserting the tape (e.g. `RUN\"CAS:\"`)"))
+                .arg(cas_file())))
 }
 
-impl Args {
+/// Convert the matches returned by clap into a `Command` and its `Output` options
+///
+/// The `--json` flag of the commands that have it overrides the `--format` option.
+///
+fn cmd(matches: ArgMatches) -> (Command, Output) {
+    let json = matches
+        .subcommand()
+        .and_then(|(_, m)| m.try_get_one::<bool>("json").ok().flatten())
+        .is_some_and(|json| *json);
+    let format = matches
+        .subcommand()
+        .and_then(|(_, m)| m.try_get_one::<Format>("format").ok().flatten())
+        .or_else(|| matches.get_one::<Format>("format"))
+        .copied()
+        .unwrap_or(Format::Text);
+    let format = if json { Format::Json } else { format };
+    let flag = |id: &str| {
+        matches.get_flag(id)
+            || matches
+                .subcommand()
+                .is_some_and(|(_, m)| m.try_get_one::<bool>(id).ok().flatten() == Some(&true))
+    };
+    let verbosity = if flag("verbose") {
+        Verbosity::Verbose
+    } else if flag("quiet") {
+        Verbosity::Quiet
+    } else {
+        Verbosity::Normal
+    };
+    (command(&matches), Output { format, verbosity })
+}
 
-    /// Parse the
-    pub fn cmd(self) -> Command {
-        if self.flag_version {
-            Command::Version
-        } else if self.flag_list {
-            Command::List(PathBuf::from(self.arg_cas_file))
-        } else if self.flag_add {
-            Command::Add(
-                PathBuf::from(self.arg_cas_file),
-                self.arg_file.iter().map(|f| PathBuf::from(f)).collect())
-        } else if self.flag_extract {
-            Command::Extract(PathBuf::from(self.arg_cas_file))
-        } else if self.flag_export {
-            Command::Export(PathBuf::from(self.arg_cas_file), PathBuf::from(self.arg_wav_file))
+fn command(matches: &ArgMatches) -> Command {
+    if matches.get_flag("version") {
+        return Command::Version;
+    }
+    let path = |m: &ArgMatches, id: &str| m.get_one::<PathBuf>(id).cloned().unwrap_or_default();
+    let profile = |m: &ArgMatches| {
+        let bauds = m
+            .get_one::<String>("baud")
+            .and_then(|b| b.parse().ok())
+            .unwrap_or(1200);
+        Profile {
+            turbo_from: m
+                .get_one::<usize>("turbo-from")
+                .copied()
+                .unwrap_or_default(),
+            ..Profile::with_bauds(bauds)
+        }
+    };
+    let scan = |m: &ArgMatches| {
+        if m.get_flag("strict") {
+            Scan::Aligned
         } else {
-            panic!("args are parsed in a inconsistent state")
+            Scan::Unaligned
+        }
+    };
+    let machine = |m: &ArgMatches| m.get_one::<Machine>("machine").copied().unwrap_or_default();
+    match matches.subcommand() {
+        Some(("list", m)) => Command::List(
+            path(m, "cas-file"),
+            ListOptions {
+                long: m.get_flag("long"),
+                csv: m.get_flag("csv"),
+                hashes: m.get_flag("hashes"),
+                index: m.get_flag("index"),
+                machine: machine(m),
+            },
+            scan(m),
+        ),
+        Some(("add", m)) => Command::Add(
+            path(m, "cas-file"),
+            m.get_many::<PathBuf>("file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            AddOptions {
+                eol: m.get_one::<LineEnding>("eol").copied(),
+                verifier: m.get_flag("with-verifier"),
+                gzip: m.get_flag("gzip"),
+                begin: m.get_one::<u16>("begin").copied(),
+                start: m.get_one::<u16>("start").copied(),
+                utf8: m.get_flag("utf8"),
+                custom: m.get_flag("custom"),
+                name: m.get_one::<String>("name").cloned(),
+                machine: machine(m),
+                loader: m.get_flag("with-loader"),
+            },
+        ),
+        Some(("extract", m)) => Command::Extract(
+            path(m, "cas-file"),
+            m.get_many::<String>("file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .chain(
+                    m.get_many::<usize>("index")
+                        .into_iter()
+                        .flatten()
+                        .map(usize::to_string),
+                )
+                .collect(),
+            m.get_one::<PathBuf>("out").cloned(),
+            ExtractOptions {
+                eol: m.get_one::<LineEnding>("eol").copied(),
+                numbered: m.get_flag("numbered"),
+                as_text: m.get_flag("as-text"),
+                utf8: m.get_flag("utf8"),
+            },
+            scan(m),
+            machine(m),
+        ),
+        Some(("cat", m)) => Command::Cat(
+            path(m, "cas-file"),
+            m.get_one::<String>("file").cloned().unwrap_or_default(),
+            m.get_flag("basic"),
+            m.get_flag("hex"),
+            scan(m),
+        ),
+        Some(("remove", m)) => Command::Remove(
+            path(m, "cas-file"),
+            m.get_many::<String>("file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        ),
+        Some(("rename", m)) => Command::Rename(
+            path(m, "cas-file"),
+            m.get_one::<String>("file").cloned().unwrap_or_default(),
+            m.get_one::<String>("new-name").cloned().unwrap_or_default(),
+        ),
+        Some(("move", m)) => Command::Move(
+            path(m, "cas-file"),
+            m.get_one::<String>("file").cloned().unwrap_or_default(),
+            m.get_one::<usize>("to").copied().unwrap_or_default(),
+        ),
+        Some(("patch", m)) => Command::Patch(
+            path(m, "cas-file"),
+            m.get_one::<String>("file").cloned().unwrap_or_default(),
+            m.get_one::<usize>("at").copied().unwrap_or_default(),
+            match m.get_one::<PathBuf>("from-file") {
+                Some(file) => Patch::File(file.clone()),
+                None => Patch::Bytes(m.get_one::<Vec<u8>>("bytes").cloned().unwrap_or_default()),
+            },
+        ),
+        Some(("browse", m)) => Command::Browse(path(m, "cas-file")),
+        Some(("merge", m)) => Command::Merge(
+            path(m, "output"),
+            m.get_many::<PathBuf>("cas-file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            MergePolicy {
+                dedup: m.get_flag("dedup"),
+                rename: m.get_flag("rename"),
+            },
+            m.get_flag("gzip"),
+        ),
+        Some(("split", m)) => {
+            Command::Split(path(m, "cas-file"), m.get_one::<PathBuf>("out").cloned())
+        }
+        Some(("export", m)) => Command::Export(
+            path(m, "cas-file"),
+            path(m, "wav-file"),
+            Profile {
+                sample_rate: m
+                    .get_one::<u32>("sample-rate")
+                    .copied()
+                    .unwrap_or(profile(m).sample_rate),
+                ..profile(m)
+            },
+        ),
+        Some(("export-asm", m)) => Command::ExportAsm(
+            path(m, "cas-file"),
+            m.get_one::<String>("file").cloned().unwrap_or_default(),
+            m.get_one::<Dialect>("syntax")
+                .copied()
+                .unwrap_or(Dialect::Sjasmplus),
+        ),
+        Some(("export-c", m)) => Command::ExportC(
+            path(m, "cas-file"),
+            m.get_one::<String>("file").cloned().unwrap_or_default(),
+            m.get_one::<PathBuf>("out").cloned(),
+        ),
+        Some(("convert", m)) => {
+            let paths: Vec<PathBuf> = m
+                .get_many::<PathBuf>("input")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect();
+            match (m.get_one::<String>("to"), paths.as_slice()) {
+                (Some(to), _) => Command::ConvertAll(
+                    paths.clone(),
+                    to.clone(),
+                    m.get_one::<PathBuf>("output-dir").cloned(),
+                    m.get_one::<u16>("jobs").map(|n| *n as usize),
+                    profile(m),
+                    scan(m)),
+                (None, [input, output]) => Command::Convert(input.clone(), output.clone(), profile(m), scan(m)),
+                (None, _) => cli().error(
+                    ErrorKind::WrongNumberOfValues,
+                    "convert takes an input and an output file, or several input files with --to").exit(),
+            }
         }
+        Some(("romload", m)) => Command::RomLoad(path(m, "rom-file"), path(m, "cas-file")),
+        Some(("import", m)) => Command::Import(
+            path(m, "cas-file"),
+            path(m, "dsk-file"),
+            m.get_many::<String>("file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        ),
+        Some(("build", m)) => Command::Build(path(m, "manifest"), m.get_flag("with-loader")),
+        Some(("master", m)) => Command::Master(
+            path(m, "wav-file"),
+            m.get_many::<PathBuf>("cas-file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            m.get_one::<u32>("gap").copied().unwrap_or(5),
+            m.get_one::<PathBuf>("index").cloned(),
+        ),
+        Some(("play", m)) => Command::Play(
+            path(m, "cas-file"),
+            profile(m),
+            m.get_one::<Line>("remote").cloned(),
+            m.get_one::<u32>("pause").copied().unwrap_or(3),
+        ),
+        Some(("load", m)) => {
+            Command::Load(path(m, "wav-file"), path(m, "cas-file"), m.get_flag("gzip"))
+        }
+        Some(("record", m)) => Command::Record(
+            path(m, "session-dir"),
+            m.get_one::<String>("input").cloned(),
+            m.get_one::<u32>("seconds").copied(),
+        ),
+        Some(("remote", m)) => Command::Remote(
+            m.get_one::<Line>("line")
+                .cloned()
+                .expect("the line is required"),
+            m.get_one::<u32>("seconds").copied(),
+        ),
+        Some(("identify", m)) => Command::Identify(path(m, "cas-file")),
+        Some(("annotate", m)) => {
+            let text = |id: &str| m.get_one::<String>(id).cloned();
+            Command::Annotate(
+                path(m, "cas-file"),
+                m.get_one::<usize>("index").copied().unwrap_or_default(),
+                Annotation {
+                    title: text("title"),
+                    notes: text("notes"),
+                    source: text("source"),
+                    dump_date: text("dump-date"),
+                },
+                m.get_flag("clear"),
+            )
+        }
+        Some(("info", m)) => Command::Info(
+            m.get_many::<PathBuf>("cas-file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        ),
+        Some(("hash", m)) => Command::Hash(
+            m.get_many::<PathBuf>("cas-file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            machine(m),
+        ),
+        Some(("verify", m)) => Command::Verify(
+            path(m, "dat-file"),
+            m.get_many::<PathBuf>("cas-file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            m.get_flag("strict"),
+        ),
+        Some(("check", m)) => Command::Check(
+            m.get_many::<PathBuf>("cas-file")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        ),
+        Some(("grep-basic", m)) => Command::GrepBasic(
+            m.get_one::<String>("pattern").cloned().unwrap_or_default(),
+            m.get_many::<PathBuf>("path")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            m.get_flag("ignore-case"),
+        ),
+        Some(("which", m)) => Command::Which(
+            path(m, "file"),
+            m.get_many::<PathBuf>("path")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        ),
+        Some(("compare-dir", m)) => Command::CompareDir(path(m, "cas-file"), path(m, "dir")),
+        Some(("diff", m)) => {
+            Command::Diff(path(m, "cas-file"), path(m, "other"), m.get_flag("blocks"))
+        }
+        Some(("edit", m)) => Command::Edit(
+            path(m, "cas-file"),
+            m.get_one::<String>("program").cloned().unwrap_or_default(),
+        ),
+        Some(("fit", m)) => Command::Fit(
+            path(m, "cas-file"),
+            m.get_one::<Cassette>("length")
+                .copied()
+                .unwrap_or(Cassette { minutes: 60 }),
+            profile(m),
+        ),
+        Some(("loadcheck", m)) => Command::LoadCheck(
+            path(m, "cas-file"),
+            m.get_many::<Ram>("ram")
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect(),
+        ),
+        Some(("gen-fixture", m)) => Command::GenFixture(
+            path(m, "cas-file"),
+            Fixture {
+                files: m.get_one::<usize>("files").copied().unwrap_or_default(),
+                types: m
+                    .get_many::<FileType>("types")
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .collect(),
+                seed: m.get_one::<u64>("seed").copied().unwrap_or_default(),
+            },
+            m.get_flag("gzip"),
+        ),
+        Some(("doctor", _)) => Command::Doctor,
+        Some(("preview", m)) => Command::Preview(path(m, "cas-file")),
+        Some(("basic", m)) => match m.subcommand() {
+            Some(("shrink", m)) => Command::BasicShrink(
+                path(m, "cas-file"),
+                m.get_one::<String>("program").cloned().unwrap_or_default(),
+            ),
+            Some(("convert", m)) => Command::BasicConvert(
+                path(m, "cas-file"),
+                m.get_one::<String>("program").cloned().unwrap_or_default(),
+                match m.get_one::<String>("to").map(String::as_str) {
+                    Some("ascii") => FileType::Ascii,
+                    _ => FileType::Basic,
+                },
+            ),
+            _ => unreachable!("basic requires a subcommand"),
+        },
+        Some(("openmsx", m)) => match m.subcommand() {
+            Some(("insert", m)) => Command::OpenMsxInsert(
+                path(m, "cas-file"),
+                m.get_one::<PathBuf>("socket").cloned(),
+                m.get_one::<String>("load").cloned(),
+            ),
+            _ => unreachable!("openmsx requires a subcommand"),
+        },
+        Some((name, m)) => Command::External(
+            name.to_string(),
+            m.get_many::<OsString>("")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        ),
+        None => cli()
+            .error(ErrorKind::MissingSubcommand, "a command is required")
+            .exit(),
+    }
+}
+
+/// Parse a memory address in decimal or hexadecimal (`0xc000`, `&HC000` or `C000h`)
+fn parse_address(s: &str) -> Result<u16, String> {
+    let lower = s.trim().to_lowercase();
+    let hex = lower
+        .strip_prefix("0x")
+        .or_else(|| lower.strip_prefix("&h"))
+        .or_else(|| lower.strip_suffix('h'));
+    match hex {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => lower.parse::<u16>(),
     }
+    .map_err(|_| {
+        format!(
+            "invalid address {:?} (expected a number from 0 to 0xffff)",
+            s
+        )
+    })
+}
+
+fn parse_offset(s: &str) -> Result<usize, String> {
+    let lower = s.trim().to_lowercase();
+    match lower.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => lower.parse::<usize>(),
+    }
+    .map_err(|_| format!("invalid offset {:?}", s))
+}
+
+fn parse_bytes(s: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!(
+            "invalid bytes {:?} (expected pairs of hexadecimal digits)",
+            s
+        ));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| {
+            format!(
+                "invalid bytes {:?} (expected pairs of hexadecimal digits)",
+                s
+            )
+        })
 }
 
 /// Parse the arguments passed to `mcp`
 ///
-/// Same as `parse_args(std::env::args())`.
+/// Same as `parse_args(std::env::args_os())`.
 ///
-#[allow(dead_code)]
-pub fn parse() -> Command {
-    parse_args(args())
+pub fn parse() -> (Command, Output) {
+    parse_args(args_os())
 }
 
-/// Parse the given arguments and return the corresponding `Command` object
-pub fn parse_args<I, S>(args: I) -> Command
-where S: AsRef<str>, I: Iterator<Item=S>, S: Into<String> {
-    let parsed: Args = Docopt::new(USAGE)
-        .and_then(|d| d.argv(args).deserialize())
-        .unwrap_or_else(|e| e.exit());
-    parsed.cmd()
+/// Parse the given arguments and return the corresponding `Command` object and the
+/// selected `Output` options
+///
+/// If the arguments are not valid, the usage is printed and the process exits.
+///
+pub fn parse_args<I, T>(args: I) -> (Command, Output)
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    cmd(cli().get_matches_from(args))
 }
 
 #[cfg(test)]
@@ -112,38 +1341,1188 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn should_have_valid_definition() {
+        cli().debug_assert();
+    }
+
     #[test]
     fn should_parse_version() {
         let argv = ["mcp", "--version"];
-        let cmd = parse_args(argv.iter().map(|a| a.to_string()));
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
         assert_eq!(Command::Version, cmd);
     }
 
     #[test]
     fn should_parse_list() {
         let argv = ["mcp", "--list", "foobar.cas"];
-        let cmd = parse_args(argv.iter().map(|a| a.to_string()));
-        assert_eq!(Command::List(PathBuf::from("foobar.cas")), cmd);
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::List(
+                PathBuf::from("foobar.cas"),
+                ListOptions::default(),
+                Scan::Unaligned
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_list_subcommand() {
+        for argv in &[["mcp", "list", "foobar.cas"], ["mcp", "-l", "foobar.cas"]] {
+            let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+            assert_eq!(
+                Command::List(
+                    PathBuf::from("foobar.cas"),
+                    ListOptions::default(),
+                    Scan::Unaligned
+                ),
+                cmd
+            );
+        }
+        let argv = ["mcp", "list", "--long", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::List(
+                PathBuf::from("foobar.cas"),
+                ListOptions {
+                    long: true,
+                    ..ListOptions::default()
+                },
+                Scan::Unaligned
+            ),
+            cmd
+        );
+        let argv = ["mcp", "list", "--csv", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::List(
+                PathBuf::from("foobar.cas"),
+                ListOptions {
+                    csv: true,
+                    ..ListOptions::default()
+                },
+                Scan::Unaligned
+            ),
+            cmd
+        );
+        let argv = ["mcp", "list", "--json", "foobar.cas"];
+        let (cmd, output) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::List(
+                PathBuf::from("foobar.cas"),
+                ListOptions::default(),
+                Scan::Unaligned
+            ),
+            cmd
+        );
+        assert_eq!(Format::Json, output.format);
+        let argv = ["mcp", "list", "--hashes", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::List(
+                PathBuf::from("foobar.cas"),
+                ListOptions {
+                    hashes: true,
+                    ..ListOptions::default()
+                },
+                Scan::Unaligned
+            ),
+            cmd
+        );
+        let argv = ["mcp", "list", "--index", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::List(
+                PathBuf::from("foobar.cas"),
+                ListOptions {
+                    index: true,
+                    ..ListOptions::default()
+                },
+                Scan::Unaligned
+            ),
+            cmd
+        );
     }
 
     #[test]
     fn should_parse_add() {
         let argv = ["mcp", "--add", "foobar.cas", "f1.bin"];
-        let cmd = parse_args(argv.iter().map(|a| a.to_string()));
-        assert_eq!(Command::Add(PathBuf::from("foobar.cas"), vec![ PathBuf::from("f1.bin")]), cmd);
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Add(
+                PathBuf::from("foobar.cas"),
+                vec![PathBuf::from("f1.bin")],
+                AddOptions::default()
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_add_with_eol() {
+        let argv = ["mcp", "--add", "--eol=crlf", "foobar.cas", "f1.asc"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Add(
+                PathBuf::from("foobar.cas"),
+                vec![PathBuf::from("f1.asc")],
+                AddOptions {
+                    eol: Some(LineEnding::CrLf),
+                    ..AddOptions::default()
+                }
+            ),
+            cmd
+        );
+        let argv = ["mcp", "add", "--utf8", "foobar.cas", "f1.asc"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Add(
+                PathBuf::from("foobar.cas"),
+                vec![PathBuf::from("f1.asc")],
+                AddOptions {
+                    utf8: true,
+                    ..AddOptions::default()
+                }
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_add_with_verifier() {
+        let argv = ["mcp", "add", "--with-verifier", "foobar.cas", "f1.bin"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Add(
+                PathBuf::from("foobar.cas"),
+                vec![PathBuf::from("f1.bin")],
+                AddOptions {
+                    verifier: true,
+                    ..AddOptions::default()
+                }
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_add_with_gzip() {
+        let argv = ["mcp", "add", "--gzip", "foobar.cas.gz", "f1.bin"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Add(
+                PathBuf::from("foobar.cas.gz"),
+                vec![PathBuf::from("f1.bin")],
+                AddOptions {
+                    gzip: true,
+                    ..AddOptions::default()
+                }
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_add_with_addresses() {
+        let argv = [
+            "mcp",
+            "add",
+            "--begin",
+            "0xC000",
+            "--start",
+            "C010h",
+            "foobar.cas",
+            "code.raw",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Add(
+                PathBuf::from("foobar.cas"),
+                vec![PathBuf::from("code.raw")],
+                AddOptions {
+                    begin: Some(0xc000),
+                    start: Some(0xc010),
+                    ..AddOptions::default()
+                }
+            ),
+            cmd
+        );
+        assert_eq!(Ok(0xc000), parse_address("&HC000"));
+        assert_eq!(Ok(49152), parse_address("49152"));
+        assert!(parse_address("0x10000").is_err());
+    }
+
+    #[test]
+    fn should_parse_add_custom() {
+        let argv = [
+            "mcp",
+            "add",
+            "--custom",
+            "--name",
+            "leveldata",
+            "foobar.cas",
+            "payload.bin",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Add(
+                PathBuf::from("foobar.cas"),
+                vec![PathBuf::from("payload.bin")],
+                AddOptions {
+                    custom: true,
+                    name: Some("leveldata".to_string()),
+                    ..AddOptions::default()
+                }
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_machine() {
+        let argv = ["mcp", "list", "--machine", "svi", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let options = ListOptions {
+            machine: Machine::Svi,
+            ..ListOptions::default()
+        };
+        assert_eq!(
+            Command::List(PathBuf::from("foobar.cas"), options, Scan::Unaligned),
+            cmd
+        );
+        let argv = ["mcp", "extract", "--machine", "svi", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Extract(
+                PathBuf::from("foobar.cas"),
+                vec![],
+                None,
+                ExtractOptions::default(),
+                Scan::Unaligned,
+                Machine::Svi
+            ),
+            cmd
+        );
+        let argv = ["mcp", "add", "--machine", "svi", "foobar.cas", "game.bin"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Add(
+                PathBuf::from("foobar.cas"),
+                vec![PathBuf::from("game.bin")],
+                AddOptions {
+                    machine: Machine::Svi,
+                    ..AddOptions::default()
+                }
+            ),
+            cmd
+        );
     }
 
     #[test]
     fn should_parse_extract() {
         let argv = ["mcp", "--extract", "foobar.cas"];
-        let cmd = parse_args(argv.iter().map(|a| a.to_string()));
-        assert_eq!(Command::Extract(PathBuf::from("foobar.cas")), cmd);
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Extract(
+                PathBuf::from("foobar.cas"),
+                vec![],
+                None,
+                ExtractOptions::default(),
+                Scan::Unaligned,
+                Machine::Msx
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_extract_with_eol() {
+        let argv = ["mcp", "--extract", "--eol=lf", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let options = ExtractOptions {
+            eol: Some(LineEnding::Lf),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(
+            Command::Extract(
+                PathBuf::from("foobar.cas"),
+                vec![],
+                None,
+                options,
+                Scan::Unaligned,
+                Machine::Msx
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_extract_numbered() {
+        let argv = ["mcp", "extract", "--numbered", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let options = ExtractOptions {
+            numbered: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(
+            Command::Extract(
+                PathBuf::from("foobar.cas"),
+                vec![],
+                None,
+                options,
+                Scan::Unaligned,
+                Machine::Msx
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_extract_selection() {
+        let argv = [
+            "mcp", "extract", "tape.cas", "GAME", "3", "*.bin", "-o", "out",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Extract(
+                PathBuf::from("tape.cas"),
+                vec!["GAME".to_string(), "3".to_string(), "*.bin".to_string()],
+                Some(PathBuf::from("out")),
+                ExtractOptions::default(),
+                Scan::Unaligned,
+                Machine::Msx
+            ),
+            cmd
+        );
+        let argv = ["mcp", "extract", "--index", "2", "tape.cas", "--index", "0"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Extract(
+                PathBuf::from("tape.cas"),
+                vec!["2".to_string(), "0".to_string()],
+                None,
+                ExtractOptions::default(),
+                Scan::Unaligned,
+                Machine::Msx
+            ),
+            cmd
+        );
+        let argv = ["mcp", "extract", "--index", "GAME", "tape.cas"];
+        assert!(cli().try_get_matches_from(argv.iter()).is_err());
+    }
+
+    #[test]
+    fn should_parse_extract_as_text() {
+        let argv = ["mcp", "extract", "--as-text", "--eol", "crlf", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let options = ExtractOptions {
+            eol: Some(LineEnding::CrLf),
+            as_text: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(
+            Command::Extract(
+                PathBuf::from("foobar.cas"),
+                vec![],
+                None,
+                options,
+                Scan::Unaligned,
+                Machine::Msx
+            ),
+            cmd
+        );
+        let argv = ["mcp", "extract", "--utf8", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let options = ExtractOptions {
+            utf8: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(
+            Command::Extract(
+                PathBuf::from("foobar.cas"),
+                vec![],
+                None,
+                options,
+                Scan::Unaligned,
+                Machine::Msx
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_cat() {
+        let argv = ["mcp", "cat", "--basic", "tape.cas", "GAME"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Cat(
+                PathBuf::from("tape.cas"),
+                "GAME".to_string(),
+                true,
+                false,
+                Scan::Unaligned
+            ),
+            cmd
+        );
+        let argv = ["mcp", "dump", "--strict", "--hex", "tape.cas", "GAME"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Cat(
+                PathBuf::from("tape.cas"),
+                "GAME".to_string(),
+                false,
+                true,
+                Scan::Aligned
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_remove_and_rename() {
+        let argv = ["mcp", "remove", "tape.cas", "GAME", "*.asc"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Remove(
+                PathBuf::from("tape.cas"),
+                vec!["GAME".to_string(), "*.asc".to_string()]
+            ),
+            cmd
+        );
+        let argv = ["mcp", "rename", "tape.cas", "0", "game.bin"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Rename(
+                PathBuf::from("tape.cas"),
+                "0".to_string(),
+                "game.bin".to_string()
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_move() {
+        let argv = ["mcp", "move", "tape.cas", "GAME", "--to", "0"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Move(PathBuf::from("tape.cas"), "GAME".to_string(), 0),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_patch() {
+        let argv = [
+            "mcp", "patch", "tape.cas", "GAME", "--at", "0x1A3", "--bytes", "21 00 C9",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Patch(
+                PathBuf::from("tape.cas"),
+                "GAME".to_string(),
+                0x1a3,
+                Patch::Bytes(vec![0x21, 0x00, 0xc9])
+            ),
+            cmd
+        );
+        let argv = [
+            "mcp",
+            "patch",
+            "tape.cas",
+            "1",
+            "--at",
+            "16",
+            "--from-file",
+            "text.bin",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Patch(
+                PathBuf::from("tape.cas"),
+                "1".to_string(),
+                16,
+                Patch::File(PathBuf::from("text.bin"))
+            ),
+            cmd
+        );
+        assert!(parse_bytes("21 0").is_err());
+        assert!(parse_bytes("zz").is_err());
+    }
+
+    #[test]
+    fn should_parse_browse() {
+        let argv = ["mcp", "browse", "tape.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(Command::Browse(PathBuf::from("tape.cas")), cmd);
+    }
+
+    #[test]
+    fn should_parse_merge_and_split() {
+        let argv = ["mcp", "merge", "--dedup", "all.cas", "a.cas", "b.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Merge(
+                PathBuf::from("all.cas"),
+                vec![PathBuf::from("a.cas"), PathBuf::from("b.cas")],
+                MergePolicy {
+                    dedup: true,
+                    rename: false
+                },
+                false
+            ),
+            cmd
+        );
+        let argv = ["mcp", "split", "-o", "files", "all.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Split(PathBuf::from("all.cas"), Some(PathBuf::from("files"))),
+            cmd
+        );
     }
 
     #[test]
     fn should_parse_export() {
         let argv = ["mcp", "--export", "foobar.cas", "foobar.wav"];
-        let cmd = parse_args(argv.iter().map(|a| a.to_string()));
-        assert_eq!(Command::Export(PathBuf::from("foobar.cas"), PathBuf::from("foobar.wav")), cmd);
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Export(
+                PathBuf::from("foobar.cas"),
+                PathBuf::from("foobar.wav"),
+                Profile::default()
+            ),
+            cmd
+        );
+        let argv = [
+            "mcp",
+            "wav",
+            "--baud",
+            "2400",
+            "--sample-rate",
+            "48000",
+            "foobar.cas",
+            "foobar.wav",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let profile = Profile {
+            bauds: 2400,
+            sample_rate: 48000,
+            ..Profile::default()
+        };
+        assert_eq!(
+            Command::Export(
+                PathBuf::from("foobar.cas"),
+                PathBuf::from("foobar.wav"),
+                profile
+            ),
+            cmd
+        );
+        let argv = [
+            "mcp",
+            "export",
+            "--baud",
+            "4800",
+            "--turbo-from",
+            "1",
+            "foobar.cas",
+            "foobar.wav",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let profile = Profile {
+            bauds: 4800,
+            sample_rate: 48000,
+            turbo_from: 1,
+            ..Profile::default()
+        };
+        assert_eq!(
+            Command::Export(
+                PathBuf::from("foobar.cas"),
+                PathBuf::from("foobar.wav"),
+                profile
+            ),
+            cmd
+        );
+        let argv = ["mcp", "export", "-", "-"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Export(PathBuf::from("-"), PathBuf::from("-"), Profile::default()),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_export_asm() {
+        let argv = [
+            "mcp",
+            "export-asm",
+            "tape.cas",
+            "GAME.bin",
+            "--syntax",
+            "pasmo",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::ExportAsm(
+                PathBuf::from("tape.cas"),
+                "GAME.bin".to_string(),
+                Dialect::Pasmo
+            ),
+            cmd
+        );
+
+        let argv = ["mcp", "export-asm", "tape.cas", "GAME"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::ExportAsm(
+                PathBuf::from("tape.cas"),
+                "GAME".to_string(),
+                Dialect::Sjasmplus
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_export_c() {
+        let argv = ["mcp", "export-c", "tape.cas", "GAME", "--out", "game.h"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::ExportC(
+                PathBuf::from("tape.cas"),
+                "GAME".to_string(),
+                Some(PathBuf::from("game.h"))
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_convert() {
+        let argv = ["mcp", "convert", "--baud", "2400", "game.cas", "game.tsx"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Convert(
+                PathBuf::from("game.cas"),
+                PathBuf::from("game.tsx"),
+                Profile::with_bauds(2400),
+                Scan::Unaligned
+            ),
+            cmd
+        );
+        let argv = [
+            "mcp",
+            "convert",
+            "--to",
+            "wav",
+            "-j",
+            "4",
+            "--output-dir",
+            "out",
+            "a.cas",
+            "b.cas",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::ConvertAll(
+                vec![PathBuf::from("a.cas"), PathBuf::from("b.cas")],
+                "wav".to_string(),
+                Some(PathBuf::from("out")),
+                Some(4),
+                Profile::default(),
+                Scan::Unaligned
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_romload() {
+        let argv = ["mcp", "romload", "game.rom", "game.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::RomLoad(PathBuf::from("game.rom"), PathBuf::from("game.cas")),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_play() {
+        let argv = ["mcp", "play", "--baud", "2400", "game.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Play(
+                PathBuf::from("game.cas"),
+                Profile::with_bauds(2400),
+                None,
+                3
+            ),
+            cmd
+        );
+        let argv = [
+            "mcp",
+            "play",
+            "--remote",
+            "dtr:/dev/ttyUSB0",
+            "--pause",
+            "5",
+            "game.cas",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Play(
+                PathBuf::from("game.cas"),
+                Profile::default(),
+                Some(Line::Dtr(PathBuf::from("/dev/ttyUSB0"))),
+                5
+            ),
+            cmd
+        );
+        let argv = ["mcp", "play", "--pause", "5", "game.cas"];
+        assert!(cli().try_get_matches_from(argv.iter()).is_err());
+    }
+
+    #[test]
+    fn should_parse_import() {
+        let argv = [
+            "mcp",
+            "import",
+            "tape.cas",
+            "image.dsk",
+            "GAME.BIN",
+            "LOADER.BAS",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Import(
+                PathBuf::from("tape.cas"),
+                PathBuf::from("image.dsk"),
+                vec!["GAME.BIN".to_string(), "LOADER.BAS".to_string()]
+            ),
+            cmd
+        );
+        let argv = ["mcp", "import", "tape.cas", "image.dsk"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Import(
+                PathBuf::from("tape.cas"),
+                PathBuf::from("image.dsk"),
+                vec![]
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_build() {
+        let argv = ["mcp", "build", "tape.toml"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(Command::Build(PathBuf::from("tape.toml"), false), cmd);
+        let argv = ["mcp", "build", "--with-loader", "tape.toml"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(Command::Build(PathBuf::from("tape.toml"), true), cmd);
+    }
+
+    #[test]
+    fn should_parse_master() {
+        let argv = [
+            "mcp",
+            "master",
+            "--gap",
+            "3",
+            "side_a.wav",
+            "a.cas",
+            "b.cas",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Master(
+                PathBuf::from("side_a.wav"),
+                vec![PathBuf::from("a.cas"), PathBuf::from("b.cas")],
+                3,
+                None
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_load() {
+        let argv = ["mcp", "load", "--gzip", "side_a.wav", "side_a.cas.gz"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Load(
+                PathBuf::from("side_a.wav"),
+                PathBuf::from("side_a.cas.gz"),
+                true
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_record() {
+        let argv = ["mcp", "record", "--input", "line-in", "session"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Record(PathBuf::from("session"), Some("line-in".to_string()), None),
+            cmd
+        );
+        let argv = ["mcp", "record", "--seconds", "90", "session"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Record(PathBuf::from("session"), None, Some(90)),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_remote() {
+        let argv = ["mcp", "remote", "--seconds", "30", "dtr:/dev/ttyUSB0"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Remote(Line::Dtr(PathBuf::from("/dev/ttyUSB0")), Some(30)),
+            cmd
+        );
+        let argv = ["mcp", "remote", "gpio:17"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(Command::Remote(Line::Gpio(17), None), cmd);
+    }
+
+    #[test]
+    fn should_parse_format() {
+        let argv = ["mcp", "--list", "foobar.cas"];
+        assert_eq!(
+            Format::Text,
+            parse_args(argv.iter().map(|a| a.to_string())).1.format
+        );
+        let argv = ["mcp", "--format", "json", "--list", "foobar.cas"];
+        assert_eq!(
+            Format::Json,
+            parse_args(argv.iter().map(|a| a.to_string())).1.format
+        );
+        let argv = ["mcp", "-l", "foobar.cas", "--format=json"];
+        let (cmd, output) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::List(
+                PathBuf::from("foobar.cas"),
+                ListOptions::default(),
+                Scan::Unaligned
+            ),
+            cmd
+        );
+        assert_eq!(Format::Json, output.format);
+    }
+
+    #[test]
+    fn should_parse_verbosity() {
+        let argv = ["mcp", "list", "foobar.cas"];
+        assert_eq!(
+            Verbosity::Normal,
+            parse_args(argv.iter().map(|a| a.to_string())).1.verbosity
+        );
+        let argv = ["mcp", "--verbose", "list", "foobar.cas"];
+        assert_eq!(
+            Verbosity::Verbose,
+            parse_args(argv.iter().map(|a| a.to_string())).1.verbosity
+        );
+        let argv = ["mcp", "convert", "-q", "game.cas", "game.tsx"];
+        assert_eq!(
+            Verbosity::Quiet,
+            parse_args(argv.iter().map(|a| a.to_string())).1.verbosity
+        );
+        let argv = ["mcp", "list", "--quiet", "--verbose", "foobar.cas"];
+        assert!(cli().try_get_matches_from(argv.iter()).is_err());
+    }
+
+    #[test]
+    fn should_parse_identify() {
+        let argv = ["mcp", "identify", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(Command::Identify(PathBuf::from("foobar.cas")), cmd);
+    }
+
+    #[test]
+    fn should_parse_annotate() {
+        let argv = ["mcp", "annotate", "--title", "Game", "foobar.cas", "2"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let annotation = Annotation {
+            title: Some("Game".to_string()),
+            ..Annotation::default()
+        };
+        assert_eq!(
+            Command::Annotate(PathBuf::from("foobar.cas"), 2, annotation, false),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_info() {
+        let argv = ["mcp", "info", "foo.cas", "bar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Info(vec![PathBuf::from("foo.cas"), PathBuf::from("bar.cas")]),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_hash() {
+        let argv = ["mcp", "hash", "foo.cas", "bar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Hash(
+                vec![PathBuf::from("foo.cas"), PathBuf::from("bar.cas")],
+                Machine::Msx
+            ),
+            cmd
+        );
+        let argv = ["mcp", "hash", "--machine", "svi", "foo.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Hash(vec![PathBuf::from("foo.cas")], Machine::Svi),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_verify() {
+        let argv = ["mcp", "verify", "msx.dat", "foo.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Verify(
+                PathBuf::from("msx.dat"),
+                vec![PathBuf::from("foo.cas")],
+                false
+            ),
+            cmd
+        );
+        let argv = ["mcp", "verify", "--strict", "msx.dat", "foo.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Verify(
+                PathBuf::from("msx.dat"),
+                vec![PathBuf::from("foo.cas")],
+                true
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_check() {
+        let argv = ["mcp", "check", "foo.cas", "bar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Check(vec![PathBuf::from("foo.cas"), PathBuf::from("bar.cas")]),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_grep_basic() {
+        let argv = ["mcp", "grep-basic", "-i", "PRESS SPACE", "games", "foo.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::GrepBasic(
+                "PRESS SPACE".to_string(),
+                vec![PathBuf::from("games"), PathBuf::from("foo.cas")],
+                true
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_which() {
+        let argv = ["mcp", "which", "GAME.bin", "games"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Which(PathBuf::from("GAME.bin"), vec![PathBuf::from("games")]),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_basic_shrink() {
+        let argv = ["mcp", "basic", "shrink", "foo.cas", "GAME"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::BasicShrink(PathBuf::from("foo.cas"), "GAME".to_string()),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_basic_convert() {
+        let argv = [
+            "mcp", "basic", "convert", "--to", "ascii", "foo.cas", "GAME",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::BasicConvert(
+                PathBuf::from("foo.cas"),
+                "GAME".to_string(),
+                FileType::Ascii
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_compare_dir() {
+        let argv = ["mcp", "compare-dir", "foo.cas", "src"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::CompareDir(PathBuf::from("foo.cas"), PathBuf::from("src")),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_diff() {
+        let argv = ["mcp", "diff", "a.cas", "b.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Diff(PathBuf::from("a.cas"), PathBuf::from("b.cas"), false),
+            cmd
+        );
+        let argv = ["mcp", "diff", "--blocks", "a.cas", "b.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Diff(PathBuf::from("a.cas"), PathBuf::from("b.cas"), true),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_edit() {
+        let argv = ["mcp", "edit", "foo.cas", "GAME"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Edit(PathBuf::from("foo.cas"), "GAME".to_string()),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_fit() {
+        let argv = ["mcp", "fit", "foo.cas", "--length", "C90", "--baud", "2400"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Fit(
+                PathBuf::from("foo.cas"),
+                Cassette { minutes: 90 },
+                Profile::with_bauds(2400)
+            ),
+            cmd
+        );
+
+        let argv = ["mcp", "fit", "foo.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::Fit(
+                PathBuf::from("foo.cas"),
+                Cassette { minutes: 60 },
+                Profile::default()
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_loadcheck() {
+        let argv = ["mcp", "loadcheck", "foo.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::LoadCheck(PathBuf::from("foo.cas"), Ram::ALL.to_vec()),
+            cmd
+        );
+        let argv = ["mcp", "loadcheck", "--ram", "16kb,64", "foo.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::LoadCheck(PathBuf::from("foo.cas"), vec![Ram::K16, Ram::K64]),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_gen_fixture() {
+        let argv = [
+            "mcp",
+            "gen-fixture",
+            "--files",
+            "3",
+            "--types",
+            "bin,ascii",
+            "--seed",
+            "42",
+            "out.cas",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        let fixture = Fixture {
+            files: 3,
+            types: vec![FileType::Bin, FileType::Ascii],
+            seed: 42,
+        };
+        assert_eq!(
+            Command::GenFixture(PathBuf::from("out.cas"), fixture, false),
+            cmd
+        );
+
+        let argv = ["mcp", "gen-fixture", "--gzip", "out.cas.gz"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::GenFixture(PathBuf::from("out.cas.gz"), Fixture::default(), true),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_doctor() {
+        let argv = ["mcp", "doctor"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(Command::Doctor, cmd);
+    }
+
+    #[test]
+    fn should_parse_preview() {
+        let argv = ["mcp", "preview", "foobar.cas"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(Command::Preview(PathBuf::from("foobar.cas")), cmd);
+    }
+
+    #[test]
+    fn should_parse_openmsx_insert() {
+        let argv = [
+            "mcp",
+            "openmsx",
+            "insert",
+            "--load",
+            "RUN\"CAS:\"",
+            "foobar.cas",
+        ];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::OpenMsxInsert(
+                PathBuf::from("foobar.cas"),
+                None,
+                Some("RUN\"CAS:\"".to_string())
+            ),
+            cmd
+        );
+    }
+
+    #[test]
+    fn should_parse_external() {
+        let argv = ["mcp", "tsxtools", "--convert", "foobar.tsx"];
+        let (cmd, _) = parse_args(argv.iter().map(|a| a.to_string()));
+        assert_eq!(
+            Command::External(
+                "tsxtools".to_string(),
+                vec![OsString::from("--convert"), OsString::from("foobar.tsx")]
+            ),
+            cmd
+        );
     }
 }
@@ -0,0 +1,1487 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::io;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::tape::File;
+
+/// The statement keywords, from token `0x81` to `0xed`
+const KEYWORDS: [&str; 109] = [
+    "END", "FOR", "NEXT", "DATA", "INPUT", "DIM", "READ", "LET", "GOTO", "RUN", "IF", "RESTORE",
+    "GOSUB", "RETURN", "REM", "STOP", "PRINT", "CLEAR", "LIST", "NEW", "ON", "WAIT", "DEF", "POKE",
+    "CONT", "CSAVE", "CLOAD", "OUT", "LPRINT", "LLIST", "CLS", "WIDTH", "ELSE", "TRON", "TROFF",
+    "SWAP", "ERASE", "ERROR", "RESUME", "DELETE", "AUTO", "RENUM", "DEFSTR", "DEFINT", "DEFSNG",
+    "DEFDBL", "LINE", "OPEN", "FIELD", "GET", "PUT", "CLOSE", "LOAD", "MERGE", "FILES", "LSET",
+    "RSET", "SAVE", "LFILES", "CIRCLE", "COLOR", "DRAW", "PAINT", "BEEP", "PLAY", "PSET", "PRESET",
+    "SOUND", "SCREEN", "VPOKE", "SPRITE", "VDP", "BASE", "CALL", "TIME", "KEY", "MAX", "MOTOR",
+    "BLOAD", "BSAVE", "DSKO$", "SET", "NAME", "KILL", "IPL", "COPY", "CMD", "LOCATE", "TO", "THEN",
+    "TAB(", "STEP", "USR", "FN", "SPC(", "NOT", "ERL", "ERR", "STRING$", "USING", "INSTR", "'",
+    "VARPTR", "CSRLIN", "ATTR$", "DSKI$", "OFF", "INKEY$", "POINT",
+];
+
+/// The operators, from token `0xee` to `0xfc`
+const OPERATORS: [&str; 15] = [
+    ">", "=", "<", "+", "-", "*", "/", "^", "AND", "OR", "XOR", "EQV", "IMP", "MOD", "\\",
+];
+
+/// The functions, encoded as `0xff` followed by a token from `0x81` to `0xb0`
+const FUNCTIONS: [&str; 48] = [
+    "LEFT$", "RIGHT$", "MID$", "SGN", "INT", "ABS", "SQR", "RND", "SIN", "LOG", "EXP", "COS",
+    "TAN", "ATN", "FRE", "INP", "POS", "LEN", "STR$", "VAL", "ASC", "CHR$", "PEEK", "VPEEK",
+    "SPACE$", "OCT$", "HEX$", "LPOS", "BIN$", "CINT", "CSNG", "CDBL", "FIX", "STICK", "STRIG",
+    "PDL", "PAD", "DSKF", "FPOS", "CVI", "CVS", "CVD", "EOF", "LOC", "LOF", "MKI$", "MKS$", "MKD$",
+];
+
+/// The keywords followed by line numbers, which are encoded as `0x0e` and two bytes
+const LINE_NUMBER_KEYWORDS: [&str; 10] = [
+    "GOTO", "GOSUB", "THEN", "ELSE", "RESTORE", "RUN", "RESUME", "LIST", "LLIST", "DELETE",
+];
+
+/// The highest line number accepted by MSX-BASIC
+pub const MAX_LINE_NUMBER: u32 = 65529;
+
+/// The maximum length of a source line accepted by MSX-BASIC
+pub const MAX_LINE_LEN: usize = 255;
+
+/// The address where Basic programs are loaded in machines with 32KB or more
+pub const PROGRAM_START: u16 = 0x8001;
+
+const TOKEN_REM: u8 = 0x8f;
+const TOKEN_DATA: u8 = 0x84;
+const TOKEN_ELSE: u8 = 0xa1;
+const TOKEN_QUOTE: u8 = 0xe6;
+
+/// Words that MSX-BASIC reads as variable names but are part of statements (`OPEN ... FOR
+/// APPEND`, and `ON INTERVAL`, stored as the `INT` function followed by `ERVAL`)
+const STATEMENT_WORDS: [&str; 2] = ["APPEND", "ERVAL"];
+
+/// A token of a tokenized MSX-BASIC program
+///
+/// Numeric literals are kept in their encoded form. Use `value()` to obtain their value.
+/// Any byte that is not part of other token (spaces, punctuation, etc) is returned as a
+/// `Char` token.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token<'a> {
+    /// A statement keyword, such as `PRINT` or `GOTO`
+    Keyword(&'static str),
+    /// A function keyword, such as `CHR$` or `PEEK`
+    Function(&'static str),
+    /// An operator, either symbolic (`+`, `=`) or named (`AND`, `MOD`)
+    Operator(&'static str),
+    /// A line number referenced by a statement (e.g., the target of a `GOTO`)
+    LineNumber(u16),
+    /// A line number already resolved into a memory address by the interpreter
+    LinePointer(u16),
+    /// A decimal integer literal
+    Integer(u16),
+    /// An octal integer literal (`&O`)
+    Octal(u16),
+    /// An hexadecimal integer literal (`&H`)
+    Hex(u16),
+    /// A single precision literal encoded in BCD
+    Single([u8; 4]),
+    /// A double precision literal encoded in BCD
+    Double([u8; 8]),
+    /// A string literal, without the quotes
+    Str(&'a [u8]),
+    /// A variable name, including its type suffix if any
+    Variable(&'a str),
+    /// Literal text after `REM`, `'` or `DATA`
+    Text(&'a [u8]),
+    /// A graphic character (encoded as `0x01` followed by the character code)
+    Graphic(u8),
+    /// Any other character
+    Char(u8),
+}
+
+impl<'a> Token<'a> {
+    /// Returns the numeric value of the token, or `None` if it is not a numeric literal.
+    pub fn value(&self) -> Option<f64> {
+        match *self {
+            Token::Integer(n) | Token::Octal(n) | Token::Hex(n) => Some(n as f64),
+            Token::Single(ref bcd) => Some(bcd_value(bcd)),
+            Token::Double(ref bcd) => Some(bcd_value(bcd)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Keyword(s) | Token::Function(s) | Token::Operator(s) => f.write_str(s),
+            Token::LineNumber(n) | Token::Integer(n) => write!(f, "{}", n),
+            Token::LinePointer(addr) => write!(f, "{{0x{:04x}}}", addr),
+            Token::Octal(n) => write!(f, "&O{:o}", n),
+            Token::Hex(n) => write!(f, "&H{:X}", n),
+            Token::Single(ref bcd) => f.write_str(&bcd_text(bcd, 'E', "!")),
+            Token::Double(ref bcd) => f.write_str(&bcd_text(bcd, 'D', "")),
+            Token::Str(s) => write!(f, "\"{}\"", latin1(s)),
+            Token::Variable(s) => f.write_str(s),
+            Token::Text(s) => f.write_str(&latin1(s)),
+            Token::Graphic(c) => write!(f, "\u{1}{}", c as char),
+            Token::Char(c) => write!(f, "{}", c as char),
+        }
+    }
+}
+
+/// A line of a tokenized MSX-BASIC program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Line<'a> {
+    number: u16,
+    body: &'a [u8],
+}
+
+impl<'a> Line<'a> {
+    /// Returns the line number.
+    pub fn number(&self) -> u16 {
+        self.number
+    }
+
+    /// Returns the tokenized bytes of the line, without the line terminator.
+    pub fn body(&self) -> &'a [u8] {
+        self.body
+    }
+
+    /// Returns an iterator over the tokens of the line.
+    pub fn tokens(&self) -> Tokens<'a> {
+        Tokens::new(self.body)
+    }
+}
+
+impl<'a> fmt::Display for Line<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ", self.number)?;
+        for token in self.tokens() {
+            write!(f, "{}", token)?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the lines of a tokenized MSX-BASIC program
+///
+/// Each line is checked to be well-formed before it is returned, so its tokens can be
+/// iterated without errors. If a malformed line is found, an error of kind `InvalidData`
+/// is returned and the iteration ends.
+///
+pub struct Lines<'a> {
+    data: &'a [u8],
+    pos: usize,
+    done: bool,
+}
+
+impl<'a> Lines<'a> {
+    fn error(&mut self, msg: String) -> Option<io::Result<Line<'a>>> {
+        self.done = true;
+        Some(Err(io::Error::new(io::ErrorKind::InvalidData, msg)))
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = io::Result<Line<'a>>;
+
+    fn next(&mut self) -> Option<io::Result<Line<'a>>> {
+        if self.done {
+            return None;
+        }
+        let rest = &self.data[self.pos..];
+        if rest.len() < 2 {
+            return self.error(format!(
+                "program truncated at offset {}: missing end of program mark",
+                self.pos
+            ));
+        }
+        if LittleEndian::read_u16(rest) == 0 {
+            self.done = true;
+            return None;
+        }
+        if rest.len() < 4 {
+            return self.error(format!(
+                "program truncated at offset {}: missing line number",
+                self.pos
+            ));
+        }
+        let number = LittleEndian::read_u16(&rest[2..]);
+        let mut scanner = Tokens::new(&rest[4..]);
+        while let Some(token) = scanner.scan_token() {
+            if let Err(msg) = token {
+                return self.error(format!("line {}: {}", number, msg));
+            }
+        }
+        if scanner.pos >= scanner.data.len() {
+            return self.error(format!("line {}: missing line terminator", number));
+        }
+        let body = &rest[4..4 + scanner.pos];
+        self.pos += 4 + scanner.pos + 1;
+        Some(Ok(Line { number, body }))
+    }
+}
+
+/// An iterator over the tokens of a line
+pub struct Tokens<'a> {
+    data: &'a [u8],
+    pos: usize,
+    mode: Mode,
+}
+
+/// How the bytes that follow are interpreted
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Normal,
+    Remark,
+    Data,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(data: &'a [u8]) -> Tokens<'a> {
+        Tokens {
+            data,
+            pos: 0,
+            mode: Mode::Normal,
+        }
+    }
+
+    fn operand(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let start = self.pos + 1;
+        let operand = self.data.get(start..start + len).ok_or_else(|| {
+            format!(
+                "token 0x{:02x} truncated at offset {}",
+                self.data[self.pos], self.pos
+            )
+        })?;
+        self.pos = start + len;
+        Ok(operand)
+    }
+
+    fn text_until<F: Fn(u8, bool) -> bool>(&mut self, stop: F) -> &'a [u8] {
+        let start = self.pos;
+        let mut quoted = false;
+        while let Some(&b) = self.data.get(self.pos) {
+            if b == 0 || stop(b, quoted) {
+                break;
+            }
+            quoted ^= b == b'"';
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    /// Scan the next token. The scan ends at the end of the data or at a line terminator.
+    fn scan_token(&mut self) -> Option<Result<Token<'a>, String>> {
+        loop {
+            let b = *self.data.get(self.pos)?;
+            if b == 0 {
+                return None;
+            }
+            match self.mode {
+                Mode::Remark => {
+                    self.mode = Mode::Normal;
+                    return Some(Ok(Token::Text(self.text_until(|_, _| false))));
+                }
+                Mode::Data => {
+                    self.mode = Mode::Normal;
+                    let text = self.text_until(|b, quoted| b == b':' && !quoted);
+                    if !text.is_empty() {
+                        return Some(Ok(Token::Text(text)));
+                    }
+                }
+                Mode::Normal => return Some(self.scan_normal(b)),
+            }
+        }
+    }
+
+    fn scan_normal(&mut self, b: u8) -> Result<Token<'a>, String> {
+        let next = self.data.get(self.pos + 1).cloned();
+        let token = match b {
+            0x01 => Token::Graphic(self.operand(1)?[0]),
+            0x0b => Token::Octal(LittleEndian::read_u16(self.operand(2)?)),
+            0x0c => Token::Hex(LittleEndian::read_u16(self.operand(2)?)),
+            0x0d => Token::LinePointer(LittleEndian::read_u16(self.operand(2)?)),
+            0x0e => Token::LineNumber(LittleEndian::read_u16(self.operand(2)?)),
+            0x0f => Token::Integer(self.operand(1)?[0] as u16),
+            0x11..=0x1a => {
+                self.pos += 1;
+                Token::Integer((b - 0x11) as u16)
+            }
+            0x1c => Token::Integer(LittleEndian::read_u16(self.operand(2)?)),
+            0x1d => {
+                let mut bcd = [0; 4];
+                bcd.copy_from_slice(self.operand(4)?);
+                Token::Single(bcd)
+            }
+            0x1f => {
+                let mut bcd = [0; 8];
+                bcd.copy_from_slice(self.operand(8)?);
+                Token::Double(bcd)
+            }
+            b'"' => {
+                self.pos += 1;
+                let text = self.text_until(|b, _| b == b'"');
+                if self.data.get(self.pos) == Some(&b'"') {
+                    self.pos += 1;
+                }
+                Token::Str(text)
+            }
+            b':' if next == Some(TOKEN_ELSE) => {
+                self.pos += 2;
+                Token::Keyword("ELSE")
+            }
+            b':' if next == Some(TOKEN_REM)
+                && self.data.get(self.pos + 2) == Some(&TOKEN_QUOTE) =>
+            {
+                self.pos += 3;
+                self.mode = Mode::Remark;
+                Token::Keyword("'")
+            }
+            0xff => {
+                let code = self.operand(1)?[0];
+                match code
+                    .checked_sub(0x81)
+                    .and_then(|i| FUNCTIONS.get(i as usize))
+                {
+                    Some(name) => Token::Function(name),
+                    None => {
+                        return Err(format!(
+                            "unknown function token 0x{:02x} at offset {}",
+                            code,
+                            self.pos - 1
+                        ))
+                    }
+                }
+            }
+            0xee..=0xfc => {
+                self.pos += 1;
+                Token::Operator(OPERATORS[(b - 0xee) as usize])
+            }
+            0x81..=0xed => {
+                self.pos += 1;
+                self.mode = match b {
+                    TOKEN_REM => Mode::Remark,
+                    TOKEN_DATA => Mode::Data,
+                    _ => Mode::Normal,
+                };
+                Token::Keyword(KEYWORDS[(b - 0x81) as usize])
+            }
+            b if b.is_ascii_alphabetic() => {
+                let start = self.pos;
+                self.pos += 1;
+                while self
+                    .data
+                    .get(self.pos)
+                    .is_some_and(|b| b.is_ascii_alphanumeric())
+                {
+                    self.pos += 1;
+                }
+                if let Some(b'$') | Some(b'%') | Some(b'!') | Some(b'#') = self.data.get(self.pos) {
+                    self.pos += 1;
+                }
+                // Only ASCII bytes were consumed, so this is valid UTF-8
+                Token::Variable(std::str::from_utf8(&self.data[start..self.pos]).unwrap())
+            }
+            b => {
+                self.pos += 1;
+                Token::Char(b)
+            }
+        };
+        Ok(token)
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        self.scan_token().and_then(Result::ok)
+    }
+}
+
+/// Returns an iterator over the lines of the given tokenized program
+///
+/// The program may start with the `0xff` byte that identifies tokenized Basic files in
+/// disk, which is skipped.
+///
+pub fn lines(program: &[u8]) -> Lines<'_> {
+    let data = if program.first() == Some(&0xff) {
+        &program[1..]
+    } else {
+        program
+    };
+    Lines {
+        data,
+        pos: 0,
+        done: false,
+    }
+}
+
+/// Returns an iterator over the lines of the given file, or `None` if it is not a Basic file
+pub fn lines_of<'a>(file: &File<'a>) -> Option<Lines<'a>> {
+    match *file {
+        File::Basic(_, data) => Some(lines(data)),
+        _ => None,
+    }
+}
+
+/// A syntax error found in a BASIC source
+///
+/// Each variant carries the number of the offending line in the source text (starting
+/// at 1), not to be confused with the BASIC line number.
+///
+#[derive(Debug, PartialEq)]
+pub enum SyntaxError {
+    /// The line does not start with a line number
+    MissingLineNumber { line: usize },
+    /// A line number is out of the range accepted by MSX-BASIC
+    InvalidLineNumber { line: usize, number: String },
+    /// The line number was already used by a previous line
+    DuplicateLine {
+        line: usize,
+        number: u16,
+        first: usize,
+    },
+    /// The line exceeds the maximum length accepted by MSX-BASIC
+    LineTooLong { line: usize, len: usize },
+    /// A statement starts with a word that is neither a keyword nor an assignment
+    UnknownKeyword { line: usize, word: String },
+}
+
+impl SyntaxError {
+    /// Returns the number of the offending line in the source text.
+    pub fn line(&self) -> usize {
+        match *self {
+            SyntaxError::MissingLineNumber { line }
+            | SyntaxError::InvalidLineNumber { line, .. }
+            | SyntaxError::DuplicateLine { line, .. }
+            | SyntaxError::LineTooLong { line, .. }
+            | SyntaxError::UnknownKeyword { line, .. } => line,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxError::MissingLineNumber { line } => {
+                write!(f, "line {}: missing line number", line)
+            }
+            SyntaxError::InvalidLineNumber { line, number } => write!(
+                f,
+                "line {}: invalid line number {} (expected 0 to {})",
+                line, number, MAX_LINE_NUMBER
+            ),
+            SyntaxError::DuplicateLine {
+                line,
+                number,
+                first,
+            } => write!(
+                f,
+                "line {}: duplicate line number {} (first defined in line {})",
+                line, number, first
+            ),
+            SyntaxError::LineTooLong { line, len } => write!(
+                f,
+                "line {}: line too long ({} characters, at most {} allowed)",
+                line, len, MAX_LINE_LEN
+            ),
+            SyntaxError::UnknownKeyword { line, word } => {
+                write!(f, "line {}: unknown keyword {}", line, word)
+            }
+        }
+    }
+}
+
+impl error::Error for SyntaxError {}
+
+impl From<SyntaxError> for io::Error {
+    fn from(e: SyntaxError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// Check the syntax of the given BASIC source before tokenizing it
+///
+/// This is not a full parser. It detects the problems that would otherwise only show up
+/// when the program is loaded in a real MSX: lines without line number or with line
+/// numbers out of range, duplicated line numbers, lines too long and statements starting
+/// with unknown keywords. All the errors found are returned, sorted by source line.
+///
+/// Lines may end with `CR`, `LF` or `CRLF`. The text after an EOF (`0x1a`) byte is ignored.
+///
+pub fn check_source(source: &[u8]) -> Result<(), Vec<SyntaxError>> {
+    let source = &source[..source
+        .iter()
+        .position(|b| *b == 0x1a)
+        .unwrap_or(source.len())];
+    let text = crate::text::convert_line_endings(source, crate::text::LineEnding::Lf);
+    let mut errors = vec![];
+    let mut numbers: HashMap<u16, usize> = HashMap::new();
+    for (i, text) in text.split(|b| *b == b'\n').enumerate() {
+        let line = i + 1;
+        if text.iter().all(|b| b.is_ascii_whitespace()) {
+            continue;
+        }
+        if text.len() > MAX_LINE_LEN {
+            errors.push(SyntaxError::LineTooLong {
+                line,
+                len: text.len(),
+            });
+        }
+        let start = text.iter().take_while(|b| **b == b' ').count();
+        let digits = text[start..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            errors.push(SyntaxError::MissingLineNumber { line });
+            continue;
+        }
+        let number = latin1(&text[start..start + digits]);
+        match number.parse::<u32>() {
+            Ok(n) if n <= MAX_LINE_NUMBER => {
+                if let Some(first) = numbers.insert(n as u16, line) {
+                    numbers.insert(n as u16, first);
+                    errors.push(SyntaxError::DuplicateLine {
+                        line,
+                        number: n as u16,
+                        first,
+                    });
+                }
+            }
+            _ => errors.push(SyntaxError::InvalidLineNumber { line, number }),
+        }
+        check_statements(line, &text[start + digits..], &mut errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Check the statements of a source line (without its line number)
+fn check_statements(line: usize, text: &[u8], errors: &mut Vec<SyntaxError>) {
+    let upper = text.to_ascii_uppercase();
+    let mut statement_start = true;
+    let mut line_number_follows = false;
+    let mut i = 0;
+    while i < upper.len() {
+        let c = upper[i];
+        match c {
+            b' ' => {
+                i += 1;
+                continue;
+            }
+            b'"' => {
+                i += 1 + upper[i + 1..]
+                    .iter()
+                    .position(|b| *b == b'"')
+                    .map_or(upper.len() - i - 1, |p| p + 1);
+                statement_start = false;
+            }
+            b':' => {
+                i += 1;
+                statement_start = true;
+            }
+            b'\'' => return,
+            b'0'..=b'9' if line_number_follows => {
+                let digits = upper[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+                let number = latin1(&upper[i..i + digits]);
+                if number.parse::<u32>().map_or(true, |n| n > MAX_LINE_NUMBER) {
+                    errors.push(SyntaxError::InvalidLineNumber { line, number });
+                }
+                i += digits;
+                statement_start = false;
+                continue;
+            }
+            b'A'..=b'Z' => {
+                let keyword = keyword_at(&upper[i..]);
+                if statement_start && keyword.is_none() && !is_assignment(&upper[i..]) {
+                    let len = upper[i..]
+                        .iter()
+                        .take_while(|b| b.is_ascii_alphanumeric())
+                        .count();
+                    errors.push(SyntaxError::UnknownKeyword {
+                        line,
+                        word: latin1(&text[i..i + len]),
+                    });
+                    return;
+                }
+                match keyword {
+                    Some("REM") => return,
+                    Some("DATA") => {
+                        let mut quoted = false;
+                        while i < upper.len() && (upper[i] != b':' || quoted) {
+                            quoted ^= upper[i] == b'"';
+                            i += 1;
+                        }
+                        statement_start = false;
+                        continue;
+                    }
+                    Some(k) => {
+                        i += k.len();
+                        statement_start = k == "THEN" || k == "ELSE";
+                        line_number_follows = LINE_NUMBER_KEYWORDS.contains(&k);
+                        continue;
+                    }
+                    None => {
+                        i += upper[i..]
+                            .iter()
+                            .take_while(|b| b.is_ascii_alphanumeric())
+                            .count();
+                        statement_start = false;
+                    }
+                }
+            }
+            b',' | b'-' if line_number_follows => {
+                i += 1;
+                continue;
+            }
+            _ => {
+                i += 1;
+                statement_start = false;
+            }
+        }
+        line_number_follows = false;
+    }
+}
+
+/// Returns the longest keyword found at the start of the given (uppercase) text
+fn keyword_at(text: &[u8]) -> Option<&'static str> {
+    KEYWORDS
+        .iter()
+        .chain(FUNCTIONS.iter())
+        .chain(OPERATORS.iter())
+        .filter(|k| k.as_bytes()[0].is_ascii_alphabetic() && text.starts_with(k.as_bytes()))
+        .max_by_key(|k| k.len())
+        .cloned()
+}
+
+/// Returns `true` if the given (uppercase) text starts with an assignment to a variable
+fn is_assignment(text: &[u8]) -> bool {
+    let mut i = text
+        .iter()
+        .take_while(|b| b.is_ascii_alphanumeric())
+        .count();
+    if let Some(b'$') | Some(b'%') | Some(b'!') | Some(b'#') = text.get(i) {
+        i += 1;
+    }
+    let skip_spaces = |i: usize| i + text[i..].iter().take_while(|b| **b == b' ').count();
+    i = skip_spaces(i);
+    if text.get(i) == Some(&b'(') {
+        let mut depth = 0;
+        while i < text.len() {
+            match text[i] {
+                b'(' => depth += 1,
+                b')' => depth -= 1,
+                _ => {}
+            }
+            i += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+        i = skip_spaces(i);
+    }
+    text.get(i) == Some(&b'=')
+}
+
+/// Returns the listing of the given tokenized program
+///
+/// Each line is written as it is displayed by `Line`, followed by `LF`. The text is
+/// returned in the MSX charset, one byte per character. Fails with an error of kind
+/// `InvalidData` if the program is malformed (see `Lines`).
+///
+pub fn detokenize(program: &[u8]) -> io::Result<Vec<u8>> {
+    let mut text = vec![];
+    for line in lines(program) {
+        text.extend(line?.to_string().chars().map(|c| c as u8));
+        text.push(b'\n');
+    }
+    Ok(text)
+}
+
+/// Returns `true` if the given data look like BASIC source rather than a tokenized program
+///
+/// Tokenized programs end every line with a zero byte, which never appears in source
+/// text, while source text starts with a line number, possibly after some blank space.
+///
+pub fn is_source(data: &[u8]) -> bool {
+    !data.contains(&0)
+        && data
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|b| b.is_ascii_digit())
+}
+
+/// Tokenize the given BASIC source into a program as stored in tapes
+///
+/// The source is checked with `check_source()` first, and all its errors are returned if
+/// any. Lines are sorted by line number and linked as if the program was loaded at
+/// `PROGRAM_START`. The result has no `0xff` ID byte of disk files.
+///
+/// Keywords, functions and operators are recognized regardless of their case, and
+/// variable names are converted to uppercase. Numbers are encoded in the shortest form of
+/// their type: integers unless they have a decimal point, an exponent or a type suffix,
+/// and double precision by default as MSX-BASIC does. The text of strings, remarks and
+/// `DATA` statements is kept as is.
+///
+pub fn tokenize(source: &[u8]) -> Result<Vec<u8>, Vec<SyntaxError>> {
+    check_source(source)?;
+    let source = &source[..source
+        .iter()
+        .position(|b| *b == 0x1a)
+        .unwrap_or(source.len())];
+    let text = crate::text::convert_line_endings(source, crate::text::LineEnding::Lf);
+    let mut lines = vec![];
+    for text in text.split(|b| *b == b'\n') {
+        if text.iter().all(|b| b.is_ascii_whitespace()) {
+            continue;
+        }
+        let start = text.iter().take_while(|b| **b == b' ').count();
+        let digits = text[start..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        // The line numbers were already validated by check_source()
+        let number: u16 = latin1(&text[start..start + digits]).parse().unwrap();
+        let body = &text[start + digits..];
+        let spaces = body.iter().take_while(|b| **b == b' ').count();
+        lines.push((number, tokenize_line(&body[spaces..])));
+    }
+    lines.sort_by_key(|(number, _)| *number);
+    Ok(link(&lines))
+}
+
+/// Returns the program made of the given lines, linked as if loaded at `PROGRAM_START`
+fn link(lines: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut program = vec![];
+    let mut addr = PROGRAM_START as usize;
+    for (number, body) in lines {
+        addr += 4 + body.len() + 1;
+        program.extend_from_slice(&(addr as u16).to_le_bytes());
+        program.extend_from_slice(&number.to_le_bytes());
+        program.extend_from_slice(body);
+        program.push(0x00);
+    }
+    program.extend_from_slice(&[0x00, 0x00]);
+    program
+}
+
+/// Shrink the given tokenized program without changing what it does
+///
+/// The program is made smaller by:
+///
+/// * Shortening variable names to the two characters MSX-BASIC tells them apart by, plus
+///   their type suffix (e.g., `SCORE%` becomes `SC%`).
+/// * Removing remarks, except those right after `THEN` or `ELSE`. Lines left empty are
+///   removed too, unless some statement refers to them.
+/// * Removing the spaces outside strings, remarks and `DATA`, except those between two
+///   names or numbers.
+/// * Merging each line into the previous one if no statement refers to it, the previous
+///   one has no `IF` (which would make the merged statements conditional) and the merged
+///   line can still be listed. No line is merged if the program uses `ERL`, which may be
+///   compared with line numbers written as plain numbers.
+///
+/// `CALL` (or `_`) and `SET` statements are kept as they are, since their words are not
+/// variables. The result has no `0xff` ID byte, and it is linked as if loaded at
+/// `PROGRAM_START`.
+///
+/// Fails with an error of kind `InvalidData` if the program is malformed, or if it refers
+/// to lines by their memory address, since shrinking moves them.
+///
+pub fn shrink(program: &[u8]) -> io::Result<Vec<u8>> {
+    let lines = lines(program).collect::<io::Result<Vec<Line>>>()?;
+    let mut referenced = HashSet::new();
+    let mut uses_erl = false;
+    for token in lines.iter().flat_map(|line| line.tokens()) {
+        match token {
+            Token::LineNumber(n) => {
+                referenced.insert(n);
+            }
+            Token::LinePointer(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the program refers to lines by their address (save it with CSAVE first)",
+                ))
+            }
+            Token::Keyword("ERL") => uses_erl = true,
+            _ => {}
+        }
+    }
+
+    let mut shrunk: Vec<(u16, Vec<u8>)> = vec![];
+    for line in &lines {
+        let body = shrink_line(line.body());
+        let number = line.number();
+        if body.is_empty() && !referenced.contains(&number) {
+            continue;
+        }
+        if let Some((last_number, last)) = shrunk.last_mut() {
+            let mergeable = !uses_erl
+                && !referenced.contains(&number)
+                && Tokens::new(last).all(|t| match t {
+                    Token::Keyword(k) => k != "IF" && k != "REM" && k != "'",
+                    Token::Text(text) => text.iter().filter(|b| **b == b'"').count() % 2 == 0,
+                    _ => true,
+                });
+            if mergeable {
+                let mut merged = last.clone();
+                if !merged.is_empty() && !body.is_empty() {
+                    merged.push(b':');
+                }
+                merged.extend_from_slice(&body);
+                let line = Line {
+                    number: *last_number,
+                    body: &merged,
+                };
+                if line.to_string().len() <= MAX_LINE_LEN {
+                    *last = merged;
+                    continue;
+                }
+            }
+        }
+        shrunk.push((number, body));
+    }
+    for (_, body) in shrunk.iter_mut().filter(|(_, body)| body.is_empty()) {
+        body.push(TOKEN_REM);
+    }
+    Ok(link(&shrunk))
+}
+
+/// Shrink the body of a line, as described in `shrink()`
+fn shrink_line(body: &[u8]) -> Vec<u8> {
+    let mut tokens = vec![];
+    let mut scanner = Tokens::new(body);
+    let mut start = 0;
+    // The line was already validated by `lines()`
+    while let Some(Ok(token)) = scanner.scan_token() {
+        tokens.push((token, &body[start..scanner.pos]));
+        start = scanner.pos;
+    }
+
+    let mut shrunk = vec![];
+    let mut statement_start = true;
+    let mut after_then = false;
+    let mut verbatim = false;
+    for (i, (token, bytes)) in tokens.iter().enumerate() {
+        match token {
+            Token::Keyword("REM") if statement_start && !after_then => {
+                if shrunk.last() == Some(&b':') {
+                    shrunk.pop();
+                }
+                break;
+            }
+            Token::Keyword("'") if !after_then => break,
+            _ if verbatim && *token != Token::Char(b':') => {
+                shrunk.extend_from_slice(bytes);
+                continue;
+            }
+            Token::Char(b' ') => {
+                let name_before = shrunk.last().is_some_and(|b| is_name_char(*b));
+                let name_after = tokens
+                    .get(i + 1)
+                    .and_then(|(_, next)| next.first())
+                    .is_some_and(|b| is_name_char(*b));
+                if name_before && name_after {
+                    shrunk.push(b' ');
+                }
+                continue;
+            }
+            Token::Keyword("CALL") | Token::Keyword("SET") | Token::Char(b'_')
+                if statement_start =>
+            {
+                verbatim = true;
+                shrunk.extend_from_slice(bytes);
+            }
+            Token::Variable(name) => shrunk.extend_from_slice(short_name(name).as_bytes()),
+            _ => shrunk.extend_from_slice(bytes),
+        }
+        after_then = matches!(token, Token::Keyword("THEN") | Token::Keyword("ELSE"));
+        statement_start = after_then || *token == Token::Char(b':');
+        verbatim &= !statement_start;
+    }
+    shrunk
+}
+
+fn is_name_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'.'
+}
+
+/// Returns the significant part of the given variable name, keeping its type suffix
+fn short_name(name: &str) -> String {
+    let (stem, suffix) = match name.find(['$', '%', '!', '#']) {
+        Some(pos) => name.split_at(pos),
+        None => (name, ""),
+    };
+    if STATEMENT_WORDS.contains(&stem) {
+        return name.to_string();
+    }
+    format!("{}{}", &stem[..stem.len().min(2)], suffix)
+}
+
+/// Tokenize the statements of a source line (without its line number)
+fn tokenize_line(text: &[u8]) -> Vec<u8> {
+    let upper = text.to_ascii_uppercase();
+    let mut body = vec![];
+    let mut line_number_follows = false;
+    let mut i = 0;
+    while i < text.len() {
+        let c = upper[i];
+        let next = upper.get(i + 1).cloned();
+        match c {
+            b' ' => {
+                body.push(c);
+                i += 1;
+                continue;
+            }
+            b',' | b'-' if line_number_follows => {
+                body.push(c);
+                i += 1;
+                continue;
+            }
+            b'0'..=b'9' if line_number_follows => {
+                let digits = upper[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+                // check_source() rejects invalid line numbers, but it may not read the line
+                // as the tokenizer does, so the digits are kept as they are if they overflow
+                match latin1(&upper[i..i + digits]).parse::<u16>() {
+                    Ok(number) => {
+                        body.push(0x0e);
+                        body.extend_from_slice(&number.to_le_bytes());
+                    }
+                    Err(_) => body.extend_from_slice(&upper[i..i + digits]),
+                }
+                i += digits;
+                continue;
+            }
+            b'"' => {
+                let end = text[i + 1..]
+                    .iter()
+                    .position(|b| *b == b'"')
+                    .map_or(text.len(), |p| i + p + 2);
+                body.extend_from_slice(&text[i..end]);
+                i = end;
+            }
+            b'\'' => {
+                body.extend_from_slice(&[b':', TOKEN_REM, TOKEN_QUOTE]);
+                body.extend_from_slice(&text[i + 1..]);
+                break;
+            }
+            b'?' => {
+                body.push(keyword_token("PRINT")[0]);
+                i += 1;
+            }
+            b'&' if next == Some(b'H') || next == Some(b'O') => {
+                let (token, radix) = if next == Some(b'H') {
+                    (0x0c, 16)
+                } else {
+                    (0x0b, 8)
+                };
+                let digits = upper[i + 2..]
+                    .iter()
+                    .take_while(|b| (**b as char).is_digit(radix))
+                    .count();
+                match u16::from_str_radix(&latin1(&upper[i + 2..i + 2 + digits]), radix) {
+                    Ok(n) => {
+                        body.push(token);
+                        body.extend_from_slice(&n.to_le_bytes());
+                    }
+                    Err(_) => body.extend_from_slice(&upper[i..i + 2 + digits]),
+                }
+                i += 2 + digits;
+            }
+            b'0'..=b'9' | b'.' if c != b'.' || next.is_some_and(|b| b.is_ascii_digit()) => {
+                i += tokenize_number(&upper[i..], &mut body);
+            }
+            b'A'..=b'Z' => match keyword_at(&upper[i..]) {
+                Some(keyword) => {
+                    i += keyword.len();
+                    body.extend_from_slice(&keyword_token(keyword));
+                    match keyword {
+                        "REM" => {
+                            body.extend_from_slice(&text[i..]);
+                            break;
+                        }
+                        "DATA" => {
+                            let mut quoted = false;
+                            while i < text.len() && (text[i] != b':' || quoted) {
+                                quoted ^= text[i] == b'"';
+                                body.push(text[i]);
+                                i += 1;
+                            }
+                        }
+                        _ => {}
+                    }
+                    line_number_follows = LINE_NUMBER_KEYWORDS.contains(&keyword);
+                    continue;
+                }
+                None => {
+                    let mut len = upper[i..]
+                        .iter()
+                        .take_while(|b| b.is_ascii_alphanumeric())
+                        .count();
+                    if let Some(b'$') | Some(b'%') | Some(b'!') | Some(b'#') = upper.get(i + len) {
+                        len += 1;
+                    }
+                    body.extend_from_slice(&upper[i..i + len]);
+                    i += len;
+                }
+            },
+            _ => {
+                match OPERATORS.iter().position(|op| op.as_bytes() == [c]) {
+                    Some(op) => body.push(0xee + op as u8),
+                    None => body.push(text[i]),
+                }
+                i += 1;
+            }
+        }
+        line_number_follows = false;
+    }
+    body
+}
+
+/// Returns the bytes that encode the given keyword, function or named operator
+fn keyword_token(keyword: &str) -> Vec<u8> {
+    if keyword == "ELSE" {
+        return vec![b':', TOKEN_ELSE];
+    }
+    if let Some(i) = KEYWORDS.iter().position(|k| *k == keyword) {
+        vec![0x81 + i as u8]
+    } else if let Some(i) = FUNCTIONS.iter().position(|k| *k == keyword) {
+        vec![0xff, 0x81 + i as u8]
+    } else {
+        let i = OPERATORS.iter().position(|k| *k == keyword).unwrap();
+        vec![0xee + i as u8]
+    }
+}
+
+/// Tokenize the numeric literal found at the start of the given (uppercase) text
+///
+/// Returns the number of bytes of the literal.
+///
+fn tokenize_number(text: &[u8], body: &mut Vec<u8>) -> usize {
+    let digits = |from: usize| {
+        text[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    let int_len = digits(0);
+    let mut len = int_len;
+    if text.get(len) == Some(&b'.') {
+        len += 1 + digits(len + 1);
+    }
+    let mut exp_char = None;
+    if let Some(&c) = text.get(len).filter(|c| **c == b'E' || **c == b'D') {
+        let sign = match text.get(len + 1) {
+            Some(b'+') | Some(b'-') => 1,
+            _ => 0,
+        };
+        let exp_len = digits(len + 1 + sign);
+        if exp_len > 0 {
+            exp_char = Some(c);
+            len += 1 + sign + exp_len;
+        }
+    }
+    let suffix = match text.get(len) {
+        Some(&c @ b'%') | Some(&c @ b'!') | Some(&c @ b'#') => {
+            len += 1;
+            Some(c)
+        }
+        _ => None,
+    };
+
+    let decimal = text.get(int_len) == Some(&b'.') || exp_char.is_some();
+    if !decimal && suffix != Some(b'!') && suffix != Some(b'#') {
+        match latin1(&text[..int_len]).parse::<u16>() {
+            Ok(n @ 0..=9) => body.push(0x11 + n as u8),
+            Ok(n @ 10..=255) => body.extend_from_slice(&[0x0f, n as u8]),
+            Ok(n @ 256..=32767) => {
+                body.push(0x1c);
+                body.extend_from_slice(&n.to_le_bytes());
+            }
+            _ if suffix.is_none() => return tokenize_number_as(text, len, 8, body),
+            _ => body.extend_from_slice(&text[..len]),
+        }
+        return len;
+    }
+    let size = if suffix == Some(b'!') || (exp_char == Some(b'E') && suffix != Some(b'#')) {
+        4
+    } else {
+        8
+    };
+    tokenize_number_as(text, len, size, body)
+}
+
+/// Encode the literal of `len` bytes at the start of `text` in BCD of the given size
+fn tokenize_number_as(text: &[u8], len: usize, size: usize, body: &mut Vec<u8>) -> usize {
+    let literal = latin1(&text[..len]);
+    let literal = literal.trim_end_matches(['%', '!', '#']);
+    let (mantissa, exp) = match literal.find(['E', 'D']) {
+        Some(pos) => (
+            &literal[..pos],
+            literal[pos + 1..].parse::<i32>().unwrap_or(0),
+        ),
+        None => (literal, 0),
+    };
+    let (int_part, frac_part) = match mantissa.find('.') {
+        Some(pos) => (&mantissa[..pos], &mantissa[pos + 1..]),
+        None => (mantissa, ""),
+    };
+    let all_digits: Vec<u8> = int_part
+        .bytes()
+        .chain(frac_part.bytes())
+        .map(|b| b - b'0')
+        .collect();
+    let zeros = all_digits.iter().take_while(|d| **d == 0).count();
+    let mut exp = exp.saturating_add(int_part.len() as i32 - zeros as i32);
+    let mut digits: Vec<u8> = all_digits[zeros..].to_vec();
+
+    let mut bcd = vec![0; size];
+    if !digits.is_empty() {
+        let max = (size - 1) * 2;
+        if digits.len() > max {
+            let round_up = digits[max] >= 5;
+            digits.truncate(max);
+            if round_up {
+                let mut i = max;
+                while i > 0 && digits[i - 1] == 9 {
+                    digits[i - 1] = 0;
+                    i -= 1;
+                }
+                if i == 0 {
+                    digits.insert(0, 1);
+                    digits.truncate(max);
+                    exp = exp.saturating_add(1);
+                } else {
+                    digits[i - 1] += 1;
+                }
+            }
+        }
+        if !(-63..=63).contains(&exp) {
+            body.extend_from_slice(&text[..len]);
+            return len;
+        }
+        bcd[0] = (exp + 64) as u8;
+        for (i, d) in digits.iter().enumerate() {
+            bcd[1 + i / 2] |= if i % 2 == 0 { d << 4 } else { *d };
+        }
+    }
+    body.push(if size == 4 { 0x1d } else { 0x1f });
+    body.extend_from_slice(&bcd);
+    len
+}
+
+fn bcd_digits(bcd: &[u8]) -> String {
+    let mut digits: String = bcd[1..]
+        .iter()
+        .flat_map(|b| vec![b >> 4, b & 0x0f])
+        .map(|d| (b'0' + d) as char)
+        .collect();
+    while digits.ends_with('0') {
+        digits.pop();
+    }
+    digits
+}
+
+fn bcd_value(bcd: &[u8]) -> f64 {
+    let exp = (bcd[0] & 0x7f) as i32 - 64;
+    let mantissa = bcd[1..]
+        .iter()
+        .flat_map(|b| vec![b >> 4, b & 0x0f])
+        .fold(0.0, |acc, d| acc * 10.0 + d as f64);
+    let shift = exp - (bcd.len() as i32 - 1) * 2;
+    let value = if shift >= 0 {
+        mantissa * 10f64.powi(shift)
+    } else {
+        mantissa / 10f64.powi(-shift)
+    };
+    if bcd[0] & 0x80 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+fn bcd_text(bcd: &[u8], exp_char: char, suffix: &str) -> String {
+    let digits = bcd_digits(bcd);
+    if bcd[0] & 0x7f == 0 || digits.is_empty() {
+        return format!("0{}", suffix);
+    }
+    let sign = if bcd[0] & 0x80 != 0 { "-" } else { "" };
+    let max = (bcd.len() as i32 - 1) * 2;
+    let n = digits.len() as i32;
+    let exp = (bcd[0] & 0x7f) as i32 - 64;
+    if exp > 0 && exp <= max {
+        let mut text = digits.clone();
+        if exp >= n {
+            text.push_str(&"0".repeat((exp - n) as usize));
+        } else {
+            text.insert(exp as usize, '.');
+        }
+        format!("{}{}{}", sign, text, suffix)
+    } else if exp <= 0 && n - exp <= max {
+        format!("{}.{}{}{}", sign, "0".repeat(-exp as usize), digits, suffix)
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let dot = if rest.is_empty() { "" } else { "." };
+        let e = exp - 1;
+        let esign = if e < 0 { '-' } else { '+' };
+        format!(
+            "{}{}{}{}{}{}{:02}",
+            sign,
+            first,
+            dot,
+            rest,
+            exp_char,
+            esign,
+            e.abs()
+        )
+    }
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn program(lines: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0xff];
+        for (number, body) in lines {
+            data.extend_from_slice(&[0x01, 0x80]);
+            data.extend_from_slice(&number.to_le_bytes());
+            data.extend_from_slice(body);
+            data.push(0x00);
+        }
+        data.extend_from_slice(&[0x00, 0x00]);
+        data
+    }
+
+    #[test]
+    fn should_iterate_lines_and_tokens() {
+        let data = program(&[
+            (10, b"\x91 \"HI\":\x89 \x0e\x0a\x00"),
+            (20, b"A\xefA\xf1\x1f\x41\x15\x00\x00\x00\x00\x00\x00"),
+            (
+                30,
+                b"\x8b A$\xef\xff\x96(\x1c\x00\x01) \xda \x12 :\xa1 \x13",
+            ),
+        ]);
+        let lines: Vec<Line> = lines(&data).map(|l| l.unwrap()).collect();
+        assert_eq!(3, lines.len());
+        assert_eq!(10, lines[0].number());
+        assert_eq!(
+            vec![
+                Token::Keyword("PRINT"),
+                Token::Char(b' '),
+                Token::Str(b"HI"),
+                Token::Char(b':'),
+                Token::Keyword("GOTO"),
+                Token::Char(b' '),
+                Token::LineNumber(10),
+            ],
+            lines[0].tokens().collect::<Vec<_>>()
+        );
+        assert_eq!("10 PRINT \"HI\":GOTO 10", lines[0].to_string());
+        assert_eq!("20 A=A+1.5", lines[1].to_string());
+        assert_eq!(Some(1.5), lines[1].tokens().last().and_then(|t| t.value()));
+        assert_eq!("30 IF A$=CHR$(256) THEN 1 ELSE 2", lines[2].to_string());
+    }
+
+    #[test]
+    fn should_keep_remarks_and_data_as_text() {
+        let data = program(&[
+            (10, b"\x8f \x91 \":"),
+            (20, b"\x84 1,\"A:B\",2:\x91 \x3a\x8f\xe6 \xf1\x00"),
+        ]);
+        let lines: Vec<Line> = lines(&data).map(|l| l.unwrap()).collect();
+        assert_eq!(
+            vec![Token::Keyword("REM"), Token::Text(b" \x91 \":")],
+            lines[0].tokens().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                Token::Keyword("DATA"),
+                Token::Text(b" 1,\"A:B\",2"),
+                Token::Char(b':'),
+                Token::Keyword("PRINT"),
+                Token::Char(b' '),
+                Token::Keyword("'"),
+                Token::Text(b" \xf1"),
+            ],
+            lines[1].tokens().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_display_numbers() {
+        assert_eq!("&HFF", Token::Hex(0xff).to_string());
+        assert_eq!("&O17", Token::Octal(0o17).to_string());
+        assert_eq!("1.5!", Token::Single([0x41, 0x15, 0, 0]).to_string());
+        assert_eq!(
+            "40000",
+            Token::Double([0x45, 0x40, 0, 0, 0, 0, 0, 0]).to_string()
+        );
+        assert_eq!(
+            ".001",
+            Token::Double([0x3e, 0x10, 0, 0, 0, 0, 0, 0]).to_string()
+        );
+        assert_eq!("1.5E+20", Token::Single([0x55, 0x15, 0, 0]).to_string());
+        assert_eq!("0", Token::Double([0; 8]).to_string());
+        assert_eq!(Some(0.001), Token::Single([0x3e, 0x10, 0, 0]).value());
+    }
+
+    #[test]
+    fn should_fail_on_malformed_program() {
+        let data = [0x01, 0x80, 0x0a, 0x00, 0x91, 0x1c, 0x01];
+        let result: Vec<io::Result<Line>> = lines(&data).collect();
+        assert_eq!(1, result.len());
+        assert!(result[0].is_err());
+
+        let data = [0x01, 0x80, 0x0a, 0x00, 0x91, 0x20];
+        assert!(lines(&data).next().unwrap().is_err());
+
+        let data = [0x01, 0x80, 0x0a, 0x00, 0xff, 0x01, 0x00, 0x00, 0x00];
+        assert!(lines(&data).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn should_accept_valid_source() {
+        let source =
+            b"10 CLS:PRINT \"HELLO\"\r\n20 A$=\"X\":B(1)=2:IF A$=\"X\" THEN 10 ELSE PRINT\r\n\
+                       30 REM FOOBAR\r\n40 DATA FOO,\"BAR:\":GOTO 10\r\n50 ON A GOSUB 10,20\r\n\
+                       60 PRINTA:'NOTHING\r\n\r\n\x1aGARBAGE";
+        assert_eq!(Ok(()), check_source(source));
+    }
+
+    #[test]
+    fn should_report_syntax_errors() {
+        let long = format!("70 PRINT \"{}\"", "X".repeat(250));
+        let source = [
+            &b"10 PRINT\nPRINT\n65530 END\n10 CLS\n40 PRITN \"A\"\n50 GOTO 99999\n"[..],
+            long.as_bytes(),
+        ]
+        .concat();
+        assert_eq!(
+            Err(vec![
+                SyntaxError::MissingLineNumber { line: 2 },
+                SyntaxError::InvalidLineNumber {
+                    line: 3,
+                    number: "65530".to_string()
+                },
+                SyntaxError::DuplicateLine {
+                    line: 4,
+                    number: 10,
+                    first: 1
+                },
+                SyntaxError::UnknownKeyword {
+                    line: 5,
+                    word: "PRITN".to_string()
+                },
+                SyntaxError::InvalidLineNumber {
+                    line: 6,
+                    number: "99999".to_string()
+                },
+                SyntaxError::LineTooLong { line: 7, len: 261 },
+            ]),
+            check_source(&source)
+        );
+    }
+
+    #[test]
+    fn should_tokenize_source() {
+        let program = tokenize(b"20 a=a+1.5:b!=1e3\r\n10 print \"hi\":goto 10\r\n").unwrap();
+        let body = b"A\xefA\xf1\x1f\x41\x15\x00\x00\x00\x00\x00\x00:B!\xef\x1d\x44\x10\x00\x00";
+        let mut expected = vec![0x12, 0x80, 0x0a, 0x00];
+        expected.extend_from_slice(b"\x91 \"hi\":\x89 \x0e\x0a\x00\x00");
+        expected.extend_from_slice(&[0x2d, 0x80, 0x14, 0x00]);
+        expected.extend_from_slice(body);
+        expected.extend_from_slice(&[0x00, 0x00, 0x00]);
+        assert_eq!(expected, program);
+
+        assert!(tokenize(b"10 PRINT\nPRINT\n").is_err());
+        assert!(tokenize(b"10 RESUME 99999\n").is_err());
+        assert_eq!(b"\x89 99999".to_vec(), tokenize_line(b"GOTO 99999"));
+    }
+
+    #[test]
+    fn should_tell_source_from_programs() {
+        assert!(is_source(b"\r\n10 PRINT \"HI\"\r\n"));
+        assert!(!is_source(&tokenize(b"10 PRINT \"HI\"\n").unwrap()));
+        assert!(!is_source(b"PRINT \"HI\"\n"));
+        assert!(!is_source(b""));
+    }
+
+    #[test]
+    fn should_round_trip_source() {
+        let source: &[u8] = b"10 CLS:PRINT \"Hi\":?CHR$(65)\n\
+                              20 FOR I=1 TO 300 STEP 2:A(I)=A(I)*-.001:NEXT\n\
+                              30 IF A>2 AND B<>&HFF THEN 10 ELSE 20 'done\n\
+                              40 DATA foo,\"a:b\":X=40000:Y=1.5!:Z#=3\n\
+                              50 ON X GOTO 10,20:REM the end\n";
+        let program = tokenize(source).unwrap();
+        let listing = detokenize(&program).unwrap();
+        assert_eq!(
+            "10 CLS:PRINT \"Hi\":PRINTCHR$(65)\n\
+             20 FOR I=1 TO 300 STEP 2:A(I)=A(I)*-.001:NEXT\n\
+             30 IF A>2 AND B<>&HFF THEN 10 ELSE 20 'done\n\
+             40 DATA foo,\"a:b\":X=40000:Y=1.5!:Z#=3\n\
+             50 ON X GOTO 10,20:REM the end\n",
+            latin1(&listing)
+        );
+        assert_eq!(program, tokenize(&listing).unwrap());
+    }
+
+    #[test]
+    fn should_iterate_lines_of_basic_files() {
+        let data = program(&[(10, b"\x81")]);
+        let file = File::Basic("foo".to_string(), &data[1..]);
+        let lines: Vec<String> = lines_of(&file)
+            .unwrap()
+            .map(|l| l.unwrap().to_string())
+            .collect();
+        assert_eq!(vec!["10 END".to_string()], lines);
+        assert!(lines_of(&File::Custom(&data)).is_none());
+    }
+
+    #[test]
+    fn should_shrink_program() {
+        let source: &[u8] = b"10 REM GAME\n\
+                              20 SCORE%=0:LIVES=3 ' init\n\
+                              30 FOR I = 1 TO 10\n\
+                              40 PRINT \"A  B\";SCORE%:OPEN \"CAS:\" FOR APPEND AS #1\n\
+                              50 NEXT I\n\
+                              60 IF LIVES>0 THEN 100 ELSE 'nothing\n\
+                              70 CALL TURBO ON:END\n\
+                              100 REM TARGET\n\
+                              110 GOTO 100\n";
+        let program = tokenize(source).unwrap();
+        let shrunk = shrink(&program).unwrap();
+        assert_eq!(
+            "20 SC%=0:LI=3:FORI=1TO10:PRINT\"A  B\";SC%:OPEN\"CAS:\"FORAPPEND AS#1:NEXTI:\
+             IFLI>0THEN100ELSE'nothing\n\
+             70 CALL TURBO ON:END\n\
+             100 GOTO100\n",
+            latin1(&detokenize(&shrunk).unwrap())
+        );
+        assert!(shrunk.len() < program.len());
+
+        let program = tokenize(b"10 ON ERROR GOTO 30\n20 A=1\n30 IF ERL=20 THEN END\n").unwrap();
+        let listing = latin1(&detokenize(&shrink(&program).unwrap()).unwrap());
+        assert_eq!(3, listing.lines().count());
+    }
+}
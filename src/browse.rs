@@ -0,0 +1,580 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Interactive browser of the files of a tape, as run by `mcp browse`
+//!
+//! `Browser` keeps the state of the browser and draws it as lines of text, and `Terminal`
+//! puts the terminal in raw mode with `stty` to read keys and draw those lines with ANSI
+//! escape sequences.
+
+use std::io;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use mcp::dir::ExtractOptions;
+use mcp::meta::Metadata;
+use mcp::tape::{self, Entry, File, Tape};
+use mcp::{basic, encoding, progress};
+
+use crate::report;
+
+/// The width of the list of files, separator included
+const LIST_WIDTH: usize = 32;
+
+/// A key pressed by the user
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    /// Ctrl+C, which does not interrupt `mcp` in raw mode
+    Interrupt,
+    Char(char),
+}
+
+/// What the browser asks its caller to do after a key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Write the tape and its annotations, then call `saved()`
+    Save,
+    Quit,
+}
+
+/// How the selected file is previewed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum View {
+    Hex,
+    /// Detokenized Basic programs and ASCII files as text, the rest as hex
+    Text,
+}
+
+/// The state of the browser
+pub struct Browser {
+    tape: Tape,
+    meta: Metadata,
+    entries: Vec<Entry>,
+    /// The directory files are extracted to
+    dir: PathBuf,
+    selected: usize,
+    view: View,
+    /// The first line of the preview shown
+    scroll: usize,
+    /// The new name being typed for the selected file
+    input: Option<String>,
+    message: Option<String>,
+    modified: bool,
+    /// Whether `q` was pressed with unsaved changes
+    quitting: bool,
+}
+
+impl Browser {
+    /// Create a browser of the given tape and its annotations, which extracts files to `dir`.
+    pub fn new(tape: Tape, meta: Metadata, dir: &Path) -> Browser {
+        let entries = tape.entries();
+        Browser {
+            tape,
+            meta,
+            entries,
+            dir: dir.to_path_buf(),
+            selected: 0,
+            view: View::Text,
+            scroll: 0,
+            input: None,
+            message: None,
+            modified: false,
+            quitting: false,
+        }
+    }
+
+    /// Returns the tape as edited so far.
+    pub fn tape(&self) -> &Tape {
+        &self.tape
+    }
+
+    /// Returns the annotations of the files, moved along with them.
+    pub fn meta(&self) -> &Metadata {
+        &self.meta
+    }
+
+    /// Tell the browser that the tape was written.
+    pub fn saved(&mut self) {
+        self.modified = false;
+        self.message = Some("Written".to_string());
+    }
+
+    /// Show the given message in the status line until the next key.
+    pub fn show(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    /// Handle the given key, returning what the caller must do next, if anything.
+    ///
+    /// The keys are the arrows to select a file, `PgUp` and `PgDn` to scroll its preview,
+    /// `Tab` to switch between hex and text previews, and `x`, `d` and `r` to extract,
+    /// delete and rename it. `w` writes the tape and `q` quits, twice if there are
+    /// unsaved changes.
+    ///
+    pub fn handle(&mut self, key: Key) -> Option<Action> {
+        self.message = None;
+        if let Some(input) = self.input.as_mut() {
+            match key {
+                Key::Char(c) if c.is_ascii_graphic() || c == ' ' => input.push(c),
+                Key::Backspace => {
+                    input.pop();
+                }
+                Key::Enter => {
+                    let name = self.input.take().unwrap_or_default();
+                    self.rename(&name);
+                }
+                Key::Escape | Key::Interrupt => self.input = None,
+                _ => {}
+            }
+            return None;
+        }
+        let quitting = std::mem::replace(&mut self.quitting, false);
+        let last = self.entries.len().saturating_sub(1);
+        match key {
+            Key::Up | Key::Char('k') => self.select(self.selected.saturating_sub(1)),
+            Key::Down | Key::Char('j') => self.select((self.selected + 1).min(last)),
+            Key::Home => self.select(0),
+            Key::End => self.select(last),
+            Key::PageUp => self.scroll = self.scroll.saturating_sub(16),
+            Key::PageDown => self.scroll += 16,
+            Key::Tab | Key::Char('v') => {
+                self.view = match self.view {
+                    View::Hex => View::Text,
+                    View::Text => View::Hex,
+                };
+                self.scroll = 0;
+            }
+            Key::Char('x') => self.extract(),
+            Key::Char('d') => self.delete(),
+            Key::Char('r') => match self.entries.get(self.selected) {
+                Some(entry) if entry.name.is_some() => self.input = Some(String::new()),
+                Some(_) => self.show("Custom files have no name".to_string()),
+                None => {}
+            },
+            Key::Char('w') => return Some(Action::Save),
+            Key::Char('q') | Key::Escape if self.modified && !quitting => {
+                self.quitting = true;
+                self.show("Unsaved changes: press w to write them or q to quit anyway".to_string());
+            }
+            Key::Char('q') | Key::Escape | Key::Interrupt => return Some(Action::Quit),
+            _ => {}
+        }
+        None
+    }
+
+    /// Returns the lines of the browser, as shown in a terminal of the given size.
+    ///
+    /// The first line has the title, the last one the status, and the lines in between
+    /// the list of files on the left and the preview of the selected file on the right.
+    /// The title and the selected file are shown in reverse video.
+    ///
+    pub fn render(&self, title: &str, width: usize, height: usize) -> Vec<String> {
+        let rows = height.saturating_sub(2);
+        let mut lines = vec![];
+        let title = format!(
+            "{} | {} files{}",
+            title,
+            self.entries.len(),
+            if self.modified { " | modified" } else { "" }
+        );
+        lines.push(reverse(&fit(&title, width)));
+
+        let top = (self.selected + 1).saturating_sub(rows);
+        let (label, preview) = self.preview();
+        let preview_width = width.saturating_sub(LIST_WIDTH);
+        let preview: Vec<String> = Some(format!("{} preview", label))
+            .into_iter()
+            .chain(preview.into_iter().skip(self.scroll))
+            .collect();
+        for row in 0..rows {
+            let index = top + row;
+            let item = match self.entries.get(index) {
+                Some(entry) => fit(&list_item(entry), LIST_WIDTH - 2),
+                None => fit("", LIST_WIDTH - 2),
+            };
+            let item = if index == self.selected && index < self.entries.len() {
+                reverse(&item)
+            } else {
+                item
+            };
+            let text = preview.get(row).map_or("", String::as_str);
+            lines.push(format!("{} |{}", item, fit_end(text, preview_width)));
+        }
+
+        let status = match (&self.input, &self.message) {
+            (Some(input), _) => format!("New name: {}", input),
+            (None, Some(message)) => message.clone(),
+            (None, None) => {
+                "arrows: select  PgUp/PgDn: scroll  Tab: hex/text  x: extract  d: delete  \
+                 r: rename  w: write  q: quit"
+                    .to_string()
+            }
+        };
+        lines.push(fit_end(&status, width));
+        lines
+    }
+
+    fn select(&mut self, index: usize) {
+        if index != self.selected {
+            self.selected = index;
+            self.scroll = 0;
+        }
+    }
+
+    fn file(&self) -> Option<File<'_>> {
+        self.tape.files().nth(self.selected)
+    }
+
+    /// Returns the label and lines of the preview of the selected file
+    fn preview(&self) -> (&'static str, Vec<String>) {
+        let file = match self.file() {
+            Some(file) => file,
+            None => return ("Empty tape", vec![]),
+        };
+        if self.view == View::Text {
+            let text = match &file {
+                File::Basic(_, program) => basic::detokenize(program).ok(),
+                File::Ascii(..) => {
+                    let payload = file.payload();
+                    let len = file.text_len().unwrap_or(payload.len());
+                    Some(payload[..len].to_vec())
+                }
+                _ => None,
+            };
+            if let Some(text) = text {
+                let text = encoding::to_utf8(&text).replace('\t', "    ");
+                return (
+                    "Text",
+                    text.lines()
+                        .map(|l| l.trim_end_matches('\r').to_string())
+                        .collect(),
+                );
+            }
+        }
+        let dump = report::hex_dump(&file.payload());
+        ("Hex", dump.lines().map(String::from).collect())
+    }
+
+    /// Write the selected file into the extraction directory
+    fn extract(&mut self) {
+        let options = ExtractOptions::default();
+        let mut progress = progress::ignore();
+        let extracted =
+            self.tape
+                .extract_files_to_dir(&[self.selected], &self.dir, &options, &mut progress);
+        let message = match extracted.first() {
+            Some(e) => match &e.result {
+                Ok(()) => format!("Extracted {} to {}", e.name, e.path.display()),
+                Err(err) => format!("Cannot extract {}: {}", e.name, err),
+            },
+            None => return,
+        };
+        self.show(message);
+    }
+
+    /// Remove the selected file from the tape
+    fn delete(&mut self) {
+        let entry = match self.entries.get(self.selected) {
+            Some(entry) => entry.clone(),
+            None => return,
+        };
+        if let Err(e) = self.tape.remove_file(self.selected) {
+            return self.show(format!("Cannot remove the file: {}", e));
+        }
+        self.meta.remove(self.selected);
+        self.meta.reconcile(&self.tape);
+        self.changed();
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+        let name = entry.name.unwrap_or_else(|| "custom file".to_string());
+        self.show(format!("Removed {}", name));
+    }
+
+    /// Rename the selected file, dropping the extension of its type if given
+    fn rename(&mut self, new_name: &str) {
+        let file = match self.file() {
+            Some(file) => file,
+            None => return,
+        };
+        let stem = match (new_name.rsplit_once('.'), file.file_type().extension()) {
+            (Some((stem, ext)), Some(file_ext)) if ext.eq_ignore_ascii_case(file_ext) => stem,
+            _ => new_name,
+        };
+        if stem.trim().is_empty() {
+            return self.show("Not renamed: the name is empty".to_string());
+        }
+        let (bytes, truncated) = tape::file_name(stem);
+        if let Err(e) = self.tape.rename_file(self.selected, &bytes) {
+            return self.show(format!("Cannot rename the file: {}", e));
+        }
+        if let (Some(annotation), Some(file)) = (
+            self.meta.remove(self.selected),
+            self.tape.files().nth(self.selected),
+        ) {
+            self.meta.set(self.selected, &file, annotation);
+        }
+        self.changed();
+        if truncated {
+            self.show(format!(
+                "Name truncated to {}",
+                String::from_utf8_lossy(&bytes)
+            ));
+        }
+    }
+
+    fn changed(&mut self) {
+        self.entries = self.tape.entries();
+        self.modified = true;
+        self.scroll = 0;
+    }
+}
+
+/// Returns the line of the given file in the list
+fn list_item(entry: &Entry) -> String {
+    format!(
+        "{:>3} {:<6} {:<10} {:>6}",
+        entry.index,
+        entry.file_type,
+        entry.name.as_deref().unwrap_or("-"),
+        entry.size
+    )
+}
+
+/// Returns the given text cut or padded to `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let mut text: String = text.chars().take(width).collect();
+    let len = text.chars().count();
+    text.extend(std::iter::repeat_n(' ', width - len));
+    text
+}
+
+/// Returns the given text cut to `width` characters, without padding
+fn fit_end(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+fn reverse(text: &str) -> String {
+    format!("\x1b[7m{}\x1b[0m", text)
+}
+
+/// Read a key from the given input, or `None` if no key was pressed
+///
+/// Keys other than those of `Key` are ignored. An escape byte not followed by a known
+/// sequence is the `Escape` key.
+///
+pub fn read_key<R: Read>(input: &mut R) -> io::Result<Option<Key>> {
+    let key = match read_byte(input)? {
+        None => return Ok(None),
+        Some(0x1b) => match read_byte(input)? {
+            Some(b'[') | Some(b'O') => match read_byte(input)? {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                Some(n @ b'1'..=b'8') => match (n, read_byte(input)?) {
+                    (b'1' | b'7', Some(b'~')) => Key::Home,
+                    (b'4' | b'8', Some(b'~')) => Key::End,
+                    (b'5', Some(b'~')) => Key::PageUp,
+                    (b'6', Some(b'~')) => Key::PageDown,
+                    _ => return Ok(None),
+                },
+                _ => return Ok(None),
+            },
+            _ => Key::Escape,
+        },
+        Some(0x03) => Key::Interrupt,
+        Some(b'\r') | Some(b'\n') => Key::Enter,
+        Some(b'\t') => Key::Tab,
+        Some(0x7f) | Some(0x08) => Key::Backspace,
+        Some(b) if b.is_ascii() && !b.is_ascii_control() => Key::Char(b as char),
+        Some(_) => return Ok(None),
+    };
+    Ok(Some(key))
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// A terminal in raw mode, restored when dropped
+pub struct Terminal {
+    /// The settings of the terminal before entering raw mode, as printed by `stty -g`
+    settings: String,
+}
+
+impl Terminal {
+    /// Put the terminal in raw mode and switch to its alternate screen.
+    ///
+    /// Reads time out after a tenth of a second, so `read_key()` returns `None` when no
+    /// key is pressed. Fails with an error of kind `InvalidInput` if the standard input
+    /// or output is not a terminal.
+    ///
+    pub fn open() -> io::Result<Terminal> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mcp browse must be run in a terminal",
+            ));
+        }
+        let settings = stty(&["-g"])?.trim().to_string();
+        stty(&["raw", "-echo", "min", "0", "time", "1"])?;
+        let terminal = Terminal { settings };
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(terminal)
+    }
+
+    /// Returns the width and height of the terminal, or 80x24 if they are unknown.
+    pub fn size(&self) -> (usize, usize) {
+        let size = stty(&["size"]).unwrap_or_default();
+        match size.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [rows, cols] => match (cols.parse(), rows.parse()) {
+                (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+                _ => (80, 24),
+            },
+            _ => (80, 24),
+        }
+    }
+
+    /// Draw the given lines from the top of the screen.
+    pub fn draw(&mut self, lines: &[String]) -> io::Result<()> {
+        let mut screen = String::from("\x1b[H");
+        screen.push_str(&lines.join("\x1b[K\r\n"));
+        screen.push_str("\x1b[K\x1b[J");
+        let mut stdout = io::stdout();
+        stdout.write_all(screen.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// Read a key pressed by the user, waiting a tenth of a second at most.
+    pub fn read_key(&mut self) -> io::Result<Option<Key>> {
+        read_key(&mut io::stdin().lock())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.settings]);
+    }
+}
+
+/// Run `stty` on the terminal of the standard input, returning its output
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = process::Command::new("stty")
+        .args(args)
+        .stdin(process::Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "stty failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn keys(browser: &mut Browser, keys: &[Key]) -> Option<Action> {
+        keys.iter().fold(None, |_, key| browser.handle(*key))
+    }
+
+    #[test]
+    fn should_browse_tape() {
+        let dir = TempDir::new("mcp").unwrap();
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("intro");
+        tape.append_ascii(&name, b"10 CLS\r\n20 END\r\n").unwrap();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin_with_addresses(&name, 0x9000, 0x9000, &[0xc9])
+            .unwrap();
+        let mut browser = Browser::new(tape, Metadata::new(), dir.path());
+
+        let lines = browser.render("tape.cas", 100, 6);
+        assert_eq!(6, lines.len());
+        assert!(lines[0].contains("tape.cas | 2 files"));
+        assert!(lines[1].contains("\x1b[7m  0 ascii  intro.asc"));
+        assert!(lines[1].ends_with("|Text preview"));
+        assert!(lines[2].ends_with("|10 CLS"));
+        assert!(lines[3].ends_with("|20 END"));
+
+        keys(&mut browser, &[Key::Down, Key::Tab]);
+        let lines = browser.render("tape.cas", 100, 6);
+        assert!(lines[2].contains("\x1b[7m  1 bin    game.bin"));
+        assert!(lines[1].ends_with("|Hex preview"));
+        assert!(lines[2].contains("|00000000  c9 00  "));
+
+        assert_eq!(None, keys(&mut browser, &[Key::Char('x')]));
+        assert_eq!(
+            vec![0xfe, 0, 0x90, 0, 0x90, 0, 0x90, 0xc9, 0],
+            std::fs::read(dir.path().join("game.bin")).unwrap()
+        );
+
+        keys(
+            &mut browser,
+            &[
+                Key::Char('r'),
+                Key::Char('l'),
+                Key::Char('v'),
+                Key::Char('l'),
+                Key::Backspace,
+                Key::Char('1'),
+            ],
+        );
+        assert_eq!("New name: lv1", browser.render("tape.cas", 100, 6)[5]);
+        keys(&mut browser, &[Key::Enter]);
+        assert_eq!(
+            Some("lv1.bin".to_string()),
+            browser.tape().files().nth(1).unwrap().name()
+        );
+
+        keys(&mut browser, &[Key::Up, Key::Char('d')]);
+        assert_eq!(1, browser.tape().files().count());
+        assert!(browser.render("tape.cas", 100, 6)[0].contains("1 files | modified"));
+        assert_eq!(None, keys(&mut browser, &[Key::Char('q')]));
+        assert_eq!(Some(Action::Quit), keys(&mut browser, &[Key::Char('q')]));
+        assert_eq!(Some(Action::Save), keys(&mut browser, &[Key::Char('w')]));
+
+        let mut input: &[u8] = b"\x1b[A\x1b[6~q\x03\x1b";
+        let mut read = vec![];
+        while let Some(key) = read_key(&mut input).unwrap() {
+            read.push(key);
+        }
+        assert_eq!(
+            vec![
+                Key::Up,
+                Key::PageDown,
+                Key::Char('q'),
+                Key::Interrupt,
+                Key::Escape
+            ],
+            read
+        );
+    }
+}
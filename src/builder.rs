@@ -0,0 +1,220 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Fluent construction of tapes
+//!
+//! The `append_*()` methods of `Tape` modify a tape in place and take names already
+//! clipped by `tape::file_name()`, so a long name is silently truncated and a failed
+//! append leaves the rest of the files in. `TapeBuilder` lists the files of a tape one
+//! after another, validates them all and only returns the tape if every file is valid:
+//!
+//! ```
+//! use mcp::builder::TapeBuilder;
+//!
+//! let tape = TapeBuilder::new()
+//!     .ascii("loader", b"10 BLOAD\"CAS:\",R\r\n")
+//!     .bin("game", 0x9000, 0x9000, &[0xaf, 0xc9])
+//!     .build()?;
+//! assert_eq!(2, tape.files().count());
+//!
+//! let result = TapeBuilder::new().bin("toolong", 0x9000, 0x9000, &[0xc9]).build();
+//! assert!(result.is_err());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io;
+
+use crate::tape::{self, Tape};
+
+/// A builder of tapes, file by file
+///
+/// Files are appended in order. The first invalid file makes `build()` fail with an
+/// error of kind `InvalidInput` that tells its position and name, and the files after
+/// it are ignored.
+///
+#[derive(Default)]
+pub struct TapeBuilder {
+    tape: Tape,
+    aligned: bool,
+    count: usize,
+    error: Option<io::Error>,
+}
+
+impl TapeBuilder {
+    /// Create a builder of an empty tape.
+    pub fn new() -> TapeBuilder {
+        TapeBuilder::default()
+    }
+
+    /// Require the binary, Basic and custom files to be aligned to 8 bytes.
+    ///
+    /// Data blocks start at offsets multiple of 8, so files of other lengths are padded
+    /// with zeros, which may not be harmless for custom files. With this option, a file
+    /// that needs padding is an error instead. ASCII files are always padded to 256 bytes
+    /// with EOF characters, so they are not checked.
+    ///
+    pub fn aligned(mut self) -> TapeBuilder {
+        self.aligned = true;
+        self
+    }
+
+    /// Append a binary file whose raw program `data` is loaded at `begin` and run from
+    /// `start`, as `Tape::append_bin_with_addresses()` does.
+    pub fn bin(self, name: &str, begin: u16, start: u16, data: &[u8]) -> TapeBuilder {
+        self.append(Some(name), true, |tape, name| {
+            tape.append_bin_with_addresses(name, begin, start, data)
+        })
+    }
+
+    /// Append a tokenized Basic program, as `Tape::append_basic()` does.
+    pub fn basic(self, name: &str, data: &[u8]) -> TapeBuilder {
+        self.append(Some(name), true, |tape, name| tape.append_basic(name, data))
+    }
+
+    /// Append an ASCII file, as `Tape::append_ascii()` does.
+    pub fn ascii(self, name: &str, data: &[u8]) -> TapeBuilder {
+        self.append(Some(name), false, |tape, name| {
+            tape.append_ascii(name, data)
+        })
+    }
+
+    /// Append a custom file, as `Tape::append_custom()` does.
+    pub fn custom(self, data: &[u8]) -> TapeBuilder {
+        self.append(None, true, |tape, _| tape.append_custom(data))
+    }
+
+    /// Returns the tape, or the error of the first invalid file.
+    pub fn build(self) -> io::Result<Tape> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.tape),
+        }
+    }
+
+    fn append<F>(mut self, name: Option<&str>, checks_alignment: bool, f: F) -> TapeBuilder
+    where
+        F: FnOnce(&mut Tape, &[u8; 6]) -> io::Result<usize>,
+    {
+        let index = self.count;
+        self.count += 1;
+        if self.error.is_some() {
+            return self;
+        }
+        let label = match name {
+            Some(name) => format!("file {} ({:?})", index, name),
+            None => format!("file {}", index),
+        };
+        let invalid = |message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: {}", label, message),
+            )
+        };
+        let bytes = match name.map(valid_name).transpose() {
+            Ok(bytes) => bytes.unwrap_or([0x20; 6]),
+            Err(message) => {
+                self.error = Some(invalid(message));
+                return self;
+            }
+        };
+        // The blocks of an invalid file are removed, so the tape only has valid files
+        let len = self.tape.blocks().len();
+        let error = match f(&mut self.tape, &bytes) {
+            Ok(padding) if self.aligned && checks_alignment && padding > 0 => Some(format!(
+                "needs {} bytes of padding to be aligned to 8 bytes",
+                padding
+            )),
+            Ok(_) => None,
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(message) = error {
+            self.tape.truncate_blocks(len);
+            self.error = Some(invalid(message));
+        }
+        self
+    }
+}
+
+/// Returns the tape name of the given name, or why it is not valid
+fn valid_name(name: &str) -> Result<[u8; 6], String> {
+    if !name.is_ascii() {
+        return Err(format!(
+            "invalid name {:?} (expected ASCII characters)",
+            name
+        ));
+    }
+    let (bytes, truncated) = tape::file_name(name);
+    if truncated || name.is_empty() {
+        return Err(format!(
+            "invalid name {:?} (expected 1 to 6 characters)",
+            name
+        ));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape::File;
+
+    #[test]
+    fn should_build_tapes() {
+        let tape = TapeBuilder::new()
+            .basic("intro", &[0x00, 0x00])
+            .bin("game", 0x9000, 0x9001, &[0x00, 0xc9])
+            .custom(&[0x01; 8])
+            .build()
+            .unwrap();
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(3, files.len());
+        assert_eq!(Some("game.bin".to_string()), files[1].name());
+        assert_eq!(Some((0x9000, 0x9001, 0x9001)), files[1].addresses());
+
+        let e = TapeBuilder::new()
+            .ascii("intro", b"10 CLS")
+            .bin("toolong", 0x9000, 0x9000, &[0xc9])
+            .basic("", &[0x00])
+            .build()
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+        assert_eq!(
+            "file 1 (\"toolong\"): invalid name \"toolong\" (expected 1 to 6 characters)",
+            e.to_string()
+        );
+        let e = TapeBuilder::new()
+            .bin("game", 0xffff, 0xffff, &[0xaf, 0xc9])
+            .build()
+            .unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("file 0 (\"game\"): invalid binary file"));
+        assert!(TapeBuilder::new().basic("año", &[0x00]).build().is_err());
+        let e = TapeBuilder::new().basic("prog", &[]).build().unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("file 0 (\"prog\"): invalid basic file"));
+        assert!(TapeBuilder::new()
+            .bin("game", 0x9000, 0x9000, &[])
+            .build()
+            .is_err());
+
+        assert!(TapeBuilder::new().custom(&[0x01; 3]).build().is_ok());
+        let e = TapeBuilder::new()
+            .aligned()
+            .ascii("intro", b"10 CLS")
+            .custom(&[0x01; 3])
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            "file 1: needs 5 bytes of padding to be aligned to 8 bytes",
+            e.to_string()
+        );
+    }
+}
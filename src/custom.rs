@@ -0,0 +1,256 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Interpretation of custom files
+//!
+//! Custom files have no header, so their contents are opaque to the tape format. Many of
+//! them follow well-known formats though (game loaders, level data, turbo schemes...).
+//! An `Interpreter` recognizes one of these formats and extracts typed information from
+//! the data. Applications register their own interpreters in a `Registry`:
+//!
+//! ```
+//! use mcp::custom::{Interpretation, Interpreter, Registry, Value};
+//!
+//! struct Levels;
+//!
+//! impl Interpreter for Levels {
+//!     fn name(&self) -> &str {
+//!         "Foo levels"
+//!     }
+//!
+//!     fn interpret(&self, data: &[u8]) -> Option<Interpretation> {
+//!         if !data.starts_with(b"LVL") {
+//!             return None;
+//!         }
+//!         let mut interpretation = Interpretation::new(self.name());
+//!         interpretation.add("levels", Value::Number(*data.get(3)? as u64));
+//!         Some(interpretation)
+//!     }
+//! }
+//!
+//! let mut registry = Registry::builtin();
+//! registry.register(Levels);
+//! ```
+
+use std::fmt;
+
+use crate::screen::{self, ScreenMode};
+use crate::tape::{File, Tape};
+
+/// A value extracted from a custom file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Number(u64),
+    /// A memory address, displayed in hexadecimal
+    Address(u16),
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Address(addr) => write!(f, "0x{:04x}", addr),
+            Value::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// The information extracted from a custom file by an `Interpreter`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interpretation {
+    /// The name of the format of the file
+    pub format: String,
+    /// The named values found in the file, in the order they were added
+    pub fields: Vec<(String, Value)>,
+}
+
+impl Interpretation {
+    /// Create an interpretation of the given format without fields.
+    pub fn new(format: &str) -> Interpretation {
+        Interpretation {
+            format: format.to_string(),
+            fields: vec![],
+        }
+    }
+
+    /// Add a field to this interpretation.
+    pub fn add(&mut self, name: &str, value: Value) {
+        self.fields.push((name.to_string(), value));
+    }
+
+    /// Returns the value of the first field with the given name.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+}
+
+impl fmt::Display for Interpretation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format)?;
+        for (i, (name, value)) in self.fields.iter().enumerate() {
+            write!(f, "{}{}={}", if i == 0 { " (" } else { ", " }, name, value)?;
+        }
+        if !self.fields.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// An object that recognizes a format of custom files
+pub trait Interpreter {
+    /// Returns the name of the format recognized by this interpreter.
+    fn name(&self) -> &str;
+
+    /// Interpret the data of a custom file, or return `None` if they do not follow the
+    /// format of this interpreter.
+    fn interpret(&self, data: &[u8]) -> Option<Interpretation>;
+}
+
+/// A collection of interpreters tried in turn on custom files
+///
+/// The interpreters are tried in the order they were registered, and the first one that
+/// recognizes the data wins. The built-in interpreters are registered first, so they
+/// take precedence over the ones registered by applications.
+///
+#[derive(Default)]
+pub struct Registry {
+    interpreters: Vec<Box<dyn Interpreter + Send + Sync>>,
+}
+
+impl Registry {
+    /// Create a registry without interpreters.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Create a registry with the built-in interpreters.
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::new();
+        registry.register(ScreenDump);
+        registry
+    }
+
+    /// Register an interpreter.
+    pub fn register<I: Interpreter + Send + Sync + 'static>(&mut self, interpreter: I) {
+        self.interpreters.push(Box::new(interpreter));
+    }
+
+    /// Returns the names of the formats recognized by the registered interpreters.
+    pub fn names(&self) -> Vec<&str> {
+        self.interpreters.iter().map(|i| i.name()).collect()
+    }
+
+    /// Interpret the given file.
+    ///
+    /// Only custom files are interpreted: files of any other type return `None`.
+    ///
+    pub fn interpret(&self, file: &File) -> Option<Interpretation> {
+        match file {
+            File::Custom(data) => self.interpreters.iter().find_map(|i| i.interpret(data)),
+            _ => None,
+        }
+    }
+
+    /// Interpret the custom files of the given tape.
+    ///
+    /// Returns the index of each recognized file along with its interpretation.
+    ///
+    pub fn interpret_tape(&self, tape: &Tape) -> Vec<(usize, Interpretation)> {
+        tape.files()
+            .enumerate()
+            .filter_map(|(index, file)| self.interpret(&file).map(|i| (index, i)))
+            .collect()
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("interpreters", &self.names())
+            .finish()
+    }
+}
+
+/// Recognizes the VRAM dumps loaded at address 0 (see `screen::vram_of()`)
+struct ScreenDump;
+
+impl Interpreter for ScreenDump {
+    fn name(&self) -> &str {
+        "VRAM dump"
+    }
+
+    fn interpret(&self, data: &[u8]) -> Option<Interpretation> {
+        let (mode, _) = screen::vram_of(&File::Custom(data))?;
+        let (width, height) = mode.size();
+        let mut interpretation = Interpretation::new(self.name());
+        let mode = match mode {
+            ScreenMode::Screen2 => "SCREEN 2",
+            ScreenMode::Screen5 => "SCREEN 5",
+            ScreenMode::Screen7 => "SCREEN 7",
+            ScreenMode::Screen8 => "SCREEN 8",
+        };
+        interpretation.add("mode", Value::Text(mode.to_string()));
+        interpretation.add("width", Value::Number(width as u64));
+        interpretation.add("height", Value::Number(height as u64));
+        Some(interpretation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape;
+
+    struct Loader;
+
+    impl Interpreter for Loader {
+        fn name(&self) -> &str {
+            "Foo loader"
+        }
+
+        fn interpret(&self, data: &[u8]) -> Option<Interpretation> {
+            if data.len() < 4 || &data[..2] != b"FL" {
+                return None;
+            }
+            let mut interpretation = Interpretation::new(self.name());
+            let addr = u16::from_le_bytes([data[2], data[3]]);
+            interpretation.add("load", Value::Address(addr));
+            Some(interpretation)
+        }
+    }
+
+    #[test]
+    fn should_interpret_custom_files() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("foo");
+        tape.append_custom(b"FL\x00\x80data").unwrap();
+        tape.append_basic(&name, b"FL\x00\x80").unwrap();
+        tape.append_custom(&[0; 0x4000]).unwrap();
+        tape.append_custom(b"unknown!").unwrap();
+
+        let mut registry = Registry::builtin();
+        registry.register(Loader);
+        assert_eq!(vec!["VRAM dump", "Foo loader"], registry.names());
+
+        let found = registry.interpret_tape(&tape);
+        assert_eq!(
+            vec![0, 2],
+            found.iter().map(|(i, _)| *i).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&Value::Address(0x8000)), found[0].1.get("load"));
+        assert_eq!("Foo loader (load=0x8000)", found[0].1.to_string());
+        assert_eq!(
+            "VRAM dump (mode=SCREEN 2, width=256, height=192)",
+            found[1].1.to_string()
+        );
+        assert!(Registry::new().interpret_tape(&tape).is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Verification of tapes against DAT files
+//!
+//! DAT files are the catalogs of known-good dumps published by preservation projects
+//! such as No-Intro and TOSEC, in the Logiqx XML format:
+//!
+//! ```xml
+//! <datafile>
+//!     <header><name>MSX - Tapes</name></header>
+//!     <game name="Arkanoid (1986)(Imagine)">
+//!         <rom name="Arkanoid (1986)(Imagine).cas" size="12345" crc="..." sha1="..."/>
+//!     </game>
+//! </datafile>
+//! ```
+//!
+//! Tapes are matched by size, CRC-32 and SHA-1 (the hashes missing in the DAT are not
+//! checked). MD5 hashes are ignored.
+
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::dir;
+use crate::hash;
+use crate::tape::Tape;
+
+/// A dump listed in a DAT file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rom {
+    /// The name of the game the dump belongs to
+    pub game: String,
+    /// The file name of the dump
+    pub name: String,
+    pub size: Option<usize>,
+    pub crc: Option<u32>,
+    pub sha1: Option<[u8; 20]>,
+}
+
+impl Rom {
+    /// Returns `true` if the given data match the size and hashes of this dump.
+    ///
+    /// A dump without any hash never matches.
+    ///
+    pub fn matches(&self, data: &[u8]) -> bool {
+        if self.crc.is_none() && self.sha1.is_none() {
+            return false;
+        }
+        self.size.is_none_or(|size| size == data.len())
+            && self.crc.is_none_or(|crc| crc == hash::crc32(data))
+            && self.sha1.is_none_or(|sha1| sha1 == hash::sha1(data))
+    }
+}
+
+/// The verification status of a tape or a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status<'a> {
+    /// The data match a dump of the DAT
+    Good(&'a Rom),
+    /// The data do not match the dump of the DAT with the same name
+    Bad(&'a Rom),
+    /// Neither the data nor the name match any dump of the DAT
+    Unknown,
+}
+
+impl<'a> Status<'a> {
+    /// Returns the name of this status: `good`, `bad` or `unknown`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Good(_) => "good",
+            Status::Bad(_) => "bad",
+            Status::Unknown => "unknown",
+        }
+    }
+
+    /// Returns the dump this status refers to, if any.
+    pub fn rom(&self) -> Option<&'a Rom> {
+        match self {
+            Status::Good(rom) | Status::Bad(rom) => Some(rom),
+            Status::Unknown => None,
+        }
+    }
+}
+
+/// The result of verifying a tape and the files it contains
+#[derive(Debug, PartialEq)]
+pub struct Verification<'a> {
+    pub tape: Status<'a>,
+    pub files: Vec<(Option<String>, Status<'a>)>,
+}
+
+/// A DAT file
+#[derive(Debug, Default, PartialEq)]
+pub struct Dat {
+    name: Option<String>,
+    roms: Vec<Rom>,
+}
+
+impl Dat {
+    /// Load the DAT file at the given path.
+    #[cfg(feature = "fs")]
+    pub fn load(path: &Path) -> io::Result<Dat> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Returns the name of the DAT as found in its header.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the dumps listed in the DAT.
+    pub fn roms(&self) -> &[Rom] {
+        &self.roms
+    }
+
+    /// Verify the given data, stored with the given file name.
+    ///
+    /// The data are `Good` if they match any dump of the DAT. Otherwise, they are `Bad`
+    /// if there is a dump with the same name, or `Unknown` if there is none.
+    ///
+    pub fn verify(&self, name: &str, data: &[u8]) -> Status<'_> {
+        if let Some(rom) = self.roms.iter().find(|r| r.matches(data)) {
+            return Status::Good(rom);
+        }
+        match self.roms.iter().find(|r| r.name.eq_ignore_ascii_case(name)) {
+            Some(rom) => Status::Bad(rom),
+            None => Status::Unknown,
+        }
+    }
+
+    /// Verify a tape given its file name and contents, and each of the files it contains.
+    ///
+    /// The files are verified in their extracted form: binary files including the `0xfe`
+    /// ID byte and the address header, and the rest of the files by their payload.
+    ///
+    pub fn verify_tape(&self, name: &str, bytes: &[u8]) -> Verification<'_> {
+        let tape = Tape::from_bytes(bytes);
+        let files = tape
+            .files()
+            .map(|file| {
+                let name = file.name();
+                let status = self.verify(
+                    name.as_deref().unwrap_or(""),
+                    &dir::extracted_bytes(&file, None),
+                );
+                (name, status)
+            })
+            .collect();
+        Verification {
+            tape: self.verify(name, bytes),
+            files,
+        }
+    }
+}
+
+impl std::str::FromStr for Dat {
+    type Err = io::Error;
+
+    /// Parse a DAT file in Logiqx XML format.
+    fn from_str(text: &str) -> io::Result<Dat> {
+        if !text.contains("<datafile") {
+            return Err(invalid("not a Logiqx XML DAT file (missing <datafile>)"));
+        }
+        let mut dat = Dat::default();
+        if let Some(header) = element_text(text, "header") {
+            dat.name = element_text(header, "name").map(|n| unescape(n.trim()));
+        }
+        let mut game = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('<') {
+            let end = rest[start..]
+                .find('>')
+                .map(|e| start + e)
+                .ok_or_else(|| invalid("unterminated XML tag"))?;
+            let tag = &rest[start + 1..end];
+            let tag_name = tag.split_whitespace().next().unwrap_or("");
+            match tag_name {
+                "game" | "machine" => game = attribute(tag, "name").unwrap_or_default(),
+                "rom" => dat.roms.push(parse_rom(tag, &game)?),
+                _ => {}
+            }
+            rest = &rest[end + 1..];
+        }
+        Ok(dat)
+    }
+}
+
+fn parse_rom(tag: &str, game: &str) -> io::Result<Rom> {
+    let name = attribute(tag, "name").ok_or_else(|| invalid("rom without name"))?;
+    let size = attribute(tag, "size")
+        .map(|s| {
+            s.parse()
+                .map_err(|_| invalid(&format!("invalid size {:?} of rom {:?}", s, name)))
+        })
+        .transpose()?;
+    let crc = attribute(tag, "crc")
+        .map(|s| {
+            u32::from_str_radix(&s, 16)
+                .map_err(|_| invalid(&format!("invalid crc {:?} of rom {:?}", s, name)))
+        })
+        .transpose()?;
+    let sha1 = attribute(tag, "sha1")
+        .map(|s| {
+            parse_sha1(&s)
+                .ok_or_else(|| invalid(&format!("invalid sha1 {:?} of rom {:?}", s, name)))
+        })
+        .transpose()?;
+    Ok(Rom {
+        game: game.to_string(),
+        name,
+        size,
+        crc,
+        sha1,
+    })
+}
+
+fn parse_sha1(s: &str) -> Option<[u8; 20]> {
+    if s.len() != 40 || !s.is_ascii() {
+        return None;
+    }
+    let mut sha1 = [0; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(sha1)
+}
+
+/// Returns the text of the first element with the given name
+fn element_text<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = text.find(&open)? + open.len();
+    let end = start + text[start..].find(&close)?;
+    Some(&text[start..end])
+}
+
+/// Returns the value of the given attribute of a tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(pos) = rest.find(name) {
+        let before = rest[..pos].chars().last();
+        let after = rest[pos + name.len()..].trim_start();
+        if before.is_some_and(char::is_whitespace) && after.starts_with('=') {
+            let value = after[1..].trim_start();
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let end = value[1..].find(quote)?;
+            return Some(unescape(&value[1..=end]));
+        }
+        rest = &rest[pos + name.len()..];
+    }
+    None
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape;
+
+    fn sample_tape() -> Vec<u8> {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xc9, 0x00])
+            .unwrap();
+        tape.blocks()
+            .iter()
+            .flat_map(|b| b.data().to_vec())
+            .collect()
+    }
+
+    fn sample_dat(tape: &[u8], bin: &[u8]) -> String {
+        format!(
+            r#"<?xml version="1.0"?>
+            <datafile>
+                <header><name>MSX &amp; friends</name></header>
+                <game name="Game (1986)">
+                    <rom name="Game (1986).cas" size="{}" crc="{:08X}" sha1="{}"/>
+                </game>
+                <game name="Other">
+                    <rom name="other.cas" size="3" crc="00000000"/>
+                    <rom name='game.bin' crc="{:08x}"/>
+                </game>
+            </datafile>"#,
+            tape.len(),
+            hash::crc32(tape),
+            hash::to_hex(&hash::sha1(tape)),
+            hash::crc32(bin),
+        )
+    }
+
+    #[test]
+    fn should_parse_dat() {
+        let dat: Dat = sample_dat(b"foo", b"bar").parse().unwrap();
+        assert_eq!(Some("MSX & friends"), dat.name());
+        assert_eq!(3, dat.roms().len());
+        assert_eq!("Game (1986)", dat.roms()[0].game);
+        assert_eq!("Game (1986).cas", dat.roms()[0].name);
+        assert_eq!(Some(3), dat.roms()[0].size);
+        assert_eq!(Some(hash::sha1(b"foo")), dat.roms()[0].sha1);
+        assert_eq!("game.bin", dat.roms()[2].name);
+        assert_eq!(None, dat.roms()[2].size);
+
+        assert!("<game><rom/></game>".parse::<Dat>().is_err());
+        assert!("<datafile><rom name=\"x\" size=\"big\"/></datafile>"
+            .parse::<Dat>()
+            .is_err());
+    }
+
+    #[test]
+    fn should_verify_data() {
+        let dat: Dat = sample_dat(b"foo", b"bar").parse().unwrap();
+        assert_eq!(Status::Good(&dat.roms()[0]), dat.verify("x.cas", b"foo"));
+        assert_eq!(Status::Bad(&dat.roms()[1]), dat.verify("OTHER.CAS", b"bad"));
+        assert_eq!(Status::Unknown, dat.verify("x.cas", b"bad"));
+    }
+
+    #[test]
+    fn should_verify_tapes() {
+        let bytes = sample_tape();
+        let bin = [0xfe, 0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xc9, 0x00];
+        let dat: Dat = sample_dat(&bytes, &bin).parse().unwrap();
+        let result = dat.verify_tape("foo.cas", &bytes);
+        assert_eq!(Status::Good(&dat.roms()[0]), result.tape);
+        assert_eq!(
+            vec![(Some("game.bin".to_string()), Status::Good(&dat.roms()[2]))],
+            result.files
+        );
+
+        let dat: Dat = sample_dat(b"foo", b"bar").parse().unwrap();
+        let result = dat.verify_tape("foo.cas", &bytes);
+        assert_eq!(Status::Unknown, result.tape);
+        assert_eq!("bad", result.files[0].1.name());
+    }
+}
@@ -0,0 +1,454 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Decoding of cassette recordings into tapes
+//!
+//! The MSX BIOS records every block as a pilot tone of short pulses followed by its bytes.
+//! Each byte is a start bit, 8 data bits from the least significant one, and 2 stop
+//! bits. A 0 bit is a long pulse (one cycle at 1200Hz when recording at 1200 bauds), and
+//! a 1 bit is two short pulses (two cycles at 2400Hz), the same pulses of the pilot.
+//!
+//! The decoder measures the time between the zero crossings of the signal, so it works
+//! at any volume and polarity, and tells short from long pulses by comparing them with
+//! the pilot tone of each block. This way, both speeds are decoded and the small speed
+//! changes of old cassette players are followed from block to block. A block ends at
+//! the first silence or pilot tone that follows a byte. If a byte cannot be decoded
+//! (e.g., because the signal drops out), the bytes read so far are kept, a `Dropout` is
+//! reported, and decoding resumes at the next pilot tone.
+
+use std::io;
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::tape::{Block, Tape};
+
+/// The minimum number of half pulses of a pilot tone
+const MIN_PILOT: usize = 512;
+
+/// The level below which the signal is considered silence, relative to full scale
+const NOISE_FLOOR: f32 = 0.02;
+
+/// The result of decoding a recording
+pub struct Decoded {
+    /// The tape made of the blocks found in the recording
+    pub tape: Tape,
+    /// The blocks that could not be decoded completely
+    pub dropouts: Vec<Dropout>,
+    /// The frames of the recording taken by each block, from the start of its pilot tone
+    pub spans: Vec<Range<usize>>,
+    /// The sample rate of the recording
+    pub rate: u32,
+}
+
+/// A block of a recording that could not be decoded completely
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dropout {
+    /// The index of the block in the decoded tape
+    pub block: usize,
+    /// The number of bytes of the block decoded before the dropout
+    pub bytes: usize,
+    /// The position of the dropout in the recording
+    pub time: Duration,
+}
+
+/// Decode the given WAV recording into a tape.
+///
+/// The recording must be PCM audio with 8 or 16 bits per sample. If it has several
+/// channels, they are mixed. Fails with an error of kind `InvalidData` if the recording
+/// is not a WAV file in one of these formats, or if no block is found in it.
+///
+pub fn decode(wav: &[u8]) -> io::Result<Decoded> {
+    let audio = Audio::parse(wav)?;
+    let halves = half_pulses(&audio);
+    let mut blocks: Vec<Vec<u8>> = vec![];
+    let mut dropouts = vec![];
+    let mut spans = vec![];
+    let mut i = 0;
+    while let Some((unit, pilot, start)) = find_pilot(&halves, i) {
+        let mut reader = BlockReader {
+            halves: &halves,
+            unit,
+            i: start,
+        };
+        let (data, complete) = reader.read_block();
+        i = reader.i.max(start + 1);
+        if data.is_empty() && complete {
+            continue;
+        }
+        let pos = halves.get(reader.i).map_or(audio.frames(), |h| h.0);
+        if !complete {
+            dropouts.push(Dropout {
+                block: blocks.len(),
+                bytes: data.len(),
+                time: Duration::from_nanos(pos as u64 * 1_000_000_000 / audio.rate as u64),
+            });
+        }
+        if !data.is_empty() {
+            blocks.push(data);
+            spans.push(halves[pilot].0..pos);
+        }
+    }
+    if blocks.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no tape blocks were found in the recording",
+        ));
+    }
+    // The blocks are padded as in CAS files, but not parsed back from the padded bytes,
+    // so a payload that contains a block prefix does not split its block
+    let last = blocks.len() - 1;
+    let blocks = blocks
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut data)| {
+            if i < last {
+                data.resize(data.len().next_multiple_of(8), 0);
+            }
+            Block::from_data(&data)
+        })
+        .collect();
+    Ok(Decoded {
+        tape: Tape::from_blocks(blocks),
+        dropouts,
+        spans,
+        rate: audio.rate,
+    })
+}
+
+/// The PCM samples of a WAV file
+struct Audio<'a> {
+    rate: u32,
+    channels: usize,
+    bits: u16,
+    data: &'a [u8],
+}
+
+impl<'a> Audio<'a> {
+    fn parse(wav: &'a [u8]) -> io::Result<Audio<'a>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+            return Err(invalid("not a WAV file"));
+        }
+        let mut format = None;
+        let mut i = 12;
+        while i + 8 <= wav.len() {
+            let id = &wav[i..i + 4];
+            let len = u32::from_le_bytes([wav[i + 4], wav[i + 5], wav[i + 6], wav[i + 7]]);
+            let body = &wav[i + 8..(i + 8).saturating_add(len as usize).min(wav.len())];
+            match id {
+                b"fmt " if body.len() >= 16 => {
+                    let tag = u16::from_le_bytes([body[0], body[1]]);
+                    let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+                    let rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                    let bits = u16::from_le_bytes([body[14], body[15]]);
+                    // 0xfffe is the extensible format, used by some tools for plain PCM
+                    if (tag != 1 && tag != 0xfffe) || (bits != 8 && bits != 16) {
+                        return Err(invalid("only 8 and 16-bit PCM WAV files are supported"));
+                    }
+                    if channels == 0 || rate == 0 {
+                        return Err(invalid("invalid WAV format chunk"));
+                    }
+                    format = Some((rate, channels, bits));
+                }
+                b"data" => {
+                    let (rate, channels, bits) =
+                        format.ok_or_else(|| invalid("WAV data found before its format"))?;
+                    return Ok(Audio {
+                        rate,
+                        channels,
+                        bits,
+                        data: body,
+                    });
+                }
+                _ => {}
+            }
+            // Chunks are aligned to 2 bytes
+            i += 8 + len as usize + (len as usize & 1);
+        }
+        Err(invalid("the WAV file has no audio data"))
+    }
+
+    /// Returns the number of frames (samples of every channel) of the audio
+    fn frames(&self) -> usize {
+        self.data.len() / (self.channels * self.bits as usize / 8)
+    }
+
+    /// Returns the samples of the audio, with the channels mixed, in the range -1..1
+    fn samples(&self) -> impl Iterator<Item = f32> + '_ {
+        let width = self.bits as usize / 8;
+        self.data
+            .chunks_exact(self.channels * width)
+            .map(move |frame| {
+                let sum: f32 = frame
+                    .chunks_exact(width)
+                    .map(|s| match width {
+                        1 => (s[0] as f32 - 128.0) / 128.0,
+                        _ => i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0,
+                    })
+                    .sum();
+                sum / self.channels as f32
+            })
+    }
+}
+
+/// Returns the position and length in frames of the half pulses of the given audio
+///
+/// A half pulse goes from a zero crossing of the signal to the next one. Crossings are
+/// detected with some hysteresis, relative to the recent peak level, so noise around
+/// zero does not split pulses.
+///
+fn half_pulses(audio: &Audio) -> Vec<(usize, usize)> {
+    let mut halves = vec![];
+    let (mut dc, mut peak) = (0.0f32, 0.0f32);
+    let mut level = 0;
+    let mut last = 0;
+    for (i, sample) in audio.samples().enumerate() {
+        // Remove the DC offset of the recording with a high-pass filter
+        dc += (sample - dc) * 0.001;
+        let x = sample - dc;
+        peak = x.abs().max(peak * 0.999);
+        let threshold = (peak * 0.25).max(NOISE_FLOOR);
+        let crossed = match level {
+            1 => x < -threshold,
+            -1 => x > threshold,
+            _ => x.abs() > threshold,
+        };
+        if crossed {
+            if level != 0 {
+                halves.push((last, i - last));
+            }
+            level = if x > 0.0 { 1 } else { -1 };
+            last = i;
+        }
+    }
+    halves
+}
+
+/// Search a pilot tone from the given half pulse on
+///
+/// Returns the average length of its half pulses, which is the length of the half
+/// pulses of 1 bits in the block that follows, the index of its first half pulse and
+/// the index of the first half pulse after the pilot.
+///
+fn find_pilot(halves: &[(usize, usize)], from: usize) -> Option<(f32, usize, usize)> {
+    let mut start = from;
+    let mut sum = 0.0;
+    for (i, (_, len)) in halves.iter().enumerate().skip(from) {
+        let len = *len as f32;
+        let count = (i - start) as f32;
+        let unit = if count > 0.0 { sum / count } else { len };
+        if len >= unit * 0.5 && len < unit * 1.5 {
+            sum += len;
+        } else if i - start >= MIN_PILOT {
+            return Some((unit, start, i));
+        } else {
+            start = i;
+            sum = len;
+        }
+    }
+    None
+}
+
+/// The classes of half pulses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Half {
+    Short,
+    Long,
+    /// A silence, or the end of the recording
+    Gap,
+}
+
+/// The reader of the bytes of a block that follow a pilot tone
+struct BlockReader<'a> {
+    halves: &'a [(usize, usize)],
+    /// The length of a short half pulse
+    unit: f32,
+    i: usize,
+}
+
+impl BlockReader<'_> {
+    /// Read the bytes of the block
+    ///
+    /// Returns the bytes read and whether the block ended as expected, with a silence
+    /// or a pilot tone after a byte.
+    ///
+    fn read_block(&mut self) -> (Vec<u8>, bool) {
+        let mut data = vec![];
+        loop {
+            // The start bit, a 0
+            if self.peek() != Half::Long {
+                return (data, true);
+            }
+            match self.read_byte() {
+                Some(byte) => data.push(byte),
+                None => return (data, false),
+            }
+            // The stop bits, two 1s of 4 half pulses each
+            for _ in 0..8 {
+                if self.peek() != Half::Short {
+                    break;
+                }
+                self.i += 1;
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        self.expect(Half::Long, 2)?;
+        let mut byte = 0;
+        for bit in 0..8 {
+            match self.peek() {
+                Half::Long => self.expect(Half::Long, 2)?,
+                Half::Short => {
+                    self.expect(Half::Short, 4)?;
+                    byte |= 1 << bit;
+                }
+                Half::Gap => return None,
+            }
+        }
+        Some(byte)
+    }
+
+    /// Consume `n` half pulses of the given class
+    fn expect(&mut self, half: Half, n: usize) -> Option<()> {
+        for _ in 0..n {
+            if self.peek() != half {
+                return None;
+            }
+            self.i += 1;
+        }
+        Some(())
+    }
+
+    fn peek(&self) -> Half {
+        match self.halves.get(self.i) {
+            Some((_, len)) if (*len as f32) < self.unit * 1.5 => Half::Short,
+            Some((_, len)) if (*len as f32) < self.unit * 3.0 => Half::Long,
+            _ => Half::Gap,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape;
+    use crate::wav::{Exporter, Profile};
+
+    fn record(tape: &Tape, profile: Profile) -> Vec<u8> {
+        let mut exporter = Exporter::with_profile(profile);
+        exporter
+            .write_tape(tape, &mut crate::progress::ignore())
+            .unwrap();
+        let mut wav = vec![];
+        exporter.export(&mut wav).unwrap();
+        wav
+    }
+
+    fn sample_tape() -> Tape {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(
+            &name,
+            &[0x00, 0x90, 0x03, 0x90, 0x00, 0x90, 0xaf, 0x3c, 0xff, 0xc9],
+        )
+        .unwrap();
+        let (name, _) = tape::file_name("text");
+        tape.append_ascii(&name, b"10 PRINT \"HELLO\"\r\n").unwrap();
+        tape
+    }
+
+    fn block_data(tape: &Tape) -> Vec<Vec<u8>> {
+        tape.blocks().iter().map(|b| b.data().to_vec()).collect()
+    }
+
+    #[test]
+    fn should_decode_recordings() {
+        let tape = sample_tape();
+        let decoded = decode(&record(&tape, Profile::default())).unwrap();
+        assert!(decoded.dropouts.is_empty());
+        assert_eq!(block_data(&tape), block_data(&decoded.tape));
+        // The first block starts with its pilot, after a silence of 2 seconds, and the
+        // second one after its bytes and a silence of 1 second
+        assert_eq!(43200, decoded.rate);
+        assert_eq!(4, decoded.spans.len());
+        assert!((86400..86410).contains(&decoded.spans[0].start));
+        let end = 86400 + 16000 * 18 + 16 * 11 * 36;
+        assert!((end - 36..end + 36).contains(&decoded.spans[0].end));
+        assert!((end + 43200..end + 43210).contains(&decoded.spans[1].start));
+        assert!(decoded.spans.windows(2).all(|s| s[0].end < s[1].start));
+
+        let profile = Profile {
+            bauds: 2400,
+            sample_rate: 44100,
+            short_gap_ms: 300,
+            long_gap_ms: 500,
+            turbo_from: 0,
+        };
+        let mut wav = record(&tape, profile);
+        // Invert the polarity, lower the volume and add some noise
+        let mut noise = 1u32;
+        for sample in &mut wav[44..] {
+            noise = noise.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let hiss = (noise >> 16) as i16 % 5 - 2;
+            *sample = (0x80 - (*sample as i16 - 0x80) / 4 + hiss) as u8;
+        }
+        let decoded = decode(&wav).unwrap();
+        assert!(decoded.dropouts.is_empty());
+        assert_eq!(block_data(&tape), block_data(&decoded.tape));
+
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            decode(b"RIFF\0\0\0\0AVI ").err().unwrap().kind()
+        );
+    }
+
+    #[test]
+    fn should_report_dropouts() {
+        let tape = sample_tape();
+        let mut wav = record(&tape, Profile::default());
+        // Silence the data of the binary file from the middle of its fourth byte. Every
+        // byte takes 11 bits of 36 samples. The first block has a long silence, a long
+        // pilot and 16 bytes, and the second a short silence and pilot.
+        let data = 44 + 86400 + 16000 * 18 + 16 * 11 * 36 + 43200 + 4000 * 18;
+        for sample in &mut wav[data + 3 * 11 * 36 + 100..data + 5 * 11 * 36] {
+            *sample = 0x80;
+        }
+        let decoded = decode(&wav).unwrap();
+        assert_eq!(1, decoded.dropouts.len());
+        assert_eq!(1, decoded.dropouts[0].block);
+        assert_eq!(3, decoded.dropouts[0].bytes);
+        assert_eq!(4, decoded.tape.blocks().len());
+        assert_eq!(
+            tape.blocks()[1].data()[..8 + 3],
+            decoded.tape.blocks()[1].data()[..8 + 3]
+        );
+        assert_eq!(tape.blocks()[2].data(), decoded.tape.blocks()[2].data());
+    }
+
+    #[test]
+    fn should_keep_block_prefixes_inside_payloads() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("prefix");
+        let mut data = vec![0x00, 0x90, 0x09, 0x90, 0x00, 0x90, 0xaf, 0xc9];
+        data.extend_from_slice(&[0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74]);
+        tape.append_bin(&name, &data).unwrap();
+        // The prefix is at an aligned offset, so the CAS bytes parse into three blocks
+        let bytes: Vec<u8> = tape
+            .blocks()
+            .iter()
+            .flat_map(|b| b.data().to_vec())
+            .collect();
+        assert_eq!(3, Tape::from_bytes(&bytes).blocks().len());
+
+        let decoded = decode(&record(&tape, Profile::default())).unwrap();
+        assert_eq!(block_data(&tape), block_data(&decoded.tape));
+        assert_eq!(2, decoded.spans.len());
+    }
+}
@@ -0,0 +1,245 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Comparison of two tapes, as reported by `mcp diff`
+//!
+//! Dumps of the same physical tape rarely match byte by byte: a block may be damaged, a
+//! file may be missing or the files may be padded differently. `files()` tells which
+//! files were added, removed or changed from one tape to the other, and `blocks()` does
+//! the same with the raw blocks, for the tapes whose files cannot be told apart.
+//!
+//! Both align the tapes on their longest common subsequence of identical items, so a
+//! file inserted in the middle of a tape is reported as such instead of shifting every
+//! following file.
+
+use crate::hash;
+use crate::tape::{Entry, Tape};
+
+/// A difference between two tapes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change<T> {
+    /// An item only found in the second tape
+    Added(T),
+    /// An item only found in the first tape
+    Removed(T),
+    /// An item of the first tape replaced by a different one in the second tape
+    Changed(T, T),
+}
+
+impl<T> Change<T> {
+    /// Returns the name of this change: `added`, `removed` or `changed`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::Added(_) => "added",
+            Change::Removed(_) => "removed",
+            Change::Changed(..) => "changed",
+        }
+    }
+
+    /// Returns the items of the first and second tapes involved in this change.
+    pub fn items(&self) -> (Option<&T>, Option<&T>) {
+        match self {
+            Change::Added(b) => (None, Some(b)),
+            Change::Removed(a) => (Some(a), None),
+            Change::Changed(a, b) => (Some(a), Some(b)),
+        }
+    }
+}
+
+/// A block of a tape, as compared by `blocks()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockEntry {
+    /// The index of the block in the tape
+    pub index: usize,
+    /// The number of bytes of the block, prefix excluded
+    pub len: usize,
+}
+
+/// Returns the differences between the files of the given tapes, in tape order
+///
+/// Files are identical if their type, name, addresses and data blocks are. A file is
+/// reported as changed when the other tape has a different file of the same type and
+/// name in its place, and as removed and added otherwise (e.g., when it was renamed).
+/// Custom files have no name, so they are paired in order.
+///
+pub fn files(a: &Tape, b: &Tape) -> Vec<Change<Entry>> {
+    let keyed = |tape: &Tape| -> Vec<(_, Entry)> {
+        tape.files()
+            .zip(tape.entries())
+            .map(|(file, entry)| {
+                let key = (
+                    entry.file_type,
+                    entry.name.clone(),
+                    entry.addresses,
+                    hash::sha1(&file.raw_data()),
+                );
+                (key, entry)
+            })
+            .collect()
+    };
+    changes(&keyed(a), &keyed(b), |x, y| {
+        x.file_type == y.file_type && x.name == y.name
+    })
+}
+
+/// Returns the differences between the blocks of the given tapes, in tape order
+///
+/// Blocks are identical if their bytes are. Blocks of the first tape that are not found
+/// in the second one are reported as changed if the second tape has other blocks in their
+/// place, and as removed otherwise.
+///
+pub fn blocks(a: &Tape, b: &Tape) -> Vec<Change<BlockEntry>> {
+    let keyed = |tape: &Tape| -> Vec<(_, BlockEntry)> {
+        tape.blocks()
+            .iter()
+            .enumerate()
+            .map(|(index, block)| {
+                let entry = BlockEntry {
+                    index,
+                    len: block.data_without_prefix().len(),
+                };
+                (hash::sha1(block.data()), entry)
+            })
+            .collect()
+    };
+    changes(&keyed(a), &keyed(b), |_, _| true)
+}
+
+/// Returns the changes from `a` to `b`, whose items are identical if their keys are
+///
+/// The items of `a` that are not in the common subsequence are paired with the first
+/// `related` item of `b` not in the subsequence either, between the same common items.
+///
+fn changes<K: PartialEq, T: Clone>(
+    a: &[(K, T)],
+    b: &[(K, T)],
+    related: impl Fn(&T, &T) -> bool,
+) -> Vec<Change<T>> {
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in common(a, b).into_iter().chain(Some((a.len(), b.len()))) {
+        gap(&a[i..next_i], &b[j..next_j], &related, &mut result);
+        i = next_i + 1;
+        j = next_j + 1;
+    }
+    result
+}
+
+/// Append the changes between items of `a` and `b` that have nothing in common
+fn gap<K, T: Clone>(
+    a: &[(K, T)],
+    b: &[(K, T)],
+    related: &impl Fn(&T, &T) -> bool,
+    result: &mut Vec<Change<T>>,
+) {
+    let mut added: Vec<Option<&T>> = b.iter().map(|(_, item)| Some(item)).collect();
+    for (_, removed) in a {
+        let pair = added
+            .iter_mut()
+            .find(|item| item.is_some_and(|item| related(removed, item)))
+            .and_then(Option::take);
+        result.push(match pair {
+            Some(item) => Change::Changed(removed.clone(), item.clone()),
+            None => Change::Removed(removed.clone()),
+        });
+    }
+    result.extend(added.into_iter().flatten().cloned().map(Change::Added));
+}
+
+/// Returns the pairs of indices of the longest common subsequence of the keys
+fn common<K: PartialEq, T>(a: &[(K, T)], b: &[(K, T)]) -> Vec<(usize, usize)> {
+    // lengths[i][j] is the length of the common subsequence of a[i..] and b[j..]
+    let mut lengths = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i].0 == b[j].0 {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].0 == b[j].0 {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape::{self, FileType};
+
+    fn tape_of(files: &[(&str, &[u8])]) -> Tape {
+        let mut tape = Tape::new();
+        for (name, data) in files {
+            let (name, _) = tape::file_name(name);
+            tape.append_bin_with_addresses(&name, 0x9000, 0x9000, data)
+                .unwrap();
+        }
+        tape
+    }
+
+    #[test]
+    fn should_diff_tapes() {
+        let a = tape_of(&[("loader", &[1]), ("game", &[2, 3]), ("old", &[4])]);
+        let b = tape_of(&[
+            ("intro", &[0]),
+            ("loader", &[1]),
+            ("game", &[2]),
+            ("new", &[4]),
+        ]);
+        let changes: Vec<(&str, Option<String>)> = files(&a, &b)
+            .iter()
+            .map(|c| match c {
+                Change::Added(e) => ("added", e.name.clone()),
+                Change::Removed(e) => ("removed", e.name.clone()),
+                Change::Changed(e, _) => ("changed", e.name.clone()),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("added", Some("intro.bin".to_string())),
+                ("changed", Some("game.bin".to_string())),
+                ("removed", Some("old.bin".to_string())),
+                ("added", Some("new.bin".to_string())),
+            ],
+            changes
+        );
+        match &files(&a, &b)[1] {
+            Change::Changed(x, y) => {
+                assert_eq!((1, FileType::Bin, 2), (x.index, x.file_type, y.index));
+                assert_eq!(Some((0x9000, 0x9001, 0x9000)), x.addresses);
+            }
+            c => panic!("unexpected change {:?}", c),
+        }
+        assert_eq!(Vec::<Change<Entry>>::new(), files(&a, &a));
+
+        let blocks = blocks(&a, &b);
+        assert_eq!(Change::Added(BlockEntry { index: 0, len: 16 }), blocks[0]);
+        assert_eq!(
+            Change::Changed(
+                BlockEntry { index: 3, len: 8 },
+                BlockEntry { index: 5, len: 8 }
+            ),
+            blocks[2]
+        );
+        assert_eq!(4, blocks.len());
+    }
+}
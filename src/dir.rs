@@ -0,0 +1,1054 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Extraction and packing of tapes from and into host directories
+//!
+//! These are the operations behind `mcp extract` and `mcp add`, so any application gets
+//! the same file typing, naming and collision handling as the command line tool.
+
+use std::borrow::Cow;
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+
+use crate::basic;
+#[cfg(feature = "fs")]
+use crate::encoding;
+#[cfg(feature = "fs")]
+use crate::file;
+use crate::hash;
+#[cfg(feature = "fs")]
+use crate::ihex;
+#[cfg(feature = "fs")]
+use crate::progress::{self, Progress};
+use crate::tape::{File, FileType, Tape};
+use crate::text::{self, LineEnding};
+
+/// The options of `Tape::extract_to_dir()`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExtractOptions {
+    /// Convert the line endings of ASCII files
+    pub eol: Option<LineEnding>,
+    /// Prefix the names with the position of the files in the tape (e.g., `01_game.bin`)
+    pub numbered: bool,
+    /// Write tokenized Basic programs as text listings (see `basic::detokenize()`)
+    pub as_text: bool,
+    /// Write ASCII files and listings in UTF-8 (see `encoding::to_utf8()`)
+    pub utf8: bool,
+}
+
+/// The result of extracting a file of a tape
+#[cfg(feature = "fs")]
+#[derive(Debug)]
+pub struct Extracted {
+    /// The index of the file in the tape
+    pub index: usize,
+    /// The file name derived from the tape (e.g., `game.bin` or `custom.000`)
+    pub name: String,
+    /// The path the file was written to
+    pub path: PathBuf,
+    /// Whether a file named `name` already existed, so the file was written to a
+    /// different path
+    pub clash: bool,
+    /// The result of writing the file
+    pub result: io::Result<()>,
+}
+
+/// The outcome of comparing a file of a tape with its host counterpart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// The contents match
+    Same,
+    /// The contents differ from the given offset of the normalized data on
+    Different(usize),
+    /// There is no host file with the name of the tape file
+    Missing,
+}
+
+impl Comparison {
+    /// Returns the name of this outcome: `same`, `different` or `missing`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Comparison::Same => "same",
+            Comparison::Different(_) => "different",
+            Comparison::Missing => "missing",
+        }
+    }
+}
+
+/// The result of comparing a file of a tape with a host file
+#[cfg(feature = "fs")]
+#[derive(Debug)]
+pub struct Compared {
+    /// The index of the file in the tape
+    pub index: usize,
+    /// The file name derived from the tape (e.g., `game.bin` or `custom.000`)
+    pub name: String,
+    /// The path of the host file
+    pub path: PathBuf,
+    /// The outcome of the comparison, or the error reading the host file
+    pub result: io::Result<Comparison>,
+}
+
+/// The hashes of the contents of a host file, used to find it in tapes
+///
+/// The contents are hashed once normalized as each file type (see `normalized_bytes()`),
+/// so a host file matches a tape file whatever the type its extension suggests, and the
+/// differences introduced by `mcp extract` (e.g., converted line endings) are ignored.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint(Vec<(FileType, [u8; 20])>);
+
+impl Fingerprint {
+    /// Returns the fingerprint of the given host file contents
+    pub fn of(data: &[u8]) -> Fingerprint {
+        let types = [
+            FileType::Bin,
+            FileType::Basic,
+            FileType::Ascii,
+            FileType::Custom,
+        ];
+        Fingerprint(
+            types
+                .iter()
+                .map(|t| (*t, hash::sha1(&normalized_bytes(*t, data))))
+                .collect(),
+        )
+    }
+
+    /// Returns `true` if the given tape file has the contents of this fingerprint.
+    pub fn matches(&self, file: &File) -> bool {
+        let file_type = file.file_type();
+        let expected = hash::sha1(&normalized_bytes(file_type, &extracted_bytes(file, None)));
+        self.0
+            .iter()
+            .any(|(t, h)| *t == file_type && *h == expected)
+    }
+}
+
+/// The options of `Tape::pack_file()` and `Tape::pack_dir()`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PackOptions {
+    /// Convert the line endings of ASCII files
+    pub eol: Option<LineEnding>,
+    /// Pack every file as a binary file of raw machine code loaded at this address
+    pub begin: Option<u16>,
+    /// The start address of the raw machine code (the begin address if not given)
+    pub start: Option<u16>,
+    /// Read ASCII files and Basic sources in UTF-8 (see `encoding::from_utf8()`)
+    pub utf8: bool,
+    /// Pack the file as this type instead of the one its extension says
+    pub file_type: Option<FileType>,
+    /// The tape file name, instead of the file stem
+    pub name: Option<[u8; 6]>,
+}
+
+/// A host file packed into a tape
+#[cfg(feature = "fs")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packed {
+    /// The path of the host file
+    pub path: PathBuf,
+    /// The type of the file, inferred from its extension unless given
+    pub file_type: FileType,
+    /// The name of the file in the tape, or `None` for custom files
+    pub name: Option<[u8; 6]>,
+    /// Whether the name of the host file was truncated to fit in six characters
+    pub truncated: bool,
+    /// The number of bytes added to align (or terminate, for ASCII files) the file data
+    pub padding: usize,
+}
+
+#[cfg(feature = "fs")]
+impl PackOptions {
+    /// Returns the type of the file at the given path when packed with these options.
+    pub fn file_type_of(&self, path: &Path) -> FileType {
+        match (self.begin, self.file_type) {
+            (Some(_), _) => FileType::Bin,
+            (None, Some(file_type)) => file_type,
+            (None, None) => file::file_type_of(path),
+        }
+    }
+}
+
+impl Tape {
+    /// Append the given host file to this tape.
+    ///
+    /// The file type is inferred from the extension (`bin`, `bas`, `asc` or anything else
+    /// for custom files), and the tape file name is the file stem truncated to six
+    /// characters. Intel HEX files (`hex` or `ihx`) are converted into binary files, and
+    /// `bas` files with source text are tokenized (see `basic::tokenize()`), failing with
+    /// their first syntax error.
+    ///
+    /// `PackOptions::file_type` and `PackOptions::name` override the type and the name
+    /// inferred from the path.
+    ///
+    /// With `PackOptions::begin`, any file is packed as a binary file whose contents are
+    /// the raw program, and the address header is generated from the given addresses (see
+    /// `append_bin_with_addresses()`).
+    ///
+    /// With `PackOptions::utf8`, ASCII files and Basic sources are converted to the MSX
+    /// character set, and their line endings to `CrLf` unless `PackOptions::eol` says
+    /// otherwise. Characters not found in the MSX character set make it fail with an error
+    /// of kind `InvalidData`.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn pack_file(&mut self, path: &Path, options: &PackOptions) -> io::Result<Packed> {
+        let file_type = options.file_type_of(path);
+        let mut data = file::read_content(path)?;
+        if options.begin.is_none() && ihex::has_extension(path) {
+            data = ihex::to_bin(&data)?;
+        }
+        let (name, truncated) = match (file_type, options.name) {
+            (FileType::Custom, _) => (None, false),
+            (_, Some(name)) => (Some(name), false),
+            _ => {
+                let (name, truncated) = file::file_name_of(path)?;
+                (Some(name), truncated)
+            }
+        };
+        let padding = match (file_type, name) {
+            (FileType::Bin, Some(name)) => match options.begin {
+                Some(begin) => {
+                    let start = options.start.unwrap_or(begin);
+                    self.append_bin_with_addresses(&name, begin, start, &data)?
+                }
+                None => self.append_bin(&name, &data)?,
+            },
+            (FileType::Basic, Some(name)) => {
+                if basic::is_source(&data) {
+                    if options.utf8 {
+                        data = encoding::from_utf8(&data)?;
+                    }
+                    data = basic::tokenize(&data).map_err(|mut errors| errors.swap_remove(0))?;
+                }
+                self.append_basic(&name, &data)?
+            }
+            (FileType::Ascii, Some(name)) => {
+                if options.utf8 {
+                    let eol = options.eol.unwrap_or(LineEnding::CrLf);
+                    data = text::convert_line_endings(&encoding::from_utf8(&data)?, eol);
+                } else if let Some(eol) = options.eol {
+                    data = text::convert_line_endings(&data, eol);
+                }
+                self.append_ascii(&name, &data)?
+            }
+            _ => self.append_custom(&data)?,
+        };
+        Ok(Packed {
+            path: path.to_path_buf(),
+            file_type,
+            name,
+            truncated,
+            padding,
+        })
+    }
+
+    /// Build a tape with the files found in the given directory.
+    ///
+    /// The files returned by `pack_entries()` are packed in order as `pack_file()` does.
+    /// Returns the tape along with the manifest of the packed files. Errors are prefixed
+    /// with the path of the offending file.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn pack_dir(dir: &Path, options: &PackOptions) -> io::Result<(Tape, Vec<Packed>)> {
+        let mut tape = Tape::new();
+        let mut manifest = vec![];
+        for path in pack_entries(dir)? {
+            let packed = tape
+                .pack_file(&path, options)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            manifest.push(packed);
+        }
+        Ok((tape, manifest))
+    }
+
+    /// Extract the files of this tape into the given directory.
+    ///
+    /// Named files are written as `<name>.<ext>` and custom files as `custom.000`,
+    /// `custom.001`, etc. Characters that are not valid in host file names are replaced
+    /// by `_`. Existing files are never overwritten: a `-1`, `-2`... suffix is added to
+    /// the name instead (see `Extracted::clash`).
+    ///
+    /// With `ExtractOptions::numbered`, names are prefixed with the position of the file
+    /// in the tape, starting at 1 and with at least two digits (e.g., `01_loader.bas`,
+    /// `02_custom.000`), so the loading order is kept when the files are listed.
+    ///
+    /// Extraction continues after a file fails to be written, so every file of the tape
+    /// gets its own entry in the returned list. Headers without data (`File::Header`)
+    /// have no contents to write, so they are skipped.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn extract_to_dir(&self, dir: &Path, options: &ExtractOptions) -> Vec<Extracted> {
+        self.extract_to_dir_with_progress(dir, options, &mut progress::ignore())
+    }
+
+    /// Extract the files of this tape as `extract_to_dir()` does, reporting the number of
+    /// files extracted so far out of the total.
+    ///
+    /// If the observer is cancelled, the extraction stops and only the files extracted so
+    /// far are returned.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn extract_to_dir_with_progress(
+        &self,
+        dir: &Path,
+        options: &ExtractOptions,
+        progress: &mut dyn Progress,
+    ) -> Vec<Extracted> {
+        let indices: Vec<usize> = (0..self.files().count()).collect();
+        self.extract_files_to_dir(&indices, dir, options, progress)
+    }
+
+    /// Extract the files of this tape at the given indices as `extract_to_dir()` does.
+    ///
+    /// Files are extracted in the order of `indices`, and numbered names keep the position
+    /// of the files in the whole tape. Indices beyond the last file are ignored. The
+    /// progress counts the files extracted so far out of those selected.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn extract_files_to_dir(
+        &self,
+        indices: &[usize],
+        dir: &Path,
+        options: &ExtractOptions,
+        progress: &mut dyn Progress,
+    ) -> Vec<Extracted> {
+        let mut names = host_names(self);
+        if options.numbered {
+            let width = names.len().to_string().len().max(2);
+            for (index, name) in names.iter_mut().enumerate() {
+                *name = format!("{:0width$}_{}", index + 1, name, width = width);
+            }
+        }
+        let files: Vec<File> = self.files().collect();
+        let selected: Vec<usize> = indices
+            .iter()
+            .copied()
+            .filter(|i| *i < files.len() && !matches!(files[*i], File::Header(..)))
+            .collect();
+        let total = selected.len() as u64;
+        let mut extracted = vec![];
+        for index in selected {
+            let (file, name) = (&files[index], names[index].clone());
+            let desired = dir.join(&name);
+            let (path, clash, result) = match file::unique_filename(&desired) {
+                Ok((path, clash)) => {
+                    let result = extracted_file_bytes(file, options)
+                        .and_then(|bytes| fs::write(&path, bytes));
+                    (path, clash, result)
+                }
+                Err(e) => (desired, false, Err(e)),
+            };
+            extracted.push(Extracted {
+                index,
+                name,
+                path,
+                clash,
+                result,
+            });
+            progress.update(extracted.len() as u64, Some(total));
+            if progress.is_cancelled() {
+                break;
+            }
+        }
+        extracted
+    }
+
+    /// Write every file of this tape as a tape of its own into the given directory.
+    ///
+    /// The tapes are named after the files, numbered as `ExtractOptions::numbered` does and
+    /// with `cas` extension (e.g., `01_loader.cas` or `02_custom.cas`). Files whose name
+    /// already exists are written to a different path, as `extract_to_dir()` does.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn split_to_dir(&self, dir: &Path) -> Vec<Extracted> {
+        let names = host_names(self);
+        let width = names.len().to_string().len().max(2);
+        let mut split = vec![];
+        for (index, name) in names.iter().enumerate() {
+            let stem = name
+                .rsplit_once('.')
+                .map_or(name.as_str(), |(stem, _)| stem);
+            let name = format!("{:0width$}_{}.cas", index + 1, stem, width = width);
+            let desired = dir.join(&name);
+            let (path, clash, result) = match file::unique_filename(&desired) {
+                Ok((path, clash)) => {
+                    let mut tape = Tape::new();
+                    let result = tape
+                        .append_file_from(self, index)
+                        .and_then(|_| fs::write(&path, tape.to_bytes()));
+                    (path, clash, result)
+                }
+                Err(e) => (desired, false, Err(e)),
+            };
+            split.push(Extracted {
+                index,
+                name,
+                path,
+                clash,
+                result,
+            });
+        }
+        split
+    }
+
+    /// Returns the first file of this tape named `name`, along with its index.
+    ///
+    /// Files are named as `extract_to_dir()` would name them (e.g., `game.bin` or
+    /// `custom.000`), or by their name in the tape without extension (e.g., `GAME`).
+    /// Names are compared ignoring the case of letters and the surrounding blanks.
+    ///
+    pub fn find(&self, name: &str) -> Option<(usize, File<'_>)> {
+        let index = *self.named(name).first()?;
+        self.files().nth(index).map(|file| (index, file))
+    }
+
+    /// Returns the indices of the files of this tape selected by the given pattern.
+    ///
+    /// The pattern is either the index of a file (starting at 0), a name as `find()`
+    /// takes, which selects every file with that name, or a glob pattern matched against
+    /// the names given by `extract_to_dir()` ignoring the case of letters, where `*`
+    /// stands for any text and `?` for any character (e.g., `*.bin`).
+    ///
+    pub fn select(&self, pattern: &str) -> Vec<usize> {
+        let pattern = pattern.trim();
+        let names = host_names(self);
+        if let Ok(index) = pattern.parse::<usize>() {
+            return if index < names.len() {
+                vec![index]
+            } else {
+                vec![]
+            };
+        }
+        if !pattern.contains(['*', '?']) {
+            return self.named(pattern);
+        }
+        let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+        names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| {
+                let name: Vec<char> = name.to_lowercase().chars().collect();
+                glob_match(&pattern, &name)
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the indices of the files of this tape named `name` (see `find()`)
+    fn named(&self, name: &str) -> Vec<usize> {
+        let name = name.trim();
+        self.files()
+            .zip(host_names(self))
+            .enumerate()
+            .filter(|(_, (file, host_name))| {
+                let stem = match file {
+                    File::Bin(stem, ..)
+                    | File::Basic(stem, _)
+                    | File::Ascii(stem, _)
+                    | File::Header(stem, _) => stem,
+                    File::Custom(_) => "",
+                };
+                host_name.eq_ignore_ascii_case(name)
+                    || !stem.trim().is_empty() && stem.trim().eq_ignore_ascii_case(name)
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the files of this tape with the contents of the given fingerprint.
+    ///
+    /// Each file is given by its index and the name `extract_to_dir()` would give it.
+    ///
+    pub fn find_fingerprint(&self, fingerprint: &Fingerprint) -> Vec<(usize, String)> {
+        self.files()
+            .zip(host_names(self))
+            .enumerate()
+            .filter(|(_, (file, _))| fingerprint.matches(file))
+            .map(|(index, (_, name))| (index, name))
+            .collect()
+    }
+
+    /// Compare the files of this tape with the same-named files of the given directory.
+    ///
+    /// Host files are looked up by the names `extract_to_dir()` would give them. Custom
+    /// files have no name, so if there is no `custom.NNN` file, the n-th custom file is
+    /// compared with the n-th file of the directory that `pack_dir()` would pack as
+    /// custom. Both sides are normalized before comparing (see `normalized_bytes()`), so
+    /// a directory of sources compares equal to the tape packed from it.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn compare_to_dir(&self, dir: &Path) -> Vec<Compared> {
+        let mut sources = pack_entries(dir)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| file::file_type_of(path) == FileType::Custom);
+        let mut compared = vec![];
+        for ((index, file), name) in self.files().enumerate().zip(host_names(self)) {
+            let file_type = file.file_type();
+            let mut path = dir.join(&name);
+            if file_type == FileType::Custom {
+                let source = sources.next();
+                if !path.exists() {
+                    path = source.unwrap_or(path);
+                }
+            }
+            let result = match fs::read(&path) {
+                Ok(host) => {
+                    let expected = normalized_bytes(file_type, &extracted_bytes(&file, None));
+                    let actual = normalized_bytes(file_type, &host);
+                    Ok(compare(&expected, &actual))
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Comparison::Missing),
+                Err(e) => Err(e),
+            };
+            compared.push(Compared {
+                index,
+                name,
+                path,
+                result,
+            });
+        }
+        compared
+    }
+}
+
+/// Returns the files of the given directory to be packed, in alphabetical order
+///
+/// Subdirectories and hidden files (those whose name starts with `.`) are skipped.
+///
+#[cfg(feature = "fs")]
+pub fn pack_entries(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Returns the contents of the given file as they are extracted to the host
+///
+/// Binary files are prefixed by the `0xfe` ID byte not present in tapes and include the
+/// address header, so they can be loaded with `BLOAD` from disk. The line endings of
+/// ASCII files are converted if `eol` is given. The rest of files are extracted as their
+/// payload.
+///
+pub fn extracted_bytes<'a>(file: &File<'a>, eol: Option<LineEnding>) -> Cow<'a, [u8]> {
+    match (file, eol) {
+        (File::Bin(..), _) => Cow::Owned([&[0xfe], &file.raw_data()[..]].concat()),
+        (File::Ascii(..), Some(eol)) => {
+            Cow::Owned(text::convert_line_endings(&file.payload(), eol))
+        }
+        _ => file.payload(),
+    }
+}
+
+/// Returns the contents of the given file as `extract_to_dir()` writes them
+///
+/// With `ExtractOptions::as_text`, tokenized Basic programs are detokenized, and fail
+/// with an error of kind `InvalidData` if they are malformed. Listings have `Lf` line
+/// endings unless `ExtractOptions::eol` says otherwise.
+///
+/// With `ExtractOptions::utf8`, ASCII files and listings are converted to UTF-8 and get
+/// `Lf` line endings unless `ExtractOptions::eol` says otherwise. The EOF padding of
+/// ASCII files is not part of their payload, so it is never written.
+///
+#[cfg(feature = "fs")]
+fn extracted_file_bytes<'a>(
+    file: &File<'a>,
+    options: &ExtractOptions,
+) -> io::Result<Cow<'a, [u8]>> {
+    let text = match file {
+        File::Basic(_, program) if options.as_text => basic::detokenize(program)?,
+        File::Ascii(..) if options.utf8 => file.payload().into_owned(),
+        _ => return Ok(extracted_bytes(file, options.eol)),
+    };
+    Ok(Cow::Owned(match (options.utf8, options.eol) {
+        (true, eol) => {
+            let text = encoding::to_utf8(&text);
+            text::convert_line_endings(text.as_bytes(), eol.unwrap_or(LineEnding::Lf))
+        }
+        (false, Some(eol)) => text::convert_line_endings(&text, eol),
+        (false, None) => text,
+    }))
+}
+
+/// Returns the given file contents in the form used to compare tapes and host files
+///
+/// The contents may come from either side, so the differences that `mcp add` and `mcp
+/// extract` introduce or tolerate are removed:
+///
+/// * Binary files lose the `0xfe` ID byte and anything beyond the end address.
+/// * Tokenized Basic files lose the `0xff` ID byte of disk files and the trailing zeros.
+///   Basic source text is tokenized first, as long as it has no syntax errors.
+/// * ASCII files are cut at the first EOF character (`0x1a`) and their line endings are
+///   converted to `Lf`. UTF-8 text is converted to the 8-bit charset first, as long as
+///   it has no characters beyond `U+00FF`.
+/// * Custom files lose the trailing zeros added as padding.
+///
+pub fn normalized_bytes(file_type: FileType, data: &[u8]) -> Vec<u8> {
+    match file_type {
+        FileType::Bin => {
+            let data = data.strip_prefix(&[0xfe]).unwrap_or(data);
+            let len = match data {
+                [b0, b1, e0, e1, ..] => {
+                    let begin = u16::from_le_bytes([*b0, *b1]) as usize;
+                    let end = u16::from_le_bytes([*e0, *e1]) as usize;
+                    6 + end.saturating_sub(begin) + 1
+                }
+                _ => data.len(),
+            };
+            data[..len.min(data.len())].to_vec()
+        }
+        FileType::Basic => {
+            let program = Some(data)
+                .filter(|data| basic::is_source(data))
+                .and_then(|source| basic::tokenize(source).ok());
+            let data = match program {
+                Some(program) => Cow::Owned(program),
+                None => Cow::Borrowed(data.strip_prefix(&[0xff]).unwrap_or(data)),
+            };
+            trim_zeros(&data).to_vec()
+        }
+        FileType::Ascii => {
+            let data = match std::str::from_utf8(data) {
+                Ok(text) if text.chars().all(|c| (c as u32) < 0x100) => {
+                    Cow::Owned(text.chars().map(|c| c as u8).collect())
+                }
+                _ => Cow::Borrowed(data),
+            };
+            let end = data.iter().position(|b| *b == 0x1a).unwrap_or(data.len());
+            text::convert_line_endings(&data[..end], LineEnding::Lf)
+        }
+        FileType::Custom => trim_zeros(data).to_vec(),
+    }
+}
+
+fn trim_zeros(data: &[u8]) -> &[u8] {
+    let len = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    &data[..len]
+}
+
+/// Compare two normalized contents
+#[cfg(feature = "fs")]
+fn compare(expected: &[u8], actual: &[u8]) -> Comparison {
+    match expected.iter().zip(actual).position(|(a, b)| a != b) {
+        Some(offset) => Comparison::Different(offset),
+        None if expected.len() != actual.len() => {
+            Comparison::Different(expected.len().min(actual.len()))
+        }
+        None => Comparison::Same,
+    }
+}
+
+/// Returns the host file names of the files of the given tape
+///
+/// Named files are named `<name>.<ext>` and custom files `custom.000`, `custom.001`, etc.
+///
+fn host_names(tape: &Tape) -> Vec<String> {
+    let mut next_custom = 0;
+    tape.files()
+        .map(|file| match file.name() {
+            Some(name) => host_name(&name),
+            None => {
+                let name = format!("custom.{:03}", next_custom);
+                next_custom += 1;
+                name
+            }
+        })
+        .collect()
+}
+
+/// Returns `true` if `text` matches the glob `pattern`, where `*` matches any sequence of
+/// characters and `?` any single character
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        Some((p, rest)) => match text.split_first() {
+            Some((c, text)) if *p == '?' || p == c => glob_match(rest, text),
+            _ => false,
+        },
+    }
+}
+
+/// Replace the characters of a tape file name that are not valid in host file names
+fn host_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod test {
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::tape;
+
+    #[test]
+    fn should_pack_dir() {
+        let dir = TempDir::new("mcp").unwrap();
+        fs::write(dir.path().join("b-loader.bas"), [0x00, 0x00]).unwrap();
+        fs::write(dir.path().join("a-longname.asc"), b"10 END\n").unwrap();
+        fs::write(dir.path().join("c.dat"), [1, 2, 3]).unwrap();
+        fs::write(dir.path().join("d.ihx"), b":02C00000AF3C53\n:00000001FF\n").unwrap();
+        fs::write(dir.path().join(".hidden"), [1]).unwrap();
+        fs::create_dir(dir.path().join("subdir")).unwrap();
+
+        let options = PackOptions {
+            eol: Some(LineEnding::CrLf),
+            ..PackOptions::default()
+        };
+        let (tape, manifest) = Tape::pack_dir(dir.path(), &options).unwrap();
+        assert_eq!(
+            vec![
+                FileType::Ascii,
+                FileType::Basic,
+                FileType::Custom,
+                FileType::Bin
+            ],
+            manifest.iter().map(|p| p.file_type).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(*b"a-long"), manifest[0].name);
+        assert!(manifest[0].truncated);
+        assert_eq!(Some(*b"b-load"), manifest[1].name);
+        assert_eq!(None, manifest[2].name);
+        assert_eq!(5, manifest[2].padding);
+
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(4, files.len());
+        assert_eq!(b"10 END\r\n", &files[0].payload()[..]);
+        assert_eq!(
+            File::Bin(
+                "d".to_string(),
+                0xc000,
+                0xc001,
+                0xc000,
+                &[0x00, 0xc0, 0x01, 0xc0, 0x00, 0xc0, 0xaf, 0x3c]
+            ),
+            files[3]
+        );
+
+        fs::write(dir.path().join("bad.bin"), [0x00]).unwrap();
+        let err = Tape::pack_dir(dir.path(), &options).unwrap_err();
+        assert!(err.to_string().contains("bad.bin"));
+    }
+
+    #[test]
+    fn should_extract_to_dir() {
+        let dir = TempDir::new("mcp").unwrap();
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9])
+            .unwrap();
+        let (name, _) = tape::file_name("a/b:c");
+        tape.append_ascii(&name, b"10 END\n").unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        fs::write(dir.path().join("game.bin"), b"existing").unwrap();
+
+        let options = ExtractOptions {
+            eol: Some(LineEnding::CrLf),
+            ..ExtractOptions::default()
+        };
+        let mut updates = vec![];
+        let extracted =
+            tape.extract_to_dir_with_progress(dir.path(), &options, &mut |done, total| {
+                updates.push((done, total))
+            });
+        assert_eq!(vec![(1, Some(3)), (2, Some(3)), (3, Some(3))], updates);
+        assert_eq!(3, extracted.len());
+        assert!(extracted.iter().all(|e| e.result.is_ok()));
+
+        assert_eq!("game.bin", extracted[0].name);
+        assert!(extracted[0].clash);
+        assert_eq!(dir.path().join("game-1.bin"), extracted[0].path);
+        assert_eq!(
+            vec![0xfe, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9, 0x00],
+            fs::read(&extracted[0].path).unwrap()
+        );
+
+        assert_eq!("a_b_c.asc", extracted[1].name);
+        assert_eq!(b"10 END\r\n", &fs::read(&extracted[1].path).unwrap()[..]);
+
+        assert_eq!("custom.000", extracted[2].name);
+        assert!(!extracted[2].clash);
+        assert_eq!(8, fs::read(&extracted[2].path).unwrap().len());
+
+        let options = ExtractOptions {
+            numbered: true,
+            ..ExtractOptions::default()
+        };
+        let extracted = tape.extract_to_dir(dir.path(), &options);
+        let names: Vec<&str> = extracted.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(vec!["01_game.bin", "02_a_b_c.asc", "03_custom.000"], names);
+        assert!(extracted.iter().all(|e| !e.clash));
+        assert_eq!(b"10 END\n", &fs::read(&extracted[1].path).unwrap()[..]);
+    }
+
+    #[test]
+    fn should_split_to_dir() {
+        let dir = TempDir::new("mcp").unwrap();
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9])
+            .unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        fs::write(dir.path().join("01_game.cas"), b"existing").unwrap();
+
+        let split = tape.split_to_dir(dir.path());
+        let names: Vec<&str> = split.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(vec!["01_game.cas", "02_custom.cas"], names);
+        assert!(split.iter().all(|e| e.result.is_ok()));
+        assert!(split[0].clash);
+
+        let game = Tape::from_file(&split[0].path).unwrap();
+        assert_eq!(tape.files().next(), game.files().next());
+        assert_eq!(1, game.files().count());
+        let custom = Tape::from_file(&split[1].path).unwrap();
+        assert_eq!(
+            vec![File::Custom(&[1, 2, 3, 0, 0, 0, 0, 0])],
+            custom.files().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_pack_basic_source() {
+        let dir = TempDir::new("mcp").unwrap();
+        let source = b"20 GOTO 10\r\n10 PRINT \"HI\"\r\n";
+        fs::write(dir.path().join("game.bas"), source).unwrap();
+        fs::write(dir.path().join("bad.bas"), b"10 PRINT\nPRINT\n").unwrap();
+
+        let mut tape = Tape::new();
+        let options = PackOptions::default();
+        let packed = tape
+            .pack_file(&dir.path().join("game.bas"), &options)
+            .unwrap();
+        assert_eq!(FileType::Basic, packed.file_type);
+        let program = basic::tokenize(source).unwrap();
+        assert_eq!(
+            program,
+            tape.files().next().unwrap().payload()[..program.len()]
+        );
+        let err = tape
+            .pack_file(&dir.path().join("bad.bas"), &options)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert_eq!("line 2: missing line number", err.to_string());
+
+        let compared = tape.compare_to_dir(dir.path());
+        assert_eq!(Comparison::Same, *compared[0].result.as_ref().unwrap());
+    }
+
+    #[test]
+    fn should_pack_raw_binary() {
+        let dir = TempDir::new("mcp").unwrap();
+        fs::write(dir.path().join("code.raw"), [0xaf, 0xc9]).unwrap();
+
+        let mut tape = Tape::new();
+        let options = PackOptions {
+            begin: Some(0xc000),
+            start: Some(0xc001),
+            ..PackOptions::default()
+        };
+        let packed = tape
+            .pack_file(&dir.path().join("code.raw"), &options)
+            .unwrap();
+        assert_eq!(FileType::Bin, packed.file_type);
+        assert_eq!(
+            Some(File::Bin(
+                "code".to_string(),
+                0xc000,
+                0xc001,
+                0xc001,
+                &[0x00, 0xc0, 0x01, 0xc0, 0x01, 0xc0, 0xaf, 0xc9]
+            )),
+            tape.files().next()
+        );
+    }
+
+    #[test]
+    fn should_select_files() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_ascii(&name, b"10 END\n").unwrap();
+        tape.append_bin(&name, &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9])
+            .unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        tape.append_ascii(&name, b"20 END\n").unwrap();
+
+        assert_eq!(Some(1), tape.find("GAME.BIN").map(|(i, _)| i));
+        assert_eq!(Some(0), tape.find(" game ").map(|(i, _)| i));
+        assert_eq!(Some(2), tape.find("custom.000").map(|(i, _)| i));
+        assert!(tape.find("other").is_none());
+
+        assert_eq!(vec![0, 1, 3], tape.select("game"));
+        assert_eq!(vec![0, 3], tape.select("game.asc"));
+        assert_eq!(vec![2], tape.select("2"));
+        assert!(tape.select("4").is_empty());
+        assert_eq!(vec![1], tape.select("*.BIN"));
+        assert_eq!(vec![0, 3], tape.select("game.a?c"));
+        assert_eq!(vec![2], tape.select("c*"));
+
+        let dir = TempDir::new("mcp").unwrap();
+        let options = ExtractOptions {
+            numbered: true,
+            ..ExtractOptions::default()
+        };
+        let extracted =
+            tape.extract_files_to_dir(&[3, 9], dir.path(), &options, &mut progress::ignore());
+        assert_eq!(1, extracted.len());
+        assert_eq!("04_game.asc", extracted[0].name);
+        assert_eq!(b"20 END\n", &fs::read(&extracted[0].path).unwrap()[..]);
+    }
+
+    #[test]
+    fn should_extract_basic_as_text() {
+        let dir = TempDir::new("mcp").unwrap();
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        let program = basic::tokenize(b"10 PRINT \"HI\"\n20 GOTO 10\n").unwrap();
+        tape.append_basic(&name, &program).unwrap();
+        let (name, _) = tape::file_name("bad");
+        tape.append_basic(&name, &[0x10, 0x80, 0x0a, 0x00, 0x91, 0x00, 0x20, 0x80])
+            .unwrap();
+
+        let options = ExtractOptions {
+            eol: Some(LineEnding::CrLf),
+            as_text: true,
+            ..ExtractOptions::default()
+        };
+        let extracted = tape.extract_to_dir(dir.path(), &options);
+        assert_eq!("game.bas", extracted[0].name);
+        assert_eq!(
+            b"10 PRINT \"HI\"\r\n20 GOTO 10\r\n",
+            &fs::read(&extracted[0].path).unwrap()[..]
+        );
+        let err = extracted[1].result.as_ref().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn should_extract_and_pack_utf8() {
+        let dir = TempDir::new("mcp").unwrap();
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("menu");
+        tape.append_ascii(&name, b"10 PRINT \"Ni\xa4o\"\r\n")
+            .unwrap();
+
+        let options = ExtractOptions {
+            utf8: true,
+            ..ExtractOptions::default()
+        };
+        let extracted = tape.extract_to_dir(dir.path(), &options);
+        let path = &extracted[0].path;
+        assert_eq!("10 PRINT \"Niño\"\n", fs::read_to_string(path).unwrap());
+
+        let options = PackOptions {
+            utf8: true,
+            ..PackOptions::default()
+        };
+        let mut packed = Tape::new();
+        packed.pack_file(path, &options).unwrap();
+        assert_eq!(
+            tape.files().next().unwrap().payload(),
+            packed.files().next().unwrap().payload()
+        );
+
+        fs::write(path, "10 PRINT \"€\"\n").unwrap();
+        let err = packed.pack_file(path, &options).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn should_compare_to_dir() {
+        let dir = TempDir::new("mcp").unwrap();
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9])
+            .unwrap();
+        let (name, _) = tape::file_name("menu");
+        tape.append_ascii(&name, b"10 PRINT \"\xa0\"\r\n").unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        tape.append_custom(&[4, 5, 6]).unwrap();
+
+        fs::write(
+            dir.path().join("game.bin"),
+            [0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9],
+        )
+        .unwrap();
+        fs::write(dir.path().join("menu.asc"), "10 PRINT \"\u{a0}\"\n").unwrap();
+        fs::write(dir.path().join("custom.000"), [1, 2, 4]).unwrap();
+
+        let compared = tape.compare_to_dir(dir.path());
+        assert_eq!(dir.path().join("custom.000"), compared[2].path);
+        let results: Vec<Comparison> = compared.into_iter().map(|c| c.result.unwrap()).collect();
+        assert_eq!(
+            vec![
+                Comparison::Same,
+                Comparison::Same,
+                Comparison::Different(2),
+                Comparison::Missing
+            ],
+            results
+        );
+
+        fs::remove_file(dir.path().join("custom.000")).unwrap();
+        fs::write(dir.path().join("a.dat"), [1, 2, 3]).unwrap();
+        fs::write(dir.path().join("b.dat"), [4, 5, 6, 0]).unwrap();
+        let compared = tape.compare_to_dir(dir.path());
+        assert_eq!(dir.path().join("b.dat"), compared[3].path);
+        assert!(compared
+            .iter()
+            .all(|c| c.result.as_ref().unwrap() == &Comparison::Same));
+    }
+
+    #[test]
+    fn should_find_fingerprint() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("menu");
+        tape.append_ascii(&name, b"10 PRINT\r\n").unwrap();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9])
+            .unwrap();
+        tape.append_custom(&[0xc9]).unwrap();
+
+        let game = Fingerprint::of(&[0xfe, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9]);
+        assert_eq!(
+            vec![(1, "game.bin".to_string())],
+            tape.find_fingerprint(&game)
+        );
+        let menu = Fingerprint::of(b"10 PRINT\n");
+        assert_eq!(
+            vec![(0, "menu.asc".to_string())],
+            tape.find_fingerprint(&menu)
+        );
+        let custom = Fingerprint::of(&[0xc9]);
+        assert_eq!(
+            vec![(2, "custom.000".to_string())],
+            tape.find_fingerprint(&custom)
+        );
+        assert!(tape
+            .find_fingerprint(&Fingerprint::of(b"10 END\n"))
+            .is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Checks of the environment `mcp` runs in, as reported by `mcp doctor`
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// The outcome of a check
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Some feature may not work, but `mcp` is usable
+    Warning,
+    /// Some feature is broken
+    Error,
+}
+
+impl Status {
+    /// Returns the name of this status: `ok`, `warning` or `error`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        }
+    }
+}
+
+/// The result of checking one aspect of the environment
+#[derive(Debug, PartialEq)]
+pub struct Check {
+    /// What was checked (e.g. `editor`)
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+    /// How to fix the problem found, if any
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, message: String) -> Check {
+        Check {
+            name,
+            status: Status::Ok,
+            message,
+            fix: None,
+        }
+    }
+
+    fn problem(name: &'static str, status: Status, message: String, fix: String) -> Check {
+        Check {
+            name,
+            status,
+            message,
+            fix: Some(fix),
+        }
+    }
+}
+
+/// Run all the checks.
+pub fn run() -> Vec<Check> {
+    vec![
+        check_temp_dir(),
+        check_editor(),
+        check_openmsx(),
+        check_player(),
+        check_recorder(),
+        check_external_commands(),
+    ]
+}
+
+/// Returns the editor command line used by `mcp edit`
+pub fn editor() -> String {
+    env::var("EDITOR").unwrap_or_else(|_| "vi".to_string())
+}
+
+/// The audio players `mcp play` pipes samples to, in order of preference
+///
+/// Each one is given the arguments to read 8-bit unsigned mono samples from its standard
+/// input, at the sample rate that replaces `{rate}`.
+///
+const PLAYERS: [(&str, &str); 3] = [
+    ("aplay", "-q -t raw -f U8 -c 1 -r {rate} -"),
+    ("paplay", "--raw --format=u8 --channels=1 --rate={rate}"),
+    (
+        "play",
+        "-q -t raw -e unsigned-integer -b 8 -c 1 -r {rate} -",
+    ),
+];
+
+/// Returns the path and arguments of the audio player used by `mcp play`, if any
+pub fn player(sample_rate: u32) -> Option<(PathBuf, Vec<String>)> {
+    PLAYERS.iter().find_map(|(program, args)| {
+        let args = args
+            .replace("{rate}", &sample_rate.to_string())
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        find_program(program).map(|path| (path, args))
+    })
+}
+
+/// The audio recorders `mcp record` reads samples from, in order of preference
+///
+/// Each one is given the arguments to write 8-bit unsigned mono samples to its standard
+/// output, at the sample rate that replaces `{rate}`, and the argument that selects the
+/// input device that replaces `{device}`.
+///
+const RECORDERS: [(&str, &str, &str); 2] = [
+    ("arecord", "-q -t raw -f U8 -c 1 -r {rate}", "-D {device}"),
+    (
+        "parec",
+        "--raw --format=u8 --channels=1 --rate={rate}",
+        "--device={device}",
+    ),
+];
+
+/// Returns the path and arguments of the audio recorder used by `mcp record`, if any
+///
+/// Without a `device`, the recorder reads from its default input.
+///
+pub fn recorder(sample_rate: u32, device: Option<&str>) -> Option<(PathBuf, Vec<String>)> {
+    RECORDERS.iter().find_map(|(program, args, device_args)| {
+        let mut args: Vec<String> = args
+            .replace("{rate}", &sample_rate.to_string())
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        if let Some(device) = device {
+            // The device may have spaces, so it is replaced once the arguments are split
+            args.extend(
+                device_args
+                    .split_whitespace()
+                    .map(|a| a.replace("{device}", device)),
+            );
+        }
+        find_program(program).map(|path| (path, args))
+    })
+}
+
+/// Returns the path of the given program found in `PATH`, if any
+///
+/// Programs given as a path (e.g. `./edit.sh`) are not looked up in `PATH`.
+///
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains(std::path::MAIN_SEPARATOR) {
+        let path = PathBuf::from(program);
+        return Some(path).filter(|p| p.is_file());
+    }
+    env::split_paths(&env::var_os("PATH").unwrap_or_default())
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// Check that temporary files can be created, as `mcp edit` does
+fn check_temp_dir() -> Check {
+    let dir = env::temp_dir();
+    let path = dir.join(format!("mcp-doctor-{}", process::id()));
+    match fs::write(&path, b"").and_then(|_| fs::remove_file(&path)) {
+        Ok(()) => Check::ok("temporary files", format!("{:?} is writable", dir)),
+        Err(e) => Check::problem(
+            "temporary files",
+            Status::Error,
+            format!("cannot write to {:?}: {}", dir, e),
+            "set TMPDIR to a writable directory".to_string(),
+        ),
+    }
+}
+
+/// Check the editor used by `mcp edit`
+fn check_editor() -> Check {
+    let editor = editor();
+    let program = editor.split_whitespace().next().unwrap_or_default();
+    match (env::var_os("EDITOR"), find_program(program)) {
+        (Some(_), Some(path)) => Check::ok("editor", format!("{:?} found at {:?}", editor, path)),
+        (Some(_), None) => Check::problem(
+            "editor",
+            Status::Error,
+            format!("editor {:?} not found", editor),
+            "set EDITOR to an installed editor, or add it to PATH".to_string(),
+        ),
+        (None, found) => Check::problem(
+            "editor",
+            if found.is_some() {
+                Status::Warning
+            } else {
+                Status::Error
+            },
+            format!(
+                "EDITOR is not set, `mcp edit` will use vi{}",
+                if found.is_some() { "" } else { " (not found)" }
+            ),
+            "set EDITOR to the editor of your choice (e.g. `export EDITOR=nano`)".to_string(),
+        ),
+    }
+}
+
+/// Check that a running openMSX can be controlled, as `mcp openmsx` does
+#[cfg(unix)]
+fn check_openmsx() -> Check {
+    use mcp::openmsx::{self, Connection};
+
+    let dir = openmsx::socket_dir();
+    let sockets = fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_name().to_string_lossy().starts_with("socket."))
+                .count()
+        })
+        .unwrap_or(0);
+    match Connection::find() {
+        Ok(_) => Check::ok("openMSX", format!("running openMSX found in {:?}", dir)),
+        Err(_) if sockets > 0 => Check::problem(
+            "openMSX",
+            Status::Warning,
+            format!(
+                "{} control sockets in {:?}, but no running openMSX accepts connections",
+                sockets, dir
+            ),
+            format!(
+                "remove the stale sockets in {:?} and start openMSX again",
+                dir
+            ),
+        ),
+        Err(_) => Check::problem(
+            "openMSX",
+            Status::Warning,
+            "no running openMSX found, `mcp openmsx` will not work".to_string(),
+            "start openMSX before running `mcp openmsx` commands".to_string(),
+        ),
+    }
+}
+
+#[cfg(not(unix))]
+fn check_openmsx() -> Check {
+    Check::problem(
+        "openMSX",
+        Status::Warning,
+        "openMSX remote control is only supported on Unix systems".to_string(),
+        "load the CAS files from the openMSX cassette player menu".to_string(),
+    )
+}
+
+/// Check that there is an audio player to pipe samples to, as `mcp play` does
+fn check_player() -> Check {
+    match player(43200) {
+        Some((path, _)) => Check::ok("audio player", format!("{:?}", path)),
+        None => Check::problem(
+            "audio player",
+            Status::Warning,
+            "no audio player found in PATH, `mcp play` will not work".to_string(),
+            "install aplay (ALSA), paplay (PulseAudio) or play (SoX)".to_string(),
+        ),
+    }
+}
+
+/// Check the audio recorder used by `mcp record`
+fn check_recorder() -> Check {
+    match recorder(43200, None) {
+        Some((path, _)) => Check::ok("audio recorder", format!("{:?}", path)),
+        None => Check::problem(
+            "audio recorder",
+            Status::Warning,
+            "no audio recorder found in PATH, `mcp record` will not work".to_string(),
+            "install arecord (ALSA) or parec (PulseAudio)".to_string(),
+        ),
+    }
+}
+
+/// Check the external commands (`mcp-<name>` programs) found in `PATH`
+fn check_external_commands() -> Check {
+    let mut found: Vec<(String, PathBuf)> = vec![];
+    for dir in env::split_paths(&env::var_os("PATH").unwrap_or_default()) {
+        for (name, path) in external_commands_in(&dir) {
+            if !found.iter().any(|(n, _)| *n == name) {
+                found.push((name, path));
+            }
+        }
+    }
+    found.sort();
+    let broken: Vec<&PathBuf> = found
+        .iter()
+        .filter(|(_, path)| !is_executable(path))
+        .map(|(_, path)| path)
+        .collect();
+    if !broken.is_empty() {
+        let paths: Vec<String> = broken.iter().map(|p| format!("{:?}", p)).collect();
+        return Check::problem(
+            "external commands",
+            Status::Warning,
+            format!("not executable: {}", paths.join(", ")),
+            format!("make them executable (e.g. `chmod +x {}`)", paths[0]),
+        );
+    }
+    let names: Vec<&str> = found.iter().map(|(n, _)| n.as_str()).collect();
+    Check::ok(
+        "external commands",
+        if names.is_empty() {
+            "none found in PATH".to_string()
+        } else {
+            names.join(", ")
+        },
+    )
+}
+
+/// Returns the names (without `mcp-` prefix) and paths of the external commands in `dir`
+fn external_commands_in(dir: &Path) -> Vec<(String, PathBuf)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name: OsString = e.file_name();
+            let name = name.to_str()?.strip_prefix("mcp-")?.to_string();
+            Some((name, e.path())).filter(|(_, path)| path.is_file())
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_: &Path) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod test {
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn should_find_external_commands() {
+        let dir = TempDir::new("mcp").unwrap();
+        fs::write(dir.path().join("mcp-foo"), b"").unwrap();
+        fs::write(dir.path().join("other"), b"").unwrap();
+        fs::create_dir(dir.path().join("mcp-dir")).unwrap();
+        assert_eq!(
+            vec![("foo".to_string(), dir.path().join("mcp-foo"))],
+            external_commands_in(dir.path())
+        );
+        assert!(!is_executable(&dir.path().join("mcp-foo")) || cfg!(not(unix)));
+
+        let program = dir.path().join("mcp-foo");
+        assert_eq!(
+            Some(program.clone()),
+            find_program(&program.to_string_lossy())
+        );
+        assert_eq!(
+            None,
+            find_program(&dir.path().join("none").to_string_lossy())
+        );
+    }
+}
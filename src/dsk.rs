@@ -0,0 +1,623 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! MSX-DOS disk images, as read by `mcp import` and written by `mcp export`
+//!
+//! A `Disk` is a FAT12 file system of a DSK image, the raw sectors of a floppy disk as
+//! used by emulators. Only the root directory is supported, which is where MSX-DOS 1
+//! keeps all its files.
+//!
+//! Disk files store the same contents as tape files, with a different header:
+//!
+//! * Binary files start with the `0xfe` ID byte followed by their addresses.
+//! * Tokenized Basic programs start with the `0xff` ID byte.
+//! * ASCII files end with an EOF character (`0x1a`) instead of being padded.
+//!
+//! `disk_bytes()` and `Tape::append_disk_file()` convert between both, so a file that
+//! goes from a tape to a disk and back is the same file.
+
+use std::io;
+
+use crate::tape::{self, File, Tape};
+
+/// The size of the entries of the root directory
+const ENTRY_LEN: usize = 32;
+
+/// The first byte of the name of a deleted directory entry
+const DELETED: u8 = 0xe5;
+
+/// The attributes of volume labels and subdirectories, which are not files
+const NOT_A_FILE: u8 = 0x18;
+
+/// The date of the files written to disks (1980-01-01), so images are reproducible
+const DATE: u16 = 0x0021;
+
+/// The printable characters not allowed in MSX-DOS file names
+const INVALID_CHARS: &[u8] = b"\"*+,./:;<=>?[\\]|";
+
+/// The FAT12 entry that ends a chain of clusters
+const LAST_CLUSTER: u16 = 0xfff;
+
+/// The layout of the file system of a disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Layout {
+    sector_len: usize,
+    cluster_sectors: usize,
+    reserved_sectors: usize,
+    fats: usize,
+    root_entries: usize,
+    sectors: usize,
+    media: u8,
+    fat_sectors: usize,
+}
+
+impl Layout {
+    /// The layout of 720KB double sided disks, the most common format of MSX-DOS
+    const DOUBLE_SIDED: Layout = Layout {
+        sector_len: 512,
+        cluster_sectors: 2,
+        reserved_sectors: 1,
+        fats: 2,
+        root_entries: 112,
+        sectors: 1440,
+        media: 0xf9,
+        fat_sectors: 3,
+    };
+
+    /// The layout of 360KB single sided disks
+    const SINGLE_SIDED: Layout = Layout {
+        sectors: 720,
+        media: 0xf8,
+        fat_sectors: 2,
+        ..Layout::DOUBLE_SIDED
+    };
+
+    /// Returns the layout given by the boot sector of an image, if it is consistent
+    fn from_boot_sector(bytes: &[u8]) -> Option<Layout> {
+        let byte = |i: usize| bytes[i] as usize;
+        let word = |i: usize| byte(i) | (byte(i + 1) << 8);
+        if bytes.len() < 512 {
+            return None;
+        }
+        let layout = Layout {
+            sector_len: word(11),
+            cluster_sectors: byte(13),
+            reserved_sectors: word(14),
+            fats: byte(16),
+            root_entries: word(17),
+            sectors: word(19),
+            media: bytes[21],
+            fat_sectors: word(22),
+        };
+        let valid = matches!(layout.sector_len, 128 | 256 | 512 | 1024)
+            && layout.cluster_sectors.is_power_of_two()
+            && layout.reserved_sectors > 0
+            && layout.fats > 0
+            && layout.root_entries > 0
+            && layout.fat_sectors > 0
+            && layout.data_sector() < layout.sectors
+            && layout.sectors * layout.sector_len <= bytes.len()
+            && layout.clusters() + 2 <= layout.fat_sectors * layout.sector_len * 2 / 3;
+        if valid {
+            Some(layout)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the offset of the first FAT
+    fn fat(&self) -> usize {
+        self.reserved_sectors * self.sector_len
+    }
+
+    /// Returns the offset of the root directory
+    fn root(&self) -> usize {
+        self.fat() + self.fats * self.fat_sectors * self.sector_len
+    }
+
+    /// Returns the first sector of the data area, where cluster 2 starts
+    fn data_sector(&self) -> usize {
+        self.reserved_sectors
+            + self.fats * self.fat_sectors
+            + (self.root_entries * ENTRY_LEN).div_ceil(self.sector_len)
+    }
+
+    /// Returns the number of clusters of the data area
+    fn clusters(&self) -> usize {
+        (self.sectors - self.data_sector()) / self.cluster_sectors
+    }
+
+    /// Returns the number of bytes of a cluster
+    fn cluster_len(&self) -> usize {
+        self.cluster_sectors * self.sector_len
+    }
+
+    /// Returns the offset of the given cluster
+    fn cluster(&self, cluster: u16) -> usize {
+        (self.data_sector() + (cluster as usize - 2) * self.cluster_sectors) * self.sector_len
+    }
+}
+
+/// A file of the root directory of a disk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// The name of the file (e.g., `GAME.BIN`)
+    pub name: String,
+    /// The number of bytes of the file
+    pub size: usize,
+    /// The index of the entry in the root directory
+    index: usize,
+    /// The first cluster of the file, or 0 if it is empty
+    cluster: u16,
+}
+
+/// An MSX-DOS disk image
+#[derive(Clone, Debug)]
+pub struct Disk {
+    bytes: Vec<u8>,
+    layout: Layout,
+}
+
+impl Default for Disk {
+    fn default() -> Disk {
+        Disk::new()
+    }
+}
+
+impl Disk {
+    /// Returns an empty 720KB disk, as formatted by MSX-DOS
+    pub fn new() -> Disk {
+        let layout = Layout::DOUBLE_SIDED;
+        let mut bytes = vec![0; layout.sectors * layout.sector_len];
+        // A jump to itself, which never runs since the disk has no system files
+        bytes[..3].copy_from_slice(&[0xeb, 0xfe, 0x90]);
+        bytes[3..11].copy_from_slice(b"MCP     ");
+        let words = [
+            (11, layout.sector_len),
+            (14, layout.reserved_sectors),
+            (17, layout.root_entries),
+            (19, layout.sectors),
+            (22, layout.fat_sectors),
+            (24, 9),
+            (26, 2),
+        ];
+        for (offset, value) in words.iter() {
+            bytes[*offset..*offset + 2].copy_from_slice(&(*value as u16).to_le_bytes());
+        }
+        bytes[13] = layout.cluster_sectors as u8;
+        bytes[16] = layout.fats as u8;
+        bytes[21] = layout.media;
+        for i in 0..layout.fats {
+            let fat = layout.fat() + i * layout.fat_sectors * layout.sector_len;
+            bytes[fat..fat + 3].copy_from_slice(&[layout.media, 0xff, 0xff]);
+        }
+        Disk { bytes, layout }
+    }
+
+    /// Returns the disk stored in the given DSK image
+    ///
+    /// The layout of the file system is read from the boot sector. Images of 360KB or
+    /// 720KB with no valid boot sector, as written by some early MSX-DOS versions, get the
+    /// layout of their size. Fails with an error of kind `InvalidData` for other images.
+    ///
+    pub fn from_bytes(bytes: Vec<u8>) -> io::Result<Disk> {
+        let layout = Layout::from_boot_sector(&bytes).or_else(|| {
+            [Layout::DOUBLE_SIDED, Layout::SINGLE_SIDED]
+                .iter()
+                .find(|l| l.sectors * l.sector_len == bytes.len())
+                .copied()
+        });
+        match layout {
+            Some(layout) => Ok(Disk { bytes, layout }),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "not an MSX-DOS disk image ({} bytes with no valid boot sector)",
+                    bytes.len()
+                ),
+            )),
+        }
+    }
+
+    /// Returns the bytes of the DSK image of this disk.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the files of the root directory of this disk, in directory order.
+    pub fn entries(&self) -> Vec<Entry> {
+        let root = self.layout.root();
+        let mut entries = vec![];
+        for index in 0..self.layout.root_entries {
+            let entry = &self.bytes[root + index * ENTRY_LEN..root + (index + 1) * ENTRY_LEN];
+            match entry[0] {
+                0x00 => break,
+                DELETED => continue,
+                _ if entry[11] & NOT_A_FILE != 0 => continue,
+                _ => {}
+            }
+            let part = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim_end().to_string();
+            let (stem, ext) = (part(&entry[..8]), part(&entry[8..11]));
+            let name = if ext.is_empty() {
+                stem
+            } else {
+                format!("{}.{}", stem, ext)
+            };
+            entries.push(Entry {
+                name,
+                size: u32::from_le_bytes([entry[28], entry[29], entry[30], entry[31]]) as usize,
+                index,
+                cluster: u16::from_le_bytes([entry[26], entry[27]]),
+            });
+        }
+        entries
+    }
+
+    /// Returns the file of this disk with the given name
+    ///
+    /// Names are compared regardless of their case. Fails with an error of kind `NotFound`
+    /// if there is no such file.
+    ///
+    pub fn entry(&self, name: &str) -> io::Result<Entry> {
+        self.entries()
+            .into_iter()
+            .find(|e| e.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no file named {} in the disk", name),
+                )
+            })
+    }
+
+    /// Returns the contents of the file of this disk with the given name
+    ///
+    /// Names are compared regardless of their case. Fails with an error of kind `NotFound`
+    /// if there is no such file, or `InvalidData` if its chain of clusters is broken.
+    ///
+    pub fn read_file(&self, name: &str) -> io::Result<Vec<u8>> {
+        let entry = self.entry(name)?;
+        let mut data = Vec::with_capacity(entry.size);
+        for cluster in self.chain(entry.cluster)? {
+            let offset = self.layout.cluster(cluster);
+            data.extend_from_slice(&self.bytes[offset..offset + self.layout.cluster_len()]);
+        }
+        if data.len() < entry.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} has {} bytes, but its clusters only hold {}",
+                    entry.name,
+                    entry.size,
+                    data.len()
+                ),
+            ));
+        }
+        data.truncate(entry.size);
+        Ok(data)
+    }
+
+    /// Write a file with the given name and contents to this disk
+    ///
+    /// The name is converted to uppercase, and a file with the same name is replaced.
+    /// Fails with an error of kind `InvalidInput` if the name is not a valid MSX-DOS name
+    /// (up to eight characters, optionally followed by a dot and up to three more), or
+    /// the file does not fit in the free space of the disk. The disk is left untouched if
+    /// the file cannot be written.
+    ///
+    pub fn write_file(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let dir_name = dir_name(name)?;
+        let replaced = match self.entry(name) {
+            Ok(entry) => Some(entry),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let freed = match &replaced {
+            Some(entry) => self.chain(entry.cluster)?,
+            None => vec![],
+        };
+        let needed = data.len().div_ceil(self.layout.cluster_len());
+        let free: Vec<u16> = (2..self.layout.clusters() as u16 + 2)
+            .filter(|c| self.fat_entry(*c) == 0 || freed.contains(c))
+            .take(needed)
+            .collect();
+        if free.len() < needed {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} does not fit in the disk ({} bytes, {} free)",
+                    name,
+                    data.len(),
+                    self.free_space() + freed.len() * self.layout.cluster_len()
+                ),
+            ));
+        }
+        let index = match (&replaced, self.free_entry()) {
+            (Some(entry), _) => entry.index,
+            (None, Some(index)) => index,
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} does not fit in the disk (the directory is full)", name),
+                ))
+            }
+        };
+
+        for cluster in freed {
+            self.set_fat_entry(cluster, 0);
+        }
+        for (i, cluster) in free.iter().enumerate() {
+            let next = free.get(i + 1).copied().unwrap_or(LAST_CLUSTER);
+            self.set_fat_entry(*cluster, next);
+            let chunk = &data[i * self.layout.cluster_len()..];
+            let chunk = &chunk[..chunk.len().min(self.layout.cluster_len())];
+            let offset = self.layout.cluster(*cluster);
+            self.bytes[offset..offset + self.layout.cluster_len()].fill(0);
+            self.bytes[offset..offset + chunk.len()].copy_from_slice(chunk);
+        }
+
+        let offset = self.layout.root() + index * ENTRY_LEN;
+        let entry = &mut self.bytes[offset..offset + ENTRY_LEN];
+        entry.fill(0);
+        entry[..11].copy_from_slice(&dir_name);
+        entry[24..26].copy_from_slice(&DATE.to_le_bytes());
+        entry[26..28].copy_from_slice(&free.first().copied().unwrap_or(0).to_le_bytes());
+        entry[28..32].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        Ok(())
+    }
+
+    /// Returns the number of bytes of the free clusters of this disk.
+    pub fn free_space(&self) -> usize {
+        let free = (2..self.layout.clusters() as u16 + 2)
+            .filter(|c| self.fat_entry(*c) == 0)
+            .count();
+        free * self.layout.cluster_len()
+    }
+
+    /// Returns the index of the first unused entry of the root directory
+    fn free_entry(&self) -> Option<usize> {
+        let root = self.layout.root();
+        (0..self.layout.root_entries)
+            .find(|i| matches!(self.bytes[root + i * ENTRY_LEN], 0x00 | DELETED))
+    }
+
+    /// Returns the clusters of the chain that starts at the given cluster
+    fn chain(&self, first: u16) -> io::Result<Vec<u16>> {
+        let mut chain = vec![];
+        let mut cluster = first;
+        while cluster != 0 && cluster < 0xff8 {
+            if cluster < 2
+                || cluster as usize >= self.layout.clusters() + 2
+                || chain.len() > self.layout.clusters()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("broken chain of clusters at cluster {}", cluster),
+                ));
+            }
+            chain.push(cluster);
+            cluster = self.fat_entry(cluster);
+        }
+        Ok(chain)
+    }
+
+    /// Returns the entry of the first FAT for the given cluster
+    fn fat_entry(&self, cluster: u16) -> u16 {
+        let offset = self.layout.fat() + cluster as usize * 3 / 2;
+        let pair = u16::from_le_bytes([self.bytes[offset], self.bytes[offset + 1]]);
+        if cluster.is_multiple_of(2) {
+            pair & 0xfff
+        } else {
+            pair >> 4
+        }
+    }
+
+    /// Set the entry of every FAT for the given cluster
+    fn set_fat_entry(&mut self, cluster: u16, value: u16) {
+        for i in 0..self.layout.fats {
+            let fat = self.layout.fat() + i * self.layout.fat_sectors * self.layout.sector_len;
+            let offset = fat + cluster as usize * 3 / 2;
+            let pair = u16::from_le_bytes([self.bytes[offset], self.bytes[offset + 1]]);
+            let pair = if cluster.is_multiple_of(2) {
+                (pair & 0xf000) | value
+            } else {
+                (pair & 0x000f) | (value << 4)
+            };
+            self.bytes[offset..offset + 2].copy_from_slice(&pair.to_le_bytes());
+        }
+    }
+}
+
+/// Returns the given file name as stored in directory entries, padded with spaces
+fn dir_name(name: &str) -> io::Result<[u8; 11]> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid MSX-DOS file name {:?}", name),
+        )
+    };
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    let valid = |part: &str, len: usize| {
+        part.len() <= len
+            && part
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && !INVALID_CHARS.contains(&b))
+    };
+    if stem.is_empty() || !valid(stem, 8) || !valid(ext, 3) {
+        return Err(invalid());
+    }
+    let mut dir_name = [b' '; 11];
+    dir_name[..stem.len()].copy_from_slice(stem.to_ascii_uppercase().as_bytes());
+    dir_name[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+    Ok(dir_name)
+}
+
+/// Returns the disk file names of the files of the given tape
+///
+/// Named files are named after their tape name and type (e.g., `GAME.BIN`, `GAME.BAS` or
+/// `GAME.ASC`), with the characters not allowed by MSX-DOS replaced by `_`. Custom files
+/// are named `CUSTOM.000`, `CUSTOM.001`, etc.
+///
+pub fn disk_names(tape: &Tape) -> Vec<String> {
+    let mut next_custom = 0;
+    tape.files()
+        .map(|file| match &file {
+            File::Bin(name, ..)
+            | File::Basic(name, _)
+            | File::Ascii(name, _)
+            | File::Header(name, _) => {
+                let stem: String = name
+                    .trim_end()
+                    .chars()
+                    .map(|c| match c {
+                        c if !c.is_ascii_graphic() || INVALID_CHARS.contains(&(c as u8)) => '_',
+                        c => c.to_ascii_uppercase(),
+                    })
+                    .collect();
+                let ext = file.file_type().extension().unwrap_or_default();
+                match stem.as_str() {
+                    "" => format!("NONAME.{}", ext.to_uppercase()),
+                    _ => format!("{}.{}", stem, ext.to_uppercase()),
+                }
+            }
+            File::Custom(_) => {
+                let name = format!("CUSTOM.{:03}", next_custom);
+                next_custom += 1;
+                name
+            }
+        })
+        .collect()
+}
+
+/// Returns the contents of the given tape file as stored in disks
+///
+/// Binary files get the `0xfe` ID byte and lose anything beyond their end address,
+/// tokenized Basic programs get the `0xff` ID byte, and ASCII files lose their EOF
+/// padding but one EOF character. Custom files are stored as they are.
+///
+pub fn disk_bytes(file: &File) -> Vec<u8> {
+    match file {
+        File::Bin(_, begin, end, _, data) => {
+            let len = (6 + end.saturating_sub(*begin) + 1).min(data.len());
+            [&[0xfe], &data[..len]].concat()
+        }
+        File::Basic(_, program) => [&[0xff], *program].concat(),
+        File::Ascii(..) => [&file.payload()[..], &[0x1a]].concat(),
+        File::Custom(data) => data.to_vec(),
+        File::Header(..) => vec![],
+    }
+}
+
+impl Tape {
+    /// Append the given disk file to this tape.
+    ///
+    /// The file type is inferred from the contents: files that start with `0xfe` are
+    /// binary files and files that start with `0xff` are tokenized Basic programs. Other
+    /// files are ASCII files if they have no control characters but tabs and line endings
+    /// before their first EOF character, and custom files otherwise. The tape file name is the stem of `name` truncated to
+    /// six characters.
+    ///
+    /// Returns the number of bytes of padding, as `append_bin()` and the rest do.
+    ///
+    pub fn append_disk_file(&mut self, name: &str, data: &[u8]) -> io::Result<usize> {
+        let stem = name.rfind('.').map_or(name, |dot| &name[..dot]);
+        let (tape_name, _) = tape::file_name(stem);
+        match data.first() {
+            Some(0xfe) => self.append_bin(&tape_name, data),
+            Some(0xff) => self.append_basic(&tape_name, data),
+            _ => {
+                let text = &data[..data.iter().position(|b| *b == 0x1a).unwrap_or(data.len())];
+                if data.is_empty() || text.iter().any(|b| *b < 0x20 && !b"\t\n\r".contains(b)) {
+                    self.append_custom(data)
+                } else {
+                    self.append_ascii(&tape_name, text)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_write_and_read_disk_files() {
+        let mut disk = Disk::new();
+        assert_eq!(737280, disk.as_bytes().len());
+        assert_eq!(713 * 1024, disk.free_space());
+
+        let big: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        disk.write_file(
+            "game.bin",
+            &[0xfe, 0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xaf, 0xc9],
+        )
+        .unwrap();
+        disk.write_file("DATA", &big).unwrap();
+        let disk = Disk::from_bytes(disk.as_bytes().to_vec()).unwrap();
+        let names: Vec<(String, usize)> = disk
+            .entries()
+            .into_iter()
+            .map(|e| (e.name, e.size))
+            .collect();
+        assert_eq!(
+            vec![("GAME.BIN".to_string(), 9), ("DATA".to_string(), 3000)],
+            names
+        );
+        assert_eq!(big, disk.read_file("data").unwrap());
+        assert_eq!(713 * 1024 - 4096, disk.free_space());
+
+        // Replacing a file frees its clusters first
+        let mut disk = disk;
+        disk.write_file("data", &big[..100]).unwrap();
+        assert_eq!(&big[..100], &disk.read_file("DATA").unwrap()[..]);
+        assert_eq!(2, disk.entries().len());
+        assert_eq!(713 * 1024 - 2048, disk.free_space());
+
+        let e = disk.read_file("nothing.bin").unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, e.kind());
+        let e = disk.write_file("toolongname.bin", &big).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+        let e = disk
+            .write_file("huge.bin", &vec![0; 720 * 1024])
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+        assert_eq!(2, disk.entries().len());
+        assert!(Disk::from_bytes(vec![0; 1000]).is_err());
+    }
+
+    #[test]
+    fn should_convert_between_tape_and_disk_files() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("GAME");
+        tape.append_bin(&name, &[0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xaf, 0xc9])
+            .unwrap();
+        tape.append_ascii(&name, b"10 PRINT \"HI\"\r\n").unwrap();
+        tape.append_custom(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(
+            vec!["GAME.BIN", "GAME.ASC", "CUSTOM.000"],
+            disk_names(&tape)
+        );
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(
+            vec![0xfe, 0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xaf, 0xc9],
+            disk_bytes(&files[0])
+        );
+        assert_eq!(b"10 PRINT \"HI\"\r\n\x1a".to_vec(), disk_bytes(&files[1]));
+
+        let mut read = Tape::new();
+        for (name, file) in disk_names(&tape).iter().zip(files.iter()) {
+            read.append_disk_file(name, &disk_bytes(file)).unwrap();
+        }
+        assert_eq!(tape.to_bytes(), read.to_bytes());
+    }
+}
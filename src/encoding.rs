@@ -0,0 +1,93 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Conversion between the MSX character set and UTF-8
+//!
+//! ASCII files and Basic listings store one byte per character. The bytes below `0x80`
+//! are ASCII, while the rest are the accented letters, symbols and graphics of the
+//! international MSX character set, which look like garbage on a modern system unless
+//! they are converted with `to_utf8()`. `from_utf8()` does the reverse conversion.
+
+use std::io;
+
+/// The characters of the bytes from `0x80` to `0xff` in the MSX character set
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    'Ã', 'ã', 'Ĩ', 'ĩ', 'Õ', 'õ', 'Ũ', 'ũ', 'Ĳ', 'ĳ', '¾', '∽', '◊', '‰', '¶', '§', //
+    '▂', '▚', '▆', '🮂', '▬', '🮅', '▎', '▞', '▊', '🮇', '🮊', '🮙', '🮘', '🭭', '🭯', '🭬', //
+    '🭮', '🮚', '🮛', '▘', '▗', '▝', '▖', '🮖', 'Δ', '‡', 'ω', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', '⌀', '∈', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Returns the given text in the MSX character set converted to UTF-8
+///
+/// Bytes below `0x80`, including control characters as `CR` or `EOF` (`0x1a`), are
+/// converted to the same ASCII characters.
+///
+pub fn to_utf8(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b {
+            0x00..=0x7f => *b as char,
+            _ => HIGH[(b - 0x80) as usize],
+        })
+        .collect()
+}
+
+/// Returns the given UTF-8 text converted to the MSX character set
+///
+/// Fails with an error of kind `InvalidData` if the bytes are not valid UTF-8 or some
+/// character has no counterpart in the MSX character set.
+///
+pub fn from_utf8(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let text =
+        std::str::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            '\u{0}'..='\u{7f}' => Ok(c as u8),
+            _ => HIGH
+                .iter()
+                .position(|h| *h == c)
+                .map(|p| 0x80 + p as u8)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "character {:?} (at position {}) not found in the MSX character set",
+                            c, i
+                        ),
+                    )
+                }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_convert_msx_text_to_and_from_utf8() {
+        let msx = b"10 PRINT \"Ni\xa4o \x9c3\"\r\n\x1a";
+        let text = to_utf8(msx);
+        assert_eq!("10 PRINT \"Niño £3\"\r\n\u{1a}", text);
+        assert_eq!(msx.to_vec(), from_utf8(text.as_bytes()).unwrap());
+
+        let all: Vec<u8> = (0..=0xff).collect();
+        assert_eq!(all, from_utf8(to_utf8(&all).as_bytes()).unwrap());
+
+        let e = from_utf8("10 PRINT \"€\"".as_bytes()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, e.kind());
+        assert!(from_utf8(&[0xa4]).is_err());
+    }
+}
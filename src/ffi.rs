@@ -0,0 +1,469 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! C interface to read and build tapes
+//!
+//! This module exports a small `extern "C"` API, declared in `include/mcp.h`, so
+//! emulators and other tools written in C or C++ can reuse the tape handling of this
+//! crate. It is only built with the `ffi` feature, and the library can be built as a
+//! static or dynamic C library with:
+//!
+//! ```text
+//! cargo rustc --release --lib --no-default-features --features ffi --crate-type staticlib
+//! ```
+//!
+//! Tapes are opaque `mcp_tape` handles created by `mcp_tape_new()` or
+//! `mcp_tape_from_bytes()` and released by `mcp_tape_free()`. Files are referred by
+//! their index in the tape. Functions that may fail return `-1` (or a null pointer) on
+//! error and `0` on success. Panics are caught before they reach the caller and reported
+//! the same way, since unwinding into C code aborts the process.
+
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::tape::{self, File, FileType, Tape};
+
+/// A tape, along with the files it is made of
+///
+/// The name and payload of the files are kept along with the tape, so C code can be
+/// given pointers to them. They are valid until the tape is modified or freed.
+///
+pub struct McpTape {
+    tape: Tape,
+    files: Vec<Entry>,
+}
+
+struct Entry {
+    file_type: FileType,
+    /// The name of the file, NUL terminated
+    name: Vec<u8>,
+    addresses: Option<(u16, u16, u16)>,
+    payload: Vec<u8>,
+}
+
+impl McpTape {
+    fn new(tape: Tape) -> McpTape {
+        let mut result = McpTape {
+            tape,
+            files: vec![],
+        };
+        result.refresh();
+        result
+    }
+
+    fn refresh(&mut self) {
+        self.files = self.tape.files().map(|file| Entry::of(&file)).collect();
+    }
+
+    /// Update the files after blocks are appended to the tape
+    ///
+    /// Appended blocks may complete the last file (e.g., a header without data), but leave
+    /// the files before it as they were, so only the last file and the new ones are read.
+    ///
+    fn refresh_last(&mut self) {
+        let from = self.files.len().saturating_sub(1);
+        self.files.truncate(from);
+        let entries: Vec<Entry> = self
+            .tape
+            .files()
+            .skip(from)
+            .map(|f| Entry::of(&f))
+            .collect();
+        self.files.extend(entries);
+    }
+}
+
+impl Entry {
+    fn of(file: &File) -> Entry {
+        let mut name = match file {
+            File::Bin(name, ..)
+            | File::Basic(name, _)
+            | File::Ascii(name, _)
+            | File::Header(name, _) => name.as_bytes().to_vec(),
+            File::Custom(_) => vec![],
+        };
+        name.push(0);
+        Entry {
+            file_type: file.file_type(),
+            name,
+            addresses: file.addresses(),
+            payload: file.payload().into_owned(),
+        }
+    }
+}
+
+/// Create an empty tape.
+#[no_mangle]
+pub extern "C" fn mcp_tape_new() -> *mut McpTape {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(McpTape::new(Tape::new())))
+    })
+}
+
+/// Create a tape from the bytes of a CAS file.
+///
+/// The bytes are copied, so the buffer can be released afterwards. Malformed files are
+/// read as custom files, as `Tape::from_bytes()` does.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, or be null if `len` is 0.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_from_bytes(data: *const u8, len: usize) -> *mut McpTape {
+    guard(ptr::null_mut(), || {
+        let bytes = bytes_of(data, len);
+        Box::into_raw(Box::new(McpTape::new(Tape::from_bytes(bytes))))
+    })
+}
+
+/// Release a tape. Releasing a null pointer does nothing.
+///
+/// # Safety
+///
+/// `tape` must be null or a tape returned by this API that was not released yet.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_free(tape: *mut McpTape) {
+    guard((), || {
+        if !tape.is_null() {
+            drop(Box::from_raw(tape));
+        }
+    })
+}
+
+/// Returns the number of files of a tape.
+///
+/// # Safety
+///
+/// `tape` must be a valid tape.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_file_count(tape: *const McpTape) -> usize {
+    guard(0, || {
+        let tape = &*tape;
+        tape.files.len()
+    })
+}
+
+/// Returns the type of a file (one of the `MCP_FILE_*` constants), or `-1` if there is
+/// no file at the given index.
+///
+/// # Safety
+///
+/// `tape` must be a valid tape.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_file_type(tape: *const McpTape, index: usize) -> c_int {
+    guard(-1, || {
+        let tape = &*tape;
+        match tape.files.get(index) {
+            Some(entry) => match entry.file_type {
+                FileType::Bin => 0,
+                FileType::Basic => 1,
+                FileType::Ascii => 2,
+                FileType::Custom => 3,
+            },
+            None => -1,
+        }
+    })
+}
+
+/// Returns the name of a file as a NUL terminated string, which is empty for custom
+/// files, or null if there is no file at the given index.
+///
+/// # Safety
+///
+/// `tape` must be a valid tape. The string is valid until the tape is modified or
+/// released.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_file_name(tape: *const McpTape, index: usize) -> *const c_char {
+    guard(ptr::null(), || {
+        let tape = &*tape;
+        match tape.files.get(index) {
+            Some(entry) => entry.name.as_ptr() as *const c_char,
+            None => ptr::null(),
+        }
+    })
+}
+
+/// Returns the payload of a file, storing its length in `len`, or null if there is no
+/// file at the given index.
+///
+/// The payload is the data of the file without its headers, as `File::payload()`
+/// returns it.
+///
+/// # Safety
+///
+/// `tape` must be a valid tape and `len` must point to a writable `size_t`. The payload
+/// is valid until the tape is modified or released.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_file_payload(
+    tape: *const McpTape,
+    index: usize,
+    len: *mut usize,
+) -> *const u8 {
+    guard(ptr::null(), || {
+        let tape = &*tape;
+        match tape.files.get(index) {
+            Some(entry) => {
+                *len = entry.payload.len();
+                entry.payload.as_ptr()
+            }
+            None => ptr::null(),
+        }
+    })
+}
+
+/// Store the begin, end and start addresses of a binary file.
+///
+/// Returns `-1` if there is no binary file at the given index.
+///
+/// # Safety
+///
+/// `tape` must be a valid tape, and `begin`, `end` and `start` must point to writable
+/// `uint16_t` values.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_file_addresses(
+    tape: *const McpTape,
+    index: usize,
+    begin: *mut u16,
+    end: *mut u16,
+    start: *mut u16,
+) -> c_int {
+    guard(-1, || {
+        let tape = &*tape;
+        match tape.files.get(index).and_then(|e| e.addresses) {
+            Some((b, e, s)) => {
+                *begin = b;
+                *end = e;
+                *start = s;
+                0
+            }
+            None => -1,
+        }
+    })
+}
+
+/// Append a file to a tape.
+///
+/// `file_type` is one of the `MCP_FILE_*` constants, and `data` the contents of the file
+/// as the `append_*()` methods of `Tape` take them (e.g., binary files start with their
+/// addresses). The name is ignored for custom files. Returns `-1` if the type is unknown,
+/// the name is longer than six characters or the data are not valid.
+///
+/// # Safety
+///
+/// `tape` must be a valid tape, `name` a NUL terminated string (or null for custom
+/// files) and `data` must point to `len` readable bytes, or be null if `len` is 0.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_append(
+    tape: *mut McpTape,
+    file_type: c_int,
+    name: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    guard(-1, || {
+        let tape = &mut *tape;
+        let data = bytes_of(data, len);
+        let name = if name.is_null() {
+            None
+        } else {
+            match CStr::from_ptr(name).to_str().map(tape::file_name) {
+                Ok((bytes, false)) => Some(bytes),
+                _ => return -1,
+            }
+        };
+        let result = match (file_type, name) {
+            (0, Some(name)) => tape.tape.append_bin(&name, data),
+            (1, Some(name)) => tape.tape.append_basic(&name, data),
+            (2, Some(name)) => tape.tape.append_ascii(&name, data),
+            (3, _) => tape.tape.append_custom(data),
+            _ => return -1,
+        };
+        if result.is_err() {
+            return -1;
+        }
+        tape.refresh_last();
+        0
+    })
+}
+
+/// Write the CAS representation of a tape to a buffer.
+///
+/// Returns the length of the CAS representation. Nothing is written if the buffer is
+/// shorter, so calling it with a null buffer tells the size of the buffer to allocate.
+/// Returns `-1` if the tape cannot be written.
+///
+/// # Safety
+///
+/// `tape` must be a valid tape, and `buffer` must point to `len` writable bytes, or be
+/// null if `len` is 0.
+///
+#[no_mangle]
+pub unsafe extern "C" fn mcp_tape_write(
+    tape: *const McpTape,
+    buffer: *mut u8,
+    len: usize,
+) -> isize {
+    guard(-1, || {
+        let tape = &*tape;
+        let bytes = tape.tape.to_bytes();
+        if bytes.len() <= len {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());
+        }
+        isize::try_from(bytes.len()).unwrap_or(-1)
+    })
+}
+
+/// Returns the result of `f`, or `default` if it panics
+fn guard<T, F: FnOnce() -> T>(default: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(default)
+}
+
+unsafe fn bytes_of<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::ffi::CString;
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::*;
+
+    #[test]
+    fn should_build_and_read_tapes() {
+        unsafe {
+            let tape = mcp_tape_new();
+            let name = CString::new("game").unwrap();
+            let code = [0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xaf, 0xc9];
+            assert_eq!(
+                0,
+                mcp_tape_append(tape, 0, name.as_ptr(), code.as_ptr(), code.len())
+            );
+            assert_eq!(0, mcp_tape_append(tape, 3, ptr::null(), [1, 2].as_ptr(), 2));
+            let long = CString::new("toolong").unwrap();
+            assert_eq!(
+                -1,
+                mcp_tape_append(tape, 0, long.as_ptr(), code.as_ptr(), 8)
+            );
+            assert_eq!(-1, mcp_tape_append(tape, 1, ptr::null(), code.as_ptr(), 8));
+            assert_eq!(-1, mcp_tape_append(tape, 0, name.as_ptr(), ptr::null(), 0));
+            assert_eq!(-1, mcp_tape_append(tape, 1, name.as_ptr(), ptr::null(), 0));
+
+            let len = mcp_tape_write(tape, ptr::null_mut(), 0);
+            let mut bytes = vec![0; len as usize];
+            assert_eq!(len, mcp_tape_write(tape, bytes.as_mut_ptr(), len as usize));
+            mcp_tape_free(tape);
+
+            let tape = mcp_tape_from_bytes(bytes.as_ptr(), bytes.len());
+            assert_eq!(2, mcp_tape_file_count(tape));
+            assert_eq!(
+                (0, 3, -1),
+                (
+                    mcp_tape_file_type(tape, 0),
+                    mcp_tape_file_type(tape, 1),
+                    mcp_tape_file_type(tape, 2)
+                )
+            );
+            assert_eq!(
+                b"game",
+                CStr::from_ptr(mcp_tape_file_name(tape, 0)).to_bytes()
+            );
+            assert_eq!(b"", CStr::from_ptr(mcp_tape_file_name(tape, 1)).to_bytes());
+            let mut len = 0;
+            let payload = mcp_tape_file_payload(tape, 0, &mut len);
+            assert_eq!(&[0xaf, 0xc9], slice::from_raw_parts(payload, len));
+            let (mut begin, mut end, mut start) = (0, 0, 0);
+            assert_eq!(
+                0,
+                mcp_tape_file_addresses(tape, 0, &mut begin, &mut end, &mut start)
+            );
+            assert_eq!((0x9000, 0x9001, 0x9000), (begin, end, start));
+            assert_eq!(
+                -1,
+                mcp_tape_file_addresses(tape, 1, &mut begin, &mut end, &mut start)
+            );
+            mcp_tape_free(tape);
+        }
+    }
+
+    #[test]
+    fn should_complete_files_on_append() {
+        unsafe {
+            // A binary file whose data block is missing, to be completed by the append
+            let code = [0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xaf, 0xc9];
+            let mut header = Tape::new();
+            header
+                .append_bin(&tape::file_name("game").0, &code)
+                .unwrap();
+            header.truncate_blocks(1);
+            let bytes = header.to_bytes();
+            let tape = mcp_tape_from_bytes(bytes.as_ptr(), bytes.len());
+            assert_eq!(-1, mcp_tape_file_type(tape, 1));
+            assert_eq!(0, mcp_tape_append(tape, 3, ptr::null(), code.as_ptr(), 8));
+            assert_eq!(0, mcp_tape_append(tape, 3, ptr::null(), [1, 2].as_ptr(), 2));
+
+            assert_eq!(2, mcp_tape_file_count(tape));
+            let mut len = 0;
+            mcp_tape_file_payload(tape, 0, &mut len);
+            assert_eq!(2, len);
+
+            let bytes = (*tape).tape.to_bytes();
+            let read = mcp_tape_from_bytes(bytes.as_ptr(), bytes.len());
+            assert_eq!(mcp_tape_file_count(read), mcp_tape_file_count(tape));
+            for index in 0..mcp_tape_file_count(tape) {
+                assert_eq!(
+                    mcp_tape_file_type(read, index),
+                    mcp_tape_file_type(tape, index)
+                );
+            }
+            mcp_tape_free(read);
+            mcp_tape_free(tape);
+        }
+    }
+
+    #[test]
+    fn should_declare_every_function_in_header() {
+        // `mcp_test.c` assigns every function to a pointer of its exact type
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let output = match Command::new("cc")
+            .args(["-fsyntax-only", "-Wall", "-Werror", "-Iinclude"])
+            .arg("include/mcp_test.c")
+            .current_dir(root)
+            .output()
+        {
+            Ok(output) => output,
+            Err(_) => {
+                eprintln!("skipping the header check: cc is not available");
+                return;
+            }
+        };
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
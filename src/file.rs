@@ -15,7 +15,7 @@ use std::path::{Path, PathBuf};
 use crate::tape;
 
 pub fn exists(file: &Path) -> bool {
-    if let Ok(_) = fs::File::open(file) { true } else { false }
+    fs::File::open(file).is_ok()
 }
 
 pub fn remove(file: &Path) -> io::Result<()> {
@@ -26,13 +26,16 @@ pub fn temporary(file: &Path) -> io::Result<PathBuf> {
     file.file_name()
         .and_then(|fname| fname.to_str())
         .map(|fname| file.with_file_name(format!("{}.temp", fname)))
-        .ok_or_else(|| io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("no temporary available for path {:?}", file)))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no temporary available for path {:?}", file),
+            )
+        })
 }
 
 pub fn read_content(file: &Path) -> io::Result<Vec<u8>> {
-    let mut data: Vec<u8> = Vec::with_capacity(64*1024);
+    let mut data: Vec<u8> = Vec::with_capacity(64 * 1024);
     fs::File::open(file)
         .map(|mut f| f.read_to_end(&mut data))
         .map(|_| data)
@@ -49,32 +52,21 @@ pub fn write_content(path: &Path, content: &[u8]) -> io::Result<()> {
     Ok(())
 }
 
-pub fn file_name_of(path: &Path) -> io::Result<([u8;6], bool)> {
-    let path_str = path
-        .file_stem()
-        .and_then(|f| f.to_str())
-        .ok_or_else(|| io::Error::new(
+pub fn file_name_of(path: &Path) -> io::Result<([u8; 6], bool)> {
+    let path_str = path.file_stem().and_then(|f| f.to_str()).ok_or_else(|| {
+        io::Error::new(
             io::ErrorKind::InvalidInput,
-            format!("cannot convert path {:?} into string", path)))?;
+            format!("cannot convert path {:?} into string", path),
+        )
+    })?;
     Ok(tape::file_name(path_str))
 }
 
-pub fn is_bin_file(path: &Path) -> bool {
-    has_extension(path, "bin")
-}
-
-pub fn is_ascii_file(path: &Path) -> bool {
-    has_extension(path, "asc")
-}
-
-pub fn is_basic_file(path: &Path) -> bool {
-    has_extension(path, "bas")
-}
-
-fn has_extension(path: &Path, ext: &str) -> bool {
+pub fn file_type_of(path: &Path) -> tape::FileType {
     path.extension()
         .and_then(|e| e.to_str())
-        .map(|e| e.to_lowercase() == ext).unwrap_or(false)
+        .map(tape::FileType::from_extension)
+        .unwrap_or(tape::FileType::Custom)
 }
 
 pub fn unique_filename(path: &Path) -> io::Result<(PathBuf, bool)> {
@@ -85,9 +77,28 @@ pub fn unique_filename(path: &Path) -> io::Result<(PathBuf, bool)> {
     }
 }
 
+pub fn tapes_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut tapes = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            tapes.extend(tapes_in(&path)?);
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("cas"))
+        {
+            tapes.push(path);
+        }
+    }
+    tapes.sort();
+    Ok(tapes)
+}
+
 fn unique_filename_for_suffix(path: &Path, suffix: usize) -> io::Result<PathBuf> {
     let stem = extract_from_path(path, |p| p.file_stem())?;
-    let ext = path.extension()
+    let ext = path
+        .extension()
         .and_then(|e| e.to_str())
         .map(|e| format!(".{}", e))
         .unwrap_or("".to_string());
@@ -100,10 +111,15 @@ fn unique_filename_for_suffix(path: &Path, suffix: usize) -> io::Result<PathBuf>
 }
 
 fn extract_from_path<F>(path: &Path, f: F) -> io::Result<&str>
-where F: FnOnce(&Path) -> Option<&OsStr> {
-    f(path).and_then(|s| s.to_str()).ok_or_else(|| io::Error::new(
-        io::ErrorKind::InvalidInput,
-        format!("cannot extract path element from {:?}", path)))
+where
+    F: FnOnce(&Path) -> Option<&OsStr>,
+{
+    f(path).and_then(|s| s.to_str()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot extract path element from {:?}", path),
+        )
+    })
 }
 
 #[cfg(test)]
@@ -126,17 +142,33 @@ mod tests {
         });
     }
 
+    #[test]
+    fn should_find_tapes_in_dir() {
+        let dir = TempDir::new("mcp").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        for name in ["b.cas", "a.txt", "sub/c.CAS"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        assert_eq!(
+            tapes_in(dir.path()).unwrap(),
+            vec![dir.path().join("b.cas"), dir.path().join("sub/c.CAS")]
+        );
+    }
+
     #[test]
     fn should_compute_temporary_file_name() {
         assert_eq!(
             temporary(Path::new("foobar")).unwrap(),
-            PathBuf::from("foobar.temp"));
+            PathBuf::from("foobar.temp")
+        );
         assert_eq!(
             temporary(Path::new("foobar.cas")).unwrap(),
-            PathBuf::from("foobar.cas.temp"));
+            PathBuf::from("foobar.cas.temp")
+        );
         assert_eq!(
             temporary(Path::new("/path/to/foobar.cas")).unwrap(),
-            PathBuf::from("/path/to/foobar.cas.temp"));
+            PathBuf::from("/path/to/foobar.cas.temp")
+        );
     }
 
     #[test]
@@ -156,32 +188,46 @@ mod tests {
     }
 
     #[test]
-    fn should_compute_is_bin_file() {
-        assert!(is_bin_file(Path::new("foobar.bin")));
-        assert!(is_bin_file(Path::new("foobar.BIN")));
-        assert!(is_bin_file(Path::new("foobar.BiN")));
-        assert!(!is_bin_file(Path::new("foobar")));
-        assert!(!is_bin_file(Path::new("foobar.bina")));
+    fn should_compute_bin_file_type() {
+        assert_eq!(file_type_of(Path::new("foobar.bin")), tape::FileType::Bin);
+        assert_eq!(file_type_of(Path::new("foobar.BIN")), tape::FileType::Bin);
+        assert_eq!(file_type_of(Path::new("foobar.BiN")), tape::FileType::Bin);
+        assert_ne!(file_type_of(Path::new("foobar")), tape::FileType::Bin);
+        assert_ne!(file_type_of(Path::new("foobar.bina")), tape::FileType::Bin);
     }
 
     #[test]
-    fn should_compute_is_ascii_file() {
-        assert!(is_ascii_file(Path::new("foobar.asc")));
-        assert!(is_ascii_file(Path::new("foobar.ASC")));
-        assert!(is_ascii_file(Path::new("foobar.AsC")));
-        assert!(!is_ascii_file(Path::new("foobar")));
-        assert!(!is_ascii_file(Path::new("foobar.asci")));
+    fn should_compute_ascii_file_type() {
+        assert_eq!(file_type_of(Path::new("foobar.asc")), tape::FileType::Ascii);
+        assert_eq!(file_type_of(Path::new("foobar.ASC")), tape::FileType::Ascii);
+        assert_eq!(file_type_of(Path::new("foobar.AsC")), tape::FileType::Ascii);
+        assert_ne!(file_type_of(Path::new("foobar")), tape::FileType::Ascii);
+        assert_ne!(
+            file_type_of(Path::new("foobar.asci")),
+            tape::FileType::Ascii
+        );
     }
 
     #[test]
-    fn should_compute_is_basic_file() {
-        assert!(is_basic_file(Path::new("foobar.bas")));
-        assert!(is_basic_file(Path::new("foobar.BAS")));
-        assert!(is_basic_file(Path::new("foobar.BaS")));
-        assert!(!is_basic_file(Path::new("foobar")));
-        assert!(!is_basic_file(Path::new("foobar.basi")));
+    fn should_compute_basic_file_type() {
+        assert_eq!(file_type_of(Path::new("foobar.bas")), tape::FileType::Basic);
+        assert_eq!(file_type_of(Path::new("foobar.BAS")), tape::FileType::Basic);
+        assert_eq!(file_type_of(Path::new("foobar.BaS")), tape::FileType::Basic);
+        assert_ne!(file_type_of(Path::new("foobar")), tape::FileType::Basic);
+        assert_ne!(
+            file_type_of(Path::new("foobar.basi")),
+            tape::FileType::Basic
+        );
     }
 
+    #[test]
+    fn should_compute_custom_file_type() {
+        assert_eq!(
+            file_type_of(Path::new("foobar.dat")),
+            tape::FileType::Custom
+        );
+        assert_eq!(file_type_of(Path::new("foobar")), tape::FileType::Custom);
+    }
 
     #[test]
     fn should_compute_unique_filename() {
@@ -209,22 +255,36 @@ mod tests {
         });
     }
 
-    fn with_unexisting_file<P, F>(filename: P, f: F) where P: AsRef<Path>, F: FnOnce(&Path) {
+    fn with_unexisting_file<P, F>(filename: P, f: F)
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&Path),
+    {
         let temp = TempDir::new("mcp").unwrap();
         let mut path_buf = temp.path().to_path_buf();
         path_buf.push(filename);
         f(&path_buf);
     }
 
-    fn with_existing_file<P, F>(filename: P, f: F) where P: AsRef<Path>, F: FnOnce(&Path) {
+    fn with_existing_file<P, F>(filename: P, f: F)
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&Path),
+    {
         with_unexisting_file(filename, |file| {
-            { File::create(file).unwrap(); }
+            {
+                File::create(file).unwrap();
+            }
             f(file);
         })
     }
 
     fn with_existing_file_from<P1, P2, F>(filename: P1, new_name: P2, f: F)
-    where P1: AsRef<Path>, P2: AsRef<OsStr>, F: FnOnce(&Path) {
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<OsStr>,
+        F: FnOnce(&Path),
+    {
         let file: &Path = filename.as_ref();
         let other = file.with_file_name(new_name);
         with_existing_file(other, f)
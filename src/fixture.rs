@@ -0,0 +1,232 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Generation of synthetic tapes for testing
+//!
+//! A `Fixture` describes the shape of a tape (how many files and of which types), and
+//! generates it from a seed. The same fixture and seed always produce the same tape, so
+//! generated tapes can be used as reproducible inputs for emulators and tests:
+//!
+//! ```
+//! use mcp::fixture::Fixture;
+//! use mcp::tape::FileType;
+//!
+//! let fixture = Fixture {
+//!     files: 3,
+//!     types: vec![FileType::Bin, FileType::Ascii],
+//!     seed: 42,
+//! };
+//! let (tape, again) = (fixture.generate(), fixture.generate());
+//! assert_eq!(3, tape.files().count());
+//! assert!(tape.files().eq(again.files()));
+//! ```
+//!
+//! The contents favor edge cases: sizes around the 8-byte alignment of blocks and the
+//! 256-byte chunks of ASCII files, multi-chunk ASCII files, and odd names (blank, short,
+//! lowercase or with punctuation).
+
+use crate::basic;
+use crate::tape::{FileType, Tape};
+
+/// Sizes close to the boundaries of blocks and ASCII chunks
+const EDGE_SIZES: [usize; 9] = [1, 6, 7, 8, 9, 255, 256, 257, 513];
+
+/// Names that exercise the handling of file names
+const ODD_NAMES: [&str; 6] = ["", "A", "ab cd", "x.y", "123456", "-_!#$%"];
+
+/// The shape of a synthetic tape
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fixture {
+    /// The number of files of the tape
+    pub files: usize,
+    /// The types the files are chosen from
+    pub types: Vec<FileType>,
+    /// The seed of the generator
+    pub seed: u64,
+}
+
+impl Default for Fixture {
+    fn default() -> Fixture {
+        Fixture {
+            files: 5,
+            types: vec![
+                FileType::Bin,
+                FileType::Basic,
+                FileType::Ascii,
+                FileType::Custom,
+            ],
+            seed: 0,
+        }
+    }
+}
+
+impl Fixture {
+    /// Generate the tape described by this fixture.
+    ///
+    /// The tape is empty if `types` is empty.
+    ///
+    pub fn generate(&self) -> Tape {
+        let mut rng = Rng::new(self.seed);
+        let mut tape = Tape::new();
+        if self.types.is_empty() {
+            return tape;
+        }
+        for index in 0..self.files {
+            let file_type = self.types[rng.below(self.types.len())];
+            let name = name(&mut rng, index);
+            // Generated contents are always valid, so appending them cannot fail.
+            let _ = match file_type {
+                FileType::Bin => tape.append_bin(&name, &bin(&mut rng)),
+                FileType::Basic => tape.append_basic(&name, &basic_program(&mut rng)),
+                FileType::Ascii => tape.append_ascii(&name, &ascii(&mut rng)),
+                FileType::Custom => tape.append_custom(&random_bytes(&mut rng)),
+            };
+        }
+        tape
+    }
+}
+
+/// A xorshift generator, so fixtures do not depend on the algorithm of any crate
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Xorshift gets stuck on zero: scramble the seed with a splitmix64 step.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn len(&mut self, max: usize) -> usize {
+        if self.below(2) == 0 {
+            EDGE_SIZES[self.below(EDGE_SIZES.len())]
+        } else {
+            1 + self.below(max)
+        }
+    }
+}
+
+fn name(rng: &mut Rng, index: usize) -> [u8; 6] {
+    let name = if rng.below(3) == 0 {
+        ODD_NAMES[rng.below(ODD_NAMES.len())].to_string()
+    } else {
+        format!("FILE{}", index)
+    };
+    let mut bytes = [b' '; 6];
+    for (b, c) in bytes.iter_mut().zip(name.bytes()) {
+        *b = c;
+    }
+    bytes
+}
+
+fn random_bytes(rng: &mut Rng) -> Vec<u8> {
+    let len = rng.len(2048);
+    (0..len).map(|_| rng.next() as u8).collect()
+}
+
+fn bin(rng: &mut Rng) -> Vec<u8> {
+    let program = random_bytes(rng);
+    let begin = 0x8000 + 0x100 * rng.below(0x40) as u16;
+    let end = begin + program.len() as u16 - 1;
+    let start = begin + rng.below(program.len()) as u16;
+    let mut data = Vec::with_capacity(program.len() + 6);
+    for addr in &[begin, end, start] {
+        data.extend_from_slice(&addr.to_le_bytes());
+    }
+    data.extend(program);
+    data
+}
+
+fn ascii(rng: &mut Rng) -> Vec<u8> {
+    let len = rng.len(1024);
+    let mut text = Vec::with_capacity(len);
+    while text.len() < len {
+        if rng.below(40) == 0 {
+            text.extend_from_slice(b"\r\n");
+        } else {
+            text.push(b' ' + rng.below(0x5f) as u8);
+        }
+    }
+    text.truncate(len);
+    text
+}
+
+fn basic_program(rng: &mut Rng) -> Vec<u8> {
+    let lines = 1 + rng.below(20);
+    let mut source = String::new();
+    for line in 1..=lines {
+        let statement = match rng.below(4) {
+            0 => format!("PRINT \"LINE {}\"", line),
+            1 => format!("A={}+{}", rng.below(1000), rng.below(100)),
+            2 => format!("IF A>{} THEN {}", rng.below(10), 10 * lines),
+            _ => format!("REM {:x}", rng.next()),
+        };
+        source.push_str(&format!("{} {}\r\n", 10 * line, statement));
+    }
+    basic::tokenize(source.as_bytes()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape::File;
+
+    #[test]
+    fn should_generate_deterministic_tapes() {
+        let fixture = Fixture {
+            files: 20,
+            seed: 7,
+            ..Fixture::default()
+        };
+        let tape = fixture.generate();
+        let data = |tape: &Tape| -> Vec<Vec<u8>> {
+            tape.blocks().iter().map(|b| b.data().to_vec()).collect()
+        };
+        assert_eq!(data(&tape), data(&fixture.generate()));
+        let other = Fixture {
+            seed: 8,
+            ..fixture.clone()
+        };
+        assert_ne!(data(&tape), data(&other.generate()));
+
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(20, files.len());
+        for file in &files {
+            if let File::Bin(_, begin, end, _, data) = file {
+                assert!(end - begin < data.len() - 6);
+            }
+            if let File::Basic(_, data) = file {
+                assert!(basic::detokenize(data).is_ok());
+            }
+        }
+
+        let fixture = Fixture {
+            files: 10,
+            types: vec![FileType::Ascii],
+            seed: 7,
+        };
+        assert!(fixture
+            .generate()
+            .files()
+            .all(|f| f.file_type() == FileType::Ascii));
+    }
+}
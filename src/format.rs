@@ -0,0 +1,484 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Containers other than CAS to store tapes in
+//!
+//! Each `TapeFormat` converts between a `Tape` and its own representation, and
+//! `for_path()` picks the format of a file by its extension:
+//!
+//! ```
+//! use std::path::Path;
+//!
+//! use mcp::format::{self, TapeFormat};
+//! use mcp::tape::{self, Tape};
+//!
+//! let mut tape = Tape::new();
+//! let (name, _) = tape::file_name("game");
+//! tape.append_bin(&name, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xaf, 0xc9])?;
+//!
+//! let tsx = format::for_path(Path::new("game.tsx")).unwrap();
+//! let mut bytes = vec![];
+//! tsx.write(&tape, &mut bytes)?;
+//! let read = tsx.read(&mut &bytes[..])?;
+//! assert_eq!(tape.to_bytes(), read.to_bytes());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::decode;
+use crate::progress;
+use crate::tape::Tape;
+use crate::wav;
+
+/// A container format able to store the contents of a tape
+///
+/// Every format translates between its own representation and the block model of
+/// `Tape`. A format may support reading, writing or both. Unsupported operations
+/// return an error of kind `std::io::ErrorKind::Unsupported`.
+///
+pub trait TapeFormat {
+    /// Returns the name of the format.
+    fn name(&self) -> &'static str;
+
+    /// Returns the file extensions (lowercase, without dot) used by this format.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Read a tape from the given input.
+    fn read(&self, input: &mut dyn Read) -> io::Result<Tape>;
+
+    /// Write the given tape to the given output.
+    fn write(&self, tape: &Tape, output: &mut dyn Write) -> io::Result<()>;
+}
+
+/// The CAS format: the raw sequence of tape blocks, each one starting with its prefix bytes
+pub struct Cas;
+
+impl TapeFormat for Cas {
+    fn name(&self) -> &'static str {
+        "cas"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["cas"]
+    }
+
+    fn read(&self, input: &mut dyn Read) -> io::Result<Tape> {
+        Tape::read(&mut &mut *input)
+    }
+
+    fn write(&self, tape: &Tape, output: &mut dyn Write) -> io::Result<()> {
+        tape.write(output)
+    }
+}
+
+/// The WAV format: 8-bit mono PCM audio as produced by `wav::Exporter`
+///
+/// Recordings of real tapes are read with `decode::decode()`, which also accepts 16-bit
+/// and multichannel audio.
+///
+pub struct Wav;
+
+impl TapeFormat for Wav {
+    fn name(&self) -> &'static str {
+        "wav"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["wav"]
+    }
+
+    fn read(&self, input: &mut dyn Read) -> io::Result<Tape> {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+        Ok(decode::decode(&bytes)?.tape)
+    }
+
+    fn write(&self, tape: &Tape, output: &mut dyn Write) -> io::Result<()> {
+        encode(tape)?.export(&mut &mut *output)
+    }
+}
+
+/// The CSW format: compressed square wave (version 1.01, RLE compression)
+///
+/// The pulses are obtained from the same signal produced for the WAV format, by
+/// measuring the length of each run of samples above or below the zero level.
+///
+pub struct Csw;
+
+impl TapeFormat for Csw {
+    fn name(&self) -> &'static str {
+        "csw"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["csw"]
+    }
+
+    fn read(&self, _input: &mut dyn Read) -> io::Result<Tape> {
+        Err(unsupported("reading CSW files"))
+    }
+
+    fn write(&self, tape: &Tape, output: &mut dyn Write) -> io::Result<()> {
+        let exporter = encode(tape)?;
+        let samples = exporter.samples();
+        let high = |s: u8| s >= 0x80;
+
+        output.write_all(b"Compressed Square Wave\x1a")?;
+        output.write_all(&[1, 1])?;
+        output.write_u16::<LittleEndian>(exporter.sample_rate() as u16)?;
+        output.write_all(&[1])?;
+        output.write_all(&[samples.first().map(|s| high(*s) as u8).unwrap_or(0)])?;
+        output.write_all(&[0, 0, 0])?;
+
+        let mut i = 0;
+        while i < samples.len() {
+            let level = high(samples[i]);
+            let len = samples[i..]
+                .iter()
+                .take_while(|s| high(**s) == level)
+                .count();
+            if len < 256 {
+                output.write_all(&[len as u8])?;
+            } else {
+                output.write_all(&[0])?;
+                output.write_u32::<LittleEndian>(len as u32)?;
+            }
+            i += len;
+        }
+        Ok(())
+    }
+}
+
+/// The TSX format: TZX 1.21 files whose data are stored in "Kansas City Standard" blocks
+///
+/// Every block of the tape is stored in a KCS block (ID `0x4B`) with the pulses of the
+/// MSX encoding at the speed `profile` gives to it, including turbo speeds, and followed
+/// by the gap that precedes the next block. Reading keeps the data of the KCS blocks and skips any other block (e.g., text
+/// descriptions and archive info), since tapes have no place for them.
+///
+/// Writing puts `archive_info` in an archive info block (ID `0x32`) at the start of the
+/// file, and every text of `descriptions` in a text description block (ID `0x30`) before
+/// the block of the tape at its index. Texts are stored in ASCII, so other characters
+/// are replaced by `?`, and cut to 255 bytes.
+///
+#[derive(Default)]
+pub struct Tsx {
+    pub profile: wav::Profile,
+    /// The archive info of the tape, as pairs of TZX text ID (e.g., `0x00` for the full
+    /// title) and text
+    pub archive_info: Vec<(u8, String)>,
+    /// The texts to describe the blocks at the given indices with
+    pub descriptions: Vec<(usize, String)>,
+}
+
+/// The clock of the TZX format, in T-states per second
+const TZX_CLOCK: u32 = 3_500_000;
+
+impl TapeFormat for Tsx {
+    fn name(&self) -> &'static str {
+        "tsx"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["tsx", "tzx"]
+    }
+
+    fn read(&self, input: &mut dyn Read) -> io::Result<Tape> {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+        if bytes.len() < 10 || &bytes[..8] != b"ZXTape!\x1a" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a TSX file"));
+        }
+        let mut tape = Tape::new();
+        let mut i = 10;
+        while i < bytes.len() {
+            let (id, body) = (bytes[i], &bytes[i + 1..]);
+            let len = tzx_block_len(id, body)
+                .filter(|len| *len <= body.len())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("truncated TSX block 0x{:02x} at offset {}", id, i),
+                    )
+                })?;
+            if id == 0x4b && len >= 16 {
+                tape.append_custom(&body[16..len])?;
+            }
+            i += 1 + len;
+        }
+        Ok(tape)
+    }
+
+    fn write(&self, tape: &Tape, output: &mut dyn Write) -> io::Result<()> {
+        let profile = &self.profile;
+        let speeds = profile.block_bauds(tape);
+        output.write_all(b"ZXTape!\x1a")?;
+        output.write_all(&[1, 21])?;
+        if !self.archive_info.is_empty() {
+            let texts: Vec<(u8, Vec<u8>)> = self
+                .archive_info
+                .iter()
+                .take(255)
+                .map(|(id, text)| (*id, tzx_text(text)))
+                .collect();
+            let len: usize = texts.iter().map(|(_, text)| 2 + text.len()).sum();
+            output.write_all(&[0x32])?;
+            output.write_u16::<LittleEndian>(1 + len as u16)?;
+            output.write_all(&[texts.len() as u8])?;
+            for (id, text) in texts {
+                output.write_all(&[id, text.len() as u8])?;
+                output.write_all(&text)?;
+            }
+        }
+        let blocks = tape.blocks();
+        for (i, block) in blocks.iter().enumerate() {
+            for (_, text) in self.descriptions.iter().filter(|(at, _)| *at == i) {
+                let text = tzx_text(text);
+                output.write_all(&[0x30, text.len() as u8])?;
+                output.write_all(&text)?;
+            }
+            // Pulses are half cycles: 1 bits are two cycles at twice the speed, 0 bits one
+            let bauds = speeds[i];
+            let one = (TZX_CLOCK / (bauds * 4)) as u16;
+            let zero = (TZX_CLOCK / (bauds * 2)) as u16;
+            let cycles = if block.is_file_header() {
+                wav::LONG_HEADER
+            } else {
+                wav::SHORT_HEADER
+            };
+            let pause = match blocks.get(i + 1) {
+                Some(next) if next.is_file_header() => profile.long_gap_ms,
+                Some(_) => profile.short_gap_ms,
+                None => 0,
+            };
+            let data = block.data_without_prefix();
+            output.write_all(&[0x4b])?;
+            output.write_u32::<LittleEndian>(12 + data.len() as u32)?;
+            output.write_u16::<LittleEndian>(pause.min(u16::MAX as u32) as u16)?;
+            output.write_u16::<LittleEndian>(one)?;
+            output.write_u16::<LittleEndian>((cycles * 2 * bauds / 1200) as u16)?;
+            output.write_u16::<LittleEndian>(zero)?;
+            output.write_u16::<LittleEndian>(one)?;
+            // 2 pulses per 0 bit and 4 per 1 bit; a 0 start bit, two 1 stop bits, LSb first
+            output.write_all(&[0x24, 0x54])?;
+            output.write_all(data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the given text as stored in TZX blocks: in ASCII and 255 bytes long at most
+fn tzx_text(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .take(255)
+        .collect()
+}
+
+/// Returns the length of the TZX block with the given ID, not counting the ID byte
+///
+/// `body` is the data that follows the ID. Returns `None` if it is too short to tell
+/// the length of the block. Blocks unknown to TZX 1.21 follow the rule of extension
+/// blocks: their length is given by the 4 bytes after the ID.
+///
+fn tzx_block_len(id: u8, body: &[u8]) -> Option<usize> {
+    let byte = |at: usize| body.get(at).map(|b| *b as usize);
+    let word = |at: usize| Some(byte(at)? | byte(at + 1)? << 8);
+    let triple = |at: usize| Some(word(at)? | byte(at + 2)? << 16);
+    let dword = |at: usize| Some(triple(at)? | byte(at + 3)? << 24);
+    match id {
+        0x10 => Some(0x04 + word(0x02)?),
+        0x11 => Some(0x12 + triple(0x0f)?),
+        0x12 => Some(0x04),
+        0x13 => Some(0x01 + 2 * byte(0x00)?),
+        0x14 => Some(0x0a + triple(0x07)?),
+        0x15 => Some(0x08 + triple(0x05)?),
+        0x20 | 0x23 | 0x24 => Some(0x02),
+        0x21 | 0x30 => Some(0x01 + byte(0x00)?),
+        0x22 | 0x25 | 0x27 => Some(0),
+        0x26 => Some(0x02 + 2 * word(0x00)?),
+        0x28 | 0x32 => Some(0x02 + word(0x00)?),
+        0x31 => Some(0x02 + byte(0x01)?),
+        0x33 => Some(0x01 + 3 * byte(0x00)?),
+        0x35 => Some(0x14 + dword(0x10)?),
+        0x5a => Some(0x09),
+        _ => Some(0x04 + dword(0x00)?),
+    }
+}
+
+/// Returns all the supported formats.
+pub fn formats() -> Vec<Box<dyn TapeFormat>> {
+    vec![
+        Box::new(Cas),
+        Box::new(Wav),
+        Box::new(Csw),
+        Box::new(Tsx::default()),
+    ]
+}
+
+/// Returns the format corresponding to the extension of the given path, if any.
+pub fn for_path(path: &Path) -> Option<Box<dyn TapeFormat>> {
+    for_extension(path.extension()?.to_str()?)
+}
+
+/// Returns the format corresponding to the given file extension (without dot), if any.
+pub fn for_extension(ext: &str) -> Option<Box<dyn TapeFormat>> {
+    let ext = ext.to_lowercase();
+    formats()
+        .into_iter()
+        .find(|f| f.extensions().contains(&ext.as_str()))
+}
+
+fn encode(tape: &Tape) -> io::Result<wav::Exporter> {
+    let mut exporter = wav::Exporter::new();
+    exporter.write_tape(tape, &mut progress::ignore())?;
+    Ok(exporter)
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported", what),
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::path::Path;
+
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use super::*;
+    use crate::tape;
+
+    fn sample_tape() -> Tape {
+        let mut tape = Tape::new();
+        let (fname, _) = tape::file_name("foobar");
+        tape.append_bin(&fname, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0x01, 0x02])
+            .unwrap();
+        tape
+    }
+
+    #[test]
+    fn should_write_and_read_cas() {
+        let tape = sample_tape();
+        let mut bytes = vec![];
+        Cas.write(&tape, &mut bytes).unwrap();
+        assert_eq!(tape.serialized_len(), bytes.len());
+
+        let read = Cas.read(&mut &bytes[..]).unwrap();
+        assert_eq!(tape.blocks().len(), read.blocks().len());
+        for (a, b) in tape.blocks().iter().zip(read.blocks()) {
+            assert_eq!(a.data(), b.data());
+        }
+    }
+
+    #[test]
+    fn should_write_and_read_wav() {
+        let mut bytes = vec![];
+        Wav.write(&sample_tape(), &mut bytes).unwrap();
+        assert_eq!(b"RIFF", &bytes[0..4]);
+        assert_eq!(
+            bytes.len() as u32 - 44,
+            LittleEndian::read_u32(&bytes[40..44])
+        );
+        let read = Wav.read(&mut &bytes[..]).unwrap();
+        assert_eq!(sample_tape().to_bytes(), read.to_bytes());
+        assert!(Wav.read(&mut &b"RIFF"[..]).is_err());
+    }
+
+    #[test]
+    fn should_write_csw() {
+        let tape = sample_tape();
+        let mut bytes = vec![];
+        Csw.write(&tape, &mut bytes).unwrap();
+        assert_eq!(b"Compressed Square Wave\x1a", &bytes[0..23]);
+        assert_eq!(&[1, 1], &bytes[23..25]);
+        assert_eq!(43200, LittleEndian::read_u16(&bytes[25..27]));
+        assert_eq!(1, bytes[27]);
+
+        let mut total = 0;
+        let mut i = 32;
+        while i < bytes.len() {
+            if bytes[i] == 0 {
+                total += LittleEndian::read_u32(&bytes[i + 1..i + 5]) as usize;
+                i += 5;
+            } else {
+                total += bytes[i] as usize;
+                i += 1;
+            }
+        }
+        assert_eq!(encode(&tape).unwrap().samples().len(), total);
+    }
+
+    #[test]
+    fn should_write_and_read_tsx() {
+        let mut tape = sample_tape();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        let mut bytes = vec![];
+        Tsx::default().write(&tape, &mut bytes).unwrap();
+        assert_eq!(b"ZXTape!\x1a\x01\x15", &bytes[0..10]);
+        assert_eq!(0x4b, bytes[10]);
+        assert_eq!(12 + 16, LittleEndian::read_u32(&bytes[11..15]));
+        assert_eq!(1000, LittleEndian::read_u16(&bytes[15..17]));
+        assert_eq!(729, LittleEndian::read_u16(&bytes[17..19]));
+        assert_eq!(32000, LittleEndian::read_u16(&bytes[19..21]));
+        assert_eq!(1458, LittleEndian::read_u16(&bytes[21..23]));
+        assert_eq!(729, LittleEndian::read_u16(&bytes[23..25]));
+        assert_eq!(&[0x24, 0x54], &bytes[25..27]);
+
+        let read = Tsx::default().read(&mut &bytes[..]).unwrap();
+        assert_eq!(tape.to_bytes(), read.to_bytes());
+
+        // Other blocks, like text descriptions, are skipped
+        let mut with_text = bytes[..10].to_vec();
+        with_text.extend_from_slice(&[0x30, 3, b'f', b'o', b'o']);
+        with_text.extend_from_slice(&bytes[10..]);
+        let read = Tsx::default().read(&mut &with_text[..]).unwrap();
+        assert_eq!(tape.to_bytes(), read.to_bytes());
+
+        assert!(Tsx::default().read(&mut &bytes[..20]).is_err());
+        assert!(Tsx::default().read(&mut &b"ZXTape"[..]).is_err());
+
+        let tsx = Tsx {
+            archive_info: vec![(0x00, "Game".to_string()), (0x03, "1986".to_string())],
+            descriptions: vec![(0, "Año".to_string())],
+            ..Tsx::default()
+        };
+        let mut with_info = vec![];
+        tsx.write(&tape, &mut with_info).unwrap();
+        assert_eq!(
+            &[0x32, 13, 0, 2, 0x00, 4, b'G', b'a', b'm', b'e', 0x03, 4, b'1', b'9', b'8', b'6'],
+            &with_info[10..26]
+        );
+        assert_eq!(&[0x30, 3, b'A', b'?', b'o', 0x4b], &with_info[26..32]);
+        assert_eq!(&bytes[10..], &with_info[31..]);
+        let read = Tsx::default().read(&mut &with_info[..]).unwrap();
+        assert_eq!(tape.to_bytes(), read.to_bytes());
+    }
+
+    #[test]
+    fn should_find_format_for_path() {
+        assert_eq!("cas", for_path(Path::new("foo.cas")).unwrap().name());
+        assert_eq!("wav", for_path(Path::new("foo.WAV")).unwrap().name());
+        assert_eq!(
+            "csw",
+            for_path(Path::new("/path/to/foo.csw")).unwrap().name()
+        );
+        assert_eq!("tsx", for_path(Path::new("foo.tzx")).unwrap().name());
+        assert!(for_path(Path::new("foo.bin")).is_none());
+        assert!(for_path(Path::new("foo")).is_none());
+        assert_eq!("tsx", for_extension("TZX").unwrap().name());
+    }
+}
@@ -0,0 +1,492 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Compression and decompression of gzip files
+//!
+//! Tape archives frequently store their dumps compressed with gzip (`.cas.gz`). This
+//! module implements the subset of gzip (RFC 1952) and deflate (RFC 1951) needed to read
+//! any gzip file and to write compressed tapes, so they can be handled without external
+//! tools. Compression uses the fixed Huffman codes of deflate, which is simple and good
+//! enough for the long runs of repeated bytes found in tapes.
+
+use std::io;
+
+/// The magic bytes every gzip file starts with
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The size of the deflate window
+const WINDOW: usize = 32 * 1024;
+
+/// The shortest and longest matches encoded by deflate
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order of the code lengths of the code length alphabet in dynamic blocks
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Returns `true` if the given bytes start like a gzip file.
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Decompress the given gzip file.
+///
+/// Files made of several gzip members are decompressed as the concatenation of their
+/// members. Fails with an error of kind `InvalidData` if the file is not a valid gzip
+/// file or its checksum does not match.
+///
+pub fn decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = vec![];
+    let mut input = bytes;
+    loop {
+        let start = output.len();
+        let data = skip_header(input)?;
+        let mut bits = BitReader::new(data);
+        inflate(&mut bits, &mut output)?;
+        let trailer = &data[bits.byte_pos()..];
+        if trailer.len() < 8 {
+            return Err(invalid("truncated gzip trailer"));
+        }
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&output[start..]) || size != (output.len() - start) as u32 {
+            return Err(invalid("gzip checksum mismatch"));
+        }
+        input = &trailer[8..];
+        if !is_gzip(input) {
+            return Ok(output);
+        }
+    }
+}
+
+/// Compress the given data as a gzip file.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // No modification time, no extra flags, unknown operating system
+    let mut output = vec![MAGIC[0], MAGIC[1], 8, 0, 0, 0, 0, 0, 0, 0xff];
+    let mut bits = BitWriter::new(&mut output);
+    deflate(data, &mut bits);
+    bits.flush();
+    output.extend_from_slice(&crc32(data).to_le_bytes());
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returns the deflate stream that follows the header of a gzip member
+fn skip_header(bytes: &[u8]) -> io::Result<&[u8]> {
+    if bytes.len() < 10 || !is_gzip(bytes) {
+        return Err(invalid("not a gzip file"));
+    }
+    if bytes[2] != 8 {
+        return Err(invalid("unsupported gzip compression method"));
+    }
+    let flags = bytes[3];
+    let mut rest = &bytes[10..];
+    let truncated = || invalid("truncated gzip header");
+    if flags & 0x04 != 0 {
+        let len = match rest {
+            [lo, hi, ..] => u16::from_le_bytes([*lo, *hi]) as usize,
+            _ => return Err(truncated()),
+        };
+        rest = rest.get(2 + len..).ok_or_else(truncated)?;
+    }
+    for flag in &[0x08, 0x10] {
+        if flags & flag != 0 {
+            let end = rest.iter().position(|b| *b == 0).ok_or_else(truncated)?;
+            rest = &rest[end + 1..];
+        }
+    }
+    if flags & 0x02 != 0 {
+        rest = rest.get(2..).ok_or_else(truncated)?;
+    }
+    Ok(rest)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    !data.iter().fold(!0u32, |crc, b| {
+        table[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            pos: 0,
+            bit: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u8) -> io::Result<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| invalid("truncated deflate stream"))?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    /// Returns the position of the next whole byte
+    fn byte_pos(&self) -> usize {
+        self.pos + (self.bit != 0) as usize
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the symbols
+/// sorted by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|s| lengths[*s as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|s| lengths[*s as usize]);
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code in deflate stream"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].iter_mut().for_each(|l| *l = 9);
+    lengths[256..280].iter_mut().for_each(|l| *l = 7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn inflate(bits: &mut BitReader, output: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let pos = bits.pos;
+                let header = bits
+                    .data
+                    .get(pos..pos + 4)
+                    .ok_or_else(|| invalid("truncated deflate stream"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(invalid("invalid stored block length"));
+                }
+                let data = bits
+                    .data
+                    .get(pos + 4..pos + 4 + len as usize)
+                    .ok_or_else(|| invalid("truncated deflate stream"))?;
+                output.extend_from_slice(data);
+                bits.pos += 4 + len as usize;
+            }
+            1 => {
+                let (litlen, dist) = fixed_codes();
+                inflate_block(bits, output, &litlen, &dist)?;
+            }
+            2 => {
+                let (litlen, dist) = dynamic_codes(bits)?;
+                inflate_block(bits, output, &litlen, &dist)?;
+            }
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn dynamic_codes(bits: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let nlen = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let ncode = bits.bits(4)? as usize + 4;
+    let mut clens = [0u8; 19];
+    for index in CLEN_ORDER.iter().take(ncode) {
+        clens[*index] = bits.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clens);
+    let mut lengths = vec![];
+    while lengths.len() < nlen + ndist {
+        let (value, repeat) = match clen.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("invalid code lengths"))?;
+                (prev, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > nlen + ndist {
+        return Err(invalid("invalid code lengths"));
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen]),
+        Huffman::new(&lengths[nlen..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    output: &mut Vec<u8>,
+    litlen: &Huffman,
+    dist: &Huffman,
+) -> io::Result<()> {
+    loop {
+        let symbol = litlen.decode(bits)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let index = symbol - 257;
+        if index >= LEN_BASE.len() {
+            return Err(invalid("invalid length in deflate stream"));
+        }
+        let len = LEN_BASE[index] as usize + bits.bits(LEN_EXTRA[index])? as usize;
+        let index = dist.decode(bits)? as usize;
+        if index >= DIST_BASE.len() {
+            return Err(invalid("invalid distance in deflate stream"));
+        }
+        let distance = DIST_BASE[index] as usize + bits.bits(DIST_EXTRA[index])? as usize;
+        if distance > output.len() {
+            return Err(invalid("invalid distance in deflate stream"));
+        }
+        let from = output.len() - distance;
+        for i in 0..len {
+            output.push(output[from + i]);
+        }
+    }
+}
+
+struct BitWriter<'a> {
+    output: &'a mut Vec<u8>,
+    acc: u32,
+    count: u8,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(output: &'a mut Vec<u8>) -> BitWriter<'a> {
+        BitWriter {
+            output,
+            acc: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u8) {
+        self.acc |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.output.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is stored starting from its most significant bit
+    fn code(&mut self, code: u32, len: u8) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.output.push(self.acc as u8);
+            self.acc = 0;
+            self.count = 0;
+        }
+    }
+}
+
+fn write_literal(bits: &mut BitWriter, symbol: u16) {
+    match symbol {
+        0..=143 => bits.code(0x30 + symbol as u32, 8),
+        144..=255 => bits.code(0x190 + (symbol - 144) as u32, 9),
+        256..=279 => bits.code((symbol - 256) as u32, 7),
+        _ => bits.code(0xc0 + (symbol - 280) as u32, 8),
+    }
+}
+
+fn write_match(bits: &mut BitWriter, len: usize, distance: usize) {
+    let index = LEN_BASE
+        .iter()
+        .rposition(|b| *b as usize <= len)
+        .unwrap_or(0);
+    write_literal(bits, 257 + index as u16);
+    bits.bits((len - LEN_BASE[index] as usize) as u32, LEN_EXTRA[index]);
+    let index = DIST_BASE
+        .iter()
+        .rposition(|b| *b as usize <= distance)
+        .unwrap_or(0);
+    bits.code(index as u32, 5);
+    bits.bits(
+        (distance - DIST_BASE[index] as usize) as u32,
+        DIST_EXTRA[index],
+    );
+}
+
+/// Compress the data as a single block with fixed Huffman codes, finding matches with a
+/// hash table of the last position of every 3-byte sequence
+fn deflate(data: &[u8], bits: &mut BitWriter) {
+    const HASH_BITS: u32 = 15;
+    let hash = |i: usize| {
+        let key = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+    let mut last = vec![usize::MAX; 1 << HASH_BITS];
+    bits.bits(1, 1);
+    bits.bits(1, 2);
+    let mut i = 0;
+    while i < data.len() {
+        let mut len = 0;
+        let mut distance = 0;
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            let candidate = last[h];
+            last[h] = i;
+            if candidate != usize::MAX && i - candidate <= WINDOW {
+                let max = MAX_MATCH.min(data.len() - i);
+                len = (0..max)
+                    .take_while(|k| data[candidate + k] == data[i + k])
+                    .count();
+                distance = i - candidate;
+            }
+        }
+        if len >= MIN_MATCH {
+            write_match(bits, len, distance);
+            for j in i + 1..(i + len).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+                last[hash(j)] = j;
+            }
+            i += len;
+        } else {
+            write_literal(bits, data[i] as u16);
+            i += 1;
+        }
+    }
+    write_literal(bits, 256);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_decompress_gzip() {
+        // `printf 'hello hello hello\n' | gzip -n`
+        let bytes = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+            0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x3b, 0x7c, 0x8a, 0xdf, 0x12, 0x00, 0x00,
+            0x00,
+        ];
+        assert!(is_gzip(&bytes));
+        assert_eq!(b"hello hello hello\n", &decompress(&bytes).unwrap()[..]);
+
+        let mut corrupted = bytes;
+        corrupted[21] ^= 1;
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            decompress(&corrupted).unwrap_err().kind()
+        );
+        assert!(decompress(b"not gzip").is_err());
+    }
+
+    #[test]
+    fn should_compress_gzip() {
+        let mut data = vec![0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+        data.extend(std::iter::repeat_n(0xd0, 10));
+        data.extend(b"GAME  ");
+        data.extend((0..4000).map(|i| (i % 7) as u8));
+        data.extend((0..300).map(|i| (i * 31 % 251) as u8));
+        let compressed = compress(&data);
+        assert!(compressed.len() < data.len() / 4);
+        assert_eq!(data, decompress(&compressed).unwrap());
+
+        let twice = [compress(b"foo"), compress(b"bar")].concat();
+        assert_eq!(b"foobar", &decompress(&twice).unwrap()[..]);
+        assert_eq!(b"", &decompress(&compress(b"")).unwrap()[..]);
+    }
+}
@@ -0,0 +1,160 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Conversion of Intel HEX files into binary files
+//!
+//! Intel HEX is the output format of many Z80 toolchains (e.g., `sdcc` and `z88dk` write
+//! `.ihx` files). Its records carry the address of every chunk of data, so the begin and
+//! end addresses of the binary file are known, and a start address record gives its
+//! entry point.
+
+use std::io;
+use std::path::Path;
+
+/// The highest address an MSX binary file can reach
+const MAX_ADDRESS: usize = 0xffff;
+
+/// Returns `true` if the given path has the extension of Intel HEX files (`hex` or `ihx`,
+/// case insensitive).
+pub fn has_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("hex") || e.eq_ignore_ascii_case("ihx"))
+}
+
+/// Convert the given Intel HEX text into the contents of a binary file.
+///
+/// The result has the layout of `.bin` files: the `0xfe` ID byte, the begin, end and start
+/// addresses, and the bytes from begin to end. Gaps between records are filled with
+/// zeros. The start address is taken from a start address record (type 03 or 05), or is
+/// the begin address if there is none.
+///
+/// Fails with an error of kind `InvalidData` if a record is malformed, its checksum does
+/// not match or its data lie beyond address `0xffff`.
+///
+pub fn to_bin(text: &[u8]) -> io::Result<Vec<u8>> {
+    let mut memory = vec![None; MAX_ADDRESS + 1];
+    let mut base = 0;
+    let mut start = None;
+    let mut eof = false;
+    let lines = text.split(|b| *b == b'\n').enumerate();
+    for (number, line) in lines.filter(|(_, l)| !l.trim_ascii().is_empty()) {
+        let error = |msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid Intel HEX record at line {}: {}", number + 1, msg),
+            )
+        };
+        if eof {
+            return Err(error("data after the end of file record"));
+        }
+        let record = parse_record(line.trim_ascii()).map_err(&error)?;
+        let (offset, kind, data) = (record.0 as usize, record.1, &record.2[..]);
+        match (kind, data.len()) {
+            (0x00, _) => {
+                let addr = base + offset;
+                if addr + data.len() > MAX_ADDRESS + 1 {
+                    return Err(error("data beyond address 0xffff"));
+                }
+                for (i, byte) in data.iter().enumerate() {
+                    memory[addr + i] = Some(*byte);
+                }
+            }
+            (0x01, 0) => eof = true,
+            (0x02, 2) => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 4,
+            (0x04, 2) => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16,
+            (0x03, 4) => start = Some(u16::from_be_bytes([data[2], data[3]])),
+            (0x05, 4) => start = Some(u16::from_be_bytes([data[2], data[3]])),
+            (0x01..=0x05, _) => return Err(error("unexpected record length")),
+            _ => return Err(error("unknown record type")),
+        }
+    }
+    let begin = memory.iter().position(|b| b.is_some());
+    let end = memory.iter().rposition(|b| b.is_some());
+    let (begin, end) = match (begin, end) {
+        (Some(begin), Some(end)) => (begin, end),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the Intel HEX file has no data",
+            ))
+        }
+    };
+    let start = start.unwrap_or(begin as u16);
+    let mut bin = vec![0xfe];
+    for addr in &[begin as u16, end as u16, start] {
+        bin.extend_from_slice(&addr.to_le_bytes());
+    }
+    bin.extend(memory[begin..=end].iter().map(|b| b.unwrap_or(0)));
+    Ok(bin)
+}
+
+/// Parse a record (`:LLAAAATT<data>CC`) into its address, type and data
+fn parse_record(line: &[u8]) -> Result<(u16, u8, Vec<u8>), &'static str> {
+    let hex = line.strip_prefix(b":").ok_or("missing start code")?;
+    if hex.len() < 10 || hex.len() % 2 != 0 {
+        return Err("truncated record");
+    }
+    let bytes = hex
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or("invalid hexadecimal digits")
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    let len = bytes[0] as usize;
+    if bytes.len() != len + 5 {
+        return Err("length does not match the data");
+    }
+    if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        return Err("checksum mismatch");
+    }
+    let addr = u16::from_be_bytes([bytes[1], bytes[2]]);
+    Ok((addr, bytes[3], bytes[4..4 + len].to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_convert_intel_hex() {
+        let text = b":03C00000C30AC0B0\r\n\
+                     :02C00A00AF3C49\r\n\
+                     :04000005 0000C00A2D\r\n\
+                     :00000001FF\r\n";
+        assert!(to_bin(text).is_err());
+
+        let text = b":03C00000C30AC0B0\r\n\
+                     :02C00A00AF3C49\r\n\
+                     :040000050000C00A2D\r\n\
+                     :00000001FF\r\n";
+        assert_eq!(
+            vec![
+                0xfe, 0x00, 0xc0, 0x0b, 0xc0, 0x0a, 0xc0, 0xc3, 0x0a, 0xc0, 0, 0, 0, 0, 0, 0, 0,
+                0xaf, 0x3c
+            ],
+            to_bin(text).unwrap()
+        );
+
+        let err = to_bin(b":03C00000C30AC0B1\n").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            "invalid Intel HEX record at line 1: checksum mismatch",
+            err.to_string()
+        );
+        assert!(to_bin(b":020000040001F9\n:01000000AA55\n").is_err());
+        assert!(to_bin(b":00000001FF\n").is_err());
+
+        assert!(has_extension(Path::new("game.IHX")));
+        assert!(!has_extension(Path::new("game.bin")));
+    }
+}
@@ -0,0 +1,245 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Generation of source code that embeds the contents of tape files
+//!
+//! Projects that reconstruct the sources of a tape release, emulator test suites and
+//! firmware often need to embed some of its files as they are. The functions of this
+//! module render the payload of a file as Z80 assembly (`asm()`) or C (`c_header()`)
+//! source code that can be included in such projects.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::tape::File;
+
+/// The number of bytes written in each line of data
+const BYTES_PER_LINE: usize = 16;
+
+/// The syntax of a Z80 assembler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    /// `sjasmplus`: `db` directives and `0x` hexadecimal numbers
+    Sjasmplus,
+    /// `pasmo`: `defb` directives and `h`-suffixed hexadecimal numbers
+    Pasmo,
+    /// `tniasm`: `db` directives and `$`-prefixed hexadecimal numbers
+    Tniasm,
+}
+
+impl Dialect {
+    fn hex(self, value: u16, digits: usize) -> String {
+        match self {
+            Dialect::Sjasmplus => format!("0x{:0digits$x}", value, digits = digits),
+            // A leading zero tells numbers from labels like `FFh`.
+            Dialect::Pasmo => format!("0{:0digits$X}h", value, digits = digits),
+            Dialect::Tniasm => format!("${:0digits$X}", value, digits = digits),
+        }
+    }
+
+    fn data_directive(self) -> &'static str {
+        match self {
+            Dialect::Pasmo => "defb",
+            _ => "db",
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Dialect::Sjasmplus => "sjasmplus",
+            Dialect::Pasmo => "pasmo",
+            Dialect::Tniasm => "tniasm",
+        })
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Dialect> {
+        match s.to_lowercase().as_str() {
+            "sjasmplus" => Ok(Dialect::Sjasmplus),
+            "pasmo" => Ok(Dialect::Pasmo),
+            "tniasm" => Ok(Dialect::Tniasm),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown assembler {:?} (expected sjasmplus, pasmo or tniasm)",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+/// Returns a label derived from the given file name
+///
+/// The label is the name in uppercase, with any character other than letters, digits
+/// and `_` replaced by `_`, and prefixed with `_` if it starts with a digit (e.g.,
+/// `game.bin` gives `GAME_BIN`).
+///
+pub fn label_of(name: &str) -> String {
+    let mut label: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if label.is_empty() || label.starts_with(|c: char| c.is_ascii_digit()) {
+        label.insert(0, '_');
+    }
+    label
+}
+
+/// Returns the payload of the given file as Z80 assembly source.
+///
+/// Binary files get `equ` definitions of their begin, end and start addresses, named
+/// after `label` (e.g., `GAME_BEGIN`), and an `org` directive at their begin address.
+/// The payload of any file follows `label` as data directives, 16 bytes per line. The
+/// padding of binary files past their end address is not included.
+///
+pub fn asm(file: &File, label: &str, dialect: Dialect) -> String {
+    let mut source = String::new();
+    if let Some(name) = file.name() {
+        source.push_str(&format!("; {}\n", name));
+    }
+    if let File::Bin(_, begin, end, start, _) = file {
+        for (suffix, addr) in &[("BEGIN", begin), ("END", end), ("START", start)] {
+            let addr = dialect.hex(**addr as u16, 4);
+            source.push_str(&format!("{}_{}: equ {}\n", label, suffix, addr));
+        }
+        source.push_str(&format!("\n\torg {}_BEGIN\n", label));
+    }
+    source.push_str(&format!("{}:\n", label));
+    for line in payload(file).chunks(BYTES_PER_LINE) {
+        let bytes: Vec<String> = line.iter().map(|b| dialect.hex(*b as u16, 2)).collect();
+        source.push_str(&format!(
+            "\t{} {}\n",
+            dialect.data_directive(),
+            bytes.join(",")
+        ));
+    }
+    source
+}
+
+/// Returns the payload of the given file as a C header.
+///
+/// The header defines `label` as a `const unsigned char` array with the payload and
+/// `<label>_LEN` as its length. Binary files also get `<label>_BEGIN`, `<label>_END` and
+/// `<label>_START` with their addresses. The padding of binary files past their end
+/// address is not included.
+///
+pub fn c_header(file: &File, label: &str) -> String {
+    let payload = payload(file);
+    let mut source = String::new();
+    if let Some(name) = file.name() {
+        source.push_str(&format!("/* {} */\n", name));
+    }
+    source.push_str(&format!("#ifndef {0}_H\n#define {0}_H\n\n", label));
+    if let File::Bin(_, begin, end, start, _) = file {
+        for (suffix, addr) in &[("BEGIN", begin), ("END", end), ("START", start)] {
+            source.push_str(&format!("#define {}_{} 0x{:04x}\n", label, suffix, addr));
+        }
+    }
+    source.push_str(&format!("#define {}_LEN {}\n\n", label, payload.len()));
+    source.push_str(&format!(
+        "static const unsigned char {0}[{0}_LEN] = {{\n",
+        label
+    ));
+    for line in payload.chunks(BYTES_PER_LINE) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02x},", b)).collect();
+        source.push_str(&format!("    {}\n", bytes.join(" ")));
+    }
+    source.push_str(&format!("}};\n\n#endif /* {}_H */\n", label));
+    source
+}
+
+/// Returns the payload of the given file, without padding for binary files
+fn payload<'a>(file: &File<'a>) -> Cow<'a, [u8]> {
+    let mut payload = file.payload();
+    if let File::Bin(_, begin, end, _, _) = file {
+        let len = (end + 1).saturating_sub(*begin);
+        if len < payload.len() {
+            payload.to_mut().truncate(len);
+        }
+    }
+    payload
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape::{self, Tape};
+
+    #[test]
+    fn should_generate_asm() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        let mut data = vec![0x00, 0x80, 0x11, 0x80, 0x10, 0x80];
+        data.extend(0..0x12);
+        tape.append_bin(&name, &data).unwrap();
+        let file = tape.files().next().unwrap();
+
+        assert_eq!("GAME_BIN", label_of(&file.name().unwrap()));
+        assert_eq!(
+            "; game.bin\n\
+             GAME_BEGIN: equ 0x8000\n\
+             GAME_END: equ 0x8011\n\
+             GAME_START: equ 0x8010\n\
+             \n\
+             \torg GAME_BEGIN\n\
+             GAME:\n\
+             \tdb 0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b,0x0c,0x0d,0x0e,0x0f\n\
+             \tdb 0x10,0x11\n",
+            asm(&file, "GAME", Dialect::Sjasmplus)
+        );
+
+        let file = tape::File::Custom(&[0xff, 0x0a]);
+        assert_eq!(
+            "_001:\n\tdefb 0FFh,00Ah\n",
+            asm(&file, &label_of("001"), Dialect::Pasmo)
+        );
+        assert_eq!("DATA:\n\tdb $FF,$0A\n", asm(&file, "DATA", Dialect::Tniasm));
+        assert_eq!(Dialect::Tniasm, "TNIASM".parse().unwrap());
+        assert!("masm".parse::<Dialect>().is_err());
+    }
+
+    #[test]
+    fn should_generate_c_header() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xaf, 0xc9])
+            .unwrap();
+        let file = tape.files().next().unwrap();
+        assert_eq!(
+            "/* game.bin */\n\
+             #ifndef GAME_H\n\
+             #define GAME_H\n\
+             \n\
+             #define GAME_BEGIN 0x8000\n\
+             #define GAME_END 0x8001\n\
+             #define GAME_START 0x8000\n\
+             #define GAME_LEN 2\n\
+             \n\
+             static const unsigned char GAME[GAME_LEN] = {\n    0xaf, 0xc9,\n};\n\
+             \n\
+             #endif /* GAME_H */\n",
+            c_header(&file, "GAME")
+        );
+    }
+}
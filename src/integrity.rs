@@ -0,0 +1,178 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Checks of the structure of tapes, as reported by `mcp check`
+//!
+//! Dumps of real cassettes and tapes written by other tools are not always well formed.
+//! `check()` walks the blocks of a tape and reports the problems that make its files
+//! unreadable or different from what they claim to be, so damaged tapes can be told
+//! apart before they are archived.
+
+use crate::load::{self, Severity};
+use crate::tape::{File, Tape};
+
+/// The bytes that start every block
+const PREFIX: [u8; 8] = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+
+/// A problem found in the structure of a tape
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    /// The index of the offending block, if any
+    pub block: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    fn error(block: Option<usize>, message: String) -> Problem {
+        Problem {
+            severity: Severity::Error,
+            block,
+            message,
+        }
+    }
+
+    fn warning(block: Option<usize>, message: String) -> Problem {
+        Problem {
+            severity: Severity::Warning,
+            block,
+            message,
+        }
+    }
+}
+
+/// Returns the problems found in the structure of the given tape, sorted by block
+///
+/// Errors are the problems that make files unreadable or inconsistent: data without a
+/// block prefix, headers without their data block, binary files whose data block does
+/// not match their addresses, ASCII files without EOF and block prefixes that are not
+/// aligned to 8 bytes, which are read as data of the previous block. Warnings are the
+/// blocks that belong to no file, which are fine for tapes with custom loaders, and
+/// other oddities that do not prevent reading the files.
+///
+pub fn check(tape: &Tape) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = tape
+        .problems()
+        .into_iter()
+        .map(|e| Problem::error(e.block(), e.to_string()))
+        .collect();
+    let reported: Vec<Option<usize>> = problems.iter().map(|p| p.block).collect();
+
+    for (blocks, file) in tape.file_extents() {
+        let block = blocks.start;
+        match file {
+            File::Bin(..) => {
+                problems.extend(load::check_length(&file).into_iter().map(|p| Problem {
+                    severity: p.severity,
+                    block: Some(block + 1),
+                    message: p.message,
+                }))
+            }
+            File::Custom(data) if !reported.contains(&Some(block)) => {
+                problems.push(Problem::warning(
+                    Some(block),
+                    format!("block of {} bytes that belongs to no file", data.len()),
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let blocks = tape.blocks();
+    let mut offset = tape.preamble().len();
+    for (i, block) in blocks.iter().enumerate() {
+        let data = block.data();
+        for pos in (1..data.len().saturating_sub(7)).filter(|p| !(offset + p).is_multiple_of(8)) {
+            if data[pos..pos + 8] == PREFIX {
+                problems.push(Problem::error(
+                    Some(i),
+                    format!(
+                        "block prefix at unaligned offset 0x{:x}, read as data of this block",
+                        offset + pos
+                    ),
+                ));
+            }
+        }
+        if i == blocks.len() - 1 && !data.len().is_multiple_of(8) {
+            problems.push(Problem::warning(
+                Some(i),
+                "the last block is not padded to a multiple of 8 bytes".to_string(),
+            ));
+        }
+        offset += data.len();
+    }
+
+    problems.sort_by_key(|p| p.block);
+    problems
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape;
+
+    #[test]
+    fn should_check_structure() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xaf, 0xc9])
+            .unwrap();
+        assert_eq!(Vec::<Problem>::new(), check(&tape));
+
+        // A data block shorter than its addresses with a prefix misaligned by 4 bytes,
+        // a block of no file and an unpadded header without its data block
+        let header = tape.blocks()[0].data();
+        let bytes = [
+            header,
+            &PREFIX[..],
+            &[0x00, 0x90, 0x20, 0x90, 0x00, 0x90, 0xaf, 0xc9, 0, 0, 0, 0][..],
+            &PREFIX[..],
+            &[0; 4][..],
+            &PREFIX[..],
+            &[0x42; 8][..],
+            &PREFIX[..],
+            &[0xea; 10][..],
+            &header[18..],
+            &[0; 4][..],
+        ]
+        .concat();
+        let problems: Vec<(Severity, Option<usize>, String)> = check(&Tape::from_bytes(&bytes))
+            .into_iter()
+            .map(|p| (p.severity, p.block, p.message))
+            .collect();
+        assert_eq!(5, problems.len());
+        assert_eq!(
+            (
+                Severity::Error,
+                Some(1),
+                "the data block has 18 bytes, but BLOAD reads 33".to_string()
+            ),
+            problems[0]
+        );
+        assert_eq!(
+            (
+                Severity::Error,
+                Some(1),
+                "block prefix at unaligned offset 0x2c, read as data of this block".to_string()
+            ),
+            problems[1]
+        );
+        assert_eq!(
+            (
+                Severity::Warning,
+                Some(2),
+                "block of 8 bytes that belongs to no file".to_string()
+            ),
+            problems[2]
+        );
+        assert_eq!((Severity::Error, Some(3)), (problems[3].0, problems[3].1));
+        assert!(problems[3].2.starts_with("header without data block"));
+        assert_eq!((Severity::Warning, Some(3)), (problems[4].0, problems[4].1));
+    }
+}
@@ -0,0 +1,114 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! MSX CAS Packager library
+//!
+//! This crate provides the tape handling logic behind the `mcp` command line tool, so
+//! it can be reused by emulators and other tools without the CLI dependencies. The
+//! `mcp` binary is only built when the `cli` feature (enabled by default) is active.
+//!
+//! The core of the crate works on bytes and `Read`/`Write` objects only, so it builds
+//! for targets without a filesystem such as `wasm32-unknown-unknown`. The functions that
+//! read and write host files (e.g., `Tape::from_file()` or the `dir` and `file` modules)
+//! are only built with the `fs` feature, which `cli` enables.
+//!
+//! The core types are `Tape`, the sequence of `Block`s of a CAS file, and `File`, the
+//! files those blocks make up. They are found in the `tape` module and re-exported at
+//! the root of the crate:
+//!
+//! ```
+//! use mcp::{File, Tape};
+//!
+//! let mut tape = Tape::new();
+//! let (name, _) = mcp::tape::file_name("game");
+//! tape.append_bin(&name, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xaf, 0xc9])?;
+//!
+//! let tape = Tape::from_bytes(&tape.to_bytes());
+//! for file in tape.files() {
+//!     if let File::Bin(name, begin, end, start, _) = file {
+//!         println!("{}: [0x{:x},0x{:x}]:0x{:x}", name, begin, end, start);
+//!     }
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! `TapeBuilder`, also at the root of the crate, builds a tape from a list of files and
+//! checks their names and data before returning it, instead of clipping long names.
+//!
+//! The rest of the modules build on them:
+//!
+//! * `format` reads and writes tapes in other containers (WAV, CSW and TSX), and `wav`
+//!   and `decode` encode and decode their audio. `remote` starts and stops a cassette
+//!   player through its remote jack. `machine` reads and writes the CAS files of the
+//!   Spectravideo SVI-318 and SVI-328, whose tape format the MSX one descends from.
+//! * `dir` extracts tapes into host directories and packs directories into tapes, as
+//!   `mcp extract` and `mcp add` do, and `dsk` does the same with MSX-DOS disk images.
+//!   `manifest` builds tapes from the list of files of a TOML manifest.
+//! * `basic`, `text`, `encoding`, `ihex` and `include` convert the contents of files:
+//!   Basic programs, ASCII text and its character set, Intel HEX files and embeddable
+//!   source code. `rom` converts cartridge ROMs into tapes.
+//! * `integrity`, `load`, `verifier`, `signature`, `dat` and `hash` check tapes: whether
+//!   they are well formed, whether they load in a real MSX, what their files contain and
+//!   whether they match a DAT file. `analysis` checks the memory binary files load into,
+//!   and `diff` compares two tapes.
+//! * `ffi` exports a C interface to read and build tapes, declared in `include/mcp.h`,
+//!   when the `ffi` feature is enabled.
+
+extern crate byteorder;
+
+#[cfg(test)]
+extern crate quickcheck;
+#[cfg(test)]
+extern crate tempdir;
+
+pub mod analysis;
+pub mod basic;
+pub mod builder;
+pub mod custom;
+pub mod dat;
+pub mod decode;
+pub mod diff;
+pub mod dir;
+pub mod dsk;
+pub mod encoding;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fs")]
+pub mod file;
+#[cfg(feature = "fixture")]
+pub mod fixture;
+pub mod format;
+pub mod gzip;
+pub mod hash;
+pub mod ihex;
+pub mod include;
+pub mod integrity;
+pub mod load;
+pub mod machine;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+#[cfg(feature = "metadata")]
+pub mod meta;
+#[cfg(all(unix, feature = "fs"))]
+pub mod openmsx;
+pub mod progress;
+#[cfg(feature = "fs")]
+pub mod remote;
+pub mod rom;
+pub mod screen;
+pub mod signature;
+pub mod stream;
+pub mod tape;
+pub mod text;
+pub mod verifier;
+pub mod wav;
+
+mod z80;
+
+pub use builder::TapeBuilder;
+pub use tape::{Block, Entry, File, FileKind, FileType, Tape, TapeError};
@@ -0,0 +1,438 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Simulation of how the MSX BIOS loads the files of a tape
+//!
+//! A tape may be well formed and still fail, or crash the machine, once loaded. The
+//! checks of this module follow what `BLOAD`, `CLOAD` and `LOAD` do on a machine without
+//! disk drives, whose Basic environment has the BIOS and Basic ROMs in the lower 32KB of
+//! the address space and the system work area from `0xf380` on:
+//!
+//! * Binary files must fit in the RAM visible in that environment, below the work area,
+//!   and their data block must be as long as their address range.
+//! * Tokenized Basic files must be valid programs that fit in the free memory.
+//! * ASCII files must end with an EOF mark, and if they are Basic programs, their lines
+//!   must be accepted by `LOAD` and the program must fit in the free memory.
+//!
+//! Custom files are read by the programs that load them, so they are not checked.
+//! `check_addresses()` runs the checks of binary files that do not depend on the RAM of
+//! the machine, which tell whether the addresses of a file are consistent at all.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::basic::{self, SyntaxError};
+use crate::tape::File;
+
+/// The first address past the BIOS and Basic ROMs
+const ROMS_END: usize = 0x8000;
+
+/// The first address of the system work area
+const WORK_AREA: usize = 0xf380;
+
+/// The RAM of an MSX machine
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Ram {
+    K16,
+    K32,
+    K64,
+}
+
+impl Ram {
+    /// All the RAM sizes, from the smallest
+    pub const ALL: [Ram; 3] = [Ram::K16, Ram::K32, Ram::K64];
+
+    /// Returns the size of this RAM in KB.
+    pub fn kb(self) -> u32 {
+        match self {
+            Ram::K16 => 16,
+            Ram::K32 => 32,
+            Ram::K64 => 64,
+        }
+    }
+
+    /// Returns the lowest RAM address visible from Basic.
+    ///
+    /// The lower 32KB of 64KB machines are hidden behind the ROMs, so they behave as
+    /// 32KB ones.
+    ///
+    pub fn bottom(self) -> usize {
+        match self {
+            Ram::K16 => 0xc000,
+            _ => 0x8000,
+        }
+    }
+
+    /// Returns the bytes free for Basic programs, as displayed at startup.
+    pub fn bytes_free(self) -> usize {
+        match self {
+            Ram::K16 => 12431,
+            _ => 28815,
+        }
+    }
+}
+
+impl fmt::Display for Ram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&format!("{}KB", self.kb()))
+    }
+}
+
+impl FromStr for Ram {
+    type Err = io::Error;
+
+    /// Parse a RAM size such as `16KB` (or just `16`)
+    fn from_str(s: &str) -> io::Result<Ram> {
+        let lower = s.to_lowercase();
+        let digits = lower
+            .strip_suffix("kb")
+            .or_else(|| lower.strip_suffix('k'))
+            .unwrap_or(&lower);
+        match digits {
+            "16" => Ok(Ram::K16),
+            "32" => Ok(Ram::K32),
+            "64" => Ok(Ram::K64),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid RAM size {:?} (expected 16, 32 or 64)", s),
+            )),
+        }
+    }
+}
+
+/// How serious a problem is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The file loads, but probably not as intended
+    Warning,
+    /// Loading the file fails or crashes the machine
+    Error,
+}
+
+impl Severity {
+    /// Returns the name of this severity: `warning` or `error`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A problem found loading a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Problem {
+    fn error(message: String) -> Problem {
+        Problem {
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Problem {
+        Problem {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+/// Returns the problems found loading the given file in a machine with the given RAM
+pub fn check(file: &File, ram: Ram) -> Vec<Problem> {
+    match file {
+        File::Bin(_, begin, end, start, data) => check_bin(*begin, *end, *start, data, ram),
+        File::Basic(_, data) => check_basic(data, ram),
+        File::Ascii(_, chunks) => {
+            let terminated = chunks.last().is_some_and(|c| c.contains(&0x1a));
+            check_ascii(&file.payload(), terminated, ram)
+        }
+        File::Custom(_) => vec![],
+        File::Header(..) => vec![Problem::error(
+            "the header is not followed by a data block".to_string(),
+        )],
+    }
+}
+
+/// Returns the problems found in the addresses of the given binary file
+///
+/// Unlike `check()`, these problems do not depend on the machine: the end address must
+/// not be before the begin address, the data block must hold the bytes from begin to
+/// end and no more than the padding that aligns it, the start address must be zero or
+/// lie between begin and end, and the file must not load over the ROMs or the system
+/// work area. Files other than binary ones have no addresses, so they have no problems.
+///
+pub fn check_addresses(file: &File) -> Vec<Problem> {
+    match file {
+        File::Bin(_, begin, end, start, data) => check_bin_addresses(*begin, *end, *start, data),
+        _ => vec![],
+    }
+}
+
+/// Returns the problems found in the length of the data block of the given binary file
+///
+/// These are the problems of `check_addresses()` that make the data block inconsistent
+/// with the addresses: a truncated address header, an end address before the begin
+/// address, and a block that does not hold the bytes from begin to end or holds more
+/// than the padding that aligns it. Files other than binary ones have no problems.
+///
+pub fn check_length(file: &File) -> Vec<Problem> {
+    match file {
+        File::Bin(_, begin, end, _, data) => check_bin_length(*begin, *end, data),
+        _ => vec![],
+    }
+}
+
+fn check_bin(begin: usize, end: usize, start: usize, data: &[u8], ram: Ram) -> Vec<Problem> {
+    let mut problems = check_bin_addresses(begin, end, start, data);
+    if data.len() >= 6 && end >= begin && begin >= ROMS_END && begin < ram.bottom() {
+        problems.push(Problem::error(format!(
+            "loads at 0x{:04x}, below the RAM visible from Basic (from 0x{:04x})",
+            begin,
+            ram.bottom()
+        )));
+    }
+    problems
+}
+
+fn check_bin_addresses(begin: usize, end: usize, start: usize, data: &[u8]) -> Vec<Problem> {
+    let mut problems = check_bin_length(begin, end, data);
+    if data.len() < 6 || end < begin {
+        return problems;
+    }
+    if begin < ROMS_END {
+        problems.push(Problem::error(format!(
+            "loads at 0x{:04x}, over the BIOS and Basic ROMs (up to 0x{:04x})",
+            begin,
+            ROMS_END - 1
+        )));
+    }
+    if end >= WORK_AREA {
+        problems.push(Problem::error(format!(
+            "loads up to 0x{:04x}, overwriting the system work area (from 0x{:04x})",
+            end, WORK_AREA
+        )));
+    }
+    if start != 0 && (start < begin || start > end) {
+        problems.push(Problem::warning(format!(
+            "starts at 0x{:04x}, outside the loaded range 0x{:04x}-0x{:04x}",
+            start, begin, end
+        )));
+    }
+    problems
+}
+
+fn check_bin_length(begin: usize, end: usize, data: &[u8]) -> Vec<Problem> {
+    if data.len() < 6 {
+        return vec![Problem::error(
+            "the address header is truncated".to_string(),
+        )];
+    }
+    if end < begin {
+        return vec![Problem::error(format!(
+            "the end address 0x{:04x} is before the begin address 0x{:04x}",
+            end, begin
+        ))];
+    }
+    let mut problems = vec![];
+    let len = end - begin + 1;
+    // Data blocks, address header included, are padded to a multiple of 8 bytes
+    let aligned = (len + 6).div_ceil(8) * 8 - 6;
+    if data.len() - 6 < len {
+        problems.push(Problem::error(format!(
+            "the data block has {} bytes, but BLOAD reads {}",
+            data.len() - 6,
+            len
+        )));
+    } else if data.len() - 6 > aligned {
+        problems.push(Problem::warning(format!(
+            "the data block has {} bytes past the end address, which BLOAD ignores",
+            data.len() - 6 - len
+        )));
+    }
+    problems
+}
+
+fn check_basic(program: &[u8], ram: Ram) -> Vec<Problem> {
+    if let Err(e) = basic::detokenize(program) {
+        return vec![Problem::error(format!(
+            "CLOAD loads an invalid program: {}",
+            e
+        ))];
+    }
+    check_size(program.len(), ram).into_iter().collect()
+}
+
+fn check_ascii(text: &[u8], terminated: bool, ram: Ram) -> Vec<Problem> {
+    let mut problems = vec![];
+    if !terminated {
+        problems.push(Problem::error(
+            "there is no EOF mark, so LOAD keeps reading past the end of the file".to_string(),
+        ));
+    }
+    let errors = match basic::tokenize(text) {
+        Ok(program) => {
+            problems.extend(check_size(program.len(), ram));
+            return problems;
+        }
+        Err(errors) => errors,
+    };
+    if let Some(SyntaxError::MissingLineNumber { line: 1 }) = errors.first() {
+        problems.push(Problem::warning(
+            "it is not a Basic program, so it can only be read with OPEN".to_string(),
+        ));
+        return problems;
+    }
+    // LOAD replaces duplicate lines, and unknown keywords fail only when run
+    let failing: Vec<&SyntaxError> = errors
+        .iter()
+        .filter(|e| {
+            !matches!(
+                e,
+                SyntaxError::DuplicateLine { .. } | SyntaxError::UnknownKeyword { .. }
+            )
+        })
+        .collect();
+    match failing.len() {
+        0 => {}
+        1 => problems.push(Problem::error(format!("LOAD fails at {}", failing[0]))),
+        n => problems.push(Problem::error(format!(
+            "LOAD fails at {} ({} lines fail)",
+            failing[0], n
+        ))),
+    }
+    problems
+}
+
+fn check_size(len: usize, ram: Ram) -> Option<Problem> {
+    if len > ram.bytes_free() {
+        Some(Problem::error(format!(
+            "the program takes {} bytes, but only {} are free",
+            len,
+            ram.bytes_free()
+        )))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape::{self, Tape};
+
+    #[test]
+    fn should_check_loading() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xaf, 0xc9])
+            .unwrap();
+        let (name, _) = tape::file_name("high");
+        tape.append_bin(&name, &[0xff, 0xf3, 0x00, 0xf4, 0xff, 0xf3, 0xc9, 0xc9])
+            .unwrap();
+        let (name, _) = tape::file_name("text");
+        tape.append_ascii(&name, b"10 PRINT\r\n").unwrap();
+        let (name, _) = tape::file_name("data");
+        tape.append_ascii(&name, b"HELLO\r\n").unwrap();
+        let files: Vec<File> = tape.files().collect();
+
+        assert_eq!(Vec::<Problem>::new(), check(&files[0], Ram::K32));
+        let problems = check(&files[0], Ram::K16);
+        assert_eq!(1, problems.len());
+        assert_eq!(
+            "loads at 0x9000, below the RAM visible from Basic (from 0xc000)",
+            problems[0].message
+        );
+        assert_eq!(Severity::Error, check(&files[1], Ram::K64)[0].severity);
+        let problems = check_bin(0x9000, 0x9000, 0xa000, &[0; 7], Ram::K64);
+        assert_eq!(
+            vec![Problem::warning(
+                "starts at 0xa000, outside the loaded range 0x9000-0x9000".to_string()
+            )],
+            problems
+        );
+        assert!(check(&files[2], Ram::K16).is_empty());
+        assert_eq!(Severity::Warning, check(&files[3], Ram::K16)[0].severity);
+        let mut long = b"10 REM ".to_vec();
+        long.resize(300, b'A');
+        assert_eq!(
+            vec![
+                Problem::error(
+                    "there is no EOF mark, so LOAD keeps reading past the end of the file"
+                        .to_string()
+                ),
+                Problem::error(
+                    "LOAD fails at line 1: line too long (300 characters, at most 255 allowed)"
+                        .to_string()
+                )
+            ],
+            check_ascii(&long, false, Ram::K64)
+        );
+        assert_eq!(
+            vec![Problem::error(
+                "LOAD fails at line 2: missing line number (3 lines fail)".to_string()
+            )],
+            check_ascii(
+                b"10 PRINT\r\nHELLO\r\nWORLD\r\n20 END\r\n!\r\n\x1a",
+                true,
+                Ram::K64
+            )
+        );
+
+        let program = basic::tokenize(b"10 PRINT\r\n").unwrap();
+        assert!(check_basic(&program, Ram::K16).is_empty());
+        assert_eq!(
+            "the program takes 13000 bytes, but only 12431 are free",
+            check_size(13000, Ram::K16).unwrap().message
+        );
+        assert!(check_size(13000, Ram::K32).is_none());
+        assert_eq!(Ram::K16, "16kb".parse().unwrap());
+        assert_eq!("64KB", "64".parse::<Ram>().unwrap().to_string());
+        assert!("48".parse::<Ram>().is_err());
+    }
+
+    #[test]
+    fn should_check_addresses() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x90, 0x01, 0x90, 0x01, 0x90, 0xaf, 0xc9])
+            .unwrap();
+        let file = tape.files().next().unwrap();
+        assert!(check_addresses(&file).is_empty());
+        assert!(check_addresses(&File::Custom(&[0; 8])).is_empty());
+
+        assert_eq!(
+            vec![Problem::error(
+                "the end address 0x8fff is before the begin address 0x9000".to_string()
+            )],
+            check_bin_addresses(0x9000, 0x8fff, 0x9000, &[0; 8])
+        );
+        assert_eq!(
+            vec![
+                Problem::error("the data block has 2 bytes, but BLOAD reads 3".to_string()),
+                Problem::error(
+                    "loads at 0x4000, over the BIOS and Basic ROMs (up to 0x7fff)".to_string()
+                ),
+            ],
+            check_bin_addresses(0x4000, 0x4002, 0x4000, &[0; 8])
+        );
+        assert_eq!(
+            vec![Problem::warning(
+                "the data block has 10 bytes past the end address, which BLOAD ignores".to_string()
+            )],
+            check_bin_addresses(0x9000, 0x9001, 0x9000, &[0; 18])
+        );
+        assert!(check_bin_addresses(0x9000, 0x9001, 0, &[0; 8]).is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! CAS files of machines other than MSX
+//!
+//! The MSX tape format descends from the one of the Spectravideo SVI-318 and SVI-328:
+//! both record blocks in the "Kansas City Standard" and start files with a header block
+//! of ten type bytes followed by six bytes for the name. Their CAS files differ in the
+//! bytes that precede every block, and may differ in the type bytes of the headers.
+//!
+//! A `Machine` translates its CAS files from and to the MSX ones `Tape` reads and
+//! writes, so the tapes of every machine are handled by the same `Tape` and `File`
+//! types:
+//!
+//! ```
+//! use mcp::machine::Machine;
+//! use mcp::tape::{self, Scan};
+//!
+//! let mut svi = vec![0x55; 16];
+//! svi.push(0x7f);
+//! svi.extend_from_slice(&[0xd3; 10]);
+//! svi.extend_from_slice(b"GAME  ");
+//!
+//! let tape = Machine::Svi.read(&svi, Scan::Unaligned);
+//! assert_eq!(Some("GAME"), tape.blocks()[0].file_name());
+//! assert_eq!(svi, Machine::Svi.to_bytes(&tape));
+//! ```
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::tape::{Entry, FileType, Scan, Tape, PREFIX};
+
+/// The sync bytes that precede every block in the CAS files of Spectravideo machines
+const SVI_PREFIX: [u8; 17] = [
+    0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55,
+    0x7f,
+];
+
+/// A machine whose tapes are stored in CAS files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Machine {
+    /// The MSX computers, whose CAS files `Tape` reads and writes as they are
+    #[default]
+    Msx,
+    /// The Spectravideo SVI-318 and SVI-328
+    Svi,
+}
+
+impl Machine {
+    /// Returns the bytes that precede every block in the CAS files of this machine.
+    pub fn prefix(self) -> &'static [u8] {
+        match self {
+            Machine::Msx => &PREFIX,
+            Machine::Svi => &SVI_PREFIX,
+        }
+    }
+
+    /// Returns the byte repeated ten times at the start of the headers of the given file
+    /// type, or `None` for custom files, which have no header.
+    pub fn header_byte(self, file_type: FileType) -> Option<u8> {
+        match (self, file_type) {
+            (Machine::Msx, FileType::Bin) | (Machine::Svi, FileType::Bin) => Some(0xd0),
+            (Machine::Msx, FileType::Basic) | (Machine::Svi, FileType::Basic) => Some(0xd3),
+            (Machine::Msx, FileType::Ascii) | (Machine::Svi, FileType::Ascii) => Some(0xea),
+            (_, FileType::Custom) => None,
+        }
+    }
+
+    /// Read a tape from the bytes of a CAS file of this machine.
+    ///
+    /// MSX tapes are read as `Tape::from_bytes_with()` does. The blocks of other machines
+    /// are found wherever their prefix is, and their headers are translated into the MSX
+    /// ones, so the files of the tape are recognized. The bytes before the first block
+    /// are kept as the preamble of the tape.
+    ///
+    pub fn read(self, bytes: &[u8], scan: Scan) -> Tape {
+        if self == Machine::Msx {
+            return Tape::from_bytes_with(bytes, scan);
+        }
+        let prefix = self.prefix();
+        let mut starts = vec![];
+        let mut i = 0;
+        while let Some(pos) = bytes[i..].windows(prefix.len()).position(|w| w == prefix) {
+            starts.push(i + pos);
+            i += pos + prefix.len();
+        }
+        starts.push(bytes.len());
+
+        let mut cas = bytes[..starts[0]].to_vec();
+        for range in starts.windows(2) {
+            cas.extend_from_slice(&PREFIX);
+            cas.extend(translate(
+                &bytes[range[0] + prefix.len()..range[1]],
+                self,
+                Machine::Msx,
+            ));
+        }
+        Tape::from_bytes_with(&cas, Scan::Unaligned)
+    }
+
+    /// Write the given tape as a CAS file of this machine.
+    ///
+    /// MSX tapes are written as `Tape::write()` does. For other machines, every block is
+    /// written with the prefix and header bytes of the machine, without the padding that
+    /// aligns the blocks of MSX CAS files.
+    ///
+    pub fn write<W: Write + ?Sized>(self, tape: &Tape, output: &mut W) -> io::Result<()> {
+        if self == Machine::Msx {
+            return tape.write(output);
+        }
+        output.write_all(tape.preamble())?;
+        for block in tape.blocks() {
+            output.write_all(self.prefix())?;
+            output.write_all(&translate(block.data_without_prefix(), Machine::Msx, self))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the entries of the files of the given tape, as `Tape::entries()` does, with
+    /// their offsets and lengths in the CAS file of this machine.
+    pub fn entries(self, tape: &Tape) -> Vec<Entry> {
+        let mut entries = tape.entries();
+        if self == Machine::Msx {
+            return entries;
+        }
+        let mut offsets = vec![tape.preamble().len()];
+        for block in tape.blocks() {
+            let len = self.prefix().len() + block.data_without_prefix().len();
+            offsets.push(offsets[offsets.len() - 1] + len);
+        }
+        for entry in entries.iter_mut() {
+            entry.offset = offsets[entry.blocks.start];
+            entry.length = offsets[entry.blocks.end] - entry.offset;
+        }
+        entries
+    }
+
+    /// Returns the bytes of the given tape as a CAS file of this machine, as written by
+    /// `write()`.
+    pub fn to_bytes(self, tape: &Tape) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.write(tape, &mut bytes).unwrap();
+        bytes
+    }
+}
+
+/// Returns the data of a block with the header bytes of machine `from` replaced by those
+/// of machine `to`
+fn translate(data: &[u8], from: Machine, to: Machine) -> Vec<u8> {
+    let mut data = data.to_vec();
+    for file_type in [FileType::Bin, FileType::Basic, FileType::Ascii]
+        .iter()
+        .copied()
+    {
+        if let (Some(a), Some(b)) = (from.header_byte(file_type), to.header_byte(file_type)) {
+            if data.len() >= 10 && data[..10].iter().all(|byte| *byte == a) {
+                data[..10].fill(b);
+                break;
+            }
+        }
+    }
+    data
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Machine::Msx => "msx",
+            Machine::Svi => "svi",
+        })
+    }
+}
+
+impl FromStr for Machine {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Machine> {
+        match s.to_lowercase().as_str() {
+            "msx" => Ok(Machine::Msx),
+            "svi" | "svi318" | "svi328" => Ok(Machine::Svi),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown machine {:?} (expected msx or svi)", s),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape::{self, File};
+
+    #[test]
+    fn should_read_and_write_svi_tapes() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xaf, 0xc9])
+            .unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+
+        let bytes = Machine::Svi.to_bytes(&tape);
+        assert_eq!(&SVI_PREFIX[..], &bytes[..17]);
+        assert_eq!(&[0xd0; 10][..], &bytes[17..27]);
+        assert_eq!(3 * 17 + 16 + 8 + 8, bytes.len());
+
+        let read = Machine::Svi.read(&bytes, Scan::Aligned);
+        assert_eq!(tape.to_bytes(), read.to_bytes());
+        let files: Vec<File> = read.files().collect();
+        assert_eq!(Some("game.bin".to_string()), files[0].name());
+        assert_eq!(FileType::Custom, files[1].file_type());
+        assert_eq!(bytes, Machine::Svi.to_bytes(&read));
+        let entries = Machine::Svi.entries(&read);
+        assert_eq!((0, 2 * 17 + 24), (entries[0].offset, entries[0].length));
+        assert_eq!(
+            (2 * 17 + 24, 17 + 8),
+            (entries[1].offset, entries[1].length)
+        );
+
+        let read = Machine::Svi.read(&[&[0x55, 0x55][..], &bytes[..]].concat(), Scan::Aligned);
+        assert_eq!(&[0x55, 0x55], read.preamble());
+        assert_eq!(
+            Ok(Machine::Svi),
+            "SVI328".parse::<Machine>().map_err(|e| e.kind())
+        );
+        assert!("m5".parse::<Machine>().is_err());
+    }
+}
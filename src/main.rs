@@ -6,34 +6,65 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-extern crate byteorder;
-extern crate docopt;
-#[macro_use]
-extern crate serde_derive;
+extern crate clap;
+extern crate mcp;
 
-#[cfg(test)]
-extern crate quickcheck;
 #[cfg(test)]
 extern crate tempdir;
 
 mod args;
-mod file;
-mod tape;
-mod wav;
+mod browse;
+mod doctor;
+mod report;
 
+use std::borrow::Cow;
 use std::convert::From;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
 use std::fs::File;
-use std::io;
-use std::io::Write;
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use mcp::fixture::Fixture;
+use mcp::machine::Machine;
+use mcp::manifest::Manifest;
+use mcp::meta::{Annotation, Metadata, TapeInfo};
+use mcp::progress::Progress;
+use mcp::remote::{Line, Remote};
+use mcp::tape::{Scan, Tape};
+use mcp::text::LineEnding;
+use mcp::{
+    analysis, basic, custom, dat, decode, diff, dir, dsk, file, format, gzip, hash, include,
+    integrity, load, progress, rom, screen, signature, tape, text, verifier, wav,
+};
 
-use crate::tape::Tape;
+use crate::report::{csv_field, json_number, json_string, Diagnostic, Reporter};
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The time audio players take to play the samples they are given, in milliseconds
+const REMOTE_LATENCY_MS: u64 = 500;
 
 #[derive(Debug)]
 enum Error {
     Io(io::Error),
+    File(PathBuf, io::Error),
+    Check(PathBuf, String),
+}
+
+impl Error {
+    fn diagnostic(&self) -> Diagnostic {
+        match self {
+            Error::Io(e) => Diagnostic::from_io(None, e),
+            Error::File(path, e) => Diagnostic::from_io(Some(path), e),
+            Error::Check(path, message) => Diagnostic::check_failed(path, message.clone()),
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -44,255 +75,3037 @@ impl From<io::Error> for Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
-#[allow(dead_code)]
+/// Attach the path of the file involved to the errors of an IO operation
+trait OnPath<T> {
+    fn on_path(self, path: &Path) -> Result<T>;
+}
+
+impl<T> OnPath<T> for io::Result<T> {
+    fn on_path(self, path: &Path) -> Result<T> {
+        self.map_err(|e| Error::File(path.to_path_buf(), e))
+    }
+}
+
 fn main() {
-    let cmd = args::parse();
+    let (cmd, output) = args::parse();
+    let out = Reporter::new(output.format).with_verbosity(output.verbosity);
+    let out = if writes_stdout(&cmd) {
+        out.on_stderr()
+    } else {
+        out
+    };
     let result = match cmd {
         args::Command::Version => print_version(),
-        args::Command::List(path) => list_files(&path),
-        args::Command::Add(path, files) => {
+        args::Command::List(path, options, scan) => list_files(&out, &path, &options, scan),
+        args::Command::Add(path, files, options) => {
             let input_files: Vec<&Path> = files.iter().map(|f| f.as_path()).collect();
-            add_files(&path, &input_files)
+            add_files(&out, &path, &input_files, &options)
         }
-        args::Command::Extract(path) => extract_all(&path),
-        args::Command::Export(path, output) => export(&*path, &*output),
-    };
-    if result.is_err() {
-        match result.unwrap_err() {
-            Error::Io(e) => println!("Error: IO operation failed: {}", e),
+        args::Command::Extract(path, names, dir, options, scan, machine) => {
+            extract(&out, &path, &names, dir.as_deref(), &options, scan, machine)
+        }
+        args::Command::Cat(path, name, as_text, hex, scan) => cat(&path, &name, as_text, hex, scan),
+        args::Command::Remove(path, names) => remove(&out, &path, &names),
+        args::Command::Rename(path, name, new_name) => rename(&out, &path, &name, &new_name),
+        args::Command::Move(path, name, to) => move_file(&out, &path, &name, to),
+        args::Command::Patch(path, name, offset, bytes) => {
+            patch(&out, &path, &name, offset, &bytes)
         }
+        args::Command::Browse(path) => browse(&path),
+        args::Command::Merge(path, paths, policy, gzip) => merge(&out, &path, &paths, policy, gzip),
+        args::Command::Split(path, dir) => split(&out, &path, dir.as_deref()),
+        args::Command::Export(path, output, profile) => export(&out, &path, &output, profile),
+        args::Command::ExportAsm(path, name, dialect) => export_asm(&path, &name, dialect),
+        args::Command::ExportC(path, name, output) => {
+            export_c(&out, &path, &name, output.as_deref())
+        }
+        args::Command::Convert(path, output, profile, scan) => {
+            convert(&out, &path, &output, profile, scan)
+        }
+        args::Command::ConvertAll(paths, to, dir, jobs, profile, scan) => {
+            convert_all(&out, &paths, &to, dir.as_deref(), jobs, profile, scan)
+        }
+        args::Command::RomLoad(rom, path) => romload(&out, &rom, &path),
+        args::Command::Import(path, disk, names) => import(&out, &path, &disk, &names),
+        args::Command::Build(manifest, loader) => build(&out, &manifest, loader),
+        args::Command::Master(path, tapes, gap, index) => {
+            master(&out, &path, &tapes, gap, index.as_deref())
+        }
+        args::Command::Play(path, profile, remote, pause) => {
+            play(&out, &path, profile, remote.as_ref(), pause)
+        }
+        args::Command::Load(wav, path, gzip) => load_recording(&out, &wav, &path, gzip),
+        args::Command::Record(dir, input, seconds) => record(&out, &dir, input.as_deref(), seconds),
+        args::Command::Remote(line, seconds) => remote(&out, &line, seconds),
+        args::Command::Identify(path) => identify(&out, &path),
+        args::Command::Annotate(path, index, annotation, clear) => {
+            annotate(&out, &path, index, annotation, clear)
+        }
+        args::Command::Info(paths) => info(&out, &paths),
+        args::Command::Hash(paths, machine) => hash_tapes(&out, &paths, machine),
+        args::Command::Verify(dat, paths, strict) => verify(&out, &dat, &paths, strict),
+        args::Command::Check(paths) => check(&out, &paths),
+        args::Command::GrepBasic(pattern, paths, ignore_case) => {
+            grep_basic(&out, &pattern, &paths, ignore_case)
+        }
+        args::Command::Which(path, paths) => which(&out, &path, &paths),
+        args::Command::CompareDir(path, dir) => compare_dir(&out, &path, &dir),
+        args::Command::Diff(path, other, blocks) => diff_tapes(&out, &path, &other, blocks),
+        args::Command::Edit(path, name) => edit(&out, &path, &name),
+        args::Command::BasicShrink(path, name) => basic_shrink(&out, &path, &name),
+        args::Command::BasicConvert(path, name, to) => basic_convert(&out, &path, &name, to),
+        args::Command::Fit(path, cassette, profile) => fit(&out, &path, cassette, profile),
+        args::Command::LoadCheck(path, ram) => loadcheck(&out, &path, &ram),
+        args::Command::GenFixture(path, fixture, gzip) => gen_fixture(&out, &path, &fixture, gzip),
+        args::Command::Doctor => run_doctor(&out),
+        args::Command::Preview(path) => preview(&out, &path),
+        args::Command::OpenMsxInsert(path, socket, load) => {
+            openmsx_insert(&out, &path, socket.as_deref(), load.as_deref())
+        }
+        args::Command::External(name, args) => run_external(&name, &args),
+    };
+    if let Err(e) = result {
+        out.report(&e.diagnostic());
+        process::exit(1);
     }
 }
 
 fn print_version() -> Result<()> {
     println!("MSX CAS Packager (MCP) v{}", VERSION);
     println!("Copyright (C) 2015 Alvaro Polo");
-    println!("");
+    println!();
     println!("This program is subject to the terms of the Mozilla Public License v2.0.");
-    println!("");
+    println!();
     Ok(())
 }
 
-fn list_files(path: &Path) -> Result<()> {
-    let tape = tape::Tape::from_file(path)?;
-    for file in tape.files() {
+/// Run the external command `mcp-<name>` found in `PATH`
+///
+/// This allows third parties to extend `mcp` with new commands without modifying it.
+/// The exit code of the external command is propagated to the caller.
+///
+fn run_external(name: &str, args: &[OsString]) -> Result<()> {
+    let program = format!("mcp-{}", name);
+    let status = process::Command::new(&program)
+        .args(args)
+        .status()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "unknown command {:?}: no {} program found in PATH",
+                    name, program
+                ),
+            ),
+            _ => e,
+        })?;
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
+
+fn list_files(out: &Reporter, path: &Path, options: &args::ListOptions, scan: Scan) -> Result<()> {
+    let (long, csv, hashes) = (options.long, options.csv, options.hashes);
+    let tape = open_machine_tape(path, scan, options.machine)?;
+    report_problems(out, path, &tape);
+    log_blocks(out, &tape, 0..tape.blocks().len());
+    let meta = Metadata::load(path).on_path(path)?;
+    let problems = |file: &tape::File| {
+        if long {
+            Some(load::check_addresses(file))
+        } else {
+            None
+        }
+    };
+    let digest = |file: &tape::File| {
+        if hashes {
+            Some(hash::Digest::of(&file.payload()))
+        } else {
+            None
+        }
+    };
+    if out.format() == report::Format::Json {
+        let entries: Vec<String> = options
+            .machine
+            .entries(&tape)
+            .iter()
+            .zip(tape.files())
+            .map(|(e, f)| {
+                entry_to_json(
+                    e,
+                    meta.get(e.index),
+                    problems(&f).as_deref(),
+                    digest(&f).as_ref(),
+                )
+            })
+            .collect();
+        println!("[{}]", entries.join(","));
+        if hashes {
+            // The checksums of the whole tape follow the array in a line of their own
+            let digest = hash::Digest::of(&options.machine.to_bytes(&tape));
+            println!("{{\"tape\":{{{}}}}}", &digest_to_json(&digest)[1..]);
+        }
+        return Ok(());
+    }
+    if csv {
+        let digest_header = if hashes { ",crc32,sha1" } else { "" };
+        println!("index,type,name,size,payload_size,begin,end,start,first_block,last_block,offset,length,title{}", digest_header);
+        for (entry, file) in options.machine.entries(&tape).into_iter().zip(tape.files()) {
+            let address = |a: Option<usize>| a.map(|a| a.to_string()).unwrap_or_default();
+            let title = meta
+                .get(entry.index)
+                .and_then(|a| a.title.as_deref())
+                .unwrap_or_default();
+            let digest = digest(&file)
+                .map(|d| format!(",{:08x},{}", d.crc32, hash::to_hex(&d.sha1)))
+                .unwrap_or_default();
+            println!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}{}",
+                entry.index,
+                entry.file_type,
+                csv_field(entry.name.as_deref().unwrap_or_default()),
+                entry.size,
+                entry.payload_size,
+                address(entry.addresses.map(|a| a.0)),
+                address(entry.addresses.map(|a| a.1)),
+                address(entry.addresses.map(|a| a.2)),
+                entry.blocks.start,
+                entry.blocks.end - 1,
+                entry.offset,
+                entry.length,
+                csv_field(title),
+                digest
+            );
+        }
+        return Ok(());
+    }
+    let offsets: Vec<usize> = options
+        .machine
+        .entries(&tape)
+        .iter()
+        .map(|e| e.offset)
+        .collect();
+    let blank = if options.index {
+        format!("{:3} | {:8} | ", "", "")
+    } else {
+        String::new()
+    };
+    if !meta.tape_info().is_empty() {
+        println!("{}", meta.tape_info());
+    }
+    for (i, file) in tape.files().enumerate() {
+        if options.index {
+            print!("{:3} | 0x{:06x} | ", i, offsets[i]);
+        }
+        let title = meta
+            .get(i)
+            .and_then(|a| a.title.as_ref())
+            .map(|t| format!(" {}", t))
+            .unwrap_or_default();
         match file {
-            tape::File::Bin(name, begin, end, start, data) => {
+            tape::File::Bin(ref name, begin, end, start, data) => {
                 println!(
-                    "bin    | {:6} | {:5} bytes | [0x{:x},0x{:x}]:0x{:x}",
+                    "{:6} | {:6} | {:5} bytes | [0x{:x},0x{:x}]:0x{:x}{}",
+                    file.file_type(),
                     name,
                     data.len(),
                     begin,
                     end,
-                    start
+                    start,
+                    if title.is_empty() {
+                        title
+                    } else {
+                        format!(" |{}", title)
+                    }
                 );
             }
-            tape::File::Basic(name, data) => {
-                println!("basic  | {:6} | {:5} bytes |", name, data.len());
-            }
-            tape::File::Ascii(name, data) => {
-                let nbytes = data.iter().fold(0, |size, chunk| size + chunk.len());
-                println!("ascii  | {:6} | {:5} bytes |", name, nbytes);
+            tape::File::Basic(ref name, _)
+            | tape::File::Ascii(ref name, _)
+            | tape::File::Header(ref name, _) => {
+                println!(
+                    "{:6} | {:6} | {:5} bytes |{}",
+                    file.file_type(),
+                    name,
+                    file.size(),
+                    title
+                );
             }
             tape::File::Custom(data) => {
-                println!("custom |        | {:5} bytes |", data.len());
+                println!(
+                    "{:6} |        | {:5} bytes |{}",
+                    file.file_type(),
+                    data.len(),
+                    title
+                );
             }
         };
+        if let Some(digest) = digest(&file) {
+            println!("{}       | {}", blank, digest_columns(&digest));
+        }
+        if let Some(notes) = meta.get(i).and_then(|a| a.notes.as_ref()).filter(|_| long) {
+            println!("{}  {}", blank, notes);
+        }
+        for problem in problems(&file).unwrap_or_default() {
+            println!("{}  warning: {}", blank, problem.message);
+        }
+    }
+    if hashes {
+        println!(
+            "{}tape   | {}",
+            blank,
+            digest_columns(&hash::Digest::of(&options.machine.to_bytes(&tape)))
+        );
     }
     Ok(())
 }
 
-fn extract_all(path: &Path) -> Result<()> {
-    let tape = tape::Tape::from_file(path)?;
-    let mut next_custom = 0;
+/// Detail the given blocks of a tape, in verbose mode
+fn log_blocks(out: &Reporter, tape: &Tape, blocks: std::ops::Range<usize>) {
+    let offsets = tape.block_offsets();
+    for i in blocks {
+        let block = &tape.blocks()[i];
+        let kind = if block.is_bin_header() {
+            "binary header"
+        } else if block.is_basic_header() {
+            "basic header"
+        } else if block.is_ascii_header() {
+            "ascii header"
+        } else {
+            "data"
+        };
+        let name = block
+            .file_name()
+            .map(|n| format!(" {:?}", n))
+            .unwrap_or_default();
+        out.detail(format_args!(
+            "block {} at 0x{:06x}: {}{}, {} bytes",
+            i,
+            offsets[i],
+            kind,
+            name,
+            block.data_without_prefix().len()
+        ));
+    }
+}
+
+/// Warn about the malformed files of a tape, which are read as custom files
+fn report_problems(out: &Reporter, path: &Path, tape: &Tape) {
+    for problem in tape.problems() {
+        out.report(&Diagnostic::from_tape_error(path, &problem));
+        out.status_line(format_args!(""));
+    }
+}
+
+fn entry_to_json(
+    entry: &tape::Entry,
+    annotation: Option<&Annotation>,
+    problems: Option<&[load::Problem]>,
+    digest: Option<&hash::Digest>,
+) -> String {
+    format!(
+        "{{\"index\":{},\"type\":{},\"name\":{},\"size\":{},\"payload_size\":{},\"begin\":{},\"end\":{},\"start\":{},\"blocks\":[{}],\"offset\":{},\"length\":{}{}{}{}}}",
+        entry.index,
+        json_string(&entry.file_type.to_string()),
+        entry.name
+            .as_ref()
+            .map(|n| json_string(n))
+            .unwrap_or_else(|| "null".to_string()),
+        entry.size,
+        entry.payload_size,
+        json_number(entry.addresses.map(|a| a.0)),
+        json_number(entry.addresses.map(|a| a.1)),
+        json_number(entry.addresses.map(|a| a.2)),
+        entry.blocks.clone().map(|b| b.to_string()).collect::<Vec<_>>().join(","),
+        entry.offset,
+        entry.length,
+        annotation.map(annotation_to_json).unwrap_or_default(),
+        problems.map(problems_to_json).unwrap_or_default(),
+        digest.map(digest_to_json).unwrap_or_default(),
+    )
+}
+
+/// Returns the fields of the given checksums as they are added to JSON objects
+fn digest_to_json(digest: &hash::Digest) -> String {
+    format!(
+        ",\"crc32\":{},\"sha1\":{}",
+        json_string(&format!("{:08x}", digest.crc32)),
+        json_string(&hash::to_hex(&digest.sha1))
+    )
+}
+
+/// Returns the given checksums as columns of the listing of a tape
+fn digest_columns(digest: &hash::Digest) -> String {
+    format!(
+        "crc32 {:08x} | sha1 {}",
+        digest.crc32,
+        hash::to_hex(&digest.sha1)
+    )
+}
+
+fn problems_to_json(problems: &[load::Problem]) -> String {
+    let problems: Vec<String> = problems
+        .iter()
+        .map(|p| {
+            format!(
+                "{{\"severity\":{},\"message\":{}}}",
+                json_string(p.severity.name()),
+                json_string(&p.message)
+            )
+        })
+        .collect();
+    format!(",\"problems\":[{}]", problems.join(","))
+}
+
+fn annotation_to_json(annotation: &Annotation) -> String {
+    let field = |value: &Option<String>| {
+        value
+            .as_ref()
+            .map(|v| json_string(v))
+            .unwrap_or_else(|| "null".to_string())
+    };
+    format!(
+        ",\"meta\":{{\"title\":{},\"notes\":{},\"source\":{},\"dump_date\":{}}}",
+        field(&annotation.title),
+        field(&annotation.notes),
+        field(&annotation.source),
+        field(&annotation.dump_date),
+    )
+}
+
+fn annotate(
+    out: &Reporter,
+    path: &Path,
+    index: usize,
+    annotation: Annotation,
+    clear: bool,
+) -> Result<()> {
+    let tape = tape::Tape::from_file_with(path, Scan::Unaligned).on_path(path)?;
+    let file = tape.files().nth(index).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there is no file at index {} in {:?}", index, path),
+        )
+    })?;
+    let mut meta = Metadata::load(path).on_path(path)?;
+    let current = if clear {
+        Annotation::default()
+    } else {
+        meta.get(index).cloned().unwrap_or_default()
+    };
+    let annotation = Annotation {
+        title: annotation.title.or(current.title),
+        notes: annotation.notes.or(current.notes),
+        source: annotation.source.or(current.source),
+        dump_date: annotation.dump_date.or(current.dump_date),
+    };
+    out.status(format_args!(
+        "Annotating file {} of {:?}... ",
+        index,
+        path.as_os_str()
+    ));
+    meta.set(index, &file, annotation);
+    let meta_path = Metadata::path_of(path);
+    meta.save(path).on_path(&meta_path)?;
+    out.status_line(format_args!("Done"));
+    Ok(())
+}
+
+fn identify(out: &Reporter, path: &Path) -> Result<()> {
+    let mut db = signature::Database::builtin();
+    if let Some(dir) = signature::default_dir() {
+        db.load_dir(&dir).on_path(&dir)?;
+    }
+    let registry = custom::Registry::builtin();
+    let tape = open_tape(path, Scan::Unaligned)?;
+    let mut entries = vec![];
     for file in tape.files() {
-        let out_path = file.name().map(|n| n.to_string()).unwrap_or_else(|| {
-            format!("custom.{:03}", {
-                next_custom += 1;
-                next_custom
-            })
-        });
-        print!("Extracting {}... ", out_path);
-        extract_file(&file, Path::new(&out_path))?;
-        println!("Done");
+        let interpretation = registry.interpret(&file);
+        let mut names: Vec<String> = db
+            .identify(&file)
+            .iter()
+            .map(|s| s.name().to_string())
+            .collect();
+        if out.format() == report::Format::Json {
+            let names: Vec<String> = names.iter().map(|n| json_string(n)).collect();
+            entries.push(format!(
+                "{{\"type\":{},\"name\":{},\"signatures\":[{}]{}}}",
+                json_string(&file.file_type().to_string()),
+                file.name()
+                    .map(|n| json_string(&n))
+                    .unwrap_or_else(|| "null".to_string()),
+                names.join(","),
+                interpretation
+                    .as_ref()
+                    .map(interpretation_to_json)
+                    .unwrap_or_default()
+            ));
+        } else {
+            names.extend(interpretation.map(|i| i.to_string()));
+            println!(
+                "{:6} | {:6} | {}",
+                file.file_type(),
+                file.name().unwrap_or_default(),
+                if names.is_empty() {
+                    "unknown".to_string()
+                } else {
+                    names.join(", ")
+                }
+            );
+        }
+    }
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
     }
     Ok(())
 }
 
-fn extract_file(file: &tape::File, out_path: &Path) -> Result<()> {
-    let (out_filename, clash) = file::unique_filename(out_path)?;
-    if clash {
-        print!(
-            "Warning: filename {:?} already exists, writing output to {:?}... ",
-            out_path, out_filename
+fn interpretation_to_json(interpretation: &custom::Interpretation) -> String {
+    let fields: Vec<String> = interpretation
+        .fields
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                custom::Value::Number(n) => n.to_string(),
+                value => json_string(&value.to_string()),
+            };
+            format!("{}:{}", json_string(name), value)
+        })
+        .collect();
+    format!(
+        ",\"interpretation\":{{\"format\":{},\"fields\":{{{}}}}}",
+        json_string(&interpretation.format),
+        fields.join(",")
+    )
+}
+
+fn info(out: &Reporter, paths: &[PathBuf]) -> Result<()> {
+    let mut stats = tape::Stats::default();
+    for path in paths {
+        stats.merge(&open_tape(path, Scan::Unaligned)?.stats());
+    }
+    if out.format() == report::Format::Json {
+        let files: Vec<String> = stats
+            .files
+            .iter()
+            .map(|(t, n)| format!("{}:{}", json_string(&t.to_string()), n))
+            .collect();
+        let sizes: Vec<String> = stats
+            .block_sizes
+            .iter()
+            .map(|(size, n)| format!("\"{}\":{}", size, n))
+            .collect();
+        println!(
+            "{{\"files\":{{{}}},\"blocks\":{},\"block_sizes\":{{{}}},\"prefix_bytes\":{},\"padding_bytes\":{},\"payload_bytes\":{},\"total_bytes\":{}}}",
+            files.join(","),
+            stats.blocks,
+            sizes.join(","),
+            stats.prefix_bytes,
+            stats.padding_bytes,
+            stats.payload_bytes,
+            stats.total_bytes
         );
+        return Ok(());
+    }
+    let files: Vec<String> = stats
+        .files
+        .iter()
+        .map(|(t, n)| format!("{}: {}", t, n))
+        .collect();
+    println!("Files:      {} ({})", stats.file_count(), files.join(", "));
+    println!("Blocks:     {}", stats.blocks);
+    println!("Total size: {} bytes", stats.total_bytes);
+    println!("Payload:    {} bytes", stats.payload_bytes);
+    println!("Padding:    {} bytes", stats.padding_bytes);
+    println!("Prefixes:   {} bytes", stats.prefix_bytes);
+    println!("Block sizes:");
+    for (size, n) in &stats.block_sizes {
+        println!("  {:6} bytes | {} blocks", size, n);
+    }
+    Ok(())
+}
+
+fn hash_tapes(out: &Reporter, paths: &[PathBuf], machine: Machine) -> Result<()> {
+    let mut entries = vec![];
+    for path in paths {
+        let tape = open_machine_tape(path, Scan::Unaligned, machine)?;
+        let digest = hash::Digest::of(&machine.to_bytes(&tape));
+        if out.format() == report::Format::Json {
+            entries.push(format!(
+                "{{\"path\":{}{}}}",
+                json_string(&path.to_string_lossy()),
+                digest_to_json(&digest)
+            ));
+        } else {
+            println!("{} | {}", digest_columns(&digest), path.display());
+        }
+    }
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
     }
-    let mut ofile = File::create(&out_filename)?;
-    match file {
-        &tape::File::Bin(_, _, _, _, data) => {
-            // First, write the BIN file ID byte not present in cassete
-            ofile.write_all(&[0xfe])?;
-            ofile.write_all(data)?;
+    Ok(())
+}
+
+fn verify(out: &Reporter, dat_path: &Path, paths: &[PathBuf], strict: bool) -> Result<()> {
+    let dat = dat::Dat::load(dat_path).on_path(dat_path)?;
+    let status_to_json = |name: Option<&str>, status: &dat::Status| {
+        format!(
+            "\"name\":{},\"status\":{},\"game\":{}",
+            name.map(json_string).unwrap_or_else(|| "null".to_string()),
+            json_string(status.name()),
+            status
+                .rom()
+                .map(|r| json_string(&r.game))
+                .unwrap_or_else(|| "null".to_string())
+        )
+    };
+    let mut entries = vec![];
+    let (mut inconsistent, mut bins) = (0, 0);
+    for path in paths {
+        let bytes = fs::read(path).on_path(path)?;
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let result = dat.verify_tape(&name, &bytes);
+        let mut problems = vec![];
+        if strict {
+            let tape = read_tape(path, Scan::Unaligned)?;
+            bins += tape
+                .files()
+                .filter(|f| f.file_type() == tape::FileType::Bin)
+                .count();
+            let names: Vec<String> = tape.files().map(|f| f.name().unwrap_or_default()).collect();
+            let findings = analysis::check(&tape);
+            // Collisions with other files are warnings, only the addresses of a file can
+            // make it inconsistent
+            let mut indices: Vec<usize> = findings
+                .iter()
+                .filter(|f| f.other.is_none())
+                .map(|f| f.index)
+                .collect();
+            indices.dedup();
+            inconsistent += indices.len();
+            problems.extend(
+                findings
+                    .into_iter()
+                    .map(|f| (names[f.index].clone(), f.problem)),
+            );
         }
-        &tape::File::Basic(_, data) => {
-            ofile.write_all(data)?;
+        if out.format() == report::Format::Json {
+            let files: Vec<String> = result
+                .files
+                .iter()
+                .map(|(n, s)| format!("{{{}}}", status_to_json(n.as_deref(), s)))
+                .collect();
+            let problems: Vec<String> = problems
+                .iter()
+                .map(|(n, p)| {
+                    format!(
+                        "{{\"name\":{},\"severity\":{},\"message\":{}}}",
+                        json_string(n),
+                        json_string(p.severity.name()),
+                        json_string(&p.message)
+                    )
+                })
+                .collect();
+            let problems = if strict {
+                format!(",\"problems\":[{}]", problems.join(","))
+            } else {
+                String::new()
+            };
+            entries.push(format!(
+                "{{{},\"files\":[{}]{}}}",
+                status_to_json(Some(&name), &result.tape),
+                files.join(","),
+                problems
+            ));
+            continue;
         }
-        &tape::File::Ascii(_, ref chunks) => {
-            for chunk in chunks {
-                let last = chunk.iter().position(|b| *b == 0x1a).unwrap_or(chunk.len());
-                ofile.write_all(&chunk[..last])?;
-            }
+        let game = |status: &dat::Status| {
+            status
+                .rom()
+                .map(|r| format!(" | {}", r.game))
+                .unwrap_or_default()
+        };
+        println!("{:7} | {}{}", result.tape.name(), name, game(&result.tape));
+        for (file, status) in &result.files {
+            println!(
+                "  {:7} | {:10}{}",
+                status.name(),
+                file.as_deref().unwrap_or(""),
+                game(status)
+            );
         }
-        &tape::File::Custom(ref data) => {
-            ofile.write_all(data)?;
+        for (file, problem) in &problems {
+            println!(
+                "  {:7} | {:10} | {}",
+                problem.severity.name(),
+                file,
+                problem.message
+            );
         }
     }
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
+    }
+    if inconsistent > 0 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} of {} binary files have inconsistent addresses",
+                inconsistent, bins
+            ),
+        )));
+    }
     Ok(())
 }
 
-fn add_files(path: &Path, files: &[&Path]) -> Result<()> {
-    let mut padding = 0;
-    let mut tape = Tape::from_file(path).unwrap_or_else(|_| Tape::new());
-    for file in files {
-        if file::is_bin_file(file) {
-            padding += add_bin_file(&mut tape, &file)?;
-        } else if file::is_ascii_file(file) {
-            add_ascii_file(&mut tape, &file)?;
-        } else if file::is_basic_file(file) {
-            padding += add_basic_file(&mut tape, &file)?;
+/// Check the structure of several tapes
+///
+/// Every tape gets a line with its status, followed by the problems found in it. Fails if
+/// any tape has errors; warnings alone do not make it fail.
+///
+fn check(out: &Reporter, paths: &[PathBuf]) -> Result<()> {
+    let mut entries = vec![];
+    let mut damaged = 0;
+    for path in paths {
+        let tape = read_tape(path, Scan::Aligned)?;
+        let problems = integrity::check(&tape);
+        let status = if problems.iter().any(|p| p.severity == load::Severity::Error) {
+            damaged += 1;
+            "error"
+        } else if !problems.is_empty() {
+            "warning"
         } else {
-            padding += add_custom_file(&mut tape, &file)?;
+            "ok"
         };
+        if out.format() == report::Format::Json {
+            let problems: Vec<String> = problems
+                .iter()
+                .map(|p| {
+                    format!(
+                        "{{\"severity\":{},\"block\":{},\"message\":{}}}",
+                        json_string(p.severity.name()),
+                        json_number(p.block),
+                        json_string(&p.message)
+                    )
+                })
+                .collect();
+            entries.push(format!(
+                "{{\"path\":{},\"status\":{},\"problems\":[{}]}}",
+                json_string(&path.to_string_lossy()),
+                json_string(status),
+                problems.join(",")
+            ));
+            continue;
+        }
+        println!("{:7} | {}", status, path.display());
+        for problem in &problems {
+            let block = problem
+                .block
+                .map(|b| format!("block {}", b))
+                .unwrap_or_default();
+            println!(
+                "  {:7} | {:9} | {}",
+                problem.severity.name(),
+                block,
+                problem.message
+            );
+        }
     }
-    save_tape(&tape, &path)?;
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
+    }
+    if damaged > 0 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} of {} tapes have structural problems",
+                damaged,
+                paths.len()
+            ),
+        )));
+    }
+    Ok(())
+}
 
-    if padding > 0 {
-        println!("");
-        println!("Warning: some files had lengths that required padding with zeroes to be aligned");
-        println!("to 8-byte boundaries. This is a constraint of CAS file format: every data block");
-        println!("must start in an offset divisible by 8.");
-        println!("");
-        println!("For binary files, this means the total length of the file excluding the");
-        println!("0x1F prefix must be 8-byte aligned.");
-        println!("");
-        println!("For ASCII files, this does not affect you. ASCII files are always aligned to");
-        println!("256-byte boundaries and padded with EOF values (0x1A) needed by MSX BIOS to");
-        println!("detect the end of the file.");
-        println!("");
-        println!("For custom files, the effect is unknown. These files are loaded using custom");
-        println!("code. And if padding zeroes affect or not depends on that code.");
-        println!("");
-        println!("Using the right file sizes is highly recommended to prevent problems. However");
-        println!("this is not considered as an error, and your CAS package has been successfully");
-        println!("generated.");
-    }
-    Ok(())
-}
-
-fn add_bin_file(tape: &mut tape::Tape, file: &Path) -> Result<usize> {
-    print!("Adding binary file {:?}... ", file.as_os_str());
-
-    let data = file::read_content(file)?;
-    let (fname, truncated) = file::file_name_of(file)?;
-    if truncated {
-        print!(
-            "Warning: file name truncated to {}... ",
-            String::from_utf8_lossy(&fname)
+fn grep_basic(out: &Reporter, pattern: &str, paths: &[PathBuf], ignore_case: bool) -> Result<()> {
+    let normalize = |text: &str| {
+        if ignore_case {
+            text.to_uppercase()
+        } else {
+            text.to_string()
+        }
+    };
+    let pattern = normalize(pattern);
+    let mut tapes = vec![];
+    for path in paths {
+        if path.is_dir() {
+            tapes.extend(file::tapes_in(path).on_path(path)?);
+        } else {
+            tapes.push(path.clone());
+        }
+    }
+    let mut entries = vec![];
+    for path in &tapes {
+        let tape = open_tape(path, Scan::Unaligned)?;
+        for (index, file) in tape.files().enumerate() {
+            let lines = match basic::lines_of(&file) {
+                Some(lines) => lines,
+                None => continue,
+            };
+            let name = file.name().unwrap_or_default();
+            for line in lines {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        out.report(&Diagnostic::warning(
+                            "invalid_basic",
+                            path,
+                            format!("file {} ({}): {}", index, name, e),
+                        ));
+                        break;
+                    }
+                };
+                let text = line.to_string();
+                if !normalize(&text).contains(&pattern) {
+                    continue;
+                }
+                if out.format() == report::Format::Json {
+                    entries.push(format!(
+                        "{{\"path\":{},\"index\":{},\"name\":{},\"line\":{},\"text\":{}}}",
+                        json_string(&path.to_string_lossy()),
+                        index,
+                        json_string(&name),
+                        line.number(),
+                        json_string(&text)
+                    ));
+                } else {
+                    println!("{}:{}:{}", path.display(), name, text);
+                }
+            }
+        }
+    }
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
+    }
+    Ok(())
+}
+
+/// Print the tapes containing a file with the contents of the given host file
+///
+/// Fails if no tape contains it, so scripts can tell orphan files apart.
+///
+fn which(out: &Reporter, path: &Path, paths: &[PathBuf]) -> Result<()> {
+    let fingerprint = dir::Fingerprint::of(&fs::read(path).on_path(path)?);
+    let mut tapes = vec![];
+    for path in paths {
+        if path.is_dir() {
+            tapes.extend(file::tapes_in(path).on_path(path)?);
+        } else {
+            tapes.push(path.clone());
+        }
+    }
+    let mut entries = vec![];
+    for tape_path in &tapes {
+        let tape = open_tape(tape_path, Scan::Unaligned)?;
+        for (index, name) in tape.find_fingerprint(&fingerprint) {
+            if out.format() == report::Format::Json {
+                entries.push(format!(
+                    "{{\"path\":{},\"index\":{},\"name\":{}}}",
+                    json_string(&tape_path.to_string_lossy()),
+                    index,
+                    json_string(&name)
+                ));
+            } else {
+                entries.push(String::new());
+                println!("{}:{}:{}", tape_path.display(), index, name);
+            }
+        }
+    }
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
+    }
+    if entries.is_empty() {
+        let e = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("none of the {} CAS files searched contains it", tapes.len()),
         );
+        return Err(Error::File(path.to_path_buf(), e));
     }
+    Ok(())
+}
 
-    let padding = tape.append_bin(&fname, &data)?;
-    if padding == 0 {
-        println!("Done");
+/// Compare the files of a tape with their sources, failing if any of them differs
+fn compare_dir(out: &Reporter, path: &Path, dir: &Path) -> Result<()> {
+    let tape = open_tape(path, Scan::Unaligned)?;
+    let mut entries = vec![];
+    let mut mismatches = 0;
+    for compared in tape.compare_to_dir(dir) {
+        let comparison = compared.result.on_path(&compared.path)?;
+        if comparison != dir::Comparison::Same {
+            mismatches += 1;
+        }
+        let offset = match comparison {
+            dir::Comparison::Different(offset) => Some(offset),
+            _ => None,
+        };
+        if out.format() == report::Format::Json {
+            entries.push(format!(
+                "{{\"index\":{},\"name\":{},\"path\":{},\"status\":{},\"offset\":{}}}",
+                compared.index,
+                json_string(&compared.name),
+                json_string(&compared.path.to_string_lossy()),
+                json_string(comparison.name()),
+                json_number(offset)
+            ));
+            continue;
+        }
+        let detail = offset
+            .map(|o| format!(" (from offset 0x{:x})", o))
+            .unwrap_or_default();
+        println!(
+            "{:9} | {}{}",
+            comparison.name(),
+            compared.path.display(),
+            detail
+        );
+    }
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
+    }
+    if mismatches > 0 {
+        let e = io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} of {} files do not match their sources",
+                mismatches,
+                tape.files().count()
+            ),
+        );
+        return Err(Error::File(dir.to_path_buf(), e));
+    }
+    Ok(())
+}
+
+/// Compare two tapes file by file, or block by block, failing if they differ
+fn diff_tapes(out: &Reporter, path: &Path, other: &Path, blocks: bool) -> Result<()> {
+    let a = open_tape(path, Scan::Unaligned)?;
+    let b = open_tape(other, Scan::Unaligned)?;
+    let lines: Vec<(&str, String)> = if blocks {
+        diff::blocks(&a, &b)
+            .iter()
+            .map(|c| (c.name(), block_change(out.format(), c)))
+            .collect()
     } else {
-        println!("Done (padded with {} bytes!)", padding);
+        diff::files(&a, &b)
+            .iter()
+            .map(|c| (c.name(), file_change(out.format(), c)))
+            .collect()
+    };
+    if out.format() == report::Format::Json {
+        let entries: Vec<&str> = lines.iter().map(|(_, line)| line.as_str()).collect();
+        println!("[{}]", entries.join(","));
+    } else {
+        for (_, line) in &lines {
+            println!("{}", line);
+        }
+    }
+    if lines.is_empty() {
+        out.status_line(format_args!("No differences found"));
+        return Ok(());
     }
-    Ok(padding)
+    let count = |name: &str| lines.iter().filter(|(n, _)| *n == name).count();
+    let e = io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "the tapes differ ({} added, {} removed, {} changed {})",
+            count("added"),
+            count("removed"),
+            count("changed"),
+            if blocks { "blocks" } else { "files" }
+        ),
+    );
+    Err(Error::File(other.to_path_buf(), e))
 }
 
-fn add_basic_file(tape: &mut tape::Tape, file: &Path) -> Result<usize> {
-    print!("Adding basic file {:?}... ", file.as_os_str());
+/// Returns the line of a change between the files of two tapes in the given format
+fn file_change(format: report::Format, change: &diff::Change<tape::Entry>) -> String {
+    if format == report::Format::Json {
+        let entry = |e: Option<&tape::Entry>| {
+            e.map(|e| entry_to_json(e, None, None, None))
+                .unwrap_or_else(|| "null".to_string())
+        };
+        let (a, b) = change.items();
+        return format!(
+            "{{\"change\":{},\"a\":{},\"b\":{}}}",
+            json_string(change.name()),
+            entry(a),
+            entry(b)
+        );
+    }
+    let addresses = |e: &tape::Entry| {
+        e.addresses
+            .map(|(begin, end, start)| format!("[0x{:x},0x{:x}]:0x{:x}", begin, end, start))
+            .unwrap_or_default()
+    };
+    let name = |e: &tape::Entry| {
+        format!(
+            "{:6} | {:10}",
+            e.file_type,
+            e.name.as_deref().unwrap_or_default()
+        )
+    };
+    match change {
+        diff::Change::Changed(a, b) => {
+            let mut details = vec![];
+            if a.size != b.size {
+                details.push(format!("{} -> {} bytes", a.size, b.size));
+            }
+            if a.addresses != b.addresses {
+                details.push(format!("{} -> {}", addresses(a), addresses(b)));
+            }
+            if details.is_empty() {
+                details.push("different contents".to_string());
+            }
+            format!("{:7} | {} | {}", change.name(), name(a), details.join(", "))
+        }
+        diff::Change::Added(e) | diff::Change::Removed(e) => {
+            let line = format!("{:7} | {} | {:5} bytes", change.name(), name(e), e.size);
+            if e.addresses.is_some() {
+                format!("{} | {}", line, addresses(e))
+            } else {
+                line
+            }
+        }
+    }
+}
 
-    let data = file::read_content(file)?;
-    let (fname, truncated) = file::file_name_of(file)?;
-    if truncated {
-        print!(
-            "Warning: file name truncated to {}... ",
-            String::from_utf8_lossy(&fname)
+/// Returns the line of a change between the blocks of two tapes in the given format
+fn block_change(format: report::Format, change: &diff::Change<diff::BlockEntry>) -> String {
+    if format == report::Format::Json {
+        let entry = |e: Option<&diff::BlockEntry>| {
+            e.map(|e| format!("{{\"index\":{},\"length\":{}}}", e.index, e.len))
+                .unwrap_or_else(|| "null".to_string())
+        };
+        let (a, b) = change.items();
+        return format!(
+            "{{\"change\":{},\"a\":{},\"b\":{}}}",
+            json_string(change.name()),
+            entry(a),
+            entry(b)
         );
     }
+    let block = |e: &diff::BlockEntry| format!("block {:4} | {:5} bytes", e.index, e.len);
+    match change {
+        diff::Change::Changed(a, b) => {
+            format!("{:7} | {} -> {}", change.name(), block(a), block(b))
+        }
+        diff::Change::Added(e) | diff::Change::Removed(e) => {
+            format!("{:7} | {}", change.name(), block(e))
+        }
+    }
+}
 
-    let padding = tape.append_basic(&fname, &data)?;
+/// Edit a Basic program of a tape as text with the editor of the user
+///
+/// The program is detokenized to a temporary file and tokenized back once the editor
+/// exits. If the new source has syntax errors, the tape is left untouched and the
+/// temporary file is kept so the changes are not lost.
+///
+fn edit(out: &Reporter, path: &Path, name: &str) -> Result<()> {
+    let mut tape = tape::Tape::from_file_with(path, Scan::Unaligned).on_path(path)?;
+    let (index, file_name, program) = find_basic(&tape, path, name)?;
+    let listing = basic::detokenize(&program).on_path(path)?;
+    let temp_path = std::env::temp_dir().join(format!("mcp-{}-{}", process::id(), file_name));
+    fs::write(&temp_path, &listing).on_path(&temp_path)?;
 
-    if padding == 0 {
-        println!("Done");
-    } else {
-        println!("Done (padded with {} bytes!)", padding);
+    let edited = run_editor(&temp_path).and_then(|_| fs::read(&temp_path));
+    let source = match edited {
+        Ok(source) => source,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(Error::File(temp_path, e));
+        }
+    };
+    if source == listing {
+        fs::remove_file(&temp_path).on_path(&temp_path)?;
+        out.status_line(format_args!("No changes in {}", file_name));
+        return Ok(());
     }
-    Ok(padding)
+    let program = match basic::tokenize(&source) {
+        Ok(program) => program,
+        Err(errors) => {
+            for e in errors {
+                out.report(&Diagnostic::from_io(Some(&temp_path), &e.into()));
+            }
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} not updated: the edited program is kept in {:?}",
+                    file_name, temp_path
+                ),
+            );
+            return Err(Error::File(path.to_path_buf(), e));
+        }
+    };
+    fs::remove_file(&temp_path).on_path(&temp_path)?;
+
+    out.status(format_args!("Updating {}... ", file_name));
+    let padding = tape.replace_basic(index, &program).on_path(path)?;
+    write_tape(path, &tape, is_compressed(path))?;
+    report_padding(out, path, padding);
+    Ok(())
 }
 
-fn add_ascii_file(tape: &mut tape::Tape, file: &Path) -> Result<usize> {
-    print!("Adding ascii file {:?}... ", file.as_os_str());
+/// Shrink a Basic program of a tape, reporting the bytes saved
+fn basic_shrink(out: &Reporter, path: &Path, name: &str) -> Result<()> {
+    let mut tape = read_tape(path, Scan::Unaligned)?;
+    let (index, file_name, program) = find_basic(&tape, path, name)?;
+    out.status(format_args!("Shrinking {}... ", file_name));
+    let shrunk = basic::shrink(&program).on_path(path)?;
+    let padding = tape.replace_basic(index, &shrunk).on_path(path)?;
+    write_tape(path, &tape, is_compressed(path))?;
+    // Compare the data blocks, so the padding of both programs is taken into account
+    let len = shrunk.len() + padding;
+    out.status_line(format_args!(
+        "Done ({} bytes saved, from {} to {})",
+        program.len().saturating_sub(len),
+        program.len(),
+        len
+    ));
+    Ok(())
+}
 
-    let data = file::read_content(file)?;
-    let (fname, truncated) = file::file_name_of(file)?;
-    if truncated {
-        print!(
-            "Warning: file name truncated to {}... ",
-            String::from_utf8_lossy(&fname)
+/// Convert a Basic program of a tape between tokenized and ASCII, keeping its position
+fn basic_convert(out: &Reporter, path: &Path, name: &str, to: tape::FileType) -> Result<()> {
+    let mut tape = read_tape(path, Scan::Unaligned)?;
+    let found = tape.files().enumerate().find(|(_, file)| match file {
+        tape::File::Basic(stem, _) | tape::File::Ascii(stem, _) => is_named(file, stem, name),
+        _ => false,
+    });
+    let (index, file) = found.ok_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there is no Basic program named {:?} in {:?}", name, path),
+        ))
+    })?;
+    let file_name = file.name().unwrap_or_default();
+    if file.file_type() == to {
+        let format = if to == tape::FileType::Ascii {
+            "ASCII"
+        } else {
+            "tokenized"
+        };
+        let e = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is already {}", file_name, format),
         );
+        return Err(Error::File(path.to_path_buf(), e));
+    }
+    let old_len = file.size();
+    let data = match to {
+        tape::FileType::Ascii => {
+            let listing = basic::detokenize(&file.payload()).on_path(path)?;
+            text::convert_line_endings(&listing, LineEnding::CrLf)
+        }
+        _ => match basic::tokenize(&file.payload()) {
+            Ok(program) => program,
+            Err(errors) => {
+                for e in errors {
+                    out.report(&Diagnostic::from_io(Some(path), &e.into()));
+                }
+                let e = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} not converted: it is not a valid Basic program",
+                        file_name
+                    ),
+                );
+                return Err(Error::File(path.to_path_buf(), e));
+            }
+        },
+    };
+
+    out.status(format_args!("Converting {}... ", file_name));
+    let padding = tape.replace_file(index, to, &data).on_path(path)?;
+    write_tape(path, &tape, is_compressed(path))?;
+    out.status_line(format_args!(
+        "Done (from {} to {} bytes)",
+        old_len,
+        data.len() + padding
+    ));
+    Ok(())
+}
+
+/// Returns the index, the full name and the program of the Basic file named `name`
+fn find_basic(tape: &Tape, path: &Path, name: &str) -> Result<(usize, String, Vec<u8>)> {
+    let found = tape
+        .files()
+        .enumerate()
+        .find_map(|(index, file)| match file {
+            tape::File::Basic(ref stem, data) if is_named(&file, stem, name) => {
+                Some((index, file.name().unwrap_or_default(), data.to_vec()))
+            }
+            _ => None,
+        });
+    found.ok_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there is no Basic program named {:?} in {:?}", name, path),
+        ))
+    })
+}
+
+/// Write the given tape to `path` in CAS format, optionally compressed with gzip
+fn write_tape(path: &Path, tape: &Tape, compressed: bool) -> Result<()> {
+    let mut bytes = tape.to_bytes();
+    if compressed {
+        bytes = gzip::compress(&bytes);
+    }
+    write_output(path, &bytes)
+}
+
+/// Returns `true` if `path` is `-`, which stands for the standard input or output
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Returns `true` if the given command writes the tape or audio it produces to the standard
+/// output, so its messages must go to stderr instead
+fn writes_stdout(cmd: &args::Command) -> bool {
+    match cmd {
+        args::Command::Merge(path, ..)
+        | args::Command::Export(_, path, _)
+        | args::Command::Convert(_, path, ..)
+        | args::Command::RomLoad(_, path)
+        | args::Command::Load(_, path, _) => is_stdio(path),
+        _ => false,
     }
-    let padding = tape.append_ascii(&fname, &data)?;
-    println!("Done");
-    Ok(padding)
 }
 
-fn add_custom_file(tape: &mut tape::Tape, file: &Path) -> Result<usize> {
-    print!("Adding custom file {:?}... ", file.as_os_str());
+/// Open the file at `path` for reading, or the standard input if `path` is `-`
+fn open_input(path: &Path) -> Result<Box<dyn Read>> {
+    if is_stdio(path) {
+        Ok(Box::new(io::stdin()))
+    } else {
+        Ok(Box::new(File::open(path).on_path(path)?))
+    }
+}
 
-    let data = file::read_content(file)?;
-    let append = tape.append_custom(&data)?;
+/// Create the file at `path` for writing, or return the standard output if `path` is `-`
+fn create_output(path: &Path) -> Result<Box<dyn Write>> {
+    if is_stdio(path) {
+        Ok(Box::new(io::stdout()))
+    } else {
+        Ok(Box::new(File::create(path).on_path(path)?))
+    }
+}
 
-    if append == 0 {
-        println!("Done");
+/// Write `bytes` into the file at `path` as `file::write_content()` does, or to the standard
+/// output if `path` is `-`
+fn write_output(path: &Path, bytes: &[u8]) -> Result<()> {
+    if is_stdio(path) {
+        io::stdout().write_all(bytes).on_path(path)
     } else {
-        println!("Done (padded with {} bytes!)", append);
+        file::write_content(path, bytes).on_path(path)
     }
+}
 
-    Ok(append)
+/// Read a CAS file, or the standard input if `path` is `-`
+///
+/// The blocks are searched for as `scan` says.
+///
+fn open_tape(path: &Path, scan: Scan) -> Result<Tape> {
+    Tape::read_with(&mut open_input(path)?, scan).on_path(path)
 }
 
-fn save_tape(tape: &tape::Tape, file: &Path) -> Result<()> {
-    let mut buff = Vec::with_capacity(64 * 1024);
-    for block in tape.blocks() {
-        buff.write_all(block.data())?;
+/// Read a CAS file of the given machine, or the standard input if `path` is `-`
+///
+/// The blocks of MSX tapes are searched for as `scan` says.
+///
+fn open_machine_tape(path: &Path, scan: Scan, machine: Machine) -> Result<Tape> {
+    if machine == Machine::Msx {
+        return open_tape(path, scan);
     }
-    file::write_content(file, &buff)?;
-    Ok(())
+    let mut bytes = vec![];
+    open_input(path)?.read_to_end(&mut bytes).on_path(path)?;
+    if gzip::is_gzip(&bytes) {
+        bytes = gzip::decompress(&bytes).on_path(path)?;
+    }
+    Ok(machine.read(&bytes, scan))
 }
 
-fn export(cas_path: &Path, wav_path: &Path) -> Result<()> {
-    let tape = Tape::from_file(cas_path)?;
-    let mut exporter = wav::Exporter::new();
-    let mut wav_file = File::create(wav_path)?;
-
-    for (block, i) in tape.blocks().iter().zip(0..tape.blocks().len()) {
-        print!("Encoding block {}... ", i);
-        let mut nbytes = 0;
-        if block.is_file_header() {
-            nbytes += exporter.write_long_silence().ok().unwrap();
-            nbytes += exporter.write_long_header().ok().unwrap();
+/// Returns `true` if the file at `path` exists and is compressed with gzip
+fn is_compressed(path: &Path) -> bool {
+    let mut magic = [0; 2];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && gzip::is_gzip(&magic)
+}
+
+/// Print the files of a tape selected by the given name, index or glob pattern as they are
+/// extracted, as listings if they are tokenized Basic programs or as hex dumps
+fn cat(path: &Path, name: &str, as_text: bool, hex: bool, scan: Scan) -> Result<()> {
+    let tape = read_tape(path, scan)?;
+    let selected = tape.select(name);
+    if selected.is_empty() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there is no file matching {:?} in {:?}", name, path),
+        )));
+    }
+    let files: Vec<tape::File> = tape.files().collect();
+    for index in selected {
+        let bytes = match files[index] {
+            tape::File::Basic(_, program) if as_text => {
+                Cow::Owned(basic::detokenize(program).on_path(path)?)
+            }
+            ref file => dir::extracted_bytes(file, None),
+        };
+        if hex {
+            io::stdout()
+                .write_all(report::hex_dump(&bytes).as_bytes())
+                .map_err(Error::Io)?;
         } else {
-            nbytes += exporter.write_short_silence().ok().unwrap();
-            nbytes += exporter.write_short_header().ok().unwrap();
+            io::stdout().write_all(&bytes).map_err(Error::Io)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the files of a tape selected by the given names, indices or glob patterns
+///
+/// The annotations of the remaining files are moved to their new indices.
+///
+fn remove(out: &Reporter, path: &Path, names: &[String]) -> Result<()> {
+    let mut tape = tape::Tape::from_file_with(path, Scan::Unaligned).on_path(path)?;
+    let mut indices = vec![];
+    for name in names {
+        let selected = tape.select(name);
+        if selected.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("there is no file matching {:?} in {:?}", name, path),
+            )));
+        }
+        indices.extend(selected);
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    let mut meta = Metadata::load(path).on_path(path)?;
+    let names: Vec<Option<String>> = tape.files().map(|f| f.name()).collect();
+    for index in indices.into_iter().rev() {
+        match &names[index] {
+            Some(name) => out.status(format_args!("Removing {}... ", name)),
+            None => out.status(format_args!("Removing custom file at index {}... ", index)),
+        }
+        tape.remove_file(index).on_path(path)?;
+        meta.remove(index);
+        out.status_line(format_args!("Done"));
+    }
+    write_tape(path, &tape, is_compressed(path))?;
+    if !meta.is_empty() {
+        let lost = meta.reconcile(&tape);
+        let meta_path = Metadata::path_of(path);
+        if !lost.is_empty() {
+            out.report(&Diagnostic::warning(
+                "annotation_lost",
+                &meta_path,
+                format!("{} annotations match no file and were dropped", lost.len()),
+            ));
+            out.status_line(format_args!(""));
+        }
+        meta.save(path).on_path(&meta_path)?;
+    }
+    Ok(())
+}
+
+/// Rename a file of a tape, given by name or index
+///
+/// The extension of the file type is dropped from the new name (e.g. `game.bin` renames
+/// a binary file to `game`), and names longer than six characters are truncated.
+///
+fn rename(out: &Reporter, path: &Path, name: &str, new_name: &str) -> Result<()> {
+    let mut tape = tape::Tape::from_file_with(path, Scan::Unaligned).on_path(path)?;
+    let index = select_one(&tape, path, name)?;
+    let file = tape.files().nth(index).unwrap_or(tape::File::Custom(&[]));
+    let old_name = file.name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the file at index {} of {:?} is a custom file, which has no name",
+                index, path
+            ),
+        )
+    })?;
+    let stem = match (new_name.rsplit_once('.'), file.file_type().extension()) {
+        (Some((stem, ext)), Some(file_ext)) if ext.eq_ignore_ascii_case(file_ext) => stem,
+        _ => new_name,
+    };
+    let (bytes, truncated) = tape::file_name(stem);
+    out.status(format_args!("Renaming {}... ", old_name));
+    if truncated {
+        out.report(&Diagnostic::warning(
+            "name_truncated",
+            path,
+            format!("file name truncated to {}", String::from_utf8_lossy(&bytes)),
+        ));
+    }
+    tape.rename_file(index, &bytes).on_path(path)?;
+    write_tape(path, &tape, is_compressed(path))?;
+    let mut meta = Metadata::load(path).on_path(path)?;
+    if let (Some(annotation), Some(file)) = (meta.remove(index), tape.files().nth(index)) {
+        meta.set(index, &file, annotation);
+        let meta_path = Metadata::path_of(path);
+        meta.save(path).on_path(&meta_path)?;
+    }
+    out.status_line(format_args!("Done"));
+    Ok(())
+}
+
+/// Move a file of a tape, given by name or index, to another position
+///
+/// The annotations of the files are moved along with them.
+///
+fn move_file(out: &Reporter, path: &Path, name: &str, to: usize) -> Result<()> {
+    let mut tape = tape::Tape::from_file_with(path, Scan::Unaligned).on_path(path)?;
+    let from = select_one(&tape, path, name)?;
+    match tape.files().nth(from).and_then(|f| f.name()) {
+        Some(name) => out.status(format_args!("Moving {} to index {}... ", name, to)),
+        None => out.status(format_args!(
+            "Moving custom file at index {} to index {}... ",
+            from, to
+        )),
+    }
+    tape.move_file(from, to).on_path(path)?;
+    write_tape(path, &tape, is_compressed(path))?;
+    let mut meta = Metadata::load(path).on_path(path)?;
+    if !meta.is_empty() {
+        let annotation = meta.remove(from);
+        meta.reconcile(&tape);
+        if let (Some(annotation), Some(file)) = (annotation, tape.files().nth(to)) {
+            meta.set(to, &file, annotation);
+        }
+        let meta_path = Metadata::path_of(path);
+        meta.save(path).on_path(&meta_path)?;
+    }
+    out.status_line(format_args!("Done"));
+    Ok(())
+}
+
+/// Overwrite the payload of a file of a tape, given by name or index, from `offset` on
+fn patch(
+    out: &Reporter,
+    path: &Path,
+    name: &str,
+    offset: usize,
+    bytes: &args::Patch,
+) -> Result<()> {
+    let bytes = match bytes {
+        args::Patch::Bytes(bytes) => bytes.clone(),
+        args::Patch::File(file) => fs::read(file).on_path(file)?,
+    };
+    let mut tape = tape::Tape::from_file_with(path, Scan::Unaligned).on_path(path)?;
+    let index = select_one(&tape, path, name)?;
+    match tape.files().nth(index).and_then(|f| f.name()) {
+        Some(name) => out.status(format_args!(
+            "Patching {} bytes of {} at 0x{:x}... ",
+            bytes.len(),
+            name,
+            offset
+        )),
+        None => out.status(format_args!(
+            "Patching {} bytes of custom file at index {} at 0x{:x}... ",
+            bytes.len(),
+            index,
+            offset
+        )),
+    }
+    tape.patch_file(index, offset, &bytes).on_path(path)?;
+    write_tape(path, &tape, is_compressed(path))?;
+    out.status_line(format_args!("Done"));
+    Ok(())
+}
+
+/// Returns the index of the only file of a tape selected by the given name or index
+fn select_one(tape: &Tape, path: &Path, name: &str) -> Result<usize> {
+    match tape.select(name).as_slice() {
+        [index] => Ok(*index),
+        [] => Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there is no file named {:?} in {:?}", name, path),
+        ))),
+        selected => Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{:?} matches {} files in {:?}, give the index of one of them",
+                name,
+                selected.len(),
+                path
+            ),
+        ))),
+    }
+}
+
+/// Browse the files of a tape interactively until the user quits
+///
+/// The edits are kept in memory until the user writes them, along with the annotations
+/// of the files. Extracted files are written to the current directory.
+///
+fn browse(path: &Path) -> Result<()> {
+    if is_stdio(path) {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "mcp browse reads keys from the standard input, so it cannot read the tape from it",
+        )));
+    }
+    let tape = tape::Tape::from_file_with(path, Scan::Unaligned).on_path(path)?;
+    let meta = Metadata::load(path).on_path(path)?;
+    let mut browser = browse::Browser::new(tape, meta, Path::new("."));
+    let mut terminal = browse::Terminal::open()?;
+    let title = path.display().to_string();
+    loop {
+        let (width, height) = terminal.size();
+        terminal.draw(&browser.render(&title, width, height))?;
+        let key = loop {
+            if let Some(key) = terminal.read_key()? {
+                break key;
+            }
+        };
+        match browser.handle(key) {
+            Some(browse::Action::Save) => {
+                let meta_path = Metadata::path_of(path);
+                let written =
+                    write_tape(path, browser.tape(), is_compressed(path)).and_then(|_| {
+                        if !meta_path.exists() {
+                            return Ok(());
+                        }
+                        browser.meta().save(path).on_path(&meta_path)
+                    });
+                match written {
+                    Ok(()) => browser.saved(),
+                    Err(e) => browser.show(e.diagnostic().message),
+                }
+            }
+            Some(browse::Action::Quit) => return Ok(()),
+            None => {}
+        }
+    }
+}
+
+/// Write the files of several tapes one after another into a new tape
+///
+/// The files skipped or renamed according to the given policy are reported.
+///
+fn merge(
+    out: &Reporter,
+    path: &Path,
+    paths: &[PathBuf],
+    policy: tape::MergePolicy,
+    gzip: bool,
+) -> Result<()> {
+    let mut merged = Tape::new();
+    for input in paths {
+        let tape = open_tape(input, Scan::Unaligned)?;
+        out.status(format_args!("Merging {:?}... ", input.as_os_str()));
+        let changes = merged.extend_from_with(&tape, policy);
+        out.status_line(format_args!("Done"));
+        for change in changes {
+            match change {
+                tape::Merged::Skipped(index, name) => out.status_line(format_args!(
+                    "  skipped {} (file {}), a duplicate of a previous file",
+                    name.unwrap_or_else(|| "custom file".to_string()),
+                    index
+                )),
+                tape::Merged::Renamed(index, name, new_name) => out.status_line(format_args!(
+                    "  renamed {} (file {}) to {}",
+                    name, index, new_name
+                )),
+            }
+        }
+    }
+    write_tape(path, &merged, gzip)?;
+    out.status_line(format_args!(
+        "{} files written to {:?}",
+        merged.files().count(),
+        path.as_os_str()
+    ));
+    Ok(())
+}
+
+/// Write every file of a tape as a tape of its own into the given directory
+fn split(out: &Reporter, path: &Path, dir: Option<&Path>) -> Result<()> {
+    let tape = open_tape(path, Scan::Unaligned)?;
+    report_problems(out, path, &tape);
+    let dir = dir.unwrap_or_else(|| Path::new(""));
+    if !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir).on_path(dir)?;
+    }
+    for split in tape.split_to_dir(dir) {
+        out.status(format_args!("Writing {}... ", split.name));
+        if split.clash {
+            out.report(&Diagnostic::warning(
+                "filename_clash",
+                Path::new(&split.name),
+                format!(
+                    "filename {:?} already exists, writing output to {:?}",
+                    split.name, split.path
+                ),
+            ));
+        }
+        split.result.on_path(&split.path)?;
+        out.status_line(format_args!("Done"));
+    }
+    Ok(())
+}
+
+fn export_asm(path: &Path, name: &str, dialect: include::Dialect) -> Result<()> {
+    let tape = read_tape(path, Scan::Unaligned)?;
+    let (file, label) = find_export(&tape, path, name)?;
+    print!("{}", include::asm(&file, &label, dialect));
+    Ok(())
+}
+
+fn export_c(out: &Reporter, path: &Path, name: &str, output: Option<&Path>) -> Result<()> {
+    let tape = read_tape(path, Scan::Unaligned)?;
+    let (file, label) = find_export(&tape, path, name)?;
+    let header = include::c_header(&file, &label);
+    match output {
+        Some(output) if !is_stdio(output) => {
+            out.status(format_args!("Writing {:?}... ", output.as_os_str()));
+            file::write_content(output, header.as_bytes()).on_path(output)?;
+            out.status_line(format_args!("Done"));
+        }
+        _ => print!("{}", header),
+    }
+    Ok(())
+}
+
+/// Returns the file named `name` to export from the given tape, along with its label
+///
+/// The label is derived from the name of the file without extension (e.g., `GAME` for
+/// `game.bin`), or the full name for custom files (e.g., `CUSTOM_000`).
+///
+fn find_export<'a>(tape: &'a Tape, path: &Path, name: &str) -> Result<(tape::File<'a>, String)> {
+    let (file, host_name) = find_file(tape, name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there is no file named {:?} in {:?}", name, path),
+        )
+    })?;
+    let label = match file {
+        tape::File::Custom(_) => include::label_of(&host_name),
+        _ => include::label_of(host_name.split('.').next().unwrap_or_default()),
+    };
+    Ok((file, label))
+}
+
+/// Returns the file of the tape named `name`, along with its full name
+///
+/// Custom files have no name, and are found by the name they are extracted with
+/// (`custom.000`, `custom.001`...).
+///
+fn find_file<'a>(tape: &'a Tape, name: &str) -> Option<(tape::File<'a>, String)> {
+    let (index, file) = tape.find(name)?;
+    let full_name = file.name().unwrap_or_else(|| {
+        let customs = tape
+            .files()
+            .take(index)
+            .filter(|f| matches!(f, tape::File::Custom(_)));
+        format!("custom.{:03}", customs.count())
+    });
+    Some((file, full_name))
+}
+
+/// Returns `true` if the given file is named `name`, either with or without extension
+///
+/// Names are compared ignoring the case of letters.
+///
+fn is_named(file: &tape::File, stem: &str, name: &str) -> bool {
+    let name = name.trim();
+    stem.trim().eq_ignore_ascii_case(name)
+        || file.name().is_some_and(|n| n.eq_ignore_ascii_case(name))
+}
+
+/// Open the given file with the editor set in `EDITOR` (or `vi`) and wait for it to exit
+fn run_editor(path: &Path) -> io::Result<()> {
+    let editor = doctor::editor();
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "editor {:?} failed ({})",
+            editor, status
+        )));
+    }
+    Ok(())
+}
+
+/// Check whether a tape fits in one side of a cassette, failing if it does not
+fn fit(out: &Reporter, path: &Path, cassette: wav::Cassette, profile: wav::Profile) -> Result<()> {
+    let tape = read_tape(path, Scan::Unaligned)?;
+    let bauds = profile.bauds;
+    let duration = wav::Timeline::new(&tape, profile).duration();
+    let side = cassette.side_duration();
+    if out.format() == report::Format::Json {
+        println!(
+            "{{\"duration_ms\":{},\"bauds\":{},\"cassette\":{},\"side_ms\":{},\"fits\":{}}}",
+            duration.as_millis(),
+            bauds,
+            json_string(&cassette.to_string()),
+            side.as_millis(),
+            duration <= side
+        );
+    } else {
+        println!(
+            "Duration:   {} at {} bauds (including gaps and leaders)",
+            minutes(duration),
+            bauds
+        );
+        println!("Cassette:   {} ({} per side)", cassette, minutes(side));
+    }
+    if duration > side {
+        let e = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the tape exceeds one side of a {} by {}",
+                cassette,
+                minutes(duration - side)
+            ),
+        );
+        return Err(Error::File(path.to_path_buf(), e));
+    }
+    out.status_line(format_args!(
+        "Fits in one side with {} to spare",
+        minutes(side - duration)
+    ));
+    Ok(())
+}
+
+/// Check whether the files of a tape would load in machines with the given RAM sizes
+///
+/// The problems common to several sizes are reported once. Fails if any file would fail
+/// to load in any of them.
+///
+fn loadcheck(out: &Reporter, path: &Path, sizes: &[load::Ram]) -> Result<()> {
+    let tape = read_tape(path, Scan::Unaligned)?;
+    let mut entries = vec![];
+    let mut failed = 0;
+    let mut next_custom = 0;
+    for (index, file) in tape.files().enumerate() {
+        let name = file.name().unwrap_or_else(|| {
+            let name = format!("custom.{:03}", next_custom);
+            next_custom += 1;
+            name
+        });
+        let mut problems: Vec<(load::Problem, Vec<load::Ram>)> = vec![];
+        for ram in sizes {
+            for problem in load::check(&file, *ram) {
+                match problems.iter_mut().find(|(p, _)| *p == problem) {
+                    Some((_, rams)) => rams.push(*ram),
+                    None => problems.push((problem, vec![*ram])),
+                }
+            }
+        }
+        if problems
+            .iter()
+            .any(|(p, _)| p.severity == load::Severity::Error)
+        {
+            failed += 1;
+        }
+        let skipped = file.file_type() == tape::FileType::Custom;
+        if out.format() == report::Format::Json {
+            let problems: Vec<String> = problems
+                .iter()
+                .map(|(p, rams)| {
+                    let rams: Vec<String> = rams.iter().map(|r| r.kb().to_string()).collect();
+                    format!(
+                        "{{\"severity\":{},\"ram\":[{}],\"message\":{}}}",
+                        json_string(p.severity.name()),
+                        rams.join(","),
+                        json_string(&p.message)
+                    )
+                })
+                .collect();
+            entries.push(format!(
+                "{{\"index\":{},\"name\":{},\"checked\":{},\"problems\":[{}]}}",
+                index,
+                json_string(&name),
+                !skipped,
+                problems.join(",")
+            ));
+            continue;
+        }
+        if problems.is_empty() {
+            println!("{:7} | {}", if skipped { "skipped" } else { "ok" }, name);
+        }
+        for (problem, rams) in &problems {
+            let rams: Vec<String> = rams.iter().map(|r| r.kb().to_string()).collect();
+            println!(
+                "{:7} | {} | {}KB | {}",
+                problem.severity.name(),
+                name,
+                rams.join("/"),
+                problem.message
+            );
+        }
+    }
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
+    }
+    if failed > 0 {
+        let message = format!(
+            "{} of {} files would fail to load",
+            failed,
+            tape.files().count()
+        );
+        return Err(Error::Check(path.to_path_buf(), message));
+    }
+    Ok(())
+}
+
+/// Record several tapes one after another into a WAV file, as the side of a cassette
+///
+/// The tapes are separated by `gap` seconds of silence. The index with the times each
+/// tape starts and ends is written to `index`, or printed if it is not given.
+///
+fn master(
+    out: &Reporter,
+    path: &Path,
+    tapes: &[PathBuf],
+    gap: u32,
+    index: Option<&Path>,
+) -> Result<()> {
+    let mut exporter = wav::Exporter::new();
+    let mut entries = vec![];
+    for (i, tape_path) in tapes.iter().enumerate() {
+        let tape = read_tape(tape_path, Scan::Unaligned)?;
+        if i > 0 {
+            exporter.write_pause(std::time::Duration::from_secs(gap as u64))?;
+        }
+        out.status(format_args!("Encoding {:?}... ", tape_path.as_os_str()));
+        let start = exporter.elapsed();
+        exporter.write_tape(&tape, &mut mcp::progress::ignore())?;
+        out.status_line(format_args!("Done"));
+        entries.push((tape_path, start, exporter.elapsed()));
+    }
+    let mut wav_file = File::create(path).on_path(path)?;
+    exporter.export(&mut wav_file).on_path(path)?;
+
+    let text = if out.format() == report::Format::Json {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(tape_path, start, end)| {
+                format!(
+                    "{{\"path\":{},\"start_ms\":{},\"end_ms\":{}}}",
+                    json_string(&tape_path.to_string_lossy()),
+                    start.as_millis(),
+                    end.as_millis()
+                )
+            })
+            .collect();
+        format!("[{}]\n", entries.join(","))
+    } else {
+        entries
+            .iter()
+            .map(|(tape_path, start, end)| {
+                format!(
+                    "{:>5} - {:>5} | {}\n",
+                    minutes(*start),
+                    minutes(*end),
+                    tape_path.display()
+                )
+            })
+            .collect()
+    };
+    match index {
+        Some(index) => {
+            out.status(format_args!("Writing index to {:?}... ", index.as_os_str()));
+            fs::write(index, text).on_path(index)?;
+            out.status_line(format_args!("Done"));
+        }
+        None => print!("{}", text),
+    }
+    out.status_line(format_args!(
+        "Recorded {} tapes in {:?} ({})",
+        tapes.len(),
+        path.as_os_str(),
+        minutes(exporter.elapsed())
+    ));
+    Ok(())
+}
+
+/// Decode a recording of a tape into a CAS file, warning about the blocks it drops out in
+fn load_recording(out: &Reporter, wav_path: &Path, path: &Path, gzip: bool) -> Result<()> {
+    let mut bytes = vec![];
+    open_input(wav_path)?
+        .read_to_end(&mut bytes)
+        .on_path(wav_path)?;
+    out.status(format_args!("Decoding {:?}... ", wav_path.as_os_str()));
+    let decoded = decode::decode(&bytes).on_path(wav_path)?;
+    for dropout in &decoded.dropouts {
+        out.report(&Diagnostic::warning(
+            "dropout",
+            wav_path,
+            format!(
+                "block {} drops out at {} after {} bytes",
+                dropout.block,
+                minutes(dropout.time),
+                dropout.bytes
+            ),
+        ));
+    }
+    write_tape(path, &decoded.tape, gzip)?;
+    out.status_line(format_args!(
+        "Done ({} files in {} blocks)",
+        decoded.tape.files().count(),
+        decoded.tape.blocks().len()
+    ));
+    Ok(())
+}
+
+/// Play a tape through the sound device, piping its samples to an external audio player
+///
+/// The pipe fills up as fast as the player consumes it, so the progress of each block is
+/// shown roughly as it is played.
+///
+/// With a remote control line, the line is on while every file plays. Between files, the
+/// player gets `pause` seconds of silence, and the line is turned off once the previous
+/// file has been played and on again before the next one, as the motor control line of
+/// an MSX would do.
+///
+fn play(
+    out: &Reporter,
+    path: &Path,
+    profile: wav::Profile,
+    remote: Option<&Line>,
+    pause: u32,
+) -> Result<()> {
+    let tape = read_tape(path, Scan::Unaligned)?;
+    let mut remote = remote.map(open_remote).transpose()?;
+    let (program, args) = doctor::player(profile.sample_rate).ok_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no audio player found in PATH (install aplay, paplay or SoX)",
+        ))
+    })?;
+    let mut child = process::Command::new(&program)
+        .args(&args)
+        .stdin(process::Stdio::piped())
+        .spawn()
+        .on_path(&program)?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .expect("the stdin of the player is piped");
+    let mut exporter = wav::Exporter::with_profile(profile);
+    let total = tape.blocks().len();
+    let bauds = profile.block_bauds(&tape);
+    let mut played = 0;
+    let started = std::time::Instant::now();
+    // The audio written so far takes a while to be played, so the line is switched once
+    // the clock reaches the given time, plus the latency of the player
+    let wait_until = |time: std::time::Duration| {
+        let time = time + std::time::Duration::from_millis(REMOTE_LATENCY_MS);
+        if let Some(left) = time.checked_sub(started.elapsed()) {
+            thread::sleep(left);
+        }
+    };
+    for (i, block) in tape.blocks().iter().enumerate() {
+        if let Some(remote) = remote.as_mut() {
+            if i > 0 && block.is_file_header() {
+                let end = exporter.elapsed();
+                out.status_line(format_args!("Pausing for {} seconds...", pause));
+                exporter.write_pause(std::time::Duration::from_secs(pause as u64))?;
+                stdin
+                    .write_all(&exporter.samples()[played..])
+                    .on_path(&program)?;
+                played = exporter.samples().len();
+                wait_until(end);
+                remote.set(false)?;
+                wait_until(exporter.elapsed());
+            }
+            remote.set(true)?;
+        }
+        out.status(format_args!("Playing block {} of {}... ", i + 1, total));
+        exporter.write_block_at(block, bauds[i])?;
+        stdin
+            .write_all(&exporter.samples()[played..])
+            .on_path(&program)?;
+        played = exporter.samples().len();
+        out.status_line(format_args!("{}", minutes(exporter.elapsed())));
+    }
+    drop(stdin);
+    let status = child.wait().on_path(&program)?;
+    drop(remote);
+    if !status.success() {
+        let e = io::Error::other(format!("the audio player failed ({})", status));
+        return Err(Error::File(program, e));
+    }
+    Ok(())
+}
+
+fn gen_fixture(out: &Reporter, path: &Path, fixture: &Fixture, gzip: bool) -> Result<()> {
+    let tape = fixture.generate();
+    write_tape(path, &tape, gzip)?;
+    out.status_line(format_args!(
+        "Generated {} files in {:?} with seed {}",
+        tape.files().count(),
+        path.as_os_str(),
+        fixture.seed
+    ));
+    Ok(())
+}
+
+fn run_doctor(out: &Reporter) -> Result<()> {
+    let checks = doctor::run();
+    if out.format() == report::Format::Json {
+        let entries: Vec<String> = checks
+            .iter()
+            .map(|c| {
+                format!(
+                    "{{\"check\":{},\"status\":{},\"message\":{},\"fix\":{}}}",
+                    json_string(c.name),
+                    json_string(c.status.name()),
+                    json_string(&c.message),
+                    c.fix
+                        .as_deref()
+                        .map(json_string)
+                        .unwrap_or_else(|| "null".to_string())
+                )
+            })
+            .collect();
+        println!("[{}]", entries.join(","));
+    } else {
+        for check in &checks {
+            println!(
+                "{:7} | {:17} | {}",
+                check.status.name(),
+                check.name,
+                check.message
+            );
+            if let Some(fix) = &check.fix {
+                println!("{:7}   {:17}   fix: {}", "", "", fix);
+            }
+        }
+    }
+    let errors = checks
+        .iter()
+        .filter(|c| c.status == doctor::Status::Error)
+        .count();
+    if errors > 0 {
+        return Err(Error::Io(io::Error::other(format!(
+            "{} of {} checks failed",
+            errors,
+            checks.len()
+        ))));
+    }
+    Ok(())
+}
+
+fn preview(out: &Reporter, path: &Path) -> Result<()> {
+    let tape = open_tape(path, Scan::Unaligned)?;
+    let mut next_custom = 0;
+    for file in tape.files() {
+        let custom = format!("custom.{:03}", next_custom);
+        if let tape::File::Custom(_) = file {
+            next_custom += 1;
+        }
+        let (mode, vram) = match screen::vram_of(&file) {
+            Some(dump) => dump,
+            None => continue,
+        };
+        let out_path = file.name().unwrap_or(custom) + ".png";
+        let out_path = Path::new(&out_path);
+        let (out_filename, _) = file::unique_filename(out_path).on_path(out_path)?;
+        out.status(format_args!(
+            "Rendering {:?} as {:?}... ",
+            out_filename.as_os_str(),
+            mode
+        ));
+        let mut ofile = File::create(&out_filename).on_path(&out_filename)?;
+        screen::render(mode, &vram)
+            .write_png(&mut ofile)
+            .on_path(&out_filename)?;
+        out.status_line(format_args!("Done"));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn openmsx_insert(
+    out: &Reporter,
+    path: &Path,
+    socket: Option<&Path>,
+    load: Option<&str>,
+) -> Result<()> {
+    use mcp::openmsx::Connection;
+
+    let mut conn = match socket {
+        Some(socket) => Connection::connect(socket).on_path(socket)?,
+        None => Connection::find()?,
+    };
+    out.status(format_args!(
+        "Inserting {:?} in openMSX... ",
+        path.as_os_str()
+    ));
+    conn.insert_tape(path).on_path(path)?;
+    if let Some(load) = load {
+        conn.type_line(load)?;
+    }
+    out.status_line(format_args!("Done"));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn openmsx_insert(_: &Reporter, _: &Path, _: Option<&Path>, _: Option<&str>) -> Result<()> {
+    Err(Error::Io(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "openMSX remote control is only supported on Unix systems",
+    )))
+}
+
+/// Extract the files of a tape selected by the given names, indices or glob patterns
+///
+/// All the files are extracted if no name is given. The files are written to `dir`,
+/// which is created if needed, or to the current directory.
+///
+fn extract(
+    out: &Reporter,
+    path: &Path,
+    names: &[String],
+    dir: Option<&Path>,
+    options: &dir::ExtractOptions,
+    scan: Scan,
+    machine: Machine,
+) -> Result<()> {
+    let tape = open_machine_tape(path, scan, machine)?;
+    report_problems(out, path, &tape);
+    let mut indices: Vec<usize> = (0..tape.files().count()).collect();
+    if !names.is_empty() {
+        indices.clear();
+        for name in names {
+            let selected = tape.select(name);
+            if selected.is_empty() {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("there is no file matching {:?} in {:?}", name, path),
+                )));
+            }
+            indices.extend(selected);
+        }
+        indices.sort_unstable();
+        indices.dedup();
+    }
+    let dir = dir.unwrap_or_else(|| Path::new(""));
+    if !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir).on_path(dir)?;
+    }
+    let entries = tape.entries();
+    for extracted in tape.extract_files_to_dir(&indices, dir, options, &mut progress::ignore()) {
+        out.status(format_args!("Extracting {}... ", extracted.name));
+        log_blocks(out, &tape, entries[extracted.index].blocks.clone());
+        if extracted.clash {
+            out.report(&Diagnostic::warning(
+                "filename_clash",
+                Path::new(&extracted.name),
+                format!(
+                    "filename {:?} already exists, writing output to {:?}",
+                    extracted.name, extracted.path
+                ),
+            ));
+        }
+        extracted.result.on_path(&extracted.path)?;
+        out.status_line(format_args!("Done"));
+    }
+    Ok(())
+}
+
+fn add_files(
+    out: &Reporter,
+    path: &Path,
+    files: &[&Path],
+    add_options: &args::AddOptions,
+) -> Result<()> {
+    let mut options = dir::PackOptions {
+        eol: add_options.eol,
+        begin: add_options.begin,
+        start: add_options.start,
+        utf8: add_options.utf8,
+        file_type: if add_options.custom {
+            Some(tape::FileType::Custom)
+        } else {
+            None
+        },
+        ..dir::PackOptions::default()
+    };
+    // Custom files have no name in the tape, so their name is kept as the title of their
+    // annotation instead
+    let mut label = None;
+    if let Some(name) = &add_options.name {
+        let file = match files {
+            [file] if !file.is_dir() => file,
+            _ => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a name can only be given when adding a single file",
+                )))
+            }
+        };
+        let (bytes, truncated) = tape::file_name(name);
+        if options.file_type_of(file) == tape::FileType::Custom {
+            label = Some(name.clone());
+        } else if truncated || name.trim().is_empty() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid name {:?} (expected 1 to 6 characters)", name),
+            )));
+        } else {
+            options.name = Some(bytes);
+        }
+    }
+    let machine = add_options.machine;
+    let mut all = if path.exists() {
+        open_machine_tape(path, Scan::Unaligned, machine)?
+    } else {
+        Tape::new()
+    };
+    let first = all.files().count();
+    let mut paths = vec![];
+    for file in files {
+        if file.is_dir() {
+            paths.extend(dir::pack_entries(file).on_path(file)?);
+        } else {
+            paths.push(file.to_path_buf());
+        }
+    }
+    let mut loader = add_options.loader;
+    if loader
+        && !paths
+            .iter()
+            .any(|p| options.file_type_of(p) == tape::FileType::Bin)
+    {
+        return Err(Error::Io(no_binary_file()));
+    }
+    let mut padding = 0;
+    let mut tape = Tape::new();
+    for file in &paths {
+        padding += add_file(out, &mut tape, file, &options)?;
+        if loader && options.file_type_of(file) == tape::FileType::Bin {
+            add_loader(out, &mut tape)?;
+            loader = false;
+        }
+    }
+    if add_options.verifier {
+        add_verifier(out, &all, &mut tape)?;
+    }
+    let first_block = all.blocks().len();
+    all.extend_from(&tape);
+    log_blocks(out, &all, first_block..all.blocks().len());
+    let names: Vec<String> = all.files().map(|f| f.name().unwrap_or_default()).collect();
+    for finding in analysis::check(&all).iter().filter(|f| f.index >= first) {
+        out.report(&Diagnostic::warning(
+            "memory_map",
+            path,
+            format!("{}: {}", names[finding.index], finding.problem.message),
+        ));
+        out.status_line(format_args!(""));
+    }
+    out.status(format_args!("Writing {:?}... ", path.as_os_str()));
+    let compressed = add_options.gzip || is_compressed(path);
+    if compressed || machine != Machine::Msx {
+        // Compressed files and the CAS files of other machines cannot be appended to, so
+        // the whole tape is rewritten
+        let mut bytes = machine.to_bytes(&all);
+        if compressed {
+            bytes = gzip::compress(&bytes);
+        }
+        write_output(path, &bytes)?;
+    } else {
+        tape.append_to_path(path).on_path(path)?;
+    }
+    out.status_line(format_args!("Done"));
+    if let (Some(label), Some(file)) = (label, tape.files().next()) {
+        let mut meta = Metadata::load(path).on_path(path)?;
+        meta.set(
+            first,
+            &file,
+            Annotation {
+                title: Some(label),
+                ..Annotation::default()
+            },
+        );
+        meta.save(path).on_path(&Metadata::path_of(path))?;
+    }
+
+    if padding > 0 {
+        out.status_line(format_args!(""));
+        out.status_line(format_args!(
+            "Warning: some files had lengths that required padding with zeroes to be aligned"
+        ));
+        out.status_line(format_args!(
+            "to 8-byte boundaries. This is a constraint of CAS file format: every data block"
+        ));
+        out.status_line(format_args!("must start in an offset divisible by 8."));
+        out.status_line(format_args!(""));
+        out.status_line(format_args!(
+            "For binary files, this means the total length of the file excluding the"
+        ));
+        out.status_line(format_args!("0x1F prefix must be 8-byte aligned."));
+        out.status_line(format_args!(""));
+        out.status_line(format_args!(
+            "For ASCII files, this does not affect you. ASCII files are always aligned to"
+        ));
+        out.status_line(format_args!(
+            "256-byte boundaries and padded with EOF values (0x1A) needed by MSX BIOS to"
+        ));
+        out.status_line(format_args!("detect the end of the file."));
+        out.status_line(format_args!(""));
+        out.status_line(format_args!(
+            "For custom files, the effect is unknown. These files are loaded using custom"
+        ));
+        out.status_line(format_args!(
+            "code. And if padding zeroes affect or not depends on that code."
+        ));
+        out.status_line(format_args!(""));
+        out.status_line(format_args!(
+            "Using the right file sizes is highly recommended to prevent problems. However"
+        ));
+        out.status_line(format_args!(
+            "this is not considered as an error, and your CAS package has been successfully"
+        ));
+        out.status_line(format_args!("generated."));
+    }
+    Ok(())
+}
+
+/// Insert a Basic loader named after the binary file last added to `tape` right before it
+fn add_loader(out: &Reporter, tape: &mut Tape) -> Result<()> {
+    let count = tape.files().count();
+    let name = match tape.files().last() {
+        Some(tape::File::Bin(name, ..)) => name,
+        _ => return Ok(()),
+    };
+    out.status(format_args!("Adding loader {:?}... ", name));
+    let (name, _) = tape::file_name(&name);
+    tape.append_loader_for(&name)?;
+    tape.move_file(count, count - 1)?;
+    out.status_line(format_args!("Done"));
+    Ok(())
+}
+
+fn no_binary_file() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "there is no binary file to add a loader for",
+    )
+}
+
+/// Append the verifier of the files of `existing` followed by the new files in `tape`
+fn add_verifier(out: &Reporter, existing: &Tape, tape: &mut Tape) -> Result<()> {
+    let mut all = Tape::new();
+    all.extend_from(existing);
+    all.extend_from(tape);
+    let checks = verifier::checks(&all);
+    let listed = checks
+        .iter()
+        .filter(|c| **c != verifier::Check::Ignored)
+        .count();
+    let checked = checks
+        .iter()
+        .filter(|c| matches!(c, verifier::Check::Sum(..)))
+        .count();
+    out.status(format_args!(
+        "Adding verifier program {:?}... ",
+        verifier::NAME
+    ));
+    let program = verifier::program(&all)?;
+    let (name, _) = tape::file_name(verifier::NAME);
+    tape.append_basic(&name, &program)?;
+    out.status_line(format_args!(
+        "Done ({} of {} files checked)",
+        checked, listed
+    ));
+    Ok(())
+}
+
+fn add_file(
+    out: &Reporter,
+    tape: &mut Tape,
+    file: &Path,
+    options: &dir::PackOptions,
+) -> Result<usize> {
+    let kind = match options.file_type_of(file) {
+        tape::FileType::Bin => "binary",
+        tape::FileType::Basic => "basic",
+        tape::FileType::Ascii => "ascii",
+        tape::FileType::Custom => "custom",
+    };
+    out.status(format_args!(
+        "Adding {} file {:?}... ",
+        kind,
+        file.as_os_str()
+    ));
+
+    let packed = tape.pack_file(file, options).on_path(file)?;
+    if packed.truncated {
+        out.report(&Diagnostic::warning(
+            "name_truncated",
+            file,
+            format!(
+                "file name truncated to {}",
+                String::from_utf8_lossy(&packed.name.unwrap_or_default())
+            ),
+        ));
+    }
+    if packed.file_type == tape::FileType::Ascii {
+        // ASCII files are always padded with EOF bytes, which is not worth a warning
+        out.status_line(format_args!("Done"));
+        return Ok(0);
+    }
+    report_padding(out, file, packed.padding);
+    Ok(packed.padding)
+}
+
+fn report_padding(out: &Reporter, file: &Path, padding: usize) {
+    if padding == 0 {
+        out.status_line(format_args!("Done"));
+    } else {
+        if out.format() == report::Format::Json {
+            out.report(&Diagnostic::warning(
+                "padded",
+                file,
+                format!("padded with {} bytes", padding),
+            ));
+        }
+        out.status_line(format_args!("Done (padded with {} bytes!)", padding));
+    }
+}
+
+/// Read a tape in the format given by the extension of `path`, or CAS if unknown
+///
+/// The blocks of CAS files are searched for as `scan` says.
+///
+fn read_tape(path: &Path, scan: Scan) -> Result<Tape> {
+    let mut file = open_input(path)?;
+    match format::for_path(path) {
+        Some(format) if format.name() != "cas" => format.read(&mut file).on_path(path),
+        _ => Tape::read_with(&mut file, scan).on_path(path),
+    }
+}
+
+/// Convert a tape to the format given by the extension of `out_path`, or CAS if unknown
+fn convert(
+    out: &Reporter,
+    path: &Path,
+    out_path: &Path,
+    profile: wav::Profile,
+    scan: Scan,
+) -> Result<()> {
+    let tape = read_tape(path, scan)?;
+    let format: Box<dyn format::TapeFormat> = match format::for_path(out_path) {
+        Some(f) if f.name() == "tsx" => Box::new(tsx_of(path, &tape, profile)?),
+        Some(f) if f.name() == "cas" => f,
+        None => Box::new(format::Cas),
+        Some(f) => {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cannot convert to {} files (try the export command)",
+                    f.name()
+                ),
+            );
+            return Err(Error::File(out_path.to_path_buf(), e));
+        }
+    };
+    if format.name() != "tsx" && profile != wav::Profile::default() {
+        let e = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the speed can only be set for TSX files",
+        );
+        return Err(Error::File(out_path.to_path_buf(), e));
+    }
+    out.status(format_args!(
+        "Converting {:?} to {}... ",
+        path.as_os_str(),
+        format.name()
+    ));
+    log_blocks(out, &tape, 0..tape.blocks().len());
+    let mut bytes = vec![];
+    format.write(&tape, &mut bytes).on_path(out_path)?;
+    write_output(out_path, &bytes)?;
+    out.status_line(format_args!("Done ({} blocks)", tape.blocks().len()));
+    Ok(())
+}
+
+/// Convert several tapes to the given format, next to them or into `dir`
+///
+/// The tapes are converted by `jobs` threads (one per CPU if `None`), and each one is
+/// reported as soon as it is done. The converted tapes are named after the originals with
+/// the extension of the format. Fails once every tape is done if any of them could not be
+/// converted.
+///
+fn convert_all(
+    out: &Reporter,
+    paths: &[PathBuf],
+    to: &str,
+    dir: Option<&Path>,
+    jobs: Option<usize>,
+    profile: wav::Profile,
+    scan: Scan,
+) -> Result<()> {
+    if !matches!(to, "tsx" | "wav") && profile != wav::Profile::default() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the speed can only be set for TSX and WAV files",
+        )));
+    }
+    profile.validate()?;
+    if paths.iter().any(|p| is_stdio(p)) {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "tapes converted with --to cannot be read from the standard input",
+        )));
+    }
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir).on_path(dir)?;
+    }
+    let jobs = jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let mut entries = vec![String::new(); paths.len()];
+    let mut failed = 0;
+    parallel(
+        paths,
+        jobs,
+        |path| convert_to(path, to, dir, profile, scan),
+        |index, result| {
+            let path = &paths[index];
+            let (status, output, message) = match &result {
+                Ok(out_path) => ("ok", Some(out_path), None),
+                Err(e) => {
+                    failed += 1;
+                    ("error", None, Some(e.diagnostic().message))
+                }
+            };
+            if out.format() == report::Format::Json {
+                entries[index] = format!(
+                    "{{\"path\":{},\"status\":{},\"output\":{},\"message\":{}}}",
+                    json_string(&path.to_string_lossy()),
+                    json_string(status),
+                    output.map_or("null".to_string(), |p| json_string(&p.to_string_lossy())),
+                    message.as_deref().map_or("null".to_string(), json_string)
+                );
+                return;
+            }
+            match (output, message) {
+                (Some(output), _) => {
+                    println!("{:7} | {} | {}", status, path.display(), output.display())
+                }
+                (None, message) => println!(
+                    "{:7} | {} | {}",
+                    status,
+                    path.display(),
+                    message.unwrap_or_default()
+                ),
+            }
+        },
+    );
+    if out.format() == report::Format::Json {
+        println!("[{}]", entries.join(","));
+    }
+    out.status_line(format_args!(
+        "Converted {} of {} tapes",
+        paths.len() - failed,
+        paths.len()
+    ));
+    if failed > 0 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} of {} tapes could not be converted", failed, paths.len()),
+        )));
+    }
+    Ok(())
+}
+
+/// Convert the tape at `path` to the given format, returning the path it was written to
+fn convert_to(
+    path: &Path,
+    to: &str,
+    dir: Option<&Path>,
+    profile: wav::Profile,
+    scan: Scan,
+) -> Result<PathBuf> {
+    let name = Path::new(path.file_name().unwrap_or_default()).with_extension(to);
+    let out_path = dir
+        .or_else(|| path.parent())
+        .unwrap_or_else(|| Path::new(""))
+        .join(name);
+    if out_path == path {
+        let e = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the tape is already a {} file", to),
+        );
+        return Err(Error::File(out_path, e));
+    }
+    let tape = read_tape(path, scan)?;
+    let mut bytes = vec![];
+    match to {
+        "wav" => {
+            let mut exporter = wav::Exporter::with_profile(profile);
+            for (block, bauds) in tape.blocks().iter().zip(profile.block_bauds(&tape)) {
+                exporter.write_block_at(block, bauds)?;
+            }
+            exporter.export(&mut bytes)?;
+        }
+        "tsx" => format::TapeFormat::write(&tsx_of(path, &tape, profile)?, &tape, &mut bytes)?,
+        "csw" => format::TapeFormat::write(&format::Csw, &tape, &mut bytes)?,
+        _ => tape.write(&mut bytes)?,
+    }
+    fs::write(&out_path, bytes).on_path(&out_path)?;
+    Ok(out_path)
+}
+
+/// Returns the TSX format to write the tape at `path` with, along with its metadata
+fn tsx_of(path: &Path, tape: &Tape, profile: wav::Profile) -> Result<format::Tsx> {
+    let meta = Metadata::load(path).on_path(path)?;
+    Ok(format::Tsx {
+        profile,
+        archive_info: meta.archive_info(),
+        descriptions: meta.descriptions(tape),
+    })
+}
+
+/// Call `f` with every item from `jobs` threads, passing its results to `done` in the
+/// order they are ready, along with the index of their item
+fn parallel<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> R + Sync,
+    mut done: impl FnMut(usize, R),
+) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            let (sender, next, f) = (sender.clone(), &next, &f);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let item = match items.get(index) {
+                    Some(item) => item,
+                    None => break,
+                };
+                if sender.send((index, f(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        for (index, result) in receiver {
+            done(index, result);
+        }
+    });
+}
+
+/// Convert a ROM image into a tape that loads and runs it
+fn romload(out: &Reporter, rom_path: &Path, path: &Path) -> Result<()> {
+    let rom = fs::read(rom_path).on_path(rom_path)?;
+    let (name, _) = file::file_name_of(rom_path).on_path(rom_path)?;
+    out.status(format_args!("Converting {:?}... ", rom_path.as_os_str()));
+    let tape = rom::to_tape(&rom, &name).on_path(rom_path)?;
+    write_tape(path, &tape, false)?;
+    out.status_line(format_args!("Done ({} files)", tape.files().count()));
+    Ok(())
+}
+
+/// Append files of an MSX-DOS disk image to a tape, all of them if no names are given
+fn import(out: &Reporter, path: &Path, dsk_path: &Path, names: &[String]) -> Result<()> {
+    let disk = dsk::Disk::from_bytes(fs::read(dsk_path).on_path(dsk_path)?).on_path(dsk_path)?;
+    let entries = if names.is_empty() {
+        disk.entries()
+    } else {
+        names
+            .iter()
+            .map(|name| disk.entry(name))
+            .collect::<io::Result<_>>()
+            .on_path(dsk_path)?
+    };
+    let mut tape = Tape::new();
+    for entry in &entries {
+        out.status(format_args!("Importing {}... ", entry.name));
+        let data = disk.read_file(&entry.name).on_path(dsk_path)?;
+        let padding = tape
+            .append_disk_file(&entry.name, &data)
+            .on_path(dsk_path)?;
+        // ASCII files are always padded with EOF bytes, which is not worth a warning
+        let ascii = tape.files().last().map(|f| f.file_type()) == Some(tape::FileType::Ascii);
+        report_padding(out, dsk_path, if ascii { 0 } else { padding });
+    }
+    if is_compressed(path) {
+        let mut all = Tape::from_file_with(path, Scan::Unaligned).on_path(path)?;
+        all.extend_from(&tape);
+        write_tape(path, &all, true)?;
+    } else {
+        tape.append_to_path(path).on_path(path)?;
+    }
+    out.status_line(format_args!(
+        "{} files imported into {:?}",
+        entries.len(),
+        path.as_os_str()
+    ));
+    Ok(())
+}
+
+/// Build the tape described by a manifest, replacing it if it exists
+fn build(out: &Reporter, manifest_path: &Path, loader: bool) -> Result<()> {
+    let manifest = Manifest::load(manifest_path).on_path(manifest_path)?;
+    let path = manifest
+        .output
+        .clone()
+        .unwrap_or_else(|| manifest_path.with_extension("cas"));
+    let mut tape = Tape::new();
+    let mut loader = loader;
+    let mut annotations = vec![];
+    for entry in &manifest.files {
+        let mut index = tape.files().count();
+        add_file(out, &mut tape, &entry.path, &entry.options)?;
+        if loader && entry.options.file_type_of(&entry.path) == tape::FileType::Bin {
+            add_loader(out, &mut tape)?;
+            loader = false;
+            index += 1;
+        }
+        let annotation = Annotation {
+            title: entry.title.clone(),
+            notes: entry.description.clone(),
+            ..Annotation::default()
+        };
+        annotations.push((index, annotation));
+    }
+    if loader {
+        return Err(Error::File(manifest_path.to_path_buf(), no_binary_file()));
+    }
+    write_tape(&path, &tape, manifest.gzip)?;
+    let mut meta = Metadata::new();
+    meta.set_tape_info(TapeInfo {
+        title: manifest.title.clone(),
+        publisher: manifest.publisher.clone(),
+        year: manifest.year,
+    });
+    let files: Vec<tape::File> = tape.files().collect();
+    for (index, annotation) in annotations {
+        meta.set(index, &files[index], annotation);
+    }
+    // Metadata written by hand is kept unless the manifest replaces it
+    if !meta.is_empty() {
+        meta.save(&path).on_path(&Metadata::path_of(&path))?;
+    }
+    out.status_line(format_args!(
+        "{} files written to {:?}",
+        tape.files().count(),
+        path.as_os_str()
+    ));
+    Ok(())
+}
+
+/// Write the files of a tape into an MSX-DOS disk image, which is created if it does not exist
+fn export_disk(out: &Reporter, tape: &Tape, dsk_path: &Path) -> Result<()> {
+    let mut disk = if dsk_path.exists() {
+        dsk::Disk::from_bytes(fs::read(dsk_path).on_path(dsk_path)?).on_path(dsk_path)?
+    } else {
+        dsk::Disk::new()
+    };
+    let names = dsk::disk_names(tape);
+    for (i, (file, name)) in tape.files().zip(names.iter()).enumerate() {
+        if names[..i].contains(name) {
+            out.report(&Diagnostic::warning(
+                "filename_clash",
+                dsk_path,
+                format!("file {} replaces a previous file named {}", i, name),
+            ));
+        }
+        out.status(format_args!("Writing {}... ", name));
+        disk.write_file(name, &dsk::disk_bytes(&file))
+            .on_path(dsk_path)?;
+        out.status_line(format_args!("Done"));
+    }
+    file::write_content(dsk_path, disk.as_bytes()).on_path(dsk_path)?;
+    out.status_line(format_args!(
+        "{} bytes free in {:?}",
+        disk.free_space(),
+        dsk_path.as_os_str()
+    ));
+    Ok(())
+}
+
+fn export(out: &Reporter, cas_path: &Path, out_path: &Path, profile: wav::Profile) -> Result<()> {
+    let tape = read_tape(cas_path, Scan::Unaligned)?;
+    if out_path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("dsk"))
+    {
+        if profile != wav::Profile::default() {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the speed and sample rate can only be set for WAV files",
+            );
+            return Err(Error::File(out_path.to_path_buf(), e));
+        }
+        return export_disk(out, &tape, out_path);
+    }
+    let format = format::for_path(out_path).unwrap_or_else(|| Box::new(format::Wav));
+    if format.name() != "wav" {
+        if profile != wav::Profile::default() {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the speed and sample rate can only be set for WAV files",
+            );
+            return Err(Error::File(out_path.to_path_buf(), e));
+        }
+        out.status(format_args!("Encoding {} file... ", format.name()));
+        let mut out_file = create_output(out_path)?;
+        format.write(&tape, &mut out_file).on_path(out_path)?;
+        out.status_line(format_args!("Done"));
+        return Ok(());
+    }
+
+    profile.validate().on_path(out_path)?;
+    let mut exporter = wav::Exporter::with_profile(profile);
+    let mut wav_file = create_output(out_path)?;
+
+    let bauds = profile.block_bauds(&tape);
+    let total = tape.blocks().len();
+    out.status(format_args!("Encoding {} blocks... ", total));
+    let mut bar = out.progress();
+    let mut nbytes = 0;
+    for (block, i) in tape.blocks().iter().zip(0..total) {
+        log_blocks(out, &tape, i..i + 1);
+        nbytes += exporter.write_block_at(block, bauds[i])?;
+        bar.update(i as u64 + 1, Some(total as u64));
+    }
+    drop(bar);
+    out.status_line(format_args!("Done ({} KiB)", nbytes / 1024));
+    exporter.export(&mut wav_file).on_path(out_path)
+}
+
+/// Record a tape from the sound device into a session directory
+///
+/// The samples are read from an external audio recorder until Enter is pressed, the given
+/// seconds pass or the recorder stops. The whole recording is saved as `recording.wav`
+/// and decoded into `tape.cas`. Every file starts with the long pilot tone of its header
+/// block, so the recording is cut there into a WAV file per file, and a summary of the
+/// files and the dropouts found is saved as `report.txt`.
+///
+fn record(out: &Reporter, dir: &Path, input: Option<&str>, seconds: Option<u32>) -> Result<()> {
+    let profile = wav::Profile::default();
+    let (program, args) = doctor::recorder(profile.sample_rate, input).ok_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no audio recorder found in PATH (install arecord or parec)",
+        ))
+    })?;
+    fs::create_dir_all(dir).on_path(dir)?;
+    let mut child = process::Command::new(&program)
+        .args(&args)
+        .stdout(process::Stdio::piped())
+        .spawn()
+        .on_path(&program)?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .expect("the stdout of the recorder is piped");
+    let reader = thread::spawn(move || {
+        let mut samples = vec![];
+        stdout.read_to_end(&mut samples).map(|_| samples)
+    });
+    // Reading the standard input cannot be interrupted, so Enter is waited in a thread. At
+    // the end of the input, the recording goes on until the recorder stops.
+    let (enter, pressed) = mpsc::channel();
+    if seconds.is_none() {
+        out.status_line(format_args!("Recording, press Enter to stop..."));
+        thread::spawn(move || {
+            if io::stdin()
+                .read_line(&mut String::new())
+                .is_ok_and(|n| n > 0)
+            {
+                let _ = enter.send(());
+            }
+        });
+    }
+    let started = std::time::Instant::now();
+    let limit = seconds.map(|s| std::time::Duration::from_secs(s as u64));
+    let stopped = loop {
+        if child.try_wait().on_path(&program)?.is_some() {
+            break false;
+        }
+        if pressed
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_ok()
+            || limit.is_some_and(|l| started.elapsed() >= l)
+        {
+            let _ = child.kill();
+            break true;
+        }
+    };
+    let status = child.wait().on_path(&program)?;
+    let samples = reader
+        .join()
+        .expect("the recorder thread does not panic")
+        .on_path(&program)?;
+    if !stopped && !status.success() {
+        let e = io::Error::other(format!("the audio recorder failed ({})", status));
+        return Err(Error::File(program, e));
+    }
+
+    let save = |name: &str, samples: &[u8]| -> Result<Vec<u8>> {
+        let path = dir.join(name);
+        let mut exporter = wav::Exporter::with_profile(profile);
+        exporter.write_samples(samples)?;
+        let mut bytes = vec![];
+        exporter.export(&mut bytes)?;
+        fs::write(&path, &bytes).on_path(&path)?;
+        Ok(bytes)
+    };
+    let wav_path = dir.join("recording.wav");
+    let bytes = save("recording.wav", &samples)?;
+    out.status(format_args!("Decoding {:?}... ", wav_path.as_os_str()));
+    let decoded = decode::decode(&bytes).on_path(&wav_path)?;
+    write_tape(&dir.join("tape.cas"), &decoded.tape, false)?;
+    let files: Vec<tape::File> = decoded.tape.files().collect();
+    out.status_line(format_args!(
+        "Done ({} files in {} blocks)",
+        files.len(),
+        decoded.tape.blocks().len()
+    ));
+
+    // The decoded tape has a block for every span of the recording, so the first block of
+    // every file tells where it starts
+    let mut starts = vec![];
+    let mut block = 0;
+    for file in &files {
+        starts.push(decoded.spans[block].start);
+        block += match file {
+            tape::File::Bin(..) | tape::File::Basic(..) => 2,
+            tape::File::Ascii(_, chunks) => 1 + chunks.len(),
+            tape::File::Custom(_) | tape::File::Header(..) => 1,
+        };
+    }
+    let seconds = |frame: usize| frame as f64 / decoded.rate as f64;
+    let time = |frame: usize| minutes(std::time::Duration::from_secs_f64(seconds(frame)));
+    let (mut report, mut entries, mut warnings) = (vec![], vec![], vec![]);
+    for (i, file) in files.iter().enumerate() {
+        let (start, end) = (
+            starts[i],
+            starts.get(i + 1).copied().unwrap_or(samples.len()),
+        );
+        let name = file.name();
+        let safe_name: String = name
+            .as_deref()
+            .unwrap_or("custom")
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let segment = format!("{:02}-{}.wav", i, safe_name);
+        save(&segment, &samples[start..end])?;
+        report.push(format!(
+            "{:3} | {:6} | {:10} | {:>5} - {:>5} | {}",
+            i,
+            file.file_type(),
+            name.as_deref().unwrap_or_default(),
+            time(start),
+            time(end),
+            segment
+        ));
+        entries.push(format!(
+            "{{\"index\":{},\"type\":{},\"name\":{},\"start\":{:.3},\"end\":{:.3},\"wav\":{}}}",
+            i,
+            json_string(&file.file_type().to_string()),
+            name.as_deref()
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string()),
+            seconds(start),
+            seconds(end),
+            json_string(&segment)
+        ));
+    }
+    for dropout in &decoded.dropouts {
+        let message = format!(
+            "block {} drops out at {} after {} bytes",
+            dropout.block,
+            minutes(dropout.time),
+            dropout.bytes
+        );
+        out.report(&Diagnostic::warning("dropout", &wav_path, message.clone()));
+        warnings.push(format!("warning: {}", message));
+    }
+    let summary = format!(
+        "Recorded {} files in {} ({} dropouts)",
+        files.len(),
+        time(samples.len()),
+        decoded.dropouts.len()
+    );
+    let report_path = dir.join("report.txt");
+    let text: String = report
+        .iter()
+        .chain(&warnings)
+        .chain(Some(&summary))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    fs::write(&report_path, text).on_path(&report_path)?;
+    if out.format() == report::Format::Json {
+        println!(
+            "{{\"files\":[{}],\"seconds\":{:.3},\"dropouts\":{}}}",
+            entries.join(","),
+            seconds(samples.len()),
+            decoded.dropouts.len()
+        );
+    }
+    for line in report.iter().chain(Some(&summary)) {
+        out.status_line(format_args!("{}", line));
+    }
+    Ok(())
+}
+
+/// Format the given duration as minutes and seconds
+fn minutes(duration: std::time::Duration) -> String {
+    let secs = (duration.as_millis() + 500) / 1000;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Open the given remote control line, attaching the serial device to its errors
+fn open_remote(line: &Line) -> Result<Remote> {
+    Remote::open(line).map_err(|e| match line {
+        Line::Dtr(path) | Line::Rts(path) => Error::File(path.clone(), e),
+        Line::Gpio(_) => Error::Io(e),
+    })
+}
+
+/// Switch on the remote relay driven by the given line, so the cassette player runs until
+/// Enter is pressed or the given seconds pass
+fn remote(out: &Reporter, line: &Line, seconds: Option<u32>) -> Result<()> {
+    let mut remote = open_remote(line)?;
+    remote.set(true)?;
+    match seconds {
+        Some(seconds) => {
+            out.status_line(format_args!("Remote on for {} seconds...", seconds));
+            thread::sleep(std::time::Duration::from_secs(seconds as u64));
+        }
+        None => {
+            out.status_line(format_args!("Remote on, press Enter to switch it off..."));
+            io::stdin().read_line(&mut String::new())?;
         }
-        nbytes += exporter
-            .write_data(block.data_without_prefix())
-            .ok()
-            .unwrap();
-        println!("{} KiB", nbytes / 1024);
     }
-    exporter.export(&mut wav_file).ok();
+    remote.set(false)?;
+    out.status_line(format_args!("Remote off"));
     Ok(())
 }
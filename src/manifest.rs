@@ -0,0 +1,295 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Manifests that describe how to build a tape from host files
+//!
+//! A manifest is a TOML file that lists the files of a tape in order, as `mcp build`
+//! reads it:
+//!
+//! ```toml
+//! output = "game.cas"
+//! title = "The Game"
+//! publisher = "Some Soft"
+//! year = 1986
+//!
+//! [[file]]
+//! path = "loader.bas"
+//! name = "GAME"
+//! description = "Loader"
+//!
+//! [[file]]
+//! path = "build/game.raw"
+//! type = "bin"
+//! begin = 0x9000
+//! start = 0x9010
+//! ```
+//!
+//! Every file is packed as `Tape::pack_file()` does, with the type and name inferred
+//! from its path unless `type` and `name` are given. The rest of the keys of a file are
+//! `begin` and `start` (for raw machine code), `eol` and `utf8`, as in `PackOptions`.
+//!
+//! The `title`, `publisher` and `year` of the tape, and the `title` and `description` of
+//! its files, are not packed: they are meant for the metadata file of the tape.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::dir::{PackOptions, Packed};
+use crate::tape::{self, FileType, Tape};
+
+/// The description of a tape to build
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Manifest {
+    /// The path of the tape, or `None` to name it after the manifest
+    pub output: Option<PathBuf>,
+    /// Write the tape compressed with gzip
+    pub gzip: bool,
+    /// The full title of the program
+    pub title: Option<String>,
+    /// The publisher of the program
+    pub publisher: Option<String>,
+    /// The year the program was published
+    pub year: Option<u16>,
+    /// The files of the tape, in order
+    pub files: Vec<Entry>,
+}
+
+/// A file listed in a manifest
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// The path of the host file
+    pub path: PathBuf,
+    /// The options to pack the file with
+    pub options: PackOptions,
+    /// A human readable title
+    pub title: Option<String>,
+    /// A description of the file
+    pub description: Option<String>,
+}
+
+impl Manifest {
+    /// Load the manifest at the given path.
+    ///
+    /// Relative paths are resolved from the directory of the manifest, and the output
+    /// defaults to the manifest path with the `cas` extension, so `output` is always set.
+    /// Errors are prefixed with the manifest path.
+    ///
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        let text = fs::read_to_string(path)?;
+        let mut manifest: Manifest = text.parse().map_err(|e: io::Error| {
+            io::Error::new(e.kind(), format!("in {}: {}", path.display(), e))
+        })?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        manifest.output = Some(match manifest.output.take() {
+            Some(output) => dir.join(output),
+            None => path.with_extension("cas"),
+        });
+        for entry in manifest.files.iter_mut() {
+            entry.path = dir.join(&entry.path);
+        }
+        Ok(manifest)
+    }
+
+    /// Build the tape described by this manifest.
+    ///
+    /// The files are packed in order with `Tape::pack_file()`. Returns the tape along with
+    /// the packed files. Errors are prefixed with the path of the offending file.
+    ///
+    pub fn build(&self) -> io::Result<(Tape, Vec<Packed>)> {
+        let mut tape = Tape::new();
+        let mut packed = vec![];
+        for entry in self.files.iter() {
+            packed.push(tape.pack_file(&entry.path, &entry.options).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {}", entry.path.display(), e))
+            })?);
+        }
+        Ok((tape, packed))
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = io::Error;
+
+    /// Parse a manifest, failing with an error of kind `InvalidData` if it is not valid.
+    ///
+    /// Besides syntax errors, names longer than six characters, names of custom files and
+    /// addresses of files that are not binary are rejected.
+    ///
+    fn from_str(text: &str) -> io::Result<Manifest> {
+        let file: files::ManifestFile = toml::from_str(text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let files = file
+            .file
+            .into_iter()
+            .map(entry_of)
+            .collect::<io::Result<_>>()?;
+        Ok(Manifest {
+            output: file.output,
+            gzip: file.gzip,
+            title: file.title,
+            publisher: file.publisher,
+            year: file.year,
+            files,
+        })
+    }
+}
+
+fn entry_of(entry: files::Entry) -> io::Result<Entry> {
+    let invalid = |message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", entry.path.display(), message),
+        )
+    };
+    let file_type = match &entry.file_type {
+        Some(t) => Some(FileType::from_str(t).map_err(|e| invalid(e.to_string()))?),
+        None => None,
+    };
+    let eol = match &entry.eol {
+        Some(eol) => Some(eol.parse().map_err(|e: io::Error| invalid(e.to_string()))?),
+        None => None,
+    };
+    let addresses = entry.begin.is_some() || entry.start.is_some();
+    if addresses && !matches!(file_type, None | Some(FileType::Bin)) {
+        return Err(invalid(format!(
+            "only binary files have addresses, not {} files",
+            file_type.unwrap_or(FileType::Custom)
+        )));
+    }
+    if entry.start.is_some() && entry.begin.is_none() {
+        return Err(invalid("start address without begin address".to_string()));
+    }
+    let name = match &entry.name {
+        Some(_) if file_type == Some(FileType::Custom) => {
+            return Err(invalid("custom files have no name".to_string()))
+        }
+        Some(name) => {
+            let (bytes, truncated) = tape::file_name(name);
+            if truncated || name.is_empty() {
+                return Err(invalid(format!(
+                    "invalid name {:?} (expected 1 to 6 characters)",
+                    name
+                )));
+            }
+            Some(bytes)
+        }
+        None => None,
+    };
+    Ok(Entry {
+        options: PackOptions {
+            eol,
+            begin: entry.begin,
+            start: entry.start,
+            utf8: entry.utf8,
+            file_type,
+            name,
+        },
+        path: entry.path,
+        title: entry.title,
+        description: entry.description,
+    })
+}
+
+mod files {
+    use std::path::PathBuf;
+
+    use super::Deserialize;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct ManifestFile {
+        pub output: Option<PathBuf>,
+        #[serde(default)]
+        pub gzip: bool,
+        pub title: Option<String>,
+        pub publisher: Option<String>,
+        pub year: Option<u16>,
+        #[serde(default)]
+        pub file: Vec<Entry>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Entry {
+        pub path: PathBuf,
+        #[serde(rename = "type")]
+        pub file_type: Option<String>,
+        pub name: Option<String>,
+        pub begin: Option<u16>,
+        pub start: Option<u16>,
+        pub eol: Option<String>,
+        #[serde(default)]
+        pub utf8: bool,
+        pub title: Option<String>,
+        pub description: Option<String>,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::tape::File;
+    use crate::text::LineEnding;
+
+    #[test]
+    fn should_build_tape_from_manifest() {
+        let dir = TempDir::new("mcp").unwrap();
+        fs::write(dir.path().join("code.raw"), [0xaf, 0xc9]).unwrap();
+        fs::write(dir.path().join("intro.txt"), b"10 CLS\n").unwrap();
+        let path = dir.path().join("game.toml");
+        fs::write(
+            &path,
+            "year = 1986\n\
+             \n\
+             [[file]]\n\
+             path = \"intro.txt\"\n\
+             type = \"ascii\"\n\
+             name = \"INTRO\"\n\
+             eol = \"crlf\"\n\
+             description = \"Intro\"\n\
+             \n\
+             [[file]]\n\
+             path = \"code.raw\"\n\
+             begin = 0xc000\n",
+        )
+        .unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(Some(dir.path().join("game.cas")), manifest.output);
+        assert_eq!(dir.path().join("intro.txt"), manifest.files[0].path);
+        assert_eq!(Some(LineEnding::CrLf), manifest.files[0].options.eol);
+        assert_eq!(Some(1986), manifest.year);
+        assert_eq!(Some("Intro"), manifest.files[0].description.as_deref());
+
+        let (tape, packed) = manifest.build().unwrap();
+        assert_eq!(2, packed.len());
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(Some("INTRO.asc".to_string()), files[0].name());
+        assert_eq!(&b"10 CLS\r\n"[..], &files[0].payload()[..8]);
+        assert_eq!(Some("code.bin".to_string()), files[1].name());
+        assert_eq!(Some((0xc000, 0xc001, 0xc000)), files[1].addresses());
+
+        for text in [
+            "[[file]]\npath = \"a.bin\"\nname = \"TOOLONG\"\n",
+            "[[file]]\npath = \"a.dat\"\ntype = \"custom\"\nname = \"A\"\n",
+            "[[file]]\npath = \"a.bas\"\ntype = \"basic\"\nbegin = 0x8000\n",
+            "[[file]]\npath = \"a.bin\"\nstart = 0x8000\n",
+            "[[file]]\npath = \"a.bin\"\nbegin = 0x10000\n",
+            "[[file]]\npath = \"a.bin\"\nunknown = 1\n",
+        ] {
+            let e = text.parse::<Manifest>().unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, e.kind(), "{}", text);
+        }
+    }
+}
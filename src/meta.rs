@@ -0,0 +1,489 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Sidecar metadata for tapes
+//!
+//! The CAS format has no room for provenance information, so the annotations of the
+//! tape `foo.cas` and its files are stored in a `foo.cas.meta` TOML file next to it:
+//!
+//! ```toml
+//! [tape]
+//! title = "The Game"
+//! publisher = "Some Soft"
+//! year = 1986
+//!
+//! [[file]]
+//! index = 0
+//! name = "game.bin"
+//! title = "Game (loader)"
+//! source = "Original cassette, side A"
+//! dump_date = "1998-05-12"
+//! ```
+//!
+//! Files are referred by their index in the tape, and their name is recorded so the
+//! annotations can follow the files when the tape is reordered (see `reconcile()`).
+
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tape::{File, Tape};
+
+/// The annotations of a file of a tape
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Annotation {
+    /// A human readable title
+    pub title: Option<String>,
+    /// Free form notes
+    pub notes: Option<String>,
+    /// Where the tape was dumped from (e.g., the original cassette)
+    pub source: Option<String>,
+    /// When the tape was dumped
+    pub dump_date: Option<String>,
+}
+
+impl Annotation {
+    /// Returns `true` if no annotation is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Annotation::default()
+    }
+}
+
+/// The annotations of a tape as a whole
+///
+/// File names have six characters at most, so they rarely tell which program a tape
+/// holds. These are the details of the release the tape comes from.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TapeInfo {
+    /// The full title of the program
+    pub title: Option<String>,
+    /// The publisher of the program
+    pub publisher: Option<String>,
+    /// The year the program was published
+    pub year: Option<u16>,
+}
+
+impl TapeInfo {
+    /// Returns `true` if no annotation is set.
+    pub fn is_empty(&self) -> bool {
+        *self == TapeInfo::default()
+    }
+}
+
+impl fmt::Display for TapeInfo {
+    /// Formats the annotations as `title (publisher, year)`, skipping the missing ones.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let details: Vec<String> = self
+            .publisher
+            .iter()
+            .cloned()
+            .chain(self.year.map(|y| y.to_string()))
+            .collect();
+        write!(f, "{}", self.title.as_deref().unwrap_or("Untitled"))?;
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The annotations of a tape and its files
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    tape: TapeInfo,
+    files: BTreeMap<usize, (Option<String>, Annotation)>,
+}
+
+impl Metadata {
+    /// Create empty metadata.
+    pub fn new() -> Metadata {
+        Metadata::default()
+    }
+
+    /// Returns the path of the metadata file of the given tape file.
+    #[cfg(feature = "fs")]
+    pub fn path_of(tape: &Path) -> PathBuf {
+        let mut path = tape.as_os_str().to_owned();
+        path.push(".meta");
+        PathBuf::from(path)
+    }
+
+    /// Load the metadata of the given tape file.
+    ///
+    /// A missing metadata file is not an error: it results in empty metadata.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn load(tape: &Path) -> io::Result<Metadata> {
+        let path = Metadata::path_of(tape);
+        match fs::read_to_string(&path) {
+            Ok(text) => text.parse().map_err(|e: io::Error| {
+                io::Error::new(e.kind(), format!("in {}: {}", path.display(), e))
+            }),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Metadata::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the metadata of the given tape file.
+    ///
+    /// If the metadata is empty, the metadata file is removed instead.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn save(&self, tape: &Path) -> io::Result<()> {
+        let path = Metadata::path_of(tape);
+        if self.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        }
+        fs::write(&path, self.to_toml())
+    }
+
+    /// Returns the metadata serialized in TOML format.
+    pub fn to_toml(&self) -> String {
+        let file = files::MetaFile {
+            tape: Some(files::TapeEntry::new(&self.tape)).filter(|_| !self.tape.is_empty()),
+            file: self
+                .files
+                .iter()
+                .map(|(index, (name, annotation))| files::Entry::new(*index, name, annotation))
+                .collect(),
+        };
+        toml::to_string(&file).expect("metadata is always serializable")
+    }
+
+    /// Returns `true` if neither the tape nor any file is annotated.
+    pub fn is_empty(&self) -> bool {
+        self.tape.is_empty() && self.files.is_empty()
+    }
+
+    /// Returns the annotations of the tape as a whole.
+    pub fn tape_info(&self) -> &TapeInfo {
+        &self.tape
+    }
+
+    /// Set the annotations of the tape as a whole.
+    pub fn set_tape_info(&mut self, info: TapeInfo) {
+        self.tape = info;
+    }
+
+    /// Returns the annotations of the tape as TZX archive info texts.
+    ///
+    /// The title, publisher and year are given the text IDs `0x00`, `0x01` and `0x03`, as
+    /// `format::Tsx` expects them.
+    ///
+    pub fn archive_info(&self) -> Vec<(u8, String)> {
+        let info = &self.tape;
+        let texts = [
+            (0x00, info.title.clone()),
+            (0x01, info.publisher.clone()),
+            (0x03, info.year.map(|y| y.to_string())),
+        ];
+        texts
+            .iter()
+            .cloned()
+            .filter_map(|(id, text)| Some((id, text?)))
+            .collect()
+    }
+
+    /// Returns the descriptions of the annotated files of the given tape, along with the
+    /// index of their first block, as `format::Tsx` expects them.
+    ///
+    /// A file is described by its title and notes, separated by a colon.
+    ///
+    pub fn descriptions(&self, tape: &Tape) -> Vec<(usize, String)> {
+        tape.entries()
+            .into_iter()
+            .filter_map(|entry| {
+                let annotation = self.get(entry.index)?;
+                let texts: Vec<&str> = annotation
+                    .title
+                    .iter()
+                    .chain(annotation.notes.iter())
+                    .map(|t| t.as_str())
+                    .collect();
+                if texts.is_empty() {
+                    return None;
+                }
+                Some((entry.blocks.start, texts.join(": ")))
+            })
+            .collect()
+    }
+
+    /// Returns the annotation of the file at the given index.
+    pub fn get(&self, index: usize) -> Option<&Annotation> {
+        self.files.get(&index).map(|(_, annotation)| annotation)
+    }
+
+    /// Set the annotation of the given file, found at the given index.
+    ///
+    /// Setting an empty annotation removes the annotation of the file.
+    ///
+    pub fn set(&mut self, index: usize, file: &File, annotation: Annotation) {
+        if annotation.is_empty() {
+            self.files.remove(&index);
+        } else {
+            self.files.insert(index, (file.name(), annotation));
+        }
+    }
+
+    /// Remove the annotation of the file at the given index.
+    pub fn remove(&mut self, index: usize) -> Option<Annotation> {
+        self.files.remove(&index).map(|(_, annotation)| annotation)
+    }
+
+    /// Update the file indices after the given tape has been edited.
+    ///
+    /// Annotations whose file is no longer at its recorded index are moved to the first
+    /// file with the recorded name that is not annotated yet. The annotations that match
+    /// no file are removed and returned.
+    ///
+    pub fn reconcile(&mut self, tape: &Tape) -> Vec<Annotation> {
+        let names: Vec<Option<String>> = tape.files().map(|f| f.name()).collect();
+        let (stay, moved): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(&mut self.files)
+            .into_iter()
+            .partition(|(index, (name, _))| {
+                names
+                    .get(*index)
+                    .is_some_and(|n| name.is_none() || n == name)
+            });
+        self.files = stay;
+        let mut lost = vec![];
+        for (_, (name, annotation)) in moved {
+            let target = (0..names.len())
+                .find(|i| names[*i] == name && name.is_some() && !self.files.contains_key(i));
+            match target {
+                Some(index) => {
+                    self.files.insert(index, (name, annotation));
+                }
+                None => lost.push(annotation),
+            }
+        }
+        lost
+    }
+}
+
+impl FromStr for Metadata {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Metadata> {
+        let file: files::MetaFile = toml::from_str(text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let mut meta = Metadata::new();
+        if let Some(tape) = file.tape {
+            meta.tape = tape.into_info();
+        }
+        for entry in file.file {
+            let (index, name, annotation) = entry.into_parts();
+            meta.files.insert(index, (name, annotation));
+        }
+        Ok(meta)
+    }
+}
+
+mod files {
+    use super::{Annotation, Deserialize, Serialize, TapeInfo};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct MetaFile {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub tape: Option<TapeEntry>,
+        #[serde(default)]
+        pub file: Vec<Entry>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct TapeEntry {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        publisher: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        year: Option<u16>,
+    }
+
+    impl TapeEntry {
+        pub fn new(info: &TapeInfo) -> TapeEntry {
+            TapeEntry {
+                title: info.title.clone(),
+                publisher: info.publisher.clone(),
+                year: info.year,
+            }
+        }
+
+        pub fn into_info(self) -> TapeInfo {
+            TapeInfo {
+                title: self.title,
+                publisher: self.publisher,
+                year: self.year,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Entry {
+        index: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        notes: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dump_date: Option<String>,
+    }
+
+    impl Entry {
+        pub fn new(index: usize, name: &Option<String>, annotation: &Annotation) -> Entry {
+            Entry {
+                index,
+                name: name.clone(),
+                title: annotation.title.clone(),
+                notes: annotation.notes.clone(),
+                source: annotation.source.clone(),
+                dump_date: annotation.dump_date.clone(),
+            }
+        }
+
+        pub fn into_parts(self) -> (usize, Option<String>, Annotation) {
+            let annotation = Annotation {
+                title: self.title,
+                notes: self.notes,
+                source: self.source,
+                dump_date: self.dump_date,
+            };
+            (self.index, self.name, annotation)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    #[cfg(feature = "fs")]
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::tape;
+
+    fn title(t: &str) -> Annotation {
+        Annotation {
+            title: Some(t.to_string()),
+            ..Annotation::default()
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn should_save_and_load_metadata() {
+        let dir = TempDir::new("mcp").unwrap();
+        let path = dir.path().join("foo.cas");
+        assert_eq!(Metadata::new(), Metadata::load(&path).unwrap());
+
+        let mut meta = Metadata::new();
+        meta.set(
+            1,
+            &File::Custom(&[0; 8]),
+            Annotation {
+                title: Some("Title \"screen\"".to_string()),
+                dump_date: Some("1998-05-12".to_string()),
+                ..Annotation::default()
+            },
+        );
+        meta.save(&path).unwrap();
+        assert!(dir.path().join("foo.cas.meta").exists());
+        let loaded = Metadata::load(&path).unwrap();
+        assert_eq!(meta, loaded);
+        assert_eq!(
+            Some("1998-05-12"),
+            loaded.get(1).unwrap().dump_date.as_deref()
+        );
+
+        Metadata::new().save(&path).unwrap();
+        assert!(!dir.path().join("foo.cas.meta").exists());
+    }
+
+    #[test]
+    fn should_annotate_tapes() {
+        let mut tape = Tape::new();
+        let (foo, _) = tape::file_name("foo");
+        tape.append_basic(&foo, &[0x00, 0x00]).unwrap();
+        tape.append_basic(&foo, &[0x00, 0x00]).unwrap();
+        let mut meta: Metadata = "[tape]\n\
+                                  title = \"The Game\"\n\
+                                  year = 1986\n\
+                                  \n\
+                                  [[file]]\n\
+                                  index = 1\n\
+                                  title = \"Game\"\n\
+                                  notes = \"Main program\"\n"
+            .parse()
+            .unwrap();
+        assert_eq!("The Game (1986)", meta.tape_info().to_string());
+        assert_eq!(
+            vec![(0x00, "The Game".to_string()), (0x03, "1986".to_string())],
+            meta.archive_info()
+        );
+        assert_eq!(
+            vec![(2, "Game: Main program".to_string())],
+            meta.descriptions(&tape)
+        );
+        assert_eq!(meta, meta.to_toml().parse().unwrap());
+
+        meta.remove(1);
+        assert!(!meta.is_empty());
+        meta.set_tape_info(TapeInfo::default());
+        assert!(meta.is_empty());
+    }
+
+    #[test]
+    fn should_reject_invalid_metadata() {
+        assert!("[[file]]\nindex = 0\nauthor = \"me\"\n"
+            .parse::<Metadata>()
+            .is_err());
+        assert!("[[file]]\ntitle = \"x\"\n".parse::<Metadata>().is_err());
+    }
+
+    #[test]
+    fn should_reconcile_after_edits() {
+        let mut tape = Tape::new();
+        let (foo, _) = tape::file_name("foo");
+        let (bar, _) = tape::file_name("bar");
+        tape.append_basic(&foo, &[0x00, 0x00]).unwrap();
+        tape.append_basic(&bar, &[0x00, 0x00]).unwrap();
+        let files: Vec<File> = tape.files().collect();
+        let mut meta = Metadata::new();
+        meta.set(0, &files[0], title("Foo"));
+        meta.set(1, &files[1], title("Bar"));
+        drop(files);
+
+        tape.sort_files_by_key(|f| f.name());
+        assert!(meta.reconcile(&tape).is_empty());
+        assert_eq!(Some(&title("Bar")), meta.get(0));
+        assert_eq!(Some(&title("Foo")), meta.get(1));
+
+        tape.retain(|f| f.name() == Some("foo.bas".to_string()));
+        assert_eq!(vec![title("Bar")], meta.reconcile(&tape));
+        assert_eq!(Some(&title("Foo")), meta.get(0));
+        assert_eq!(None, meta.get(1));
+    }
+}
@@ -0,0 +1,226 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Remote control of a running openMSX emulator
+//!
+//! openMSX listens for commands in a Unix socket located at
+//! `$TMPDIR/openmsx-<user>/socket.<pid>`. Commands are Tcl expressions wrapped in a
+//! small XML protocol, and each of them is answered with a `<reply>` element.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A connection to the control socket of openMSX
+pub struct Connection {
+    stream: UnixStream,
+    pending: Vec<u8>,
+}
+
+impl Connection {
+    /// Connect to the openMSX control socket at the given path.
+    pub fn connect(path: &Path) -> io::Result<Connection> {
+        let mut stream = UnixStream::connect(path)?;
+        stream.write_all(b"<openmsx-control>\n")?;
+        Ok(Connection {
+            stream,
+            pending: vec![],
+        })
+    }
+
+    /// Connect to the most recently started openMSX found in the socket directory.
+    ///
+    /// An error of kind `NotFound` is returned if no running openMSX accepts the
+    /// connection.
+    ///
+    pub fn find() -> io::Result<Connection> {
+        let mut sockets: Vec<(PathBuf, Option<SystemTime>)> = fs::read_dir(socket_dir())
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_name().to_string_lossy().starts_with("socket."))
+                    .map(|e| (e.path(), e.metadata().and_then(|m| m.modified()).ok()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        sockets.sort_by_key(|s| std::cmp::Reverse(s.1));
+        sockets
+            .iter()
+            .find_map(|(path, _)| Connection::connect(path).ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no running openMSX found in {:?}", socket_dir()),
+                )
+            })
+    }
+
+    /// Execute the given Tcl command and return its result.
+    ///
+    /// An error of kind `Other` with the message reported by openMSX is returned if the
+    /// command fails.
+    ///
+    pub fn command(&mut self, cmd: &str) -> io::Result<String> {
+        writeln!(self.stream, "<command>{}</command>", xml_escape(cmd))?;
+        loop {
+            if let Some((ok, text, len)) = parse_reply(&self.pending) {
+                self.pending.drain(..len);
+                return if ok {
+                    Ok(text)
+                } else {
+                    Err(io::Error::other(format!("openMSX: {}", text.trim_end())))
+                };
+            }
+            let mut buf = [0; 1024];
+            let n = self.stream.read(&mut buf)?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "openMSX closed the connection",
+                ));
+            }
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    /// Insert the given CAS file in the cassette player and rewind it.
+    pub fn insert_tape(&mut self, path: &Path) -> io::Result<()> {
+        let path = fs::canonicalize(path)?;
+        self.command(&format!(
+            "cassetteplayer insert {}",
+            tcl_quote(&path.to_string_lossy())
+        ))?;
+        self.command("cassetteplayer rewind")?;
+        Ok(())
+    }
+
+    /// Type the given text in the MSX keyboard followed by a carriage return.
+    pub fn type_line(&mut self, text: &str) -> io::Result<()> {
+        self.command(&format!("type {}", tcl_quote(&format!("{}\r", text))))?;
+        Ok(())
+    }
+}
+
+/// Returns the directory where openMSX creates its control sockets
+pub fn socket_dir() -> PathBuf {
+    let user = env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .unwrap_or_default();
+    env::temp_dir().join(format!("openmsx-{}", user))
+}
+
+/// Parse the first `<reply>` element of the given input
+///
+/// Returns whether the command succeeded, the text of the reply and the number of bytes
+/// consumed, or `None` if the input does not contain a complete reply yet.
+///
+fn parse_reply(input: &[u8]) -> Option<(bool, String, usize)> {
+    let input = String::from_utf8_lossy(input);
+    let start = input.find("<reply")?;
+    let body = start + input[start..].find('>')? + 1;
+    let end = body + input[body..].find("</reply>")?;
+    let ok = input[start..body].contains("result=\"ok\"");
+    Some((ok, xml_unescape(&input[body..end]), end + "</reply>".len()))
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Quote the given text as a single Tcl word
+fn tcl_quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\\' | '"' | '[' | ']' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\r' => quoted.push_str("\\r"),
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn should_quote_tcl_words() {
+        assert_eq!("\"foo bar\"", tcl_quote("foo bar"));
+        assert_eq!("\"RUN\\\"CAS:\\\"\\r\"", tcl_quote("RUN\"CAS:\"\r"));
+        assert_eq!("\"\\$x\\[y\\]\"", tcl_quote("$x[y]"));
+    }
+
+    #[test]
+    fn should_parse_replies() {
+        assert_eq!(
+            None,
+            parse_reply(b"<openmsx-output>\n<reply result=\"ok\">")
+        );
+        assert_eq!(
+            Some((true, "a<b".to_string(), 50)),
+            parse_reply(b"<openmsx-output>\n<reply result=\"ok\">a&lt;b</reply>")
+        );
+        assert_eq!(
+            Some((false, "bad".to_string(), 31)),
+            parse_reply(b"<reply result=\"nok\">bad</reply>\n")
+        );
+    }
+
+    #[test]
+    fn should_send_commands() {
+        let dir = TempDir::new("mcp").unwrap();
+        let path = dir.path().join("socket.1");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"<openmsx-output>\n").unwrap();
+            let mut received = vec![];
+            let mut buf = [0; 256];
+            while !String::from_utf8_lossy(&received).contains("</command>") {
+                let n = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"<reply result=\"ok\">done</reply>\n")
+                .unwrap();
+            String::from_utf8(received).unwrap()
+        });
+        let mut conn = Connection::connect(&path).unwrap();
+        assert_eq!("done", conn.command("set power on").unwrap());
+        assert_eq!(
+            "<openmsx-control>\n<command>set power on</command>\n",
+            server.join().unwrap()
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Progress reporting and cancellation of long-running operations
+//!
+//! The library never writes to the standard output. Instead, the operations that may
+//! take a while (streaming parse, audio encoding, batch extraction) have a variant that
+//! reports its progress to a `Progress` observer, so applications can render it as they
+//! see fit. The observer is also asked periodically whether the operation was cancelled
+//! (see `CancelToken`), so interactive frontends and servers can abort the work cleanly.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// An object that receives the progress of an operation
+///
+/// Any closure accepting the units done and the total units is an observer. The units
+/// depend on the operation (bytes, blocks or files), and the total may be unknown (e.g.,
+/// when reading from a stream).
+///
+pub trait Progress {
+    fn update(&mut self, done: u64, total: Option<u64>);
+
+    /// Returns `true` if the operation must stop as soon as possible.
+    ///
+    /// Operations check it along with each progress update. Cancelled operations fail
+    /// with an error of kind `Interrupted` (see `cancelled()`), unless documented
+    /// otherwise.
+    ///
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+impl<F> Progress for F
+where
+    F: FnMut(u64, Option<u64>),
+{
+    fn update(&mut self, done: u64, total: Option<u64>) {
+        self(done, total)
+    }
+}
+
+/// An observer that ignores the progress
+pub fn ignore() -> impl Progress {
+    |_, _| {}
+}
+
+/// Returns the error produced by cancelled operations.
+pub fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "operation cancelled")
+}
+
+/// A token to cancel operations from another thread
+///
+/// Clones of a token share its state, so one clone may be handed to the operation (as
+/// its progress observer) while another one is kept to cancel it.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Create a new token, not cancelled yet.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancel the operations observed by this token or any of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns an observer that forwards the progress updates to `progress` and is
+    /// cancelled along with this token.
+    pub fn observe<P: Progress>(&self, progress: P) -> Cancellable<P> {
+        Cancellable {
+            progress,
+            token: self.clone(),
+        }
+    }
+}
+
+/// A token observes the cancellation only, ignoring the progress
+impl Progress for CancelToken {
+    fn update(&mut self, _done: u64, _total: Option<u64>) {}
+
+    fn is_cancelled(&self) -> bool {
+        CancelToken::is_cancelled(self)
+    }
+}
+
+/// A progress observer that can be cancelled, returned by `CancelToken::observe()`
+#[derive(Debug)]
+pub struct Cancellable<P> {
+    progress: P,
+    token: CancelToken,
+}
+
+impl<P: Progress> Progress for Cancellable<P> {
+    fn update(&mut self, done: u64, total: Option<u64>) {
+        self.progress.update(done, total)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled() || self.progress.is_cancelled()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_cancel_observers() {
+        let token = CancelToken::new();
+        let mut updates = 0;
+        let mut observer = token.observe(|_, _| updates += 1);
+        observer.update(1, None);
+        assert!(!observer.is_cancelled());
+        token.clone().cancel();
+        assert!(observer.is_cancelled());
+        drop(observer);
+        assert_eq!(1, updates);
+    }
+}
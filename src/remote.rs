@@ -0,0 +1,247 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Control of a cassette remote relay
+//!
+//! Cassette players are started and stopped through their remote jack, which the MSX
+//! drives with its motor control line. When a tape is played from a computer instead, a
+//! relay connected to a control line of the computer does the same: a GPIO pin, or the
+//! DTR or RTS line of a serial port. Lines are given as `gpio:<number>`,
+//! `dtr:<serial device>` or `rts:<serial device>` (e.g., `dtr:/dev/ttyUSB0`).
+//!
+//! GPIO pins are driven through the sysfs interface and serial lines through the modem
+//! control ioctls, both only available on Linux.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A control line that drives a remote relay
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    /// The GPIO pin of the given number
+    Gpio(u32),
+    /// The DTR line of the given serial device
+    Dtr(PathBuf),
+    /// The RTS line of the given serial device
+    Rts(PathBuf),
+}
+
+impl FromStr for Line {
+    type Err = io::Error;
+
+    /// Parse a line such as `gpio:17` or `dtr:/dev/ttyUSB0`
+    fn from_str(s: &str) -> io::Result<Line> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid control line {:?} (expected gpio:<number>, dtr:<device> or rts:<device>)",
+                    s
+                ),
+            )
+        };
+        let (kind, target) = s.split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "gpio" => target.parse().map(Line::Gpio).map_err(|_| invalid()),
+            "dtr" if !target.is_empty() => Ok(Line::Dtr(PathBuf::from(target))),
+            "rts" if !target.is_empty() => Ok(Line::Rts(PathBuf::from(target))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Gpio(pin) => write!(f, "gpio:{}", pin),
+            Line::Dtr(path) => write!(f, "dtr:{}", path.display()),
+            Line::Rts(path) => write!(f, "rts:{}", path.display()),
+        }
+    }
+}
+
+/// An open control line, released when dropped
+///
+/// The relay is expected to close (starting the cassette player) while the line is
+/// asserted.
+///
+pub struct Remote {
+    line: Line,
+    /// The open serial device, for serial lines
+    port: Option<fs::File>,
+    on: bool,
+}
+
+impl Remote {
+    /// Open the given control line, released.
+    ///
+    /// An error of kind `Unsupported` is returned on systems other than Linux.
+    ///
+    pub fn open(line: &Line) -> io::Result<Remote> {
+        let port = match line {
+            Line::Gpio(pin) => {
+                sys::export_gpio(*pin)?;
+                None
+            }
+            Line::Dtr(path) | Line::Rts(path) => Some(sys::open_serial(path)?),
+        };
+        let mut remote = Remote {
+            line: line.clone(),
+            port,
+            on: true,
+        };
+        remote.set(false)?;
+        Ok(remote)
+    }
+
+    /// Returns `true` if the line is asserted.
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// Assert or release the line.
+    pub fn set(&mut self, on: bool) -> io::Result<()> {
+        if on == self.on {
+            return Ok(());
+        }
+        match (&self.line, &self.port) {
+            (Line::Gpio(pin), _) => sys::set_gpio(*pin, on)?,
+            (Line::Dtr(_), Some(port)) => sys::set_modem_line(port, sys::TIOCM_DTR, on)?,
+            (Line::Rts(_), Some(port)) => sys::set_modem_line(port, sys::TIOCM_RTS, on)?,
+            _ => unreachable!("serial lines are open"),
+        }
+        self.on = on;
+        Ok(())
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        let _ = self.set(false);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::fs;
+    use std::io;
+    use std::os::raw::c_int;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::{Path, PathBuf};
+
+    pub use libc::{TIOCM_DTR, TIOCM_RTS};
+
+    fn gpio_dir(pin: u32) -> PathBuf {
+        PathBuf::from(format!("/sys/class/gpio/gpio{}", pin))
+    }
+
+    /// Export the given GPIO pin, if needed, and make it an output
+    pub fn export_gpio(pin: u32) -> io::Result<()> {
+        if !gpio_dir(pin).exists() {
+            fs::write("/sys/class/gpio/export", pin.to_string())?;
+        }
+        fs::write(gpio_dir(pin).join("direction"), "out")
+    }
+
+    pub fn set_gpio(pin: u32, on: bool) -> io::Result<()> {
+        fs::write(gpio_dir(pin).join("value"), if on { "1" } else { "0" })
+    }
+
+    /// Open the given serial device, without waiting for a carrier
+    pub fn open_serial(path: &Path) -> io::Result<fs::File> {
+        fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)
+    }
+
+    pub fn set_modem_line(port: &fs::File, line: c_int, on: bool) -> io::Result<()> {
+        let request = if on { libc::TIOCMBIS } else { libc::TIOCMBIC };
+        // SAFETY: the descriptor is open and the argument points to a valid c_int
+        if unsafe { libc::ioctl(port.as_raw_fd(), request, &line as *const c_int) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::fs;
+    use std::io;
+    use std::os::raw::c_int;
+    use std::path::Path;
+
+    pub const TIOCM_DTR: c_int = 0;
+    pub const TIOCM_RTS: c_int = 0;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "control lines are only supported on Linux",
+        )
+    }
+
+    pub fn export_gpio(_: u32) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn set_gpio(_: u32, _: bool) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn open_serial(_: &Path) -> io::Result<fs::File> {
+        Err(unsupported())
+    }
+
+    pub fn set_modem_line(_: &fs::File, _: c_int, _: bool) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_parse_lines() {
+        assert_eq!(Line::Gpio(17), "gpio:17".parse().unwrap());
+        assert_eq!(
+            Line::Dtr(PathBuf::from("/dev/ttyUSB0")),
+            "dtr:/dev/ttyUSB0".parse().unwrap()
+        );
+        assert_eq!(
+            Line::Rts(PathBuf::from("/dev/ttyS0")),
+            "rts:/dev/ttyS0".parse().unwrap()
+        );
+        assert_eq!(
+            "rts:/dev/ttyS0",
+            Line::Rts(PathBuf::from("/dev/ttyS0")).to_string()
+        );
+        for s in ["gpio:x", "gpio", "dtr:", "cts:/dev/ttyS0", ""].iter() {
+            let e = s.parse::<Line>().unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn should_fail_to_open_missing_lines() {
+        let line = Line::Dtr(PathBuf::from("/nonexistent/ttyUSB0"));
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            Remote::open(&line).err().unwrap().kind()
+        );
+    }
+}
@@ -0,0 +1,529 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use mcp::progress::Progress;
+use mcp::tape::TapeError;
+
+/// The format used by `mcp` to present its output
+///
+/// In `Text` format, the output is intended for humans. In `Json` format, progress
+/// messages are omitted, listings are printed as JSON documents and errors and warnings
+/// are printed to stderr as JSON objects, one per line.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Format> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown output format {:?} (expected text or json)", s),
+            )),
+        }
+    }
+}
+
+/// How much `mcp` tells about what it is doing
+///
+/// `Quiet` omits progress messages, for scripts that only care about the results and
+/// the diagnostics. `Verbose` also details the blocks that are processed.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+/// The output options selected in the command line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Output {
+    pub format: Format,
+    pub verbosity: Verbosity,
+}
+
+/// The kind of the diagnostics of files that failed a check, not an IO operation
+const CHECK_FAILED: &str = "check_failed";
+
+/// The severity of a diagnostic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found while running a command
+///
+/// Besides the human readable message, a diagnostic carries a stable `kind` identifier
+/// and, when known, the path of the file involved, the index and byte offset of the
+/// offending tape block and the bytes found there in hexadecimal.
+///
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: &'static str,
+    pub message: String,
+    pub path: Option<PathBuf>,
+    pub block: Option<usize>,
+    pub offset: Option<usize>,
+    pub context: Option<String>,
+}
+
+impl Diagnostic {
+    /// Create a new warning diagnostic.
+    pub fn warning(kind: &'static str, path: &Path, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            kind,
+            message,
+            path: Some(path.to_path_buf()),
+            block: None,
+            offset: None,
+            context: None,
+        }
+    }
+
+    /// Create an error diagnostic for a file that failed a check, such as `loadcheck`.
+    pub fn check_failed(path: &Path, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            kind: CHECK_FAILED,
+            message,
+            path: Some(path.to_path_buf()),
+            block: None,
+            offset: None,
+            context: None,
+        }
+    }
+
+    /// Create an error diagnostic from an IO error, optionally related to the given path.
+    ///
+    /// If the error was caused by a `TapeError`, its kind, block, offset and context are
+    /// reported.
+    ///
+    pub fn from_io(path: Option<&Path>, e: &io::Error) -> Diagnostic {
+        let tape_error = e.get_ref().and_then(|e| e.downcast_ref::<TapeError>());
+        Diagnostic {
+            severity: Severity::Error,
+            kind: tape_error
+                .map(tape_error_kind)
+                .unwrap_or_else(|| io_error_kind(e.kind())),
+            message: e.to_string(),
+            path: path.map(Path::to_path_buf),
+            block: tape_error.and_then(TapeError::block),
+            offset: tape_error.map(TapeError::offset),
+            context: tape_error.map(|e| e.context().to_string()),
+        }
+    }
+
+    /// Create a warning diagnostic from a problem found in the structure of a tape.
+    pub fn from_tape_error(path: &Path, e: &TapeError) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            kind: tape_error_kind(e),
+            message: e.to_string(),
+            path: Some(path.to_path_buf()),
+            block: e.block(),
+            offset: Some(e.offset()),
+            context: Some(e.context().to_string()),
+        }
+    }
+
+    /// Returns the diagnostic encoded as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        format!(
+            "{{\"severity\":{},\"kind\":{},\"message\":{},\"path\":{},\"block\":{},\"offset\":{},\"context\":{}}}",
+            json_string(severity),
+            json_string(self.kind),
+            json_string(&self.message),
+            self.path
+                .as_ref()
+                .map(|p| json_string(&p.to_string_lossy()))
+                .unwrap_or_else(|| "null".to_string()),
+            json_number(self.block),
+            json_number(self.offset),
+            self.context
+                .as_ref()
+                .map(|c| json_string(c))
+                .unwrap_or_else(|| "null".to_string()),
+        )
+    }
+}
+
+/// The object in charge of presenting the output of `mcp` in the selected format
+pub struct Reporter {
+    format: Format,
+    verbosity: Verbosity,
+    stderr: bool,
+    /// Whether a progress message is waiting for the end of its line
+    pending: Cell<bool>,
+}
+
+impl Reporter {
+    /// Create a new reporter for the given format.
+    pub fn new(format: Format) -> Reporter {
+        Reporter {
+            format,
+            verbosity: Verbosity::Normal,
+            stderr: false,
+            pending: Cell::new(false),
+        }
+    }
+
+    /// Returns this reporter with the given verbosity.
+    pub fn with_verbosity(self, verbosity: Verbosity) -> Reporter {
+        Reporter { verbosity, ..self }
+    }
+
+    /// Returns this reporter printing everything to stderr.
+    ///
+    /// This leaves the standard output to commands that write their data there.
+    ///
+    pub fn on_stderr(self) -> Reporter {
+        Reporter {
+            stderr: true,
+            ..self
+        }
+    }
+
+    /// Returns the output format of this reporter.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Print a progress message.
+    ///
+    /// Progress messages are only shown in text format, unless the verbosity is `Quiet`.
+    ///
+    pub fn status(&self, args: fmt::Arguments) {
+        if self.shows_status() {
+            self.print(format_args!("{}", args));
+            self.pending.set(true);
+        }
+    }
+
+    /// Print a progress message ending the current line.
+    pub fn status_line(&self, args: fmt::Arguments) {
+        if self.shows_status() {
+            self.print(format_args!("{}\n", args));
+            self.pending.set(false);
+        }
+    }
+
+    /// Print a line detailing the work done, only shown in text format and `Verbose` mode.
+    ///
+    /// A progress message waiting for the end of its line is ended first.
+    ///
+    pub fn detail(&self, args: fmt::Arguments) {
+        if self.format == Format::Text && self.verbosity == Verbosity::Verbose {
+            if self.pending.replace(false) {
+                self.print(format_args!("\n"));
+            }
+            self.print(format_args!("  {}\n", args));
+        }
+    }
+
+    /// Returns a progress bar to draw after the current progress message.
+    ///
+    /// The bar is only drawn when the verbosity is `Normal` (in `Verbose` mode the details
+    /// tell the progress instead) and stderr is a terminal. It is erased when dropped.
+    ///
+    pub fn progress(&self) -> Bar {
+        let enabled = self.shows_status()
+            && self.verbosity == Verbosity::Normal
+            && io::stderr().is_terminal();
+        if enabled {
+            let _ = io::stdout().flush();
+        }
+        Bar {
+            enabled,
+            drawn: 0,
+            last: None,
+        }
+    }
+
+    fn shows_status(&self) -> bool {
+        self.format == Format::Text && self.verbosity != Verbosity::Quiet
+    }
+
+    /// Report the given diagnostic.
+    pub fn report(&self, d: &Diagnostic) {
+        match (self.format, d.severity) {
+            (Format::Json, _) => eprintln!("{}", d.to_json()),
+            (Format::Text, Severity::Warning) => {
+                self.print(format_args!("Warning: {}... ", d.message))
+            }
+            (Format::Text, Severity::Error) if d.kind == CHECK_FAILED => {
+                self.print(format_args!("Error: {}\n", d.message))
+            }
+            (Format::Text, Severity::Error) => {
+                self.print(format_args!("Error: IO operation failed: {}\n", d.message))
+            }
+        }
+    }
+
+    fn print(&self, args: fmt::Arguments) {
+        if self.stderr {
+            eprint!("{}", args);
+        } else {
+            print!("{}", args);
+        }
+    }
+}
+
+/// A progress bar drawn on stderr after the current progress message
+///
+/// The bar is redrawn in place with backspaces, so it can share the line of a progress
+/// message printed to stdout.
+///
+pub struct Bar {
+    enabled: bool,
+    drawn: usize,
+    last: Option<String>,
+}
+
+impl Bar {
+    /// The width of the bar, in characters
+    const WIDTH: u64 = 20;
+
+    fn erase(&mut self) {
+        let back = "\x08".repeat(self.drawn);
+        eprint!("{}{}{}", back, " ".repeat(self.drawn), back);
+        self.drawn = 0;
+    }
+}
+
+impl Progress for Bar {
+    fn update(&mut self, done: u64, total: Option<u64>) {
+        if !self.enabled {
+            return;
+        }
+        let text = match total {
+            Some(total) if total > 0 => {
+                let filled = (done.min(total) * Bar::WIDTH / total) as usize;
+                let width = total.to_string().len();
+                format!(
+                    "[{:<bar$}] {:>width$}/{}",
+                    "=".repeat(filled),
+                    done,
+                    total,
+                    bar = Bar::WIDTH as usize,
+                    width = width
+                )
+            }
+            _ => done.to_string(),
+        };
+        if self.last.as_ref() != Some(&text) {
+            self.erase();
+            eprint!("{}", text);
+            self.drawn = text.len();
+            self.last = Some(text);
+        }
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        if self.drawn > 0 {
+            self.erase();
+        }
+    }
+}
+
+/// Encode the given string as a JSON string literal.
+pub fn json_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Encode the given optional number as a JSON value.
+pub fn json_number(n: Option<usize>) -> String {
+    n.map(|n| n.to_string())
+        .unwrap_or_else(|| "null".to_string())
+}
+
+/// Encode the given string as a CSV field, quoted only if needed.
+pub fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Format the given bytes as a hex dump, 16 bytes per line.
+///
+/// Each line has the offset of its first byte, the bytes in hexadecimal and the bytes
+/// as text, with a dot for anything but printable ASCII characters.
+///
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let (left, right) = hex.split_at(hex.len().min(8));
+        let text: String = line
+            .iter()
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        dump.push_str(&format!(
+            "{:08x}  {:<23}  {:<23}  |{}|\n",
+            i * 16,
+            left.join(" "),
+            right.join(" "),
+            text
+        ));
+    }
+    dump
+}
+
+fn tape_error_kind(e: &TapeError) -> &'static str {
+    match e {
+        TapeError::MissingPrefix { .. } => "missing_prefix",
+        TapeError::ShortHeader { .. } => "short_header",
+        TapeError::MissingData { .. } => "missing_data",
+        TapeError::ShortBinData { .. } => "short_bin_data",
+        TapeError::MissingEof { .. } => "missing_eof",
+    }
+}
+
+fn io_error_kind(kind: io::ErrorKind) -> &'static str {
+    match kind {
+        io::ErrorKind::NotFound => "not_found",
+        io::ErrorKind::PermissionDenied => "permission_denied",
+        io::ErrorKind::AlreadyExists => "already_exists",
+        io::ErrorKind::InvalidInput => "invalid_input",
+        io::ErrorKind::InvalidData => "invalid_data",
+        io::ErrorKind::UnexpectedEof => "unexpected_eof",
+        io::ErrorKind::Unsupported => "unsupported",
+        _ => "io",
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use mcp::tape::Context;
+
+    use super::*;
+
+    #[test]
+    fn should_parse_format() {
+        assert_eq!(Format::Text, "text".parse().unwrap());
+        assert_eq!(Format::Json, "JSON".parse().unwrap());
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn should_encode_json_string() {
+        assert_eq!("\"foo\"", json_string("foo"));
+        assert_eq!("\"a\\\"b\\\\c\\nd\\u0001\"", json_string("a\"b\\c\nd\u{1}"));
+    }
+
+    #[test]
+    fn should_encode_csv_field() {
+        assert_eq!("foo.bin", csv_field("foo.bin"));
+        assert_eq!("\"a,\"\"b\"\"\"", csv_field("a,\"b\""));
+    }
+
+    #[test]
+    fn should_format_hex_dump() {
+        let data = b"\x1f\xa6\xde\xba\xcc\x13\x7d\x74hello\r\n\x00\xea\xea";
+        assert_eq!(
+            "00000000  1f a6 de ba cc 13 7d 74  68 65 6c 6c 6f 0d 0a 00  |......}thello...|\n\
+             00000010  ea ea                                             |..|\n",
+            hex_dump(data)
+        );
+        assert_eq!("", hex_dump(&[]));
+    }
+
+    #[test]
+    fn should_encode_io_error() {
+        let e = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let d = Diagnostic::from_io(Some(Path::new("foo.cas")), &e);
+        assert_eq!(
+            "{\"severity\":\"error\",\"kind\":\"not_found\",\"message\":\"no such file\",\
+             \"path\":\"foo.cas\",\"block\":null,\"offset\":null,\"context\":null}",
+            d.to_json()
+        );
+    }
+
+    #[test]
+    fn should_encode_check_failure() {
+        let d = Diagnostic::check_failed(Path::new("foo.cas"), "1 of 3 files".to_string());
+        assert_eq!(Severity::Error, d.severity);
+        assert!(d
+            .to_json()
+            .starts_with("{\"severity\":\"error\",\"kind\":\"check_failed\""));
+    }
+
+    #[test]
+    fn should_encode_tape_error() {
+        let e = io::Error::from(TapeError::MissingData {
+            block: 3,
+            offset: 0x40,
+            context: Context::new(&[0x1f, 0xa6, 0xde, 0xba]),
+        });
+        let d = Diagnostic::from_io(None, &e);
+        assert_eq!("missing_data", d.kind);
+        assert_eq!(Some(3), d.block);
+        assert_eq!(Some(0x40), d.offset);
+        assert!(d
+            .to_json()
+            .contains("\"path\":null,\"block\":3,\"offset\":64"));
+        assert_eq!(Some("1f a6 de ba"), d.context.as_deref());
+
+        let e = TapeError::MissingEof {
+            block: 1,
+            offset: 0x10,
+            context: Context::new(&[0x1f]),
+        };
+        let d = Diagnostic::from_tape_error(Path::new("foo.cas"), &e);
+        assert_eq!(Severity::Warning, d.severity);
+        assert_eq!("missing_eof", d.kind);
+        assert_eq!(Some(0x10), d.offset);
+    }
+}
@@ -0,0 +1,264 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Conversion of cartridge ROM images into self-starting tapes
+//!
+//! Cartridge ROMs run from pages 1 and 2 (`0x4000` to `0xbfff`), where Basic keeps its
+//! interpreter and programs, so they cannot be loaded with `BLOAD`. `to_tape()` builds a
+//! tape with three parts, loaded with `RUN"CAS:"`:
+//!
+//! * An ASCII program that loads the next file with `BLOAD"CAS:",R`.
+//! * A machine code loader at `0xc000`, which selects the RAM in pages 1 and 2 and reads
+//!   the following files with the tape routines of the BIOS.
+//! * The ROM, split into binary files of 16KB (one per page) that the loader reads into
+//!   their place. Then the loader jumps to the init address of the ROM.
+//!
+//! The RAM of pages 1 and 2 must be in the slot of the RAM of page 3, as in most MSX with
+//! 64KB of RAM. ROMs with mappers (larger than 32KB) are not supported.
+
+use std::io;
+
+use crate::tape::{self, Tape};
+use crate::z80::{Assembler, Cond, Reg16, Reg8};
+
+/// The address the loader runs at
+const LOADER: u16 = 0xc000;
+
+/// The size of the binary files the ROM is split into
+const SEGMENT_LEN: usize = 0x4000;
+
+/// The BIOS routine that starts the motor and reads the header of a block
+const TAPION: u16 = 0x00e1;
+
+/// The BIOS routine that reads a byte of a block
+const TAPIN: u16 = 0x00e4;
+
+/// The BIOS routine that stops the motor
+const TAPIOF: u16 = 0x00e7;
+
+/// The table of the slots that are expanded
+const EXPTBL: u16 = 0xfcc1;
+
+/// The port of the primary slot register
+const PPI_A: u8 = 0xa8;
+
+/// The secondary slot register of the slot selected in page 3
+const SSLOT: u16 = 0xffff;
+
+/// The header of a ROM image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The address of the first byte of the ROM
+    pub base: u16,
+    /// The address the ROM is initialized from
+    pub init: u16,
+}
+
+/// Returns the header of the given ROM image
+///
+/// ROMs start with `AB` followed by their init address. ROMs of 32KB are located at
+/// `0x4000`, and smaller ones at `0x8000` if their init address is in page 2, or `0x4000`
+/// otherwise. Fails with an error of kind `InvalidData` if the ROM has no header or init
+/// address, or its size is not 8KB, 16KB or 32KB.
+///
+pub fn header(rom: &[u8]) -> io::Result<Header> {
+    if !matches!(rom.len(), 0x2000 | 0x4000 | 0x8000) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unsupported ROM size ({} bytes, expected 8KB, 16KB or 32KB)",
+                rom.len()
+            ),
+        ));
+    }
+    if !rom.starts_with(b"AB") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the ROM does not start with the `AB` header",
+        ));
+    }
+    let init = u16::from_le_bytes([rom[2], rom[3]]);
+    if init == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the ROM has no init address (Basic ROMs are not supported)",
+        ));
+    }
+    let base = if rom.len() <= SEGMENT_LEN && init >= 0x8000 {
+        0x8000
+    } else {
+        0x4000
+    };
+    Ok(Header { base, init })
+}
+
+/// Returns a tape that loads the given ROM image and runs it
+///
+/// The files are named after `name`, and the binary files of the ROM get the number of
+/// their segment as last character (e.g., `GAME1` and `GAME2`). Fails as `header()` does
+/// if the ROM is not supported.
+///
+pub fn to_tape(rom: &[u8], name: &[u8; 6]) -> io::Result<Tape> {
+    let header = header(rom)?;
+    let segments: Vec<(u16, &[u8])> = rom
+        .chunks(SEGMENT_LEN)
+        .enumerate()
+        .map(|(i, data)| (header.base + (i * SEGMENT_LEN) as u16, data))
+        .collect();
+    let mut tape = Tape::new();
+    tape.append_loader_for(name)?;
+    let lengths: Vec<(u16, u16)> = segments
+        .iter()
+        .map(|(addr, data)| (*addr, data.len() as u16))
+        .collect();
+    tape.append_bin_with_addresses(name, LOADER, LOADER, &loader(&lengths, header.init)?)?;
+    let stem = String::from_utf8_lossy(name).trim_end().to_string();
+    for (i, (addr, data)) in segments.iter().enumerate() {
+        let (segment_name, _) = tape::file_name(&format!(
+            "{}{}",
+            stem.chars().take(5).collect::<String>(),
+            i + 1
+        ));
+        tape.append_bin_with_addresses(&segment_name, *addr, *addr, data)?;
+    }
+    Ok(tape)
+}
+
+/// Assemble the loader of the binary files that contain the given segments of a ROM
+///
+/// The 16 bytes of the name block of each file and the 6 bytes of its addresses are read
+/// into the place of its segment, which overwrites them. If the tape cannot be read, the
+/// primary slots are restored and the loader returns to Basic.
+///
+fn loader(segments: &[(u16, u16)], init: u16) -> io::Result<Vec<u8>> {
+    let mut asm = Assembler::new(LOADER);
+    asm.di().in_a_n(PPI_A).push(Reg16::SP);
+    select_ram(&mut asm).out_n_a(PPI_A);
+
+    // B has the slot of page 3 in its two highest bits
+    asm.ld_r_r(Reg8::A, Reg8::B)
+        .rlca()
+        .rlca()
+        .ld_r_r(Reg8::C, Reg8::A)
+        .ld_r_n(Reg8::B, 0)
+        .ld_rr_nn(Reg16::HL, EXPTBL)
+        .add_hl_rr(Reg16::BC)
+        .ld_a_hl()
+        .and_n(0x80)
+        .jr_cc_label(Cond::Z, "load")
+        .ld_a_mem(SSLOT)
+        .cpl();
+    select_ram(&mut asm).ld_mem_a(SSLOT);
+
+    asm.label("load");
+    for (addr, len) in segments {
+        // The name block, then the data block with the addresses and the segment
+        asm.call(TAPION).jp_cc_label(Cond::C, "fail");
+        read(&mut asm, *addr, 16);
+        asm.call(TAPION).jp_cc_label(Cond::C, "fail");
+        read(&mut asm, *addr, 6);
+        read(&mut asm, *addr, *len);
+    }
+    asm.call(TAPIOF).jp(init);
+
+    asm.label("fail")
+        .call(TAPIOF)
+        .pop(Reg16::SP)
+        .out_n_a(PPI_A)
+        .ei()
+        .ret();
+
+    asm.label("read")
+        .push(Reg16::HL)
+        .push(Reg16::BC)
+        .call(TAPIN)
+        .pop(Reg16::BC)
+        .pop(Reg16::HL)
+        .ret_cc(Cond::C)
+        .ld_hl_a()
+        .inc_rr(Reg16::HL)
+        .dec_rr(Reg16::BC)
+        .ld_r_r(Reg8::A, Reg8::B)
+        .or_r(Reg8::C)
+        .jr_cc_label(Cond::NZ, "read")
+        .ret();
+    asm.assemble()
+}
+
+/// Append the code that reads `len` bytes of the current block into `addr`
+fn read(asm: &mut Assembler, addr: u16, len: u16) {
+    asm.ld_rr_nn(Reg16::HL, addr)
+        .ld_rr_nn(Reg16::BC, len)
+        .call_label("read")
+        .jp_cc_label(Cond::C, "fail");
+}
+
+/// Append the code that copies the slot of page 3 in A to pages 1 and 2
+///
+/// The slot of page 3 is left in B, in its two highest bits.
+///
+fn select_ram(asm: &mut Assembler) -> &mut Assembler {
+    asm.ld_r_r(Reg8::C, Reg8::A)
+        .and_n(0xc0)
+        .ld_r_r(Reg8::B, Reg8::A)
+        .rrca()
+        .rrca()
+        .ld_r_r(Reg8::D, Reg8::A)
+        .rrca()
+        .rrca()
+        .or_r(Reg8::D)
+        .ld_r_r(Reg8::D, Reg8::A)
+        .ld_r_r(Reg8::A, Reg8::C)
+        .and_n(0xc3)
+        .or_r(Reg8::D)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape::File;
+
+    #[test]
+    fn should_convert_rom_to_tape() {
+        let mut rom = vec![0; 0x8000];
+        rom[..4].copy_from_slice(&[b'A', b'B', 0x10, 0x40]);
+        rom[0x4000] = 0xc9;
+        assert_eq!(
+            Header {
+                base: 0x4000,
+                init: 0x4010
+            },
+            header(&rom).unwrap()
+        );
+        let (name, _) = tape::file_name("game");
+        let tape = to_tape(&rom, &name).unwrap();
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(4, files.len());
+        assert_eq!(&b"10 BLOAD\"CAS:\",R\r\n"[..], &files[0].payload()[..]);
+        assert_eq!(
+            Some((0xc000, 0xc000, 0xc000)),
+            files[1].addresses().map(|(b, _, s)| (b, b, s))
+        );
+        assert_eq!(&[0xf3, 0xdb, 0xa8, 0xf5], &files[1].payload()[..4]);
+        assert_eq!(Some("game1.bin".to_string()), files[2].name());
+        assert_eq!(Some((0x4000, 0x7fff, 0x4000)), files[2].addresses());
+        assert_eq!(&rom[..0x4000], &files[2].payload()[..0x4000]);
+        assert_eq!(Some((0x8000, 0xbfff, 0x8000)), files[3].addresses());
+        assert_eq!(&rom[0x4000..], &files[3].payload()[..0x4000]);
+
+        let mut rom = vec![0; 0x4000];
+        rom[..4].copy_from_slice(&[b'A', b'B', 0x00, 0x80]);
+        assert_eq!(0x8000, header(&rom).unwrap().base);
+        rom[3] = 0x00;
+        assert_eq!(io::ErrorKind::InvalidData, header(&rom).unwrap_err().kind());
+        rom[0] = b'C';
+        assert!(header(&rom).is_err());
+        assert!(header(&[b'A', b'B', 0x10, 0x40]).is_err());
+    }
+}
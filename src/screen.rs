@@ -0,0 +1,338 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::io::Write;
+
+use crate::hash::crc32;
+use crate::tape::File;
+
+/// The palette of the TMS9918 (MSX1) video chip, in RGB
+const TMS9918_PALETTE: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [0, 0, 0],
+    [33, 200, 66],
+    [94, 220, 120],
+    [84, 85, 237],
+    [125, 118, 252],
+    [212, 82, 77],
+    [66, 235, 245],
+    [252, 85, 84],
+    [255, 121, 120],
+    [212, 193, 84],
+    [230, 206, 128],
+    [33, 176, 59],
+    [201, 91, 186],
+    [204, 204, 204],
+    [255, 255, 255],
+];
+
+/// The default palette of the V9938 (MSX2) video chip, as 3-bit RGB levels
+const V9938_PALETTE: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [0, 0, 0],
+    [1, 6, 1],
+    [3, 7, 3],
+    [1, 1, 7],
+    [2, 3, 7],
+    [5, 1, 1],
+    [2, 6, 7],
+    [7, 1, 1],
+    [7, 3, 3],
+    [6, 6, 1],
+    [6, 6, 4],
+    [1, 4, 1],
+    [6, 2, 5],
+    [5, 5, 5],
+    [7, 7, 7],
+];
+
+/// A screen mode whose VRAM dumps can be previewed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScreenMode {
+    /// 256x192 pattern based mode with 16 colors (MSX1)
+    Screen2,
+    /// 256x212 bitmap mode with 16 colors out of 512 (MSX2)
+    Screen5,
+    /// 512x212 bitmap mode with 16 colors out of 512 (MSX2)
+    Screen7,
+    /// 256x212 bitmap mode with 256 fixed colors (MSX2)
+    Screen8,
+}
+
+impl ScreenMode {
+    /// Returns the dimensions in pixels of the screen.
+    pub fn size(self) -> (usize, usize) {
+        match self {
+            ScreenMode::Screen2 => (256, 192),
+            ScreenMode::Screen5 | ScreenMode::Screen8 => (256, 212),
+            ScreenMode::Screen7 => (512, 212),
+        }
+    }
+
+    /// Returns the VRAM address of the palette table, if the mode has a palette.
+    fn palette_addr(self) -> Option<usize> {
+        match self {
+            ScreenMode::Screen5 => Some(0x7680),
+            ScreenMode::Screen7 => Some(0xfa80),
+            _ => None,
+        }
+    }
+
+    /// Guess the screen mode of a VRAM dump from the range of addresses it covers
+    ///
+    /// SCREEN 2 dumps span the name, pattern and color tables (up to `0x37ff`). SCREEN 5
+    /// dumps span the bitmap (up to `0x69ff`) and possibly the palette. SCREEN 7 and 8 span
+    /// the same bitmap size (up to `0xd3ff`), so dumps including the SCREEN 7 palette at
+    /// `0xfa80` are considered SCREEN 7.
+    ///
+    pub fn detect(begin: usize, end: usize) -> Option<ScreenMode> {
+        if begin != 0 {
+            return None;
+        }
+        match end {
+            0x37ff..=0x3fff => Some(ScreenMode::Screen2),
+            0x69ff..=0x7fff => Some(ScreenMode::Screen5),
+            0xd3ff..=0xfa9e => Some(ScreenMode::Screen8),
+            0xfa9f..=0xffff => Some(ScreenMode::Screen7),
+            _ => None,
+        }
+    }
+}
+
+/// A rendered screen image, as 8-bit RGB pixels
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// Returns the VRAM contents of the given file, if it looks like a screen dump
+///
+/// Binary files are placed at their begin address. Custom files are considered to start at
+/// VRAM address 0.
+///
+pub fn vram_of(file: &File) -> Option<(ScreenMode, Vec<u8>)> {
+    let begin = match *file {
+        File::Bin(_, begin, _, _, _) => begin,
+        File::Custom(_) => 0,
+        _ => return None,
+    };
+    let data = file.payload();
+    if data.is_empty() {
+        return None;
+    }
+    let mode = ScreenMode::detect(begin, begin + data.len() - 1)?;
+    let mut vram = vec![0; 0x10000];
+    let len = data.len().min(vram.len() - begin);
+    vram[begin..begin + len].copy_from_slice(&data[..len]);
+    Some((mode, vram))
+}
+
+/// Render the given VRAM contents in the given screen mode
+pub fn render(mode: ScreenMode, vram: &[u8]) -> Image {
+    let (width, height) = mode.size();
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    let vram_at = |addr: usize| vram.get(addr).cloned().unwrap_or(0);
+    let palette = palette(mode, vram);
+    for y in 0..height {
+        for x in 0..width {
+            let rgb = match mode {
+                ScreenMode::Screen2 => {
+                    let (row, col) = (y / 8, x / 8);
+                    let name = vram_at(0x1800 + row * 32 + col) as usize;
+                    let offset = (row / 8) * 2048 + name * 8 + y % 8;
+                    let pattern = vram_at(offset);
+                    let color = vram_at(0x2000 + offset);
+                    let index = if pattern & (0x80 >> (x % 8)) != 0 {
+                        color >> 4
+                    } else {
+                        color & 0x0f
+                    };
+                    palette[index as usize]
+                }
+                ScreenMode::Screen5 | ScreenMode::Screen7 => {
+                    let byte = vram_at(y * width / 2 + x / 2);
+                    let index = if x % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+                    palette[index as usize]
+                }
+                ScreenMode::Screen8 => {
+                    let byte = vram_at(y * 256 + x);
+                    [
+                        level((byte >> 2) & 0x07, 7),
+                        level(byte >> 5, 7),
+                        level(byte & 0x03, 3),
+                    ]
+                }
+            };
+            pixels.extend_from_slice(&rgb);
+        }
+    }
+    Image {
+        width,
+        height,
+        pixels,
+    }
+}
+
+fn palette(mode: ScreenMode, vram: &[u8]) -> [[u8; 3]; 16] {
+    let mut palette = [[0; 3]; 16];
+    if mode == ScreenMode::Screen2 {
+        return TMS9918_PALETTE;
+    }
+    let table = mode
+        .palette_addr()
+        .and_then(|addr| vram.get(addr..addr + 32))
+        .filter(|table| table.iter().any(|b| *b != 0));
+    for (i, entry) in palette.iter_mut().enumerate() {
+        let [r, g, b] = match table {
+            Some(table) => [
+                (table[i * 2] >> 4) & 0x07,
+                table[i * 2 + 1] & 0x07,
+                table[i * 2] & 0x07,
+            ],
+            None => V9938_PALETTE[i],
+        };
+        *entry = [level(r, 7), level(g, 7), level(b, 7)];
+    }
+    palette
+}
+
+fn level(value: u8, max: u8) -> u8 {
+    (value as u16 * 255 / max as u16) as u8
+}
+
+impl Image {
+    /// Write the image in PNG format to the given output
+    ///
+    /// The image data is stored without compression, which keeps the encoder small at the
+    /// expense of larger files.
+    ///
+    pub fn write_png<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut ihdr = vec![];
+        ihdr.extend_from_slice(&(self.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(self.height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        output.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_chunk(output, b"IHDR", &ihdr)?;
+        write_chunk(output, b"IDAT", &zlib_stored(&raw))?;
+        write_chunk(output, b"IEND", &[])
+    }
+}
+
+fn write_chunk<W: Write>(output: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    output.write_all(kind)?;
+    output.write_all(data)?;
+    let crc = crc32(&[&kind[..], data].concat());
+    output.write_all(&crc.to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut result = vec![0x78, 0x01];
+    let mut chunks = data.chunks(0xffff).peekable();
+    if chunks.peek().is_none() {
+        result.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        result.push(chunks.peek().is_none() as u8);
+        result.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+        result.extend_from_slice(&(!(chunk.len() as u16)).to_le_bytes());
+        result.extend_from_slice(chunk);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    result.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    result
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_detect_screen_modes() {
+        assert_eq!(Some(ScreenMode::Screen2), ScreenMode::detect(0, 0x37ff));
+        assert_eq!(Some(ScreenMode::Screen5), ScreenMode::detect(0, 0x69ff));
+        assert_eq!(Some(ScreenMode::Screen5), ScreenMode::detect(0, 0x769f));
+        assert_eq!(Some(ScreenMode::Screen8), ScreenMode::detect(0, 0xd3ff));
+        assert_eq!(Some(ScreenMode::Screen7), ScreenMode::detect(0, 0xfa9f));
+        assert_eq!(None, ScreenMode::detect(0x8000, 0xd3ff));
+        assert_eq!(None, ScreenMode::detect(0, 0x1000));
+    }
+
+    #[test]
+    fn should_render_screen8() {
+        let mut vram = vec![0; 0xd400];
+        vram[0] = 0xff;
+        vram[257] = 0x1c;
+        let image = render(ScreenMode::Screen8, &vram);
+        assert_eq!((256, 212), (image.width, image.height));
+        assert_eq!(&[255, 255, 255], &image.pixels[0..3]);
+        assert_eq!(&[255, 0, 0], &image.pixels[257 * 3..258 * 3]);
+    }
+
+    #[test]
+    fn should_render_screen5_with_palette() {
+        let mut vram = vec![0; 0x76a0];
+        vram[0] = 0x12;
+        vram[0x7680 + 2] = 0x70;
+        vram[0x7680 + 4] = 0x07;
+        let image = render(ScreenMode::Screen5, &vram);
+        assert_eq!(&[255, 0, 0], &image.pixels[0..3]);
+        assert_eq!(&[0, 0, 255], &image.pixels[3..6]);
+    }
+
+    #[test]
+    fn should_render_screen2() {
+        let mut vram = vec![0; 0x3800];
+        vram[0x1800] = 1;
+        vram[8] = 0x80;
+        vram[0x2000 + 8] = 0xf1;
+        let image = render(ScreenMode::Screen2, &vram);
+        assert_eq!(&[255, 255, 255], &image.pixels[0..3]);
+        assert_eq!(&[0, 0, 0], &image.pixels[3..6]);
+    }
+
+    #[test]
+    fn should_find_vram_of_files() {
+        let mut data = vec![0x00, 0x00, 0xff, 0x37, 0x00, 0x00];
+        data.resize(6 + 0x3800, 0);
+        let file = File::Bin("foo".to_string(), 0, 0x37ff, 0, &data);
+        assert_eq!(ScreenMode::Screen2, vram_of(&file).unwrap().0);
+        assert!(vram_of(&File::Custom(&[0; 16])).is_none());
+    }
+
+    #[test]
+    fn should_write_png() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![255, 0, 0, 0, 255, 0],
+        };
+        let mut png = vec![];
+        image.write_png(&mut png).unwrap();
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &png[0..8]);
+        assert_eq!(
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82],
+            &png[png.len() - 12..]
+        );
+    }
+}
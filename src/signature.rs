@@ -0,0 +1,380 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#[cfg(feature = "fs")]
+use std::env;
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+
+use crate::tape::{File, FileType};
+
+/// A rule that identifies the contents of a tape file
+///
+/// A signature is a byte pattern, where any byte may be a wildcard, searched in the
+/// payload of the files (see `File::payload()`). If the signature has an offset, the
+/// pattern must be found at that offset. Otherwise it may be found anywhere. Signatures
+/// may be restricted to files of a given type.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    name: String,
+    file_type: Option<FileType>,
+    offset: Option<usize>,
+    pattern: Vec<Option<u8>>,
+}
+
+impl Signature {
+    /// Create a new signature from a pattern of hexadecimal bytes.
+    ///
+    /// The pattern is a sequence of bytes written as two hexadecimal digits, optionally
+    /// separated by spaces. A `??` matches any byte. An error of kind `InvalidInput` is
+    /// returned if the pattern is empty or malformed.
+    ///
+    pub fn new(
+        name: &str,
+        file_type: Option<FileType>,
+        offset: Option<usize>,
+        pattern: &str,
+    ) -> io::Result<Signature> {
+        let digits: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid pattern {:?} in signature {:?}", pattern, name),
+            )
+        };
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let pattern = digits
+            .chunks(2)
+            .map(|pair| match pair {
+                ['?', '?'] => Ok(None),
+                [h, l] => match (h.to_digit(16), l.to_digit(16)) {
+                    (Some(h), Some(l)) => Ok(Some((h * 16 + l) as u8)),
+                    _ => Err(invalid()),
+                },
+                _ => Err(invalid()),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Signature {
+            name: name.to_string(),
+            file_type,
+            offset,
+            pattern,
+        })
+    }
+
+    /// Create a new signature matching the given text.
+    pub fn from_text(
+        name: &str,
+        file_type: Option<FileType>,
+        offset: Option<usize>,
+        text: &str,
+    ) -> Signature {
+        Signature {
+            name: name.to_string(),
+            file_type,
+            offset,
+            pattern: text.bytes().map(Some).collect(),
+        }
+    }
+
+    /// Returns the name of the signature.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns `true` if the given file matches this signature.
+    pub fn matches(&self, file: &File) -> bool {
+        if self.file_type.is_some_and(|t| t != file.file_type()) {
+            return false;
+        }
+        let data = file.payload();
+        let matches_at = |pos: usize| {
+            data.get(pos..pos + self.pattern.len())
+                .is_some_and(|window| {
+                    window
+                        .iter()
+                        .zip(&self.pattern)
+                        .all(|(b, p)| p.is_none_or(|p| p == *b))
+                })
+        };
+        match self.offset {
+            Some(offset) => matches_at(offset),
+            None => (0..data.len()).any(matches_at),
+        }
+    }
+}
+
+/// A collection of signatures used to identify the contents of tape files
+///
+/// The database starts with the built-in signatures. Additional signatures may be added
+/// with `add()` or loaded from TOML files (when the `signature-files` feature is
+/// enabled). Each TOML file contains one or more `[[signature]]` tables:
+///
+/// ```toml
+/// [[signature]]
+/// name = "Foo turbo loader"
+/// type = "custom"            # optional: bin, basic, ascii or custom
+/// offset = 0                 # optional: the pattern may be found anywhere if missing
+/// pattern = "F3 21 ?? ?? 11" # hexadecimal bytes, `??` matches any byte
+///
+/// [[signature]]
+/// name = "Bar loader"
+/// text = "BAR LOADER"        # instead of pattern, to match plain text
+/// ```
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Database {
+    signatures: Vec<Signature>,
+}
+
+impl Database {
+    /// Create a database with the built-in signatures.
+    pub fn builtin() -> Database {
+        let signature = |name, file_type, offset, pattern| {
+            Signature::new(name, file_type, offset, pattern).expect("valid built-in signature")
+        };
+        Database {
+            signatures: vec![
+                signature("ROM image", Some(FileType::Bin), Some(0), "41 42"),
+                signature(
+                    "BLOAD loader",
+                    Some(FileType::Basic),
+                    None,
+                    "CF 22 43 41 53 3A",
+                ),
+                Signature::from_text("BLOAD loader", Some(FileType::Ascii), None, "BLOAD\"CAS:"),
+                Signature::from_text("CLOAD loader", Some(FileType::Ascii), None, "CLOAD"),
+            ],
+        }
+    }
+
+    /// Add a signature to the database.
+    pub fn add(&mut self, signature: Signature) {
+        self.signatures.push(signature);
+    }
+
+    /// Returns the signatures of the database.
+    pub fn signatures(&self) -> &[Signature] {
+        &self.signatures
+    }
+
+    /// Returns the signatures that match the given file, without duplicated names.
+    pub fn identify(&self, file: &File) -> Vec<&Signature> {
+        let mut found: Vec<&Signature> = vec![];
+        for signature in self.signatures.iter().filter(|s| s.matches(file)) {
+            if !found.iter().any(|s| s.name == signature.name) {
+                found.push(signature);
+            }
+        }
+        found
+    }
+
+    /// Load the signatures defined in the given TOML text, returning how many were loaded.
+    #[cfg(feature = "signature-files")]
+    pub fn load_str(&mut self, text: &str) -> io::Result<usize> {
+        let file: files::SignatureFile = toml::from_str(text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let signatures = file
+            .signature
+            .into_iter()
+            .map(files::SignatureDef::into_signature)
+            .collect::<io::Result<Vec<_>>>()?;
+        let count = signatures.len();
+        self.signatures.extend(signatures);
+        Ok(count)
+    }
+
+    /// Load the signatures of all the `.toml` files in the given directory.
+    ///
+    /// The files are loaded in alphabetical order. A missing directory is not an error.
+    /// Returns how many signatures were loaded.
+    ///
+    #[cfg(feature = "signature-files")]
+    pub fn load_dir(&mut self, dir: &Path) -> io::Result<usize> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut paths = entries
+            .map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.retain(|p| p.extension().is_some_and(|e| e == "toml"));
+        paths.sort();
+        let mut count = 0;
+        for path in paths {
+            let text = std::fs::read_to_string(&path)?;
+            count += self
+                .load_str(&text)
+                .map_err(|e| io::Error::new(e.kind(), format!("in {}: {}", path.display(), e)))?;
+        }
+        Ok(count)
+    }
+}
+
+impl Default for Database {
+    fn default() -> Database {
+        Database::builtin()
+    }
+}
+
+/// Returns the directory where additional signature files are looked for
+///
+/// This is `$MCP_CONFIG_DIR/signatures` if the variable is defined. Otherwise, it is the
+/// `mcp/signatures` directory under `$XDG_CONFIG_HOME` or `$HOME/.config`.
+///
+#[cfg(feature = "fs")]
+pub fn default_dir() -> Option<PathBuf> {
+    let config = env::var_os("MCP_CONFIG_DIR")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("XDG_CONFIG_HOME").map(|d| Path::new(&d).join("mcp")))
+        .or_else(|| env::var_os("HOME").map(|d| Path::new(&d).join(".config").join("mcp")))?;
+    Some(config.join("signatures"))
+}
+
+#[cfg(feature = "signature-files")]
+mod files {
+    use std::io;
+
+    use serde::Deserialize;
+
+    use super::Signature;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct SignatureFile {
+        #[serde(default)]
+        pub signature: Vec<SignatureDef>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct SignatureDef {
+        name: String,
+        #[serde(rename = "type")]
+        file_type: Option<String>,
+        offset: Option<usize>,
+        pattern: Option<String>,
+        text: Option<String>,
+    }
+
+    impl SignatureDef {
+        pub fn into_signature(self) -> io::Result<Signature> {
+            let file_type = self.file_type.map(|t| t.parse()).transpose()?;
+            match (self.pattern, self.text) {
+                (Some(pattern), None) => {
+                    Signature::new(&self.name, file_type, self.offset, &pattern)
+                }
+                (None, Some(text)) if !text.is_empty() => Ok(Signature::from_text(
+                    &self.name,
+                    file_type,
+                    self.offset,
+                    &text,
+                )),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "signature {:?} must have either a pattern or a text",
+                        self.name
+                    ),
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_parse_patterns() {
+        let sig = Signature::new("foo", None, None, "F3 21??11").unwrap();
+        assert_eq!(vec![Some(0xf3), Some(0x21), None, Some(0x11)], sig.pattern);
+        assert!(Signature::new("foo", None, None, "").is_err());
+        assert!(Signature::new("foo", None, None, "F3 2").is_err());
+        assert!(Signature::new("foo", None, None, "XY").is_err());
+    }
+
+    #[test]
+    fn should_match_files() {
+        let data = [0x00, 0xf3, 0x21, 0x34, 0x12, 0x11];
+        let file = File::Custom(&data);
+        assert!(Signature::new("foo", None, None, "21 ?? 12")
+            .unwrap()
+            .matches(&file));
+        assert!(Signature::new("foo", None, Some(1), "F3 21")
+            .unwrap()
+            .matches(&file));
+        assert!(!Signature::new("foo", None, Some(0), "F3 21")
+            .unwrap()
+            .matches(&file));
+        assert!(!Signature::new("foo", Some(FileType::Bin), None, "F3")
+            .unwrap()
+            .matches(&file));
+        assert!(!Signature::new("foo", None, Some(5), "11 00")
+            .unwrap()
+            .matches(&file));
+    }
+
+    #[test]
+    fn should_identify_with_builtin_signatures() {
+        let db = Database::builtin();
+        let rom = [0x00, 0x40, 0xff, 0x7f, 0x00, 0x40, 0x41, 0x42, 0x10, 0x40];
+        let file = File::Bin("game".to_string(), 0x4000, 0x7fff, 0x4000, &rom);
+        let names: Vec<&str> = db.identify(&file).iter().map(|s| s.name()).collect();
+        assert_eq!(vec!["ROM image"], names);
+
+        let loader = [0x0a, 0x00, 0xcf, 0x22, 0x43, 0x41, 0x53, 0x3a, 0x22];
+        let file = File::Basic("loader".to_string(), &loader);
+        let names: Vec<&str> = db.identify(&file).iter().map(|s| s.name()).collect();
+        assert_eq!(vec!["BLOAD loader"], names);
+
+        assert!(db.identify(&File::Custom(&[0x00; 8])).is_empty());
+    }
+
+    #[cfg(feature = "signature-files")]
+    #[test]
+    fn should_load_signature_files() {
+        use std::fs;
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("mcp").unwrap();
+        fs::write(
+            dir.path().join("a.toml"),
+            "[[signature]]\nname = \"Foo\"\ntype = \"custom\"\noffset = 0\npattern = \"01 ??\"\n\n\
+             [[signature]]\nname = \"Bar\"\ntext = \"BAR\"\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("ignored.txt"), "garbage").unwrap();
+
+        let mut db = Database::builtin();
+        let builtin = db.signatures().len();
+        assert_eq!(2, db.load_dir(dir.path()).unwrap());
+        assert_eq!(builtin + 2, db.signatures().len());
+
+        let data = [0x01, 0x02, b'B', b'A', b'R'];
+        let names: Vec<&str> = db
+            .identify(&File::Custom(&data))
+            .iter()
+            .map(|s| s.name())
+            .collect();
+        assert_eq!(vec!["Foo", "Bar"], names);
+
+        assert_eq!(0, db.load_dir(&dir.path().join("missing")).unwrap());
+        assert!(db.load_str("[[signature]]\nname = \"Baz\"\n").is_err());
+        assert!(db
+            .load_str("[[signature]]\nname = \"Baz\"\npattern = \"01\"\ntype = \"foo\"\n")
+            .is_err());
+    }
+}
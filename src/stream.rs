@@ -0,0 +1,397 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::io::{BufReader, Read};
+
+use crate::progress::{self, Progress};
+use crate::tape::FileType;
+
+const PREFIX: [u8; 8] = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+
+/// The maximum number of bytes delivered in a single `DataChunk` event
+const MAX_CHUNK: usize = 4096;
+
+/// The number of bytes parsed between progress updates
+const PROGRESS_STEP: usize = 64 * 1024;
+
+/// An event produced while parsing a tape in streaming mode
+///
+/// For each block found in the input a `BlockStart` event is produced. If the block is a
+/// file header, it is followed by a `HeaderFound` event. Otherwise, the block contents
+/// are delivered in one or more `DataChunk` events. A `FileEnd` event is produced after
+/// the last block of each file. Custom files have no header block, so their data chunks
+/// are not preceded by any `HeaderFound` event.
+///
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    BlockStart { index: usize, offset: usize },
+    HeaderFound { file_type: FileType, name: String },
+    DataChunk(&'a [u8]),
+    FileEnd,
+}
+
+/// An object that receives the events produced by `parse()`
+///
+/// Any closure accepting an `Event` and returning `io::Result<()>` is a visitor. If the
+/// visitor returns an error, the parsing stops and the error is returned by `parse()`.
+///
+pub trait Visitor {
+    fn visit(&mut self, event: Event) -> io::Result<()>;
+}
+
+impl<F> Visitor for F
+where
+    F: FnMut(Event) -> io::Result<()>,
+{
+    fn visit(&mut self, event: Event) -> io::Result<()> {
+        self(event)
+    }
+}
+
+/// Parse the tape read from `input` delivering its events to `visitor`
+///
+/// The input is consumed incrementally, so the whole tape is never kept in memory. As in
+/// `Tape::from_bytes()`, block prefixes are only recognized at 8-byte aligned offsets and
+/// any bytes preceding the first block are ignored.
+///
+pub fn parse<R: Read, V: Visitor>(input: &mut R, visitor: &mut V) -> io::Result<()> {
+    parse_with_progress(input, visitor, &mut progress::ignore())
+}
+
+/// Parse the tape read from `input` as `parse()` does, reporting the bytes read so far
+///
+/// The total number of bytes is unknown until the end of the input, where a final
+/// update with the total is produced. If the observer is cancelled, the parsing stops
+/// and an error of kind `Interrupted` is returned.
+///
+pub fn parse_with_progress<R: Read, V: Visitor>(
+    input: &mut R,
+    visitor: &mut V,
+    progress: &mut dyn Progress,
+) -> io::Result<()> {
+    let mut parser = Parser {
+        visitor,
+        block: None,
+        head: Vec::with_capacity(16),
+        data: Vec::with_capacity(MAX_CHUNK),
+        expect: Expect::Nothing,
+        kind: BlockKind::Unknown,
+        eof_found: false,
+    };
+    let mut input = BufReader::new(input);
+    let mut offset = 0;
+    let mut unit = [0; 8];
+    loop {
+        let n = read_unit(&mut input, &mut unit)?;
+        if n == 0 {
+            break;
+        }
+        if unit[..n] == PREFIX {
+            parser.start_block(offset)?;
+        } else if parser.block.is_some() {
+            parser.feed(&unit[..n])?;
+        }
+        offset += n;
+        if offset.is_multiple_of(PROGRESS_STEP) {
+            progress.update(offset as u64, None);
+            if progress.is_cancelled() {
+                return Err(progress::cancelled());
+            }
+        }
+    }
+    parser.finish()?;
+    progress.update(offset as u64, Some(offset as u64));
+    Ok(())
+}
+
+/// What the parser expects to find in the next block
+#[derive(Clone, Copy, PartialEq)]
+enum Expect {
+    Nothing,
+    Data,
+    AsciiData,
+}
+
+/// The classification of the block being parsed
+#[derive(Clone, Copy, PartialEq)]
+enum BlockKind {
+    Unknown,
+    Header,
+    Data,
+}
+
+struct Parser<'v, V: Visitor> {
+    visitor: &'v mut V,
+    block: Option<usize>,
+    head: Vec<u8>,
+    data: Vec<u8>,
+    expect: Expect,
+    kind: BlockKind,
+    eof_found: bool,
+}
+
+impl<'v, V: Visitor> Parser<'v, V> {
+    fn start_block(&mut self, offset: usize) -> io::Result<()> {
+        self.end_block()?;
+        let index = self.block.map(|i| i + 1).unwrap_or(0);
+        self.block = Some(index);
+        self.visitor.visit(Event::BlockStart { index, offset })
+    }
+
+    fn feed(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.kind {
+            BlockKind::Header => Ok(()),
+            BlockKind::Data => self.push_data(bytes),
+            BlockKind::Unknown => {
+                self.head.extend_from_slice(bytes);
+                if self.head.len() >= 16 {
+                    self.classify()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn classify(&mut self) -> io::Result<()> {
+        let head = std::mem::take(&mut self.head);
+        let header = header_type(&head);
+        if header.is_some() && self.expect != Expect::Nothing {
+            // A new file starts before the previous one is complete
+            self.expect = Expect::Nothing;
+            self.visitor.visit(Event::FileEnd)?;
+        }
+        match header {
+            Some(file_type) => {
+                self.kind = BlockKind::Header;
+                self.expect = if file_type == FileType::Ascii {
+                    Expect::AsciiData
+                } else {
+                    Expect::Data
+                };
+                let name = head.get(10..16).unwrap_or(&head[10..]);
+                let name = String::from_utf8_lossy(name)
+                    .trim_end_matches(&['\0', ' '][..])
+                    .to_string();
+                self.visitor.visit(Event::HeaderFound { file_type, name })
+            }
+            None => {
+                self.kind = BlockKind::Data;
+                self.push_data(&head)
+            }
+        }
+    }
+
+    fn push_data(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.expect == Expect::AsciiData && bytes.contains(&0x1a) {
+            self.eof_found = true;
+        }
+        self.data.extend_from_slice(bytes);
+        if self.data.len() >= MAX_CHUNK {
+            self.flush_data()?;
+        }
+        Ok(())
+    }
+
+    fn flush_data(&mut self) -> io::Result<()> {
+        if !self.data.is_empty() {
+            self.visitor.visit(Event::DataChunk(&self.data))?;
+            self.data.clear();
+        }
+        Ok(())
+    }
+
+    fn end_block(&mut self) -> io::Result<()> {
+        if self.block.is_none() {
+            return Ok(());
+        }
+        if self.kind == BlockKind::Unknown {
+            self.classify()?;
+        }
+        self.flush_data()?;
+        if self.kind == BlockKind::Data {
+            let file_end = match self.expect {
+                Expect::AsciiData => self.eof_found,
+                _ => true,
+            };
+            if file_end {
+                self.expect = Expect::Nothing;
+                self.eof_found = false;
+                self.visitor.visit(Event::FileEnd)?;
+            }
+        }
+        self.kind = BlockKind::Unknown;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.end_block()?;
+        if self.expect != Expect::Nothing {
+            self.expect = Expect::Nothing;
+            self.visitor.visit(Event::FileEnd)?;
+        }
+        Ok(())
+    }
+}
+
+fn header_type(head: &[u8]) -> Option<FileType> {
+    if head.starts_with(&[0xd0; 10]) {
+        Some(FileType::Bin)
+    } else if head.starts_with(&[0xd3; 10]) {
+        Some(FileType::Basic)
+    } else if head.starts_with(&[0xea; 10]) {
+        Some(FileType::Ascii)
+    } else {
+        None
+    }
+}
+
+fn read_unit<R: Read>(input: &mut R, unit: &mut [u8; 8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < unit.len() {
+        match input.read(&mut unit[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::tape::{file_name, Tape};
+
+    #[derive(Debug, PartialEq)]
+    enum Owned {
+        BlockStart(usize, usize),
+        HeaderFound(FileType, String),
+        DataChunk(Vec<u8>),
+        FileEnd,
+    }
+
+    fn events_of(bytes: &[u8]) -> Vec<Owned> {
+        let mut events = vec![];
+        parse(&mut &bytes[..], &mut |e: Event| {
+            events.push(match e {
+                Event::BlockStart { index, offset } => Owned::BlockStart(index, offset),
+                Event::HeaderFound { file_type, name } => Owned::HeaderFound(file_type, name),
+                Event::DataChunk(data) => Owned::DataChunk(data.to_vec()),
+                Event::FileEnd => Owned::FileEnd,
+            });
+            Ok(())
+        })
+        .unwrap();
+        events
+    }
+
+    #[test]
+    fn should_stream_empty_input() {
+        assert!(events_of(&[]).is_empty());
+    }
+
+    #[test]
+    fn should_stream_tape_events() {
+        let mut tape = Tape::new();
+        let (fname, _) = file_name("foo");
+        let bin = [0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0x01, 0x02];
+        tape.append_bin(&fname, &bin).unwrap();
+        tape.append_ascii(&fname, b"10 END").unwrap();
+        tape.append_custom(&[0x01; 8]).unwrap();
+        let bytes: Vec<u8> = tape
+            .blocks()
+            .iter()
+            .flat_map(|b| b.data().to_vec())
+            .collect();
+
+        let mut text = b"10 END".to_vec();
+        text.resize(256, 0x1a);
+        assert_eq!(
+            vec![
+                Owned::BlockStart(0, 0),
+                Owned::HeaderFound(FileType::Bin, "foo".to_string()),
+                Owned::BlockStart(1, 24),
+                Owned::DataChunk(bin.to_vec()),
+                Owned::FileEnd,
+                Owned::BlockStart(2, 40),
+                Owned::HeaderFound(FileType::Ascii, "foo".to_string()),
+                Owned::BlockStart(3, 64),
+                Owned::DataChunk(text),
+                Owned::FileEnd,
+                Owned::BlockStart(4, 328),
+                Owned::DataChunk(vec![0x01; 8]),
+                Owned::FileEnd,
+            ],
+            events_of(&bytes)
+        );
+    }
+
+    #[test]
+    fn should_end_file_on_header_without_data() {
+        let bytes = [&PREFIX[..], &[0xd3; 10][..], b"FOO   "].concat();
+        assert_eq!(
+            vec![
+                Owned::BlockStart(0, 0),
+                Owned::HeaderFound(FileType::Basic, "FOO".to_string()),
+                Owned::FileEnd,
+            ],
+            events_of(&bytes)
+        );
+    }
+
+    #[test]
+    fn should_stop_on_visitor_error() {
+        let bytes = [&PREFIX[..], &[0x01; 8][..], &PREFIX[..], &[0x02; 8][..]].concat();
+        let mut count = 0;
+        let result = parse(&mut &bytes[..], &mut |_: Event| {
+            count += 1;
+            Err(io::Error::other("stop"))
+        });
+        assert!(result.is_err());
+        assert_eq!(1, count);
+    }
+
+    #[test]
+    fn should_report_progress() {
+        let mut bytes = PREFIX.to_vec();
+        bytes.resize(PROGRESS_STEP * 2 + 8, 0x01);
+        let mut updates = vec![];
+        parse_with_progress(
+            &mut &bytes[..],
+            &mut |_: Event| Ok(()),
+            &mut |done, total| updates.push((done, total)),
+        )
+        .unwrap();
+        let (step, len) = (PROGRESS_STEP as u64, bytes.len() as u64);
+        assert_eq!(
+            vec![(step, None), (2 * step, None), (len, Some(len))],
+            updates
+        );
+    }
+
+    #[test]
+    fn should_stop_when_cancelled() {
+        let mut bytes = PREFIX.to_vec();
+        bytes.resize(PROGRESS_STEP * 2 + 8, 0x01);
+        let token = progress::CancelToken::new();
+        let mut updates = 0;
+        let result = parse_with_progress(
+            &mut &bytes[..],
+            &mut |_: Event| Ok(()),
+            &mut token.observe(|_, _| {
+                updates += 1;
+                token.cancel();
+            }),
+        );
+        assert_eq!(io::ErrorKind::Interrupted, result.unwrap_err().kind());
+        assert_eq!(1, updates);
+    }
+}
@@ -6,43 +6,117 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+//! Tapes in CAS format and the files they contain
+//!
+//! A CAS file is the sequence of blocks recorded in a tape, each one preceded by the
+//! block prefix bytes. `Tape` reads, modifies and writes that sequence of `Block`s, and
+//! `Tape::files()` groups them into the `File`s they make up: a header block and its
+//! data for binary, Basic and ASCII files, and a single block for custom files.
+//!
+//! Reading a tape never fails: bytes that do not form well-formed files are read as
+//! custom files, and `Tape::problems()` tells what was wrong with them. `Tape::parse()`
+//! rejects them instead:
+//!
+//! ```
+//! use mcp::tape::{self, FileType, Tape};
+//!
+//! let mut tape = Tape::new();
+//! let (name, _) = tape::file_name("hello");
+//! tape.append_ascii(&name, b"10 PRINT \"HELLO\"\r\n")?;
+//! let bytes = tape.to_bytes();
+//!
+//! let tape = Tape::parse(&bytes)?;
+//! let file = tape.files().next().unwrap();
+//! assert_eq!(FileType::Ascii, file.file_type());
+//! assert_eq!(Some("hello.asc".to_string()), file.name());
+//! assert_eq!(b"10 PRINT \"HELLO\"\r\n", &file.payload()[..]);
+//!
+//! let truncated = Tape::from_bytes(&bytes[..24]);
+//! assert!(Tape::parse(&bytes[..24]).is_err());
+//! assert_eq!(1, truncated.problems().len());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+#[cfg(feature = "fs")]
 use std::fs;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+#[cfg(feature = "fs")]
 use std::path::Path;
-use std::str::from_utf8;
+use std::str::{from_utf8, FromStr};
+use std::sync::Arc;
 
+#[cfg(feature = "arbitrary")]
+use std::ops::ControlFlow;
+
+#[cfg(feature = "arbitrary")]
+use arbitrary::{Arbitrary, Unstructured};
 use byteorder::{ByteOrder, LittleEndian};
 
+use crate::gzip;
+
+/// The bytes that start every block
+pub(crate) const PREFIX: [u8; 8] = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+
 /// A block of data contained in a tape.
 ///
 /// A tape file is comprised by a sequence of blocks. Each block starts with the prefix bytes
 /// `1fa6debacc137d74` followed by the block data. The `Block` type stores the block data
 /// including the prefix bytes.
 ///
-#[derive(Debug)]
+/// The blocks of a parsed tape share the buffer the tape was read from, so parsing does
+/// not copy the block data. A block gets its own copy of the data only when it is
+/// modified (e.g., padded by an append operation).
+///
+#[derive(Clone)]
 pub struct Block {
-    data: Vec<u8>,
+    buffer: Arc<Vec<u8>>,
+    range: Range<usize>,
 }
 
 impl Block {
     /// Generates a new block from the data bytes (without the prefix bytes).
     pub fn from_data(bytes: &[u8]) -> Block {
         let mut data = Vec::with_capacity(bytes.len() + 8);
-        data.write(&[0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74])
+        data.write_all(&[0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74])
             .unwrap();
-        data.write(bytes).unwrap();
-        Block { data: data }
+        data.write_all(bytes).unwrap();
+        let range = 0..data.len();
+        Block {
+            buffer: Arc::new(data),
+            range,
+        }
     }
 
     /// Returns the block data (including the prefix bytes).
     pub fn data(&self) -> &[u8] {
-        &self.data[..]
+        &self.buffer[self.range.clone()]
     }
 
     /// Returns the block data (without the prefix bytes).
     pub fn data_without_prefix(&self) -> &[u8] {
-        &self.data[8..]
+        &self.data()[8..]
+    }
+
+    /// Returns the block data for modification, copying it if it is shared.
+    fn data_mut(&mut self) -> &mut Vec<u8> {
+        if self.range != (0..self.buffer.len()) || Arc::get_mut(&mut self.buffer).is_none() {
+            self.buffer = Arc::new(self.data().to_vec());
+            self.range = 0..self.buffer.len();
+        }
+        Arc::get_mut(&mut self.buffer).unwrap()
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.data_mut().push(byte);
+        self.range.end += 1;
     }
 
     /// Returns `true` if the block is detected as a binary header.
@@ -52,7 +126,7 @@ impl Block {
     /// this pattern, `false` otherwise.
     pub fn is_bin_header(&self) -> bool {
         let data = self.data_without_prefix();
-        data[..10] == [0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0]
+        data.starts_with(&[0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0])
     }
 
     /// Returns `true` if the block is detected as a Basic header.
@@ -62,7 +136,7 @@ impl Block {
     /// this pattern, `false` otherwise.
     pub fn is_basic_header(&self) -> bool {
         let data = self.data_without_prefix();
-        data[..10] == [0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3]
+        data.starts_with(&[0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3])
     }
 
     /// Returns `true` if the block is detected as an ASCII header.
@@ -72,7 +146,7 @@ impl Block {
     /// this pattern, `false` otherwise.
     pub fn is_ascii_header(&self) -> bool {
         let data = self.data_without_prefix();
-        data[..10] == [0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea]
+        data.starts_with(&[0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea])
     }
 
     /// Returns `true` if the block is detected as a file header (either bin, basic or ascii).
@@ -83,7 +157,7 @@ impl Block {
     /// Returns the file name in case of a binary, ascii or basic header, `None` otherwise.
     pub fn file_name(&self) -> Option<&str> {
         if self.is_bin_header() || self.is_basic_header() || self.is_ascii_header() {
-            let name = &self.data_without_prefix()[10..16];
+            let name = self.data_without_prefix().get(10..16)?;
             let whites: &[_] = &['\0', ' '];
             from_utf8(name).ok().map(|n| n.trim_end_matches(whites))
         } else {
@@ -92,6 +166,229 @@ impl Block {
     }
 }
 
+impl fmt::Debug for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Block").field("data", &self.data()).finish()
+    }
+}
+
+/// A few bytes of the input found where a problem was detected
+///
+/// Contexts are displayed as hexadecimal bytes, so error messages show what was actually
+/// found in the input.
+///
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Context {
+    bytes: [u8; 16],
+    len: usize,
+}
+
+impl Context {
+    /// Create a context with the first bytes (up to 16) of the given data.
+    pub fn new(data: &[u8]) -> Context {
+        let len = data.len().min(16);
+        let mut bytes = [0; 16];
+        bytes[..len].copy_from_slice(&data[..len]);
+        Context { bytes, len }
+    }
+
+    /// Returns the bytes of this context.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.as_bytes().iter().enumerate() {
+            write!(f, "{}{:02x}", if i == 0 { "" } else { " " }, byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Context({})", self)
+    }
+}
+
+/// An error found while parsing a tape in strict mode
+///
+/// Each variant carries the index of the offending block, its byte offset in the parsed
+/// input and the bytes found at that offset, so the problem can be located in the
+/// original file.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum TapeError {
+    /// The input contains bytes that are not preceded by a block prefix
+    MissingPrefix { offset: usize, context: Context },
+    /// A file header block is too short to contain the file name
+    ShortHeader {
+        block: usize,
+        offset: usize,
+        context: Context,
+    },
+    /// A file header block is not followed by its data block
+    MissingData {
+        block: usize,
+        offset: usize,
+        context: Context,
+    },
+    /// A binary data block is too short to contain the address header
+    ShortBinData {
+        block: usize,
+        offset: usize,
+        context: Context,
+    },
+    /// An ASCII file ends without an EOF (`0x1a`) byte
+    MissingEof {
+        block: usize,
+        offset: usize,
+        context: Context,
+    },
+}
+
+impl TapeError {
+    /// Returns the index of the offending block, if any.
+    pub fn block(&self) -> Option<usize> {
+        match *self {
+            TapeError::MissingPrefix { .. } => None,
+            TapeError::ShortHeader { block, .. }
+            | TapeError::MissingData { block, .. }
+            | TapeError::ShortBinData { block, .. }
+            | TapeError::MissingEof { block, .. } => Some(block),
+        }
+    }
+
+    /// Returns the byte offset of the problem in the parsed input.
+    pub fn offset(&self) -> usize {
+        match *self {
+            TapeError::MissingPrefix { offset, .. }
+            | TapeError::ShortHeader { offset, .. }
+            | TapeError::MissingData { offset, .. }
+            | TapeError::ShortBinData { offset, .. }
+            | TapeError::MissingEof { offset, .. } => offset,
+        }
+    }
+
+    /// Returns the bytes found at the offset of the problem.
+    pub fn context(&self) -> &Context {
+        match self {
+            TapeError::MissingPrefix { context, .. }
+            | TapeError::ShortHeader { context, .. }
+            | TapeError::MissingData { context, .. }
+            | TapeError::ShortBinData { context, .. }
+            | TapeError::MissingEof { context, .. } => context,
+        }
+    }
+}
+
+impl fmt::Display for TapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self {
+            TapeError::MissingPrefix { .. } => "unexpected data without block prefix",
+            TapeError::ShortHeader { .. } => "header too short",
+            TapeError::MissingData { .. } => "header without data block",
+            TapeError::ShortBinData { .. } => "binary data too short to contain addresses",
+            TapeError::MissingEof { .. } => "ASCII file without EOF",
+        };
+        write!(f, "{} at offset 0x{:x}", what, self.offset())?;
+        if let Some(block) = self.block() {
+            write!(f, " (block {})", block)?;
+        }
+        write!(f, ": {}", self.context())
+    }
+}
+
+impl error::Error for TapeError {}
+
+impl From<TapeError> for io::Error {
+    fn from(e: TapeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A region of the input skipped by `Tape::parse_with_recovery()`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkippedRegion {
+    /// The error found at the start of the region
+    pub error: TapeError,
+    /// The byte range of the region in the parsed input
+    pub range: Range<usize>,
+}
+
+/// The type of a file contained in a tape
+///
+/// `FileType` values are parsed from strings (e.g., `"bin"`, `"basic"`) with `FromStr`
+/// and from host file extensions with `from_extension()`. They display as the lowercase
+/// names used in the `mcp` listing output.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileType {
+    Bin,
+    Basic,
+    Ascii,
+    Custom,
+}
+
+/// The kind of a file contained in a tape, as `File::kind()` returns it
+pub type FileKind = FileType;
+
+impl FileType {
+    /// Returns the file type corresponding to the given host file extension.
+    ///
+    /// Extensions `bin`, `bas` and `asc` (case insensitive) map to binary, Basic and ASCII
+    /// files respectively. Intel HEX files (`hex` and `ihx`) are converted into binary
+    /// files (see `ihex::to_bin()`). Any other extension maps to a custom file.
+    pub fn from_extension(ext: &str) -> FileType {
+        match ext.to_lowercase().as_str() {
+            "bin" | "hex" | "ihx" => FileType::Bin,
+            "bas" => FileType::Basic,
+            "asc" => FileType::Ascii,
+            _ => FileType::Custom,
+        }
+    }
+
+    /// Returns the host file extension for this file type, or `None` for custom files.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            FileType::Bin => Some("bin"),
+            FileType::Basic => Some("bas"),
+            FileType::Ascii => Some("asc"),
+            FileType::Custom => None,
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            FileType::Bin => "bin",
+            FileType::Basic => "basic",
+            FileType::Ascii => "ascii",
+            FileType::Custom => "custom",
+        })
+    }
+}
+
+impl FromStr for FileType {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<FileType> {
+        match s.to_lowercase().as_str() {
+            "bin" | "binary" => Ok(FileType::Bin),
+            "bas" | "basic" => Ok(FileType::Basic),
+            "asc" | "ascii" => Ok(FileType::Ascii),
+            "custom" => Ok(FileType::Custom),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown file type {:?}", s),
+            )),
+        }
+    }
+}
+
 /// A file contained in a tape
 ///
 /// Files stored in a tape can be one of:
@@ -104,6 +401,9 @@ impl Block {
 /// * Custom files. They contain arbitrary data generated by a program using direct calls to
 ///   casette IO addresses. Its contents cannot be processed from Basic but loaded from the
 ///   program that generates them in a custom way.
+/// * Headers. They are the header blocks of binary, Basic or ASCII files that are not
+///   followed by their data block, e.g. at the end of a truncated tape. They have the name
+///   and type of a file but no contents.
 ///
 /// `File` instances are generated in iteration from `files()` function of `Tape` type.
 ///
@@ -113,17 +413,136 @@ pub enum File<'a> {
     Basic(String, &'a [u8]),
     Ascii(String, Vec<&'a [u8]>),
     Custom(&'a [u8]),
+    Header(String, FileType),
 }
 
 impl<'a> File<'a> {
     /// Returns the name of this file, or `None` if it has no name.
     pub fn name(&self) -> Option<String> {
         match self {
-            &File::Bin(ref name, _, _, _, _) => {
-                Some(format!("{}.bin", File::normalized_name(name)))
+            File::Bin(name, _, _, _, _)
+            | File::Basic(name, _)
+            | File::Ascii(name, _)
+            | File::Header(name, _) => self
+                .file_type()
+                .extension()
+                .map(|ext| format!("{}.{}", File::normalized_name(name), ext)),
+            _ => None,
+        }
+    }
+
+    /// Returns the type of this file.
+    pub fn file_type(&self) -> FileType {
+        match self {
+            File::Bin(..) => FileType::Bin,
+            File::Basic(..) => FileType::Basic,
+            File::Ascii(..) => FileType::Ascii,
+            File::Custom(..) => FileType::Custom,
+            File::Header(_, file_type) => *file_type,
+        }
+    }
+
+    /// Returns the kind of this file, the same as `file_type()`.
+    pub fn kind(&self) -> FileKind {
+        self.file_type()
+    }
+
+    /// Returns the begin, end and start addresses of this file, or `None` if it is not a
+    /// binary file.
+    pub fn addresses(&self) -> Option<(u16, u16, u16)> {
+        match self {
+            File::Bin(_, begin, end, start, _) => Some((*begin as u16, *end as u16, *start as u16)),
+            _ => None,
+        }
+    }
+
+    /// Returns the addresses `BLOAD` loads this file with (begin, end and start), or `None`
+    /// if it is not a binary file.
+    ///
+    /// This is the same as `addresses()`, for code that reads the load information of any
+    /// file without matching on its type.
+    ///
+    pub fn load_info(&self) -> Option<(u16, u16, u16)> {
+        self.addresses()
+    }
+
+    /// Returns the number of bytes of `raw_data()`, without concatenating ASCII chunks.
+    pub fn size(&self) -> usize {
+        match self {
+            File::Bin(_, _, _, _, data) | File::Basic(_, data) | File::Custom(data) => data.len(),
+            File::Ascii(_, chunks) => chunks.iter().map(|c| c.len()).sum(),
+            File::Header(..) => 0,
+        }
+    }
+
+    /// Returns the bytes of this file exactly as they are stored in its data blocks.
+    ///
+    /// For binary files this includes the 6-byte address header (begin, end and start
+    /// addresses). For ASCII files this is the concatenation of all the chunks, including
+    /// the EOF padding of the last one.
+    pub fn raw_data(&self) -> Cow<'a, [u8]> {
+        match self {
+            File::Bin(_, _, _, _, data) => Cow::Borrowed(data),
+            File::Basic(_, data) => Cow::Borrowed(data),
+            File::Ascii(_, chunks) if chunks.len() == 1 => Cow::Borrowed(chunks[0]),
+            File::Ascii(_, chunks) => Cow::Owned(chunks.concat()),
+            File::Custom(data) => Cow::Borrowed(data),
+            File::Header(..) => Cow::Borrowed(&[]),
+        }
+    }
+
+    /// Returns the contents of this file, with the chunks of ASCII files concatenated.
+    ///
+    /// Binary files have their 6-byte address header stripped, which `raw_data()` keeps.
+    /// Unlike `payload()`, ASCII files keep their EOF padding.
+    ///
+    pub fn data(&self) -> Cow<'a, [u8]> {
+        match self {
+            File::Bin(_, _, _, _, data) => Cow::Borrowed(&data[6.min(data.len())..]),
+            _ => self.raw_data(),
+        }
+    }
+
+    /// Returns the payload of this file.
+    ///
+    /// The payload is the meaningful content of the file: for binary files the program bytes
+    /// that follow the 6-byte address header, and for ASCII files the text that precedes the
+    /// EOF (`0x1a`) byte. For Basic and custom files this is the same as `raw_data()`.
+    pub fn payload(&self) -> Cow<'a, [u8]> {
+        match self {
+            File::Bin(..) => self.data(),
+            File::Ascii(_, _) => {
+                let len = self.text_len().unwrap_or_default();
+                match self.raw_data() {
+                    Cow::Borrowed(data) => Cow::Borrowed(&data[..len]),
+                    Cow::Owned(mut data) => {
+                        data.truncate(len);
+                        Cow::Owned(data)
+                    }
+                }
+            }
+            _ => self.raw_data(),
+        }
+    }
+
+    /// Returns the length of the text of this file if it is an ASCII file.
+    ///
+    /// The text ends at the first EOF (`0x1a`) byte, as the MSX BIOS reads it, so the
+    /// padding of the last chunk and anything after the EOF byte are not counted. ASCII
+    /// files without EOF byte count the bytes of all their chunks.
+    ///
+    pub fn text_len(&self) -> Option<usize> {
+        match self {
+            File::Ascii(_, chunks) => {
+                let mut len = 0;
+                for chunk in chunks {
+                    match chunk.iter().position(|b| *b == 0x1a) {
+                        Some(eof) => return Some(len + eof),
+                        None => len += chunk.len(),
+                    }
+                }
+                Some(len)
             }
-            &File::Basic(ref name, _) => Some(format!("{}.bas", File::normalized_name(name))),
-            &File::Ascii(ref name, _) => Some(format!("{}.asc", File::normalized_name(name))),
             _ => None,
         }
     }
@@ -147,64 +566,240 @@ impl<'a> Iterator for Files<'a> {
     type Item = File<'a>;
 
     fn next(&mut self) -> Option<File<'a>> {
-        let nblocks = self.tape.blocks.len();
-        while self.i < nblocks {
-            let block = &self.tape.blocks[self.i];
-            if block.is_bin_header() {
-                let name = block.file_name().unwrap().to_string();
-                let content = &self.tape.blocks[self.i + 1].data_without_prefix();
-                let begin = LittleEndian::read_u16(&content[0..2]) as usize;
-                let end = LittleEndian::read_u16(&content[2..4]) as usize;
-                let start = LittleEndian::read_u16(&content[4..6]) as usize;
-                let data = &content[..];
-                self.i += 2;
-                return Some(File::Bin(name, begin, end, start, data));
-            } else if block.is_basic_header() {
-                let name = block.file_name().unwrap().to_string();
-                let content = &self.tape.blocks[self.i + 1].data_without_prefix();
-                self.i += 2;
-                return Some(File::Basic(name, &content[..]));
-            } else if block.is_ascii_header() {
-                let name = block.file_name().unwrap().to_string();
-                let mut data = Vec::<&[u8]>::new();
-                self.i += 1;
-                while {
-                    let chunk = &self.tape.blocks[self.i].data_without_prefix();
-                    data.push(chunk);
-                    self.i < nblocks && !chunk.contains(&0x1a)
-                } {
-                    self.i += 1
-                }
+        let blocks = &self.tape.blocks;
+        let block = blocks.get(self.i)?;
+        let name = || {
+            String::from_utf8_lossy(&block.data_without_prefix()[10..16])
+                .trim_end_matches(&['\0', ' '][..])
+                .to_string()
+        };
+        let next = match self.tape.check_file(self.i) {
+            Ok(next) if block.is_file_header() => next,
+            // The text of ASCII files without EOF is kept up to the next file
+            Err(TapeError::MissingEof { .. }) => (self.i + 1..blocks.len())
+                .find(|j| blocks[*j].is_file_header())
+                .unwrap_or(blocks.len()),
+            Err(TapeError::MissingData { .. }) => {
                 self.i += 1;
-                return Some(File::Ascii(name, data));
-            } else {
+                let file_type = if block.is_bin_header() {
+                    FileType::Bin
+                } else if block.is_basic_header() {
+                    FileType::Basic
+                } else {
+                    FileType::Ascii
+                };
+                return Some(File::Header(name(), file_type));
+            }
+            _ => {
                 self.i += 1;
-                return Some(File::Custom(&block.data_without_prefix()[..]));
+                return Some(File::Custom(block.data_without_prefix()));
             }
+        };
+        let name = name();
+        let content = blocks[self.i + 1].data_without_prefix();
+        let file = if block.is_bin_header() {
+            let begin = LittleEndian::read_u16(&content[0..2]) as usize;
+            let end = LittleEndian::read_u16(&content[2..4]) as usize;
+            let start = LittleEndian::read_u16(&content[4..6]) as usize;
+            File::Bin(name, begin, end, start, content)
+        } else if block.is_basic_header() {
+            File::Basic(name, content)
+        } else {
+            let chunks = blocks[self.i + 1..next].iter();
+            File::Ascii(name, chunks.map(|b| b.data_without_prefix()).collect())
+        };
+        self.i = next;
+        Some(file)
+    }
+}
+
+/// A file of a tape along with the place it takes in the tape
+///
+/// Entries are plain views of `File` values that own their fields, so they can be kept
+/// after the tape is gone and written as records (e.g. in JSON or CSV format). They are
+/// generated by `entries()` function of `Tape` type.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Index of the file in the tape
+    pub index: usize,
+    pub file_type: FileType,
+    /// Name of the file, with the extension of its type (see `File::name()`)
+    pub name: Option<String>,
+    /// Bytes stored in the data blocks (see `File::raw_data()`)
+    pub size: usize,
+    /// Bytes of payload (see `File::payload()`)
+    pub payload_size: usize,
+    /// Begin, end and start addresses of binary files
+    pub addresses: Option<(usize, usize, usize)>,
+    /// Indices of the blocks of the file, header included
+    pub blocks: Range<usize>,
+    /// Byte offset of the first block in the on-disk representation of the tape
+    pub offset: usize,
+    /// Bytes taken by the blocks in the on-disk representation, alignment included
+    pub length: usize,
+}
+
+/// Statistics about the contents of a tape
+///
+/// Statistics of several tapes can be aggregated with `merge()`, e.g. to summarize a
+/// whole collection.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Number of files of each type
+    pub files: BTreeMap<FileType, usize>,
+    /// Number of blocks of each data size (excluding the prefix bytes)
+    pub block_sizes: BTreeMap<usize, usize>,
+    /// Total number of blocks
+    pub blocks: usize,
+    /// Bytes taken by block prefixes
+    pub prefix_bytes: usize,
+    /// Bytes added to align binary files or terminate ASCII files
+    pub padding_bytes: usize,
+    /// Bytes of file payload (see `File::payload()`)
+    pub payload_bytes: usize,
+    /// Total size in bytes of the serialized tapes
+    pub total_bytes: usize,
+}
+
+impl Stats {
+    /// Returns the total number of files.
+    pub fn file_count(&self) -> usize {
+        self.files.values().sum()
+    }
+
+    /// Add the statistics of another tape to these ones.
+    pub fn merge(&mut self, other: &Stats) {
+        for (file_type, n) in &other.files {
+            *self.files.entry(*file_type).or_insert(0) += n;
+        }
+        for (size, n) in &other.block_sizes {
+            *self.block_sizes.entry(*size).or_insert(0) += n;
         }
-        None
+        self.blocks += other.blocks;
+        self.prefix_bytes += other.prefix_bytes;
+        self.padding_bytes += other.padding_bytes;
+        self.payload_bytes += other.payload_bytes;
+        self.total_bytes += other.total_bytes;
     }
 }
 
+/// How `Tape::extend_from_with()` handles the files of the other tape
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MergePolicy {
+    /// Skip the files whose name and payload match those of a file already in the tape
+    pub dedup: bool,
+    /// Rename the files whose name matches that of a file already in the tape
+    pub rename: bool,
+}
+
+/// A file that was not appended as is by `Tape::extend_from_with()`
+///
+/// Files are identified by their index in the other tape.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Merged {
+    /// The file was skipped because it was a duplicate: `(index, name)`
+    Skipped(usize, Option<String>),
+    /// The file was renamed to avoid a name collision: `(index, old name, new name)`
+    Renamed(usize, String, String),
+}
+
+/// Where block prefixes are searched for when a tape is read
+///
+/// Block prefixes are expected at offsets aligned to 8 bytes, where `mcp` and most tools
+/// write them. Some tools do not pad the blocks, so their prefixes are found at any offset.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scan {
+    /// Only at offsets aligned to 8 bytes; prefixes found elsewhere are data of a block
+    #[default]
+    Aligned,
+    /// At any offset where a block is expected: before a file header, after a file header
+    /// or after a full chunk of an ASCII file. Prefixes found elsewhere at unaligned
+    /// offsets are data of a block (e.g., a binary program that contains the prefix bytes)
+    Unaligned,
+}
+
 /// An MSX tape.
 ///
 /// A tape is a sequence of byte blocks (see `Blocks` for more details). The blocks may be
 /// grouped such as the tape is seen as a sequence of files through `files()` method.
 ///
-#[derive(Debug)]
+/// Parsed tapes keep any bytes found before the first block (see `preamble()`), so they
+/// are serialized back byte for byte unless they are explicitly normalized with
+/// `normalize()`.
+///
+#[derive(Debug, Default)]
 pub struct Tape {
+    preamble: Vec<u8>,
     blocks: Vec<Block>,
 }
 
 impl Tape {
     /// Create a new empty tape.
     pub fn new() -> Tape {
-        Tape { blocks: vec![] }
+        Tape::default()
     }
 
+    #[cfg(feature = "fs")]
     pub fn from_file(filename: &Path) -> io::Result<Tape> {
+        Tape::from_file_with(filename, Scan::Aligned)
+    }
+
+    /// Read a `Tape` instance from the given file, searching block prefixes as `scan` says.
+    #[cfg(feature = "fs")]
+    pub fn from_file_with(filename: &Path, scan: Scan) -> io::Result<Tape> {
         let mut file = fs::File::open(filename)?;
-        Tape::read(&mut file)
+        Tape::read_with(&mut file, scan)
+    }
+
+    /// Append the blocks of this tape to the tape stored in the given file.
+    ///
+    /// The blocks are written at the end of the file without rewriting its current
+    /// contents, padded as `to_bytes()` pads them. If the file does not exist, it is
+    /// created. The existing file must start with a block prefix, or an error of kind
+    /// `InvalidData` is returned and the file is left untouched.
+    ///
+    /// If the length of the file is not a multiple of 8, the appended blocks would not start
+    /// at an aligned offset. In that case the whole tape is read and written back with the
+    /// new blocks, padded as `to_bytes()` pads them.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn append_to_path(&self, path: &Path) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let len = file.seek(SeekFrom::End(0))?;
+        if len > 0 {
+            let mut prefix = [0; 8];
+            file.seek(SeekFrom::Start(0))?;
+            if file.read_exact(&mut prefix).is_err() || prefix != PREFIX {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("cannot append to {:?}: it is not a CAS file", path),
+                ));
+            }
+            file.seek(SeekFrom::End(0))?;
+        }
+        if len % 8 != 0 {
+            file.seek(SeekFrom::Start(0))?;
+            let mut tape = Tape::read_with(&mut file, Scan::Unaligned)?;
+            tape.extend_from(self);
+            let bytes = tape.to_bytes();
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&bytes)?;
+            file.set_len(bytes.len() as u64)?;
+            return file.flush();
+        }
+        for segment in self.segments() {
+            file.write_all(segment)?;
+        }
+        file.flush()
     }
 
     /// Read a `Tape` instance from the given `Read` object.
@@ -213,11 +808,22 @@ impl Tape {
     /// contents of the `Read` passed as argument (e.g., a file), or an `std::io::Error`
     /// if there is an error while reading.
     ///
-    #[allow(dead_code)]
+    /// Contents compressed with gzip (e.g., `.cas.gz` files) are detected by their magic
+    /// bytes and decompressed transparently.
+    ///
     pub fn read<R: Read>(input: &mut R) -> io::Result<Tape> {
+        Tape::read_with(input, Scan::Aligned)
+    }
+
+    /// Read a `Tape` instance from the given `Read` object as `read()` does, searching
+    /// block prefixes as `scan` says.
+    pub fn read_with<R: Read>(input: &mut R, scan: Scan) -> io::Result<Tape> {
         let mut bytes: Vec<u8> = vec![];
         input.read_to_end(&mut bytes)?;
-        Ok(Tape::from_bytes(&bytes[..]))
+        if gzip::is_gzip(&bytes) {
+            bytes = gzip::decompress(&bytes)?;
+        }
+        Ok(Tape::from_buffer(Arc::new(bytes), scan))
     }
 
     /// Read a `Tape` instance from the given bytes.
@@ -225,9 +831,131 @@ impl Tape {
     /// This function returns a new `Tape` instance as result of processing the bytes passed
     /// as argument.
     pub fn from_bytes(bytes: &[u8]) -> Tape {
+        Tape::from_bytes_with(bytes, Scan::Aligned)
+    }
+
+    /// Read a `Tape` instance from the given bytes, searching block prefixes as `scan` says.
+    ///
+    /// With `Scan::Unaligned`, the blocks of tapes written by tools that do not pad them
+    /// are found instead of being read as the data of the previous block. They are padded
+    /// when the tape is written, so the result is an aligned tape.
+    ///
+    pub fn from_bytes_with(bytes: &[u8], scan: Scan) -> Tape {
+        Tape::from_buffer(Arc::new(bytes.to_vec()), scan)
+    }
+
+    /// Read a `Tape` instance from the given bytes, taking ownership of them.
+    ///
+    /// The blocks of the tape refer to the given buffer instead of copies of it, so this
+    /// function avoids the copy `from_bytes()` makes when the bytes are no longer needed
+    /// by the caller (e.g., when converting many files read into memory).
+    ///
+    pub fn from_vec(bytes: Vec<u8>) -> Tape {
+        Tape::from_buffer(Arc::new(bytes), Scan::Aligned)
+    }
+
+    /// Create a tape made of the given blocks, with no preamble.
+    pub(crate) fn from_blocks(blocks: Vec<Block>) -> Tape {
         Tape {
-            blocks: Tape::parse_blocks(bytes),
+            preamble: vec![],
+            blocks,
+        }
+    }
+
+    /// Parse a `Tape` instance from the given bytes validating its structure.
+    ///
+    /// Unlike `from_bytes()`, which accepts any input, this function returns a `TapeError`
+    /// if the bytes do not conform a well-formed tape: data outside blocks, truncated
+    /// headers, headers without data or ASCII files without EOF.
+    ///
+    pub fn parse(bytes: &[u8]) -> Result<Tape, TapeError> {
+        let tape = Tape::from_bytes(bytes);
+        if !bytes.is_empty()
+            && !bytes.starts_with(&[0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74])
+        {
+            return Err(TapeError::MissingPrefix {
+                offset: 0,
+                context: Context::new(bytes),
+            });
         }
+        tape.validate()?;
+        Ok(tape)
+    }
+
+    /// Parse a `Tape` instance from the given bytes, skipping the malformed regions.
+    ///
+    /// This is the recovery mode of `parse()`: instead of failing at the first malformed
+    /// file, the parser skips forward to the next block prefix that starts a file header
+    /// and continues from there. Each skipped region is returned along with the error
+    /// that caused it, so a damaged block does not make the rest of the tape inaccessible.
+    /// The returned tape contains the well-formed files only, and no preamble.
+    ///
+    pub fn parse_with_recovery(bytes: &[u8]) -> (Tape, Vec<SkippedRegion>) {
+        let mut tape = Tape::from_bytes(bytes);
+        let offsets: Vec<usize> = tape.blocks.iter().map(|b| b.range.start).collect();
+        let mut skipped = vec![];
+        let first = offsets.first().copied().unwrap_or(bytes.len());
+        if first > 0 {
+            skipped.push(SkippedRegion {
+                error: TapeError::MissingPrefix {
+                    offset: 0,
+                    context: Context::new(bytes),
+                },
+                range: 0..first,
+            });
+        }
+        let mut keep = vec![];
+        let mut i = 0;
+        while i < tape.blocks.len() {
+            match tape.check_file(i) {
+                Ok(next) => {
+                    keep.push(i..next);
+                    i = next;
+                }
+                Err(error) => {
+                    let next = (i + 1..tape.blocks.len())
+                        .find(|j| tape.blocks[*j].is_file_header())
+                        .unwrap_or(tape.blocks.len());
+                    let end = offsets.get(next).copied().unwrap_or(bytes.len());
+                    skipped.push(SkippedRegion {
+                        error,
+                        range: offsets[i]..end,
+                    });
+                    i = next;
+                }
+            }
+        }
+        tape.preamble.clear();
+        tape.rebuild(keep);
+        (tape, skipped)
+    }
+
+    /// Returns the problems found in the structure of this tape.
+    ///
+    /// This is the lenient counterpart of `parse()`: every malformed file is reported
+    /// instead of only the first one, and the tape is still usable. `files()` returns
+    /// the blocks of malformed files as custom files, except the text of ASCII files
+    /// without EOF, which is kept up to the next file.
+    ///
+    pub fn problems(&self) -> Vec<TapeError> {
+        let mut problems = vec![];
+        if !self.preamble.is_empty() {
+            problems.push(TapeError::MissingPrefix {
+                offset: 0,
+                context: Context::new(&self.preamble),
+            });
+        }
+        let mut i = 0;
+        while i < self.blocks.len() {
+            match self.check_file(i) {
+                Ok(next) => i = next,
+                Err(e) => {
+                    problems.push(e);
+                    i += 1;
+                }
+            }
+        }
+        problems
     }
 
     /// Returns the blocks of this tape.
@@ -235,11 +963,140 @@ impl Tape {
         &self.blocks[..]
     }
 
+    /// Returns the bytes found before the first block when this tape was parsed.
+    ///
+    /// These bytes are not part of any block, so they are ignored by the rest of the
+    /// operations. They are only kept to serialize the tape back as it was read.
+    ///
+    pub fn preamble(&self) -> &[u8] {
+        &self.preamble
+    }
+
+    /// Normalize the on-disk representation of this tape.
+    ///
+    /// The preamble is dropped and the last block is padded with zeroes so its length is
+    /// a multiple of 8, as if the tape was built with the `append_*()` methods. Returns
+    /// the number of bytes added (positive) or removed (negative).
+    ///
+    pub fn normalize(&mut self) -> isize {
+        let removed = std::mem::take(&mut self.preamble).len();
+        let added = self.extend_last_block(8, 0);
+        added as isize - removed as isize
+    }
+
+    /// Returns the size in bytes of the on-disk representation of this tape.
+    ///
+    /// This is the exact number of bytes that result from dumping every block
+    /// (prefix bytes, data and alignment padding) without actually serializing them.
+    pub fn serialized_len(&self) -> usize {
+        self.segments().iter().map(|s| s.len()).sum()
+    }
+
+    /// Write the on-disk representation of this tape to the given `Write` object.
+    ///
+    /// The bytes written are the same `reader()` produces, so a tape that is read and
+    /// written back is reproduced byte by byte. Blocks are padded with zeros as needed
+    /// to start at offsets aligned to 8 bytes, where they are found when the tape is read
+    /// again.
+    ///
+    pub fn write<W: Write + ?Sized>(&self, output: &mut W) -> io::Result<()> {
+        for segment in self.segments() {
+            output.write_all(segment)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the on-disk representation of this tape, as written by `write()`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.serialized_len());
+        for segment in self.segments() {
+            bytes.extend_from_slice(segment);
+        }
+        bytes
+    }
+
+    /// Returns a seekable reader of the on-disk representation of this tape.
+    ///
+    /// The reader produces the same bytes `append_to_path()` would write to an empty file,
+    /// reading them from the blocks on demand instead of serializing the whole tape.
+    ///
+    pub fn reader(&self) -> TapeReader<'_> {
+        let segments = self.segments();
+        let mut offsets = Vec::with_capacity(segments.len());
+        let mut offset = 0;
+        for segment in &segments {
+            offsets.push(offset);
+            offset += segment.len();
+        }
+        TapeReader {
+            segments,
+            offsets,
+            len: offset as u64,
+            pos: 0,
+        }
+    }
+
+    /// Returns the entries of the files of this tape, in the same order as `files()`.
+    pub fn entries(&self) -> Vec<Entry> {
+        let offsets = self.block_offsets();
+        let mut entries = vec![];
+        for (blocks, file) in self.file_extents() {
+            entries.push(Entry {
+                index: entries.len(),
+                file_type: file.file_type(),
+                name: file.name(),
+                size: file.size(),
+                payload_size: file.payload().len(),
+                addresses: file
+                    .addresses()
+                    .map(|(b, e, s)| (b as usize, e as usize, s as usize)),
+                offset: offsets[blocks.start],
+                length: offsets[blocks.end] - offsets[blocks.start],
+                blocks,
+            });
+        }
+        entries
+    }
+
+    /// Returns statistics about the files and blocks of this tape.
+    ///
+    /// The padding of binary files is the data that follows the program bytes, and the
+    /// padding of ASCII files is everything from the EOF byte on. The padding of Basic
+    /// and custom files cannot be told apart from their contents, so it is not counted.
+    ///
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            blocks: self.blocks.len(),
+            prefix_bytes: self.blocks.len() * 8,
+            total_bytes: self.serialized_len(),
+            ..Stats::default()
+        };
+        for block in &self.blocks {
+            *stats
+                .block_sizes
+                .entry(block.data_without_prefix().len())
+                .or_insert(0) += 1;
+        }
+        for file in self.files() {
+            *stats.files.entry(file.file_type()).or_insert(0) += 1;
+            let payload = file.payload().len();
+            stats.payload_bytes += payload;
+            stats.padding_bytes += match file {
+                File::Bin(_, begin, end, _, _) if end >= begin => {
+                    payload.saturating_sub(end - begin + 1)
+                }
+                File::Ascii(..) => file.size() - payload,
+                _ => 0,
+            };
+        }
+        stats
+    }
+
     /// Return the files contained in the tape.
     ///
     /// This function returns an `Iterator` over the files found in the tape blocks.
     ///
-    pub fn files(&self) -> Files {
+    pub fn files(&self) -> Files<'_> {
         Files { tape: self, i: 0 }
     }
 
@@ -254,13 +1111,12 @@ impl Tape {
     ///
     pub fn append_bin(&mut self, name: &[u8; 6], data: &[u8]) -> io::Result<usize> {
         // Skip bin file ID byte if present
-        let bytes = if data[0] == 0xfe {
-            &data[1..]
-        } else {
-            &data[..]
+        let bytes = match data.split_first() {
+            Some((0xfe, rest)) => rest,
+            _ => data,
         };
 
-        Self::validate_bin(&bytes)?;
+        Self::validate_bin(bytes)?;
 
         let hblock = Block::from_data(&[
             0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, 0xd0, name[0], name[1], name[2],
@@ -272,6 +1128,43 @@ impl Tape {
         Ok(self.append_block(dblock, 8, 0))
     }
 
+    /// Append a binary file to this tape, given its payload and addresses
+    ///
+    /// Unlike `append_bin()`, `payload` is the raw program, without the ID byte and the
+    /// address header, which is generated from the given `begin` and `start` addresses.
+    /// The end address is that of the last byte of the payload loaded at `begin`.
+    ///
+    /// Fails with an error of kind `InvalidInput` if the payload is empty or does not fit
+    /// in memory from `begin` on.
+    ///
+    pub fn append_bin_with_addresses(
+        &mut self,
+        name: &[u8; 6],
+        begin: u16,
+        start: u16,
+        payload: &[u8],
+    ) -> io::Result<usize> {
+        let end = begin as usize + payload.len().max(1) - 1;
+        if payload.is_empty() || end > 0xffff {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid binary file: {} bytes do not fit in memory from {:04X}",
+                    payload.len(),
+                    begin
+                ),
+            ));
+        }
+        // The ID byte keeps `append_bin()` from taking a begin address like 0x80fe for it
+        let mut data = Vec::with_capacity(payload.len() + 7);
+        data.push(0xfe);
+        for addr in &[begin, end as u16, start] {
+            data.extend_from_slice(&addr.to_le_bytes());
+        }
+        data.extend_from_slice(payload);
+        self.append_bin(name, &data)
+    }
+
     /// Append a binary file to this tape
     ///
     /// This method appends a binary file to the tape by generating the corresponding
@@ -283,13 +1176,12 @@ impl Tape {
     ///
     pub fn append_basic(&mut self, name: &[u8; 6], data: &[u8]) -> io::Result<usize> {
         // Skip tokenized basic file ID byte if present
-        let bytes = if data[0] == 0xff {
-            &data[1..]
-        } else {
-            &data[..]
+        let bytes = match data.split_first() {
+            Some((0xff, rest)) => rest,
+            _ => data,
         };
 
-        Self::validate_basic(&bytes)?;
+        Self::validate_basic(bytes)?;
 
         let hblock = Block::from_data(&[
             0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, 0xd3, name[0], name[1], name[2],
@@ -314,7 +1206,12 @@ impl Tape {
     /// EOF byte. As result, the last block is padded with EOFs until it occupies 256 bytes.
     /// If the text length is a multiple of 256, the last block is 256 EOF bytes.
     ///
+    /// The text ends at the first EOF byte of `data`, if any. Whatever follows it would
+    /// never be read by the MSX, and its chunks would be taken as custom files, so it is
+    /// left out.
+    ///
     pub fn append_ascii(&mut self, name: &[u8; 6], data: &[u8]) -> io::Result<usize> {
+        let data = &data[..data.iter().position(|b| *b == 0x1a).unwrap_or(data.len())];
         let hblock = Block::from_data(&[
             0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, name[0], name[1], name[2],
             name[3], name[4], name[5],
@@ -323,10 +1220,7 @@ impl Tape {
 
         let mut padding = 0;
         for chunk in data.chunks(256) {
-            let dblock = Block::from_data(chunk);
-            if chunk.len() < 256 {
-                padding += self.append_block(dblock, 256, 0x1a);
-            }
+            padding += self.append_block(Block::from_data(chunk), 256, 0x1a);
         }
         if padding == 0 {
             // Last block had no free space to pad it with EOFs.
@@ -336,38 +1230,609 @@ impl Tape {
         Ok(padding)
     }
 
+    /// Append a Basic loader for the binary file that follows it in the tape.
+    ///
+    /// The loader is an ASCII program named `name` with the single line
+    /// `10 BLOAD"CAS:",R`, so `RUN"CAS:"` loads it and it loads and runs the next binary
+    /// file. MSX Basic only runs ASCII programs from tape with `RUN`, so the loader is not
+    /// tokenized. Returns the number of EOF bytes the program was padded with.
+    ///
+    pub fn append_loader_for(&mut self, name: &[u8; 6]) -> io::Result<usize> {
+        self.append_ascii(name, b"10 BLOAD\"CAS:\",R\r\n")
+    }
+
     /// Append a custom file to the tape.
     pub fn append_custom(&mut self, data: &[u8]) -> io::Result<usize> {
         Ok(self.append_block(Block::from_data(data), 8, 0))
     }
 
-    fn parse_blocks(bytes: &[u8]) -> Vec<Block> {
+    /// Replace the program of the Basic file at the given index.
+    ///
+    /// The header of the file is kept, so the file keeps its name and position in the
+    /// tape. Returns the number of bytes added to align the new data block, or an error of
+    /// kind `InvalidInput` if there is no Basic file at `index` or `data` is not a valid
+    /// tokenized program.
+    ///
+    pub fn replace_basic(&mut self, index: usize, data: &[u8]) -> io::Result<usize> {
+        // Skip tokenized basic file ID byte if present
+        let bytes = if data.first() == Some(&0xff) {
+            &data[1..]
+        } else {
+            data
+        };
+
+        Self::validate_basic(bytes)?;
+
+        let block = match self.file_extents().get(index) {
+            Some((blocks, File::Basic(..))) => blocks.start + 1,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("there is no Basic file at index {}", index),
+                ))
+            }
+        };
+        let mut dblock = Block::from_data(bytes);
+        let mut padding = 0;
+        while !dblock.data_without_prefix().len().is_multiple_of(8) {
+            dblock.push(0);
+            padding += 1;
+        }
+        self.blocks[block] = dblock;
+        Ok(padding)
+    }
+
+    /// Replace the file at the given index with a file of another type.
+    ///
+    /// The new file keeps the name of the old one and takes its position in the tape. Its
+    /// blocks are generated from `data` as `append_bin()`, `append_basic()`,
+    /// `append_ascii()` or `append_custom()` would do, so ASCII files are chunked and
+    /// padded with EOFs again. Returns the number of padding bytes of the new file, or an
+    /// error of kind `InvalidInput` if there is no named file at `index` or `data` is not
+    /// valid for `file_type`.
+    ///
+    pub fn replace_file(
+        &mut self,
+        index: usize,
+        file_type: FileType,
+        data: &[u8],
+    ) -> io::Result<usize> {
+        let (blocks, name) = match self.file_extents().get(index) {
+            Some((blocks, file)) if file.name().is_some() => {
+                let mut name = [0; 6];
+                name.copy_from_slice(&self.blocks[blocks.start].data_without_prefix()[10..16]);
+                (blocks.clone(), name)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("there is no named file at index {}", index),
+                ))
+            }
+        };
+        let mut file = Tape::new();
+        let padding = match file_type {
+            FileType::Bin => file.append_bin(&name, data),
+            FileType::Basic => file.append_basic(&name, data),
+            FileType::Ascii => file.append_ascii(&name, data),
+            FileType::Custom => file.append_custom(data),
+        }?;
+        self.blocks.splice(blocks, file.blocks);
+        Ok(padding)
+    }
+
+    /// Remove the file at the given index from this tape.
+    ///
+    /// All the blocks of the file, header and data, are removed. Fails with an error of
+    /// kind `InvalidInput` if there is no file at that index.
+    ///
+    pub fn remove_file(&mut self, index: usize) -> io::Result<()> {
+        let blocks = match self.file_extents().get(index) {
+            Some((blocks, _)) => blocks.clone(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("there is no file at index {}", index),
+                ))
+            }
+        };
+        self.blocks.drain(blocks);
+        Ok(())
+    }
+
+    /// Rename the file at the given index.
+    ///
+    /// The new name is written in the header block of the file, and the rest of its
+    /// blocks are kept as they are. Fails with an error of kind `InvalidInput` if there
+    /// is no named file at that index.
+    ///
+    pub fn rename_file(&mut self, index: usize, name: &[u8; 6]) -> io::Result<()> {
+        let header = match self.file_extents().get(index) {
+            Some((blocks, file)) if file.name().is_some() => blocks.start,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("there is no named file at index {}", index),
+                ))
+            }
+        };
+        let mut data = self.blocks[header].data_without_prefix().to_vec();
+        data[10..16].copy_from_slice(name);
+        self.blocks[header] = Block::from_data(&data);
+        Ok(())
+    }
+
+    /// Move the file at index `from` to index `to`, shifting the files in between.
+    ///
+    /// All the blocks of the file, header and data, are moved together, and the rest of
+    /// the blocks keep their order. Fails with an error of kind `InvalidInput` if there is
+    /// no file at either index.
+    ///
+    pub fn move_file(&mut self, from: usize, to: usize) -> io::Result<()> {
+        let extents: Vec<Range<usize>> = self
+            .file_extents()
+            .into_iter()
+            .map(|(blocks, _)| blocks)
+            .collect();
+        if let Some(index) = [from, to].iter().copied().find(|i| *i >= extents.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("there is no file at index {}", index),
+            ));
+        }
+        if from == to {
+            return Ok(());
+        }
+        let moved: Vec<Block> = self.blocks.drain(extents[from].clone()).collect();
+        let at = if to < from {
+            extents[to].start
+        } else {
+            extents[to].end - moved.len()
+        };
+        self.blocks.splice(at..at, moved);
+        Ok(())
+    }
+
+    /// Overwrite the payload of the file at the given index with `bytes` from `offset` on.
+    ///
+    /// The payload is the one `File::payload()` returns, up to the end address for binary
+    /// files. Bytes past its end extend it, moving the end address of binary files, so
+    /// `offset` may be at most the length of the payload. The file is rebuilt with its
+    /// name, type and addresses as the `append_*()` methods build it, so its blocks are
+    /// padded again. Returns the number of bytes added to align them, or an error of kind
+    /// `InvalidInput` if there is no file at `index`, `offset` is past the end of the
+    /// payload or a binary file would no longer fit in memory.
+    ///
+    pub fn patch_file(&mut self, index: usize, offset: usize, bytes: &[u8]) -> io::Result<usize> {
+        let (blocks, name, file) = match self.file_extents().into_iter().nth(index) {
+            Some((blocks, file)) => {
+                let mut name = [0; 6];
+                if file.name().is_some() {
+                    name.copy_from_slice(&self.blocks[blocks.start].data_without_prefix()[10..16]);
+                }
+                (blocks, name, file)
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("there is no file at index {}", index),
+                ))
+            }
+        };
+        let mut payload = match file {
+            File::Bin(_, begin, end, _, data) => {
+                let len = (end + 1)
+                    .saturating_sub(begin)
+                    .min(data.len().saturating_sub(6));
+                data[6.min(data.len())..6.min(data.len()) + len].to_vec()
+            }
+            _ => file.payload().into_owned(),
+        };
+        if offset > payload.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "offset 0x{:x} is past the end of the file ({} bytes)",
+                    offset,
+                    payload.len()
+                ),
+            ));
+        }
+        let end = (offset + bytes.len()).max(payload.len());
+        payload.resize(end, 0);
+        payload[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        let mut patched = Tape::new();
+        let padding = match file {
+            File::Bin(_, begin, _, start, _) => {
+                patched.append_bin_with_addresses(&name, begin as u16, start as u16, &payload)
+            }
+            File::Basic(..) => patched.append_basic(&name, &payload),
+            File::Ascii(..) => patched.append_ascii(&name, &payload),
+            File::Custom(_) => patched.append_custom(&payload),
+            File::Header(..) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("the file at index {} has no data block", index),
+                ))
+            }
+        }?;
+        self.blocks.splice(blocks, patched.blocks);
+        Ok(padding)
+    }
+
+    /// Retain only the files that match the given predicate.
+    ///
+    /// Every file for which `f` returns `false` is removed from the tape, together with
+    /// all its blocks (header and data).
+    ///
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&File) -> bool,
+    {
+        let keep: Vec<Range<usize>> = self
+            .file_extents()
+            .into_iter()
+            .filter(|(_, file)| f(file))
+            .map(|(blocks, _)| blocks)
+            .collect();
+        self.rebuild(keep);
+    }
+
+    /// Sort the files of this tape with the given comparator function.
+    ///
+    /// The sort is stable, and the blocks of each file are kept contiguous in the
+    /// resulting tape.
+    ///
+    pub fn sort_files_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&File, &File) -> Ordering,
+    {
+        let mut extents = self.file_extents();
+        extents.sort_by(|(_, a), (_, b)| compare(a, b));
+        let order: Vec<Range<usize>> = extents.into_iter().map(|(blocks, _)| blocks).collect();
+        self.rebuild(order);
+    }
+
+    /// Sort the files of this tape with the given key extraction function.
+    ///
+    /// For instance, `tape.sort_files_by_key(|f| f.file_type())` groups the files by type
+    /// and `tape.sort_files_by_key(|f| f.size())` sorts them by size.
+    ///
+    pub fn sort_files_by_key<K, F>(&mut self, mut f: F)
+    where
+        K: Ord,
+        F: FnMut(&File) -> K,
+    {
+        self.sort_files_by(|a, b| f(a).cmp(&f(b)))
+    }
+
+    /// Remove the files whose name and data match those of an earlier file.
+    ///
+    /// The data is compared as stored in the tape, so binary files loaded at different
+    /// addresses are not duplicates even if their program bytes are the same.
+    ///
+    /// This function returns the removed entries as pairs of file index (relative to the
+    /// tape before removal) and file name (`None` for custom files).
+    ///
+    pub fn dedup_files(&mut self) -> Vec<(usize, Option<String>)> {
+        let mut removed = vec![];
+        let mut keep: Vec<Range<usize>> = vec![];
+        {
+            let extents = self.file_extents();
+            for (i, (blocks, file)) in extents.iter().enumerate() {
+                let duplicated = extents[..i].iter().any(|(_, prev)| {
+                    prev.name() == file.name() && prev.raw_data() == file.raw_data()
+                });
+                if duplicated {
+                    removed.push((i, file.name()));
+                } else {
+                    keep.push(blocks.clone());
+                }
+            }
+        }
+        self.rebuild(keep);
+        removed
+    }
+
+    /// Append the file at the given index of another tape to this one.
+    ///
+    /// The blocks of the file, header and data, are shared with `other`, not copied.
+    /// Fails with an error of kind `InvalidInput` if there is no file at that index.
+    ///
+    pub fn append_file_from(&mut self, other: &Tape, index: usize) -> io::Result<()> {
+        match other.file_extents().get(index) {
+            Some((blocks, _)) => {
+                self.blocks.extend_from_slice(&other.blocks[blocks.clone()]);
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("there is no file at index {}", index),
+            )),
+        }
+    }
+
+    /// Append all the files of another tape to this one.
+    ///
+    /// The blocks of `other` are shared, not copied. This is the same as calling
+    /// `extend_from_with()` with the default policy, which appends every file as is.
+    ///
+    pub fn extend_from(&mut self, other: &Tape) {
+        self.extend_from_with(other, MergePolicy::default());
+    }
+
+    /// Append the files of another tape to this one according to the given policy.
+    ///
+    /// Duplicates and name collisions are checked against the files already in this tape
+    /// as well as the files appended before. Renamed files get a numeric suffix that
+    /// replaces the end of their name if needed (e.g. `GAME` becomes `GAME1` and
+    /// `LOADER` becomes `LOADE1`). Returns the files that were skipped or renamed.
+    ///
+    pub fn extend_from_with(&mut self, other: &Tape, policy: MergePolicy) -> Vec<Merged> {
+        let mut merged = vec![];
+        let mut names: Vec<String> = self.files().filter_map(|f| f.name()).collect();
+        let mut contents: Vec<(Option<String>, Vec<u8>)> = if policy.dedup {
+            self.files()
+                .map(|f| (f.name(), f.payload().into_owned()))
+                .collect()
+        } else {
+            vec![]
+        };
+        for (i, (blocks, file)) in other.file_extents().into_iter().enumerate() {
+            let name = file.name();
+            if policy.dedup {
+                let payload = file.payload();
+                if contents.iter().any(|(n, p)| *n == name && *p == *payload) {
+                    merged.push(Merged::Skipped(i, name));
+                    continue;
+                }
+                contents.push((name.clone(), payload.into_owned()));
+            }
+            let mut new_blocks: Vec<Block> = other.blocks[blocks].to_vec();
+            if let Some(name) = name {
+                let name = if policy.rename && names.contains(&name) {
+                    let (header, new_name) = Tape::renamed_header(&new_blocks[0], &file, &names);
+                    new_blocks[0] = header;
+                    merged.push(Merged::Renamed(i, name, new_name.clone()));
+                    new_name
+                } else {
+                    name
+                };
+                names.push(name);
+            }
+            self.blocks.extend(new_blocks);
+        }
+        merged
+    }
+
+    /// Returns a copy of the given header block with a name not found in `names`.
+    fn renamed_header(header: &Block, file: &File, names: &[String]) -> (Block, String) {
+        let base = header.file_name().unwrap_or("");
+        let ext = file.file_type().extension().unwrap_or("");
+        for n in 1.. {
+            let suffix = n.to_string();
+            let mut stem = base.to_string();
+            while stem.len() + suffix.len() > 6 {
+                stem.pop();
+            }
+            let candidate = format!("{}{}", stem, suffix);
+            let full_name = format!("{}.{}", candidate, ext);
+            if !names.contains(&full_name) {
+                let (bytes, _) = file_name(&candidate);
+                let mut data = header.data_without_prefix().to_vec();
+                data[10..16].copy_from_slice(&bytes);
+                return (Block::from_data(&data), full_name);
+            }
+        }
+        unreachable!("there is always a free name")
+    }
+
+    /// Remove the blocks of this tape past the first `len` ones.
+    pub(crate) fn truncate_blocks(&mut self, len: usize) {
+        self.blocks.truncate(len);
+    }
+
+    /// Returns the files of this tape along with the range of blocks each one occupies.
+    pub(crate) fn file_extents(&self) -> Vec<(Range<usize>, File<'_>)> {
+        let mut extents = vec![];
+        let mut files = self.files();
+        let mut from = files.i;
+        while let Some(file) = files.next() {
+            extents.push((from..files.i, file));
+            from = files.i;
+        }
+        extents
+    }
+
+    /// Rebuild the blocks of this tape from the given block ranges, in the given order.
+    ///
+    /// Blocks not covered by any range are dropped.
+    fn rebuild<I>(&mut self, ranges: I)
+    where
+        I: IntoIterator<Item = Range<usize>>,
+    {
+        let mut old: Vec<Option<Block>> = self.blocks.drain(..).map(Some).collect();
+        for range in ranges {
+            for i in range {
+                if let Some(block) = old.get_mut(i).and_then(|b| b.take()) {
+                    self.blocks.push(block);
+                }
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), TapeError> {
+        let mut i = 0;
+        while i < self.blocks.len() {
+            i = self.check_file(i)?;
+        }
+        Ok(())
+    }
+
+    /// Check the structure of the file that starts at block `i`.
+    ///
+    /// Returns the index of the block that follows the file. Blocks that are not file
+    /// headers are considered custom files of a single block.
+    ///
+    fn check_file(&self, i: usize) -> Result<usize, TapeError> {
+        let offset = |i: usize| self.blocks[i].range.start;
+        let block = &self.blocks[i];
+        if !block.is_file_header() {
+            return Ok(i + 1);
+        }
+        if block.data_without_prefix().len() < 16 {
+            return Err(TapeError::ShortHeader {
+                block: i,
+                offset: offset(i),
+                context: Context::new(block.data()),
+            });
+        }
+        let next = self.blocks.get(i + 1).filter(|b| !b.is_file_header());
+        if next.is_none() {
+            return Err(TapeError::MissingData {
+                block: i,
+                offset: offset(i),
+                context: Context::new(block.data()),
+            });
+        }
+        if block.is_ascii_header() {
+            let eof = (i + 1..self.blocks.len())
+                .take_while(|j| !self.blocks[*j].is_file_header())
+                .find(|j| self.blocks[*j].data_without_prefix().contains(&0x1a));
+            match eof {
+                Some(j) => Ok(j + 1),
+                None => Err(TapeError::MissingEof {
+                    block: i,
+                    offset: offset(i),
+                    context: Context::new(block.data()),
+                }),
+            }
+        } else {
+            if block.is_bin_header() && next.unwrap().data_without_prefix().len() < 6 {
+                return Err(TapeError::ShortBinData {
+                    block: i + 1,
+                    offset: offset(i + 1),
+                    context: Context::new(self.blocks[i + 1].data()),
+                });
+            }
+            Ok(i + 2)
+        }
+    }
+
+    /// Returns the slices of bytes that make up the on-disk representation of this tape.
+    ///
+    /// Blocks are only found at aligned offsets when a tape is read, so the preamble and
+    /// every block but the last are followed by the zeros that align the next block, if
+    /// they are not aligned already (e.g., a truncated last block of a tape that got
+    /// more files appended).
+    ///
+    fn segments(&self) -> Vec<&[u8]> {
+        static ZEROS: [u8; 8] = [0; 8];
+        let mut segments = Vec::with_capacity(self.blocks.len() + 1);
+        let data = Some(&self.preamble[..])
+            .filter(|p| !p.is_empty())
+            .into_iter()
+            .chain(self.blocks.iter().map(|b| b.data()));
+        let mut data = data.peekable();
+        while let Some(segment) = data.next() {
+            segments.push(segment);
+            let misalignment = segment.len() % 8;
+            if misalignment != 0 && data.peek().is_some() {
+                segments.push(&ZEROS[misalignment..]);
+            }
+        }
+        segments
+    }
+
+    /// Returns the offsets of the blocks in the on-disk representation of this tape,
+    /// followed by its length.
+    pub fn block_offsets(&self) -> Vec<usize> {
+        let mut offsets = Vec::with_capacity(self.blocks.len() + 1);
+        let mut offset = self.preamble.len().div_ceil(8) * 8;
+        for block in &self.blocks {
+            offsets.push(offset);
+            offset += block.data().len().div_ceil(8) * 8;
+        }
+        offsets.push(self.serialized_len());
+        offsets
+    }
+
+    fn from_buffer(bytes: Arc<Vec<u8>>, scan: Scan) -> Tape {
+        let blocks = Tape::parse_blocks(Arc::clone(&bytes), scan);
+        let first = blocks.first().map_or(bytes.len(), |b| b.range.start);
+        Tape {
+            preamble: bytes[..first].to_vec(),
+            blocks,
+        }
+    }
+
+    fn parse_blocks(bytes: Arc<Vec<u8>>, scan: Scan) -> Vec<Block> {
         let mut blocks: Vec<Block> = vec![];
         let mut hindex: Vec<usize> = vec![];
         let mut i = 0;
 
         // First of all, we compute the indices of all block headers.
-        for chunk in bytes.chunks(8) {
-            if chunk == [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74] {
-                hindex.push(i);
+        match scan {
+            Scan::Aligned => {
+                for chunk in bytes.chunks(8) {
+                    if chunk == PREFIX {
+                        hindex.push(i);
+                    }
+                    i += 8;
+                }
+            }
+            Scan::Unaligned => {
+                // Only the positions of the first byte of the prefix are compared
+                while let Some(pos) = bytes[i..].iter().position(|b| *b == PREFIX[0]) {
+                    i += pos;
+                    if bytes[i..].starts_with(&PREFIX)
+                        && (i % 8 == 0 || Tape::expects_block(&bytes, &hindex, i))
+                    {
+                        hindex.push(i);
+                        i += PREFIX.len();
+                    } else {
+                        i += 1;
+                    }
+                }
             }
-            i = i + 8;
         }
 
         // Now we use the block header indices to generate the blocks
+        blocks.reserve(hindex.len());
         for i in 0..hindex.len() {
-            let from = hindex[i] + 8;
+            let from = hindex[i];
             let to = if i == hindex.len() - 1 {
                 bytes.len()
             } else {
                 hindex[i + 1]
             };
-            let block = Block::from_data(&bytes[from..to]);
-            blocks.push(block);
+            blocks.push(Block {
+                buffer: Arc::clone(&bytes),
+                range: from..to,
+            });
         }
         blocks
     }
 
+    /// Returns whether a block is expected at the given offset of an unaligned tape whose
+    /// blocks found so far start at `hindex`
+    fn expects_block(bytes: &[u8], hindex: &[usize], offset: usize) -> bool {
+        let header_at = |i: usize| header_type(&bytes[i + PREFIX.len()..]);
+        if header_at(offset).is_some() {
+            return true;
+        }
+        let last = match hindex.last() {
+            Some(last) => *last,
+            None => return false,
+        };
+        if header_at(last).is_some() {
+            return true;
+        }
+        let header = hindex.iter().rev().find_map(|i| header_at(*i));
+        header == Some(FileType::Ascii) && offset - last == PREFIX.len() + 256
+    }
+
     fn append_block(&mut self, block: Block, align: usize, padding_byte: u8) -> usize {
         self.blocks.push(block);
         self.extend_last_block(align, padding_byte)
@@ -377,8 +1842,8 @@ impl Tape {
         if let Some(last_block) = self.blocks.last_mut() {
             let mut n = 0;
             while last_block.data_without_prefix().len() % align != 0 {
-                last_block.data.push(padding_byte);
-                n = n + 1;
+                last_block.push(padding_byte);
+                n += 1;
             }
             return n;
         }
@@ -447,6 +1912,188 @@ impl Tape {
     }
 }
 
+/// A seekable reader of the serialized bytes of a tape, returned by `Tape::reader()`
+#[derive(Clone, Debug)]
+pub struct TapeReader<'a> {
+    segments: Vec<&'a [u8]>,
+    offsets: Vec<usize>,
+    len: u64,
+    pos: u64,
+}
+
+impl<'a> TapeReader<'a> {
+    /// Returns the total length in bytes of the serialized tape.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the tape has no blocks.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<'a> Read for TapeReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let pos = self.pos as usize;
+        let index = self.offsets.partition_point(|offset| *offset <= pos) - 1;
+        let data = &self.segments[index][pos - self.offsets[index]..];
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for TapeReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.len, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Tape {
+    type Error = TapeError;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Tape, TapeError> {
+        Tape::parse(bytes)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+const EDGE_SIZES: [usize; 9] = [0, 1, 6, 7, 8, 9, 255, 256, 257];
+
+#[cfg(feature = "arbitrary")]
+fn arbitrary_len(u: &mut Unstructured) -> arbitrary::Result<usize> {
+    if u.ratio(1, 2)? {
+        Ok(*u.choose(&EDGE_SIZES)?)
+    } else {
+        u.int_in_range(0..=1024)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+fn arbitrary_name(u: &mut Unstructured) -> arbitrary::Result<[u8; 6]> {
+    let mut name: [u8; 6] = u.arbitrary()?;
+    if u.ratio(3, 4)? {
+        for c in name.iter_mut() {
+            *c = b' ' + *c % 0x5f;
+        }
+    }
+    Ok(name)
+}
+
+/// Generates blocks that are file headers, file data or random bytes of edge sizes
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for Block {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Block> {
+        let header = match u.int_in_range(0..=3)? {
+            0 => 0xd0,
+            1 => 0xd3,
+            2 => 0xea,
+            _ => {
+                let len = arbitrary_len(u)?;
+                return Ok(Block::from_data(u.bytes(len)?));
+            }
+        };
+        let mut data = vec![header; 10];
+        data.extend_from_slice(&arbitrary_name(u)?);
+        Ok(Block::from_data(&data))
+    }
+}
+
+/// Generates files of every type with consistent addresses and multi-chunk ASCII text
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for File<'a> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<File<'a>> {
+        let name = String::from_utf8_lossy(&arbitrary_name(u)?).into_owned();
+        let len = arbitrary_len(u)?;
+        Ok(match u.int_in_range(0..=3)? {
+            0 => {
+                let data = u.bytes(len + 6)?;
+                let begin = LittleEndian::read_u16(&data[0..2]) as usize;
+                let end = LittleEndian::read_u16(&data[2..4]) as usize;
+                let start = LittleEndian::read_u16(&data[4..6]) as usize;
+                File::Bin(name, begin, end, start, data)
+            }
+            1 => File::Basic(name, u.bytes(len)?),
+            2 => {
+                let mut chunks = vec![];
+                for _ in 0..len / 256 {
+                    chunks.push(u.bytes(256)?);
+                }
+                chunks.push(u.bytes(len % 256)?);
+                File::Ascii(name, chunks)
+            }
+            _ => File::Custom(u.bytes(len)?),
+        })
+    }
+}
+
+/// Generates tapes made of well-formed files mixed with some stray (aligned) blocks
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for Tape {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Tape> {
+        let mut tape = Tape::new();
+        u.arbitrary_loop(None, Some(16), |u| {
+            let name = arbitrary_name(u)?;
+            let len = arbitrary_len(u)?;
+            // Invalid files are rejected by the append methods and just skipped
+            let _ = match u.int_in_range(0..=4)? {
+                0 => {
+                    let begin = u.int_in_range(0..=0xffff - len.max(1) as u16)?;
+                    let mut data = vec![];
+                    data.extend_from_slice(&begin.to_le_bytes());
+                    data.extend_from_slice(&(begin + len.max(1) as u16 - 1).to_le_bytes());
+                    data.extend_from_slice(&u.arbitrary::<u16>()?.to_le_bytes());
+                    data.extend_from_slice(u.bytes(len.max(1))?);
+                    tape.append_bin(&name, &data)
+                }
+                1 => tape.append_basic(&name, u.bytes(len.max(2))?),
+                2 => tape.append_ascii(&name, u.bytes(len)?),
+                3 => tape.append_custom(u.bytes(len)?),
+                _ => Ok(tape.append_block(u.arbitrary()?, 8, 0)),
+            };
+            Ok(ControlFlow::Continue(()))
+        })?;
+        Ok(tape)
+    }
+}
+
+/// Returns the type of the file whose header block has the given data (without the prefix
+/// bytes), or `None` if it is not a file header
+fn header_type(data: &[u8]) -> Option<FileType> {
+    let file_type = match data.first()? {
+        0xd0 => FileType::Bin,
+        0xd3 => FileType::Basic,
+        0xea => FileType::Ascii,
+        _ => return None,
+    };
+    match data.get(..10) {
+        Some(id) if id.iter().all(|b| *b == data[0]) => Some(file_type),
+        _ => None,
+    }
+}
+
 /// Converts a string into a tape filename
 ///
 /// This function converts the string passed as argument into a tape file name.
@@ -460,9 +2107,7 @@ pub fn file_name(s: &str) -> ([u8; 6], bool) {
 
     let mut name: [u8; 6] = [0x20; 6];
     let bytes = &s.as_bytes()[..last];
-    for i in 0..last {
-        name[i] = bytes[i]
-    }
+    name[..last].copy_from_slice(bytes);
     (name, s.len() > last)
 }
 
@@ -473,6 +2118,8 @@ mod test {
     use std::iter::FromIterator;
 
     use quickcheck::{quickcheck, TestResult};
+    #[cfg(feature = "fs")]
+    use tempdir::TempDir;
 
     use super::*;
 
@@ -517,23 +2164,143 @@ mod test {
         };
     }
 
-    fn block_read_from_bytes_prop(bytes: Vec<u8>) -> TestResult {
-        let block = Block::from_data(&bytes[..]);
-        let data = block.data();
-        require_prop!(
-            "prefix bytes are present",
-            &data[0..8] == [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74]
-        );
-        require_prop!(
-            "data is present",
-            &data[8..] == &bytes[..] && block.data_without_prefix() == &bytes[..]
+    fn block_read_from_bytes_prop(bytes: Vec<u8>) -> TestResult {
+        let block = Block::from_data(&bytes[..]);
+        let data = block.data();
+        require_prop!(
+            "prefix bytes are present",
+            data[0..8] == [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74]
+        );
+        require_prop!(
+            "data is present",
+            data[8..] == bytes[..] && block.data_without_prefix() == &bytes[..]
+        );
+        TestResult::from_bool(true)
+    }
+
+    #[test]
+    fn should_block_read_from_bytes() {
+        quickcheck(block_read_from_bytes_prop as fn(Vec<u8>) -> TestResult);
+    }
+
+    #[test]
+    fn should_share_buffer_between_parsed_blocks() {
+        let bytes = [
+            0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74, 1, 2, 3, 4, 5, 6, 7, 8, 0x1f, 0xa6,
+            0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74, 9, 10,
+        ];
+        let tape = Tape::from_bytes(&bytes);
+        let blocks = tape.blocks();
+        assert_eq!(2, blocks.len());
+        assert!(Arc::ptr_eq(&blocks[0].buffer, &blocks[1].buffer));
+        assert_eq!(&[9, 10], blocks[1].data_without_prefix());
+
+        let mut block = blocks[0].clone();
+        block.push(0xff);
+        assert_eq!(&[1, 2, 3, 4, 5, 6, 7, 8], blocks[0].data_without_prefix());
+        assert_eq!(&[1, 2, 3, 4, 5, 6, 7, 8, 0xff], block.data_without_prefix());
+    }
+
+    #[test]
+    fn should_find_unaligned_blocks() {
+        let mut tape = Tape::new();
+        let (game, _) = file_name("game");
+        let (intro, _) = file_name("intro");
+        tape.append_bin(&game, &[0x00, 0x90, 0x00, 0x90, 0x00, 0x90, 0xc9])
+            .unwrap();
+        tape.append_ascii(&intro, &[0x41; 300]).unwrap();
+        tape.append_bin(&game, &[0x00, 0x90, 0x00, 0x90, 0x00, 0x90, 0xc9])
+            .unwrap();
+        let blocks = tape.blocks();
+        // The blocks without padding, as written by tools that do not align blocks
+        let mut bytes = vec![];
+        for (i, block) in blocks.iter().enumerate() {
+            let unpadded = [24, 15, 24, 264, 264, 24, 15];
+            bytes.extend_from_slice(&block.data()[..unpadded[i]]);
+        }
+
+        assert_eq!(2, Tape::from_bytes(&bytes).blocks().len());
+        let unaligned = Tape::from_bytes_with(&bytes, Scan::Unaligned);
+        assert_eq!(blocks.len(), unaligned.blocks().len());
+        assert_eq!(&[0xc9, 0x00, 0x1f], &unaligned.to_bytes()[38..41]);
+        let files: Vec<FileType> = unaligned.files().map(|f| f.file_type()).collect();
+        assert_eq!(vec![FileType::Bin, FileType::Ascii, FileType::Bin], files);
+        let written = Tape::from_bytes(&unaligned.to_bytes());
+        assert_eq!(blocks.len(), written.blocks().len());
+    }
+
+    #[test]
+    fn should_keep_unaligned_prefixes_in_payloads() {
+        let mut tape = Tape::new();
+        let (game, _) = file_name("game");
+        let program = [
+            &[0xaf, 0x00, 0x00][..],
+            &PREFIX[..],
+            &[0x00; 6][..],
+            &[0xc9],
+        ]
+        .concat();
+        tape.append_bin_with_addresses(&game, 0x8000, 0x8000, &program)
+            .unwrap();
+        let bytes = tape.to_bytes();
+
+        let mut unaligned = Tape::from_bytes_with(&bytes, Scan::Unaligned);
+        assert_eq!(2, unaligned.blocks().len());
+        let (foo, _) = file_name("foo");
+        unaligned.rename_file(0, &foo).unwrap();
+        let renamed = Tape::from_bytes_with(&unaligned.to_bytes(), Scan::Unaligned);
+        assert_eq!(bytes.len(), renamed.to_bytes().len());
+        assert_eq!(&program[..], &renamed.files().next().unwrap().payload()[..]);
+
+        let mut patched = renamed;
+        patched.patch_file(0, 1, &[0x3e]).unwrap();
+        let patched = Tape::from_bytes_with(&patched.to_bytes(), Scan::Unaligned);
+        let expected = [&[0xaf, 0x3e, 0x00][..], &program[3..]].concat();
+        assert_eq!(
+            &expected[..],
+            &patched.files().next().unwrap().payload()[..]
         );
-        TestResult::from_bool(true)
     }
 
     #[test]
-    fn should_block_read_from_bytes() {
-        quickcheck(block_read_from_bytes_prop as fn(Vec<u8>) -> TestResult);
+    fn should_read_from_vec_without_copying() {
+        let bytes = [
+            &[0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74][..],
+            &[0xea; 10],
+            &[0x1a; 6],
+        ]
+        .concat();
+        let (ptr, bytes_len) = (bytes.as_ptr(), bytes.len());
+        let tape = Tape::from_vec(bytes);
+        assert_eq!(ptr, tape.blocks()[0].data().as_ptr());
+        assert_eq!(bytes_len, tape.to_bytes().len());
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn should_generate_arbitrary_tapes() {
+        let input: Vec<u8> = (1..4097u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let mut u = Unstructured::new(&input);
+        let tape: Tape = u.arbitrary().unwrap();
+        assert!(!tape.blocks().is_empty());
+
+        let bytes: Vec<u8> = tape
+            .blocks()
+            .iter()
+            .flat_map(|b| b.data().to_vec())
+            .collect();
+        let read = Tape::from_bytes(&bytes);
+        assert_eq!(tape.blocks().len(), read.blocks().len());
+        for (a, b) in tape.blocks().iter().zip(read.blocks()) {
+            assert_eq!(a.data(), b.data());
+        }
+
+        let file: File = u.arbitrary().unwrap();
+        if let File::Bin(_, _, _, _, data) = file {
+            assert!(data.len() >= 6);
+        }
     }
 
     #[test]
@@ -603,9 +2370,9 @@ mod test {
                 return TestResult::discard();
             }
             bytes
-                .write(&[0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74])
+                .write_all(&[0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74])
                 .unwrap();
-            bytes.write(&block[..]).unwrap();
+            bytes.write_all(&block[..]).unwrap();
         }
         let tape = Tape::from_bytes(&bytes);
 
@@ -628,6 +2395,78 @@ mod test {
         quickcheck(should_load_tape_with_some_blocks_prop as fn(Vec<Vec<u8>>) -> TestResult);
     }
 
+    fn should_round_trip_bytes_prop(preamble: Vec<u8>, blocks: Vec<Vec<u8>>) -> TestResult {
+        let prefix = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+        if preamble.windows(8).any(|w| w == prefix) {
+            return TestResult::discard();
+        }
+        let mut bytes = preamble;
+        for block in &blocks {
+            while !bytes.len().is_multiple_of(8) {
+                bytes.push(0);
+            }
+            bytes.extend_from_slice(&prefix);
+            bytes.extend_from_slice(block);
+        }
+        let tape = Tape::from_bytes(&bytes);
+        let mut read = vec![];
+        tape.reader().read_to_end(&mut read).unwrap();
+        require_prop!("the bytes are reproduced", read == bytes);
+        require_prop!(
+            "the length is the input length",
+            tape.serialized_len() == bytes.len()
+        );
+        require_prop!("the bytes are written back", tape.to_bytes() == bytes);
+        TestResult::from_bool(true)
+    }
+
+    #[test]
+    fn should_round_trip_bytes() {
+        quickcheck(should_round_trip_bytes_prop as fn(Vec<u8>, Vec<Vec<u8>>) -> TestResult);
+    }
+
+    #[test]
+    fn should_write_aligned_blocks() {
+        let prefix = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+        let mut tape = Tape::from_bytes(&[&prefix[..], &[1, 2, 3]].concat());
+        tape.append_custom(&[4; 8]).unwrap();
+
+        let bytes = tape.to_bytes();
+        assert_eq!(
+            [&prefix[..], &[1, 2, 3, 0, 0, 0, 0, 0], &prefix, &[4; 8]].concat(),
+            bytes
+        );
+        assert_eq!(bytes.len(), tape.serialized_len());
+        let mut written = vec![];
+        tape.write(&mut written).unwrap();
+        assert_eq!(bytes, written);
+        let mut read = vec![];
+        tape.reader().read_to_end(&mut read).unwrap();
+        assert_eq!(bytes, read);
+        assert_eq!(2, Tape::from_bytes(&bytes).blocks().len());
+    }
+
+    #[test]
+    fn should_normalize_tape() {
+        let bytes = [
+            &[0x01; 8][..],
+            &[0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74][..],
+            &[0x41; 5][..],
+        ]
+        .concat();
+        let mut tape = Tape::from_bytes(&bytes);
+        assert_eq!(&[0x01; 8], tape.preamble());
+        assert_eq!(bytes.len(), tape.serialized_len());
+
+        assert_eq!(-5, tape.normalize());
+        assert!(tape.preamble().is_empty());
+        assert_eq!(
+            &[0x41, 0x41, 0x41, 0x41, 0x41, 0x00, 0x00, 0x00],
+            tape.blocks()[0].data_without_prefix()
+        );
+        assert_eq!(0, tape.normalize());
+    }
+
     #[test]
     fn should_load_tape_with_some_files() {
         let bytes: Vec<u8> = vec![
@@ -672,12 +2511,905 @@ mod test {
         );
     }
 
+    #[test]
+    fn should_return_file_payload_and_raw_data() {
+        let bin = File::Bin(
+            "FOO".to_string(),
+            0x8000,
+            0x8001,
+            0x8000,
+            &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xc9, 0x00],
+        );
+        assert_eq!(
+            &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xc9, 0x00],
+            &bin.raw_data()[..]
+        );
+        assert_eq!(&[0xc9, 0x00], &bin.payload()[..]);
+        assert_eq!(&[0xc9, 0x00], &bin.data()[..]);
+        assert_eq!(Some((0x8000, 0x8001, 0x8000)), bin.addresses());
+        assert_eq!(Some((0x8000, 0x8001, 0x8000)), bin.load_info());
+        assert_eq!(FileKind::Bin, bin.kind());
+        assert_eq!(8, bin.size());
+
+        let ascii = File::Ascii(
+            "FOO".to_string(),
+            vec![&[0x41, 0x42, 0x43, 0x44], &[0x45, 0x1a, 0x1a, 0x1a]],
+        );
+        assert_eq!(
+            &[0x41, 0x42, 0x43, 0x44, 0x45, 0x1a, 0x1a, 0x1a],
+            &ascii.raw_data()[..]
+        );
+        assert_eq!(&[0x41, 0x42, 0x43, 0x44, 0x45], &ascii.payload()[..]);
+        assert_eq!(&ascii.raw_data(), &ascii.data());
+        assert_eq!(None, ascii.addresses());
+        assert_eq!(None, ascii.load_info());
+        assert_eq!(FileKind::Ascii, ascii.kind());
+        assert_eq!(8, ascii.size());
+
+        let custom = File::Custom(&[0x01, 0x02, 0x03]);
+        assert_eq!(&[0x01, 0x02, 0x03], &custom.raw_data()[..]);
+        assert_eq!(&[0x01, 0x02, 0x03], &custom.payload()[..]);
+        assert_eq!(&[0x01, 0x02, 0x03], &custom.data()[..]);
+        assert_eq!(FileKind::Custom, custom.kind());
+    }
+
+    #[test]
+    fn should_parse_and_display_file_type() {
+        assert_eq!(FileType::Bin, "bin".parse().unwrap());
+        assert_eq!(FileType::Bin, "Binary".parse().unwrap());
+        assert_eq!(FileType::Basic, "BAS".parse().unwrap());
+        assert_eq!(FileType::Ascii, "ascii".parse().unwrap());
+        assert_eq!(FileType::Custom, "custom".parse().unwrap());
+        assert!("foobar".parse::<FileType>().is_err());
+
+        assert_eq!(FileType::Bin, FileType::from_extension("BIN"));
+        assert_eq!(FileType::Bin, FileType::from_extension("ihx"));
+        assert_eq!(FileType::Ascii, FileType::from_extension("asc"));
+        assert_eq!(FileType::Custom, FileType::from_extension("dat"));
+
+        assert_eq!("basic ", format!("{:6}", FileType::Basic));
+        assert_eq!("custom", FileType::Custom.to_string());
+    }
+
+    #[test]
+    fn should_compute_serialized_len() {
+        let mut tape = Tape::new();
+        assert_eq!(0, tape.serialized_len());
+
+        let (fname, _) = file_name("foobar");
+        tape.append_bin(
+            &fname,
+            &[0x00, 0x80, 0x02, 0x80, 0x00, 0x80, 0x01, 0x02, 0x03],
+        )
+        .unwrap();
+        tape.append_ascii(&fname, b"10 PRINT").unwrap();
+        let bytes: Vec<u8> = tape
+            .blocks()
+            .iter()
+            .flat_map(|b| b.data().to_vec())
+            .collect();
+        assert_eq!(bytes.len(), tape.serialized_len());
+        assert_eq!(24 + 24 + 24 + 264, tape.serialized_len());
+    }
+
+    #[test]
+    fn should_compute_stats() {
+        let mut tape = Tape::new();
+        assert_eq!(Stats::default(), tape.stats());
+
+        let (fname, _) = file_name("foobar");
+        tape.append_bin(
+            &fname,
+            &[0x00, 0x80, 0x02, 0x80, 0x00, 0x80, 0x01, 0x02, 0x03],
+        )
+        .unwrap();
+        tape.append_ascii(&fname, b"10 PRINT").unwrap();
+        let stats = tape.stats();
+        assert_eq!(2, stats.file_count());
+        assert_eq!(Some(&1), stats.files.get(&FileType::Bin));
+        assert_eq!(Some(&1), stats.files.get(&FileType::Ascii));
+        assert_eq!(
+            vec![(16, 3), (256, 1)],
+            stats.block_sizes.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(4, stats.blocks);
+        assert_eq!(32, stats.prefix_bytes);
+        assert_eq!(7 + 248, stats.padding_bytes);
+        assert_eq!(10 + 8, stats.payload_bytes);
+        assert_eq!(tape.serialized_len(), stats.total_bytes);
+
+        let mut total = tape.stats();
+        total.merge(&tape.stats());
+        assert_eq!(4, total.file_count());
+        assert_eq!(Some(&6), total.block_sizes.get(&16));
+        assert_eq!(2 * tape.serialized_len(), total.total_bytes);
+    }
+
+    #[test]
+    fn should_list_entries() {
+        let mut tape = Tape::new();
+        let (fname, _) = file_name("foobar");
+        tape.append_bin(
+            &fname,
+            &[0x00, 0x80, 0x02, 0x80, 0x00, 0x80, 0x01, 0x02, 0x03],
+        )
+        .unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        tape.append_ascii(&fname, b"10 PRINT").unwrap();
+
+        let entries = tape.entries();
+        assert_eq!(3, entries.len());
+        assert_eq!(
+            Entry {
+                index: 0,
+                file_type: FileType::Bin,
+                name: Some("foobar.bin".to_string()),
+                size: 16,
+                payload_size: 10,
+                addresses: Some((0x8000, 0x8002, 0x8000)),
+                blocks: 0..2,
+                offset: 0,
+                length: 48,
+            },
+            entries[0]
+        );
+        assert_eq!(
+            (2..3, 48, 16),
+            (
+                entries[1].blocks.clone(),
+                entries[1].offset,
+                entries[1].length
+            )
+        );
+        assert_eq!(None, entries[1].name);
+        assert_eq!((3..5, 64), (entries[2].blocks.clone(), entries[2].offset));
+        assert_eq!(tape.serialized_len(), entries[2].offset + entries[2].length);
+        assert_eq!(8, entries[2].payload_size);
+    }
+
+    #[test]
+    fn should_remove_and_rename_files() {
+        let mut tape = Tape::new();
+        let (foo, _) = file_name("foo");
+        let (bar, _) = file_name("bar");
+        tape.append_bin(&foo, &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9])
+            .unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        tape.append_ascii(&foo, b"10 PRINT").unwrap();
+
+        tape.rename_file(2, &bar).unwrap();
+        assert!(tape.rename_file(1, &bar).is_err());
+        tape.remove_file(0).unwrap();
+        assert!(tape.remove_file(2).is_err());
+        let names: Vec<Option<String>> = tape.files().map(|f| f.name()).collect();
+        assert_eq!(vec![None, Some("bar.asc".to_string())], names);
+        assert_eq!(3, tape.blocks().len());
+    }
+
+    #[test]
+    fn should_move_files() {
+        let mut tape = Tape::new();
+        for name in ["a", "b", "c", "d"] {
+            let (name, _) = file_name(name);
+            tape.append_ascii(&name, b"10 PRINT").unwrap();
+        }
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        let names =
+            |tape: &Tape| -> Vec<Option<String>> { tape.files().map(|f| f.name()).collect() };
+        let bytes = tape.to_bytes().len();
+
+        tape.move_file(0, 2).unwrap();
+        tape.move_file(4, 1).unwrap();
+        tape.move_file(3, 3).unwrap();
+        assert_eq!(
+            vec![
+                Some("b.asc".to_string()),
+                None,
+                Some("c.asc".to_string()),
+                Some("a.asc".to_string()),
+                Some("d.asc".to_string()),
+            ],
+            names(&tape)
+        );
+        assert_eq!(bytes, tape.to_bytes().len());
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            tape.move_file(5, 0).unwrap_err().kind()
+        );
+        assert!(tape.move_file(0, 5).is_err());
+    }
+
+    #[test]
+    fn should_patch_files() {
+        let mut tape = Tape::new();
+        let (name, _) = file_name("game");
+        tape.append_bin_with_addresses(&name, 0x9000, 0x9000, &[0x3e, 0x01, 0xc9])
+            .unwrap();
+        tape.append_ascii(&name, b"10 PRINT 1\r\n").unwrap();
+        tape.append_custom(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+        assert_eq!(7, tape.patch_file(0, 1, &[0x02]).unwrap());
+        tape.patch_file(0, 3, &[0x00, 0x00]).unwrap();
+        tape.patch_file(1, 9, b"2").unwrap();
+        tape.patch_file(2, 6, &[0xff, 0xff, 0xff]).unwrap();
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(Some((0x9000, 0x9004, 0x9000)), files[0].addresses());
+        assert_eq!(
+            &[0x3e, 0x02, 0xc9, 0x00, 0x00][..],
+            &files[0].payload()[..5]
+        );
+        assert_eq!(&b"10 PRINT 2\r\n"[..], &files[1].payload()[..]);
+        assert_eq!(
+            &[1, 2, 3, 4, 5, 6, 0xff, 0xff, 0xff][..],
+            &files[2].payload()[..9]
+        );
+        assert_eq!(16, files[2].size());
+
+        let e = tape.patch_file(1, 100, &[0]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+        assert!(tape.patch_file(3, 0, &[0]).is_err());
+    }
+
+    #[test]
+    fn should_append_loader() {
+        let mut tape = Tape::new();
+        let (name, _) = file_name("game");
+        tape.append_loader_for(&name).unwrap();
+        tape.append_bin(&name, &[0x00, 0x90, 0x00, 0x90, 0x00, 0x90, 0xc9, 0x00])
+            .unwrap();
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(Some("game.asc".to_string()), files[0].name());
+        assert_eq!(&b"10 BLOAD\"CAS:\",R\r\n"[..], &files[0].payload()[..]);
+        assert_eq!(FileType::Bin, files[1].file_type());
+    }
+
+    #[test]
+    fn should_append_file_from_other_tape() {
+        let mut other = Tape::new();
+        let (foo, _) = file_name("foo");
+        other.append_custom(&[1, 2, 3]).unwrap();
+        other.append_ascii(&foo, b"10 PRINT").unwrap();
+
+        let mut tape = Tape::new();
+        tape.append_file_from(&other, 1).unwrap();
+        tape.append_file_from(&other, 0).unwrap();
+        assert!(tape.append_file_from(&other, 2).is_err());
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(
+            vec![
+                File::Ascii(
+                    "foo".to_string(),
+                    vec![&[b"10 PRINT".as_ref(), &[0x1a; 248]].concat()]
+                ),
+                File::Custom(&[1, 2, 3, 0, 0, 0, 0, 0]),
+            ],
+            files
+        );
+    }
+
+    #[test]
+    fn should_append_bin_with_addresses() {
+        let mut tape = Tape::new();
+        let (name, _) = file_name("game");
+        assert_eq!(
+            7,
+            tape.append_bin_with_addresses(&name, 0x80fe, 0x80ff, &[0xaf, 0xc9, 0x00])
+                .unwrap()
+        );
+        assert!(tape
+            .append_bin_with_addresses(&name, 0x8000, 0x8000, &[])
+            .is_err());
+        assert!(tape
+            .append_bin_with_addresses(&name, 0xffff, 0xffff, &[0xaf, 0xc9])
+            .is_err());
+        assert!(tape.append_bin(&name, &[]).is_err());
+        assert!(tape.append_basic(&name, &[]).is_err());
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(1, files.len());
+        assert_bin!(
+            &files[0],
+            "game",
+            0x80fe,
+            0x8100,
+            0x80ff,
+            &[0xaf, 0xc9, 0x00, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn should_replace_basic_program() {
+        let mut tape = Tape::new();
+        let (foo, _) = file_name("foo");
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        tape.append_basic(&foo, &[0xff, 0x01, 0x02, 0x00, 0x00])
+            .unwrap();
+
+        assert_eq!(5, tape.replace_basic(1, &[0x01, 0x03, 0x00]).unwrap());
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(
+            File::Basic("foo".to_string(), &[0x01, 0x03, 0x00, 0, 0, 0, 0, 0]),
+            files[1]
+        );
+        assert_eq!(3, tape.blocks().len());
+
+        let err = tape.replace_basic(0, &[0x00, 0x00]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(tape.replace_basic(1, &[0x00]).is_err());
+    }
+
+    #[test]
+    fn should_replace_file() {
+        let mut tape = Tape::new();
+        let (foo, _) = file_name("foo");
+        tape.append_basic(&foo, &[0x01, 0x02, 0x00, 0x00]).unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+
+        assert_eq!(
+            212,
+            tape.replace_file(0, FileType::Ascii, &[0x41; 300]).unwrap()
+        );
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(2, files.len());
+        assert_eq!("foo.asc", files[0].name().unwrap());
+        assert_eq!(&[0x41; 300], &files[0].payload()[..300]);
+        assert_eq!(File::Custom(&[1, 2, 3, 0, 0, 0, 0, 0]), files[1]);
+        assert_eq!(4, tape.blocks().len());
+
+        tape.replace_file(0, FileType::Basic, &[0xff, 0x01, 0x02, 0x00, 0x00])
+            .unwrap();
+        assert_eq!(3, tape.blocks().len());
+        assert_eq!(FileType::Basic, tape.files().next().unwrap().file_type());
+
+        let err = tape.replace_file(1, FileType::Ascii, b"A").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(tape.replace_file(0, FileType::Basic, &[0x00]).is_err());
+    }
+
+    #[test]
+    fn should_retain_matching_files() {
+        let mut tape = Tape::new();
+        let (foo, _) = file_name("foo");
+        let (bar, _) = file_name("bar");
+        tape.append_bin(&foo, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0x01, 0x02])
+            .unwrap();
+        tape.append_custom(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a])
+            .unwrap();
+        tape.append_ascii(&bar, &[0x41; 100]).unwrap();
+        assert_eq!(5, tape.blocks().len());
+
+        tape.retain(|f| f.file_type() != FileType::Custom);
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(2, files.len());
+        assert_eq!("foo.bin", files[0].name().unwrap());
+        assert_eq!("bar.asc", files[1].name().unwrap());
+        assert_eq!(4, tape.blocks().len());
+
+        tape.retain(|f| f.file_type() == FileType::Ascii);
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(1, files.len());
+        assert_eq!("bar.asc", files[0].name().unwrap());
+        assert_eq!(2, tape.blocks().len());
+    }
+
+    #[test]
+    fn should_sort_files() {
+        let mut tape = Tape::new();
+        let (foo, _) = file_name("foo");
+        let (bar, _) = file_name("bar");
+        tape.append_ascii(&foo, &[0x41; 100]).unwrap();
+        tape.append_custom(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a])
+            .unwrap();
+        tape.append_bin(&bar, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0x01, 0x02])
+            .unwrap();
+
+        tape.sort_files_by(|a, b| a.name().cmp(&b.name()));
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(3, files.len());
+        assert_eq!(FileType::Custom, files[0].file_type());
+        assert_eq!("bar.bin", files[1].name().unwrap());
+        assert_eq!("foo.asc", files[2].name().unwrap());
+        assert_eq!(5, tape.blocks().len());
+
+        tape.sort_files_by_key(|f| f.file_type());
+        let types: Vec<FileType> = tape.files().map(|f| f.file_type()).collect();
+        assert_eq!(
+            vec![FileType::Bin, FileType::Ascii, FileType::Custom],
+            types
+        );
+
+        tape.sort_files_by_key(|f| f.size());
+        let sizes: Vec<usize> = tape.files().map(|f| f.size()).collect();
+        assert_eq!(vec![8, 16, 256], sizes);
+    }
+
+    #[test]
+    fn should_dedup_files() {
+        let mut tape = Tape::new();
+        let (foo, _) = file_name("foo");
+        let (bar, _) = file_name("bar");
+        let bin = [0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0x01, 0x02];
+        tape.append_bin(&foo, &bin).unwrap();
+        tape.append_bin(&bar, &bin).unwrap();
+        tape.append_custom(&[0x01; 16]).unwrap();
+        tape.append_bin(&foo, &bin).unwrap();
+        tape.append_custom(&[0x01; 16]).unwrap();
+        tape.append_custom(&[0x02; 16]).unwrap();
+
+        let removed = tape.dedup_files();
+        assert_eq!(vec![(3, Some("foo.bin".to_string())), (4, None)], removed);
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(4, files.len());
+        assert_eq!("foo.bin", files[0].name().unwrap());
+        assert_eq!("bar.bin", files[1].name().unwrap());
+        assert_eq!(File::Custom(&[0x01; 16]), files[2]);
+        assert_eq!(File::Custom(&[0x02; 16]), files[3]);
+        assert!(tape.dedup_files().is_empty());
+    }
+
+    #[test]
+    fn should_not_dedup_files_at_other_addresses() {
+        let mut tape = Tape::new();
+        let (foo, _) = file_name("foo");
+        tape.append_bin(&foo, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0x01, 0x02])
+            .unwrap();
+        tape.append_bin(&foo, &[0x00, 0xc0, 0x01, 0xc0, 0x00, 0xc0, 0x01, 0x02])
+            .unwrap();
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(files[0].payload(), files[1].payload());
+
+        assert!(tape.dedup_files().is_empty());
+        assert_eq!(2, tape.files().count());
+    }
+
+    #[test]
+    fn should_extend_from_other_tape() {
+        let (foo, _) = file_name("foo");
+        let (loader, _) = file_name("loader");
+        let mut tape = Tape::new();
+        tape.append_basic(&foo, &[0x01, 0x02]).unwrap();
+        tape.append_basic(&loader, &[0x03, 0x04]).unwrap();
+        let mut other = Tape::new();
+        other.append_basic(&foo, &[0x01, 0x02]).unwrap();
+        other.append_basic(&foo, &[0x05, 0x06]).unwrap();
+        other.append_basic(&loader, &[0x07, 0x08]).unwrap();
+        other.append_custom(&[0x09]).unwrap();
+
+        let mut plain = Tape::new();
+        plain.append_basic(&foo, &[0x01, 0x02]).unwrap();
+        plain.extend_from(&other);
+        let names: Vec<Option<String>> = plain.files().map(|f| f.name()).collect();
+        assert_eq!(5, names.len());
+        assert_eq!(Some("loader.bas".to_string()), names[3]);
+
+        let merged = tape.extend_from_with(
+            &other,
+            MergePolicy {
+                dedup: true,
+                rename: true,
+            },
+        );
+        assert_eq!(
+            vec![
+                Merged::Skipped(0, Some("foo.bas".to_string())),
+                Merged::Renamed(1, "foo.bas".to_string(), "foo1.bas".to_string()),
+                Merged::Renamed(2, "loader.bas".to_string(), "loade1.bas".to_string()),
+            ],
+            merged
+        );
+        let files: Vec<(Option<String>, Vec<u8>)> = tape
+            .files()
+            .map(|f| (f.name(), f.payload().to_vec()))
+            .collect();
+        assert_eq!(5, files.len());
+        assert_eq!(Some("foo1.bas".to_string()), files[2].0);
+        assert_eq!(&[0x05, 0x06], &files[2].1[..2]);
+        assert_eq!(Some("loade1.bas".to_string()), files[3].0);
+        assert_eq!(None, files[4].0);
+    }
+
+    #[test]
+    fn should_parse_valid_tape() {
+        let mut tape = Tape::new();
+        let (fname, _) = file_name("foobar");
+        tape.append_bin(&fname, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0x01, 0x02])
+            .unwrap();
+        tape.append_ascii(&fname, b"10 PRINT").unwrap();
+        tape.append_custom(&[0x01, 0x02, 0x03]).unwrap();
+        let bytes: Vec<u8> = tape
+            .blocks()
+            .iter()
+            .flat_map(|b| b.data().to_vec())
+            .collect();
+
+        let parsed = Tape::try_from(&bytes[..]).unwrap();
+        assert_eq!(tape.blocks().len(), parsed.blocks().len());
+        assert!(Tape::parse(&[]).is_ok());
+    }
+
+    #[test]
+    fn should_fail_to_parse_malformed_tape() {
+        let prefix = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+        let bin_header = [0xd0; 10];
+        let ascii_header = [0xea; 10];
+
+        let bytes = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        assert_eq!(
+            Err(TapeError::MissingPrefix {
+                offset: 0,
+                context: Context::new(&bytes)
+            }),
+            Tape::parse(&bytes).map(|_| ())
+        );
+
+        let bytes = [&prefix[..], &bin_header[..], &[0x20; 6][..]].concat();
+        assert_eq!(
+            Err(TapeError::MissingData {
+                block: 0,
+                offset: 0,
+                context: Context::new(&bytes[0..])
+            }),
+            Tape::parse(&bytes).map(|_| ())
+        );
+
+        let bytes = [
+            &prefix[..],
+            &bin_header[..],
+            &[0x20; 6][..],
+            &prefix[..],
+            &[0x00, 0x80, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00][..],
+        ]
+        .concat();
+        assert!(Tape::parse(&bytes).is_ok());
+        let bytes = [&prefix[..], &bin_header[..], &[0x20; 6][..], &prefix[..]].concat();
+        assert_eq!(
+            Err(TapeError::ShortBinData {
+                block: 1,
+                offset: 24,
+                context: Context::new(&bytes[24..])
+            }),
+            Tape::parse(&bytes).map(|_| ())
+        );
+
+        let bytes = [
+            &prefix[..],
+            &[0x00; 8][..],
+            &prefix[..],
+            &ascii_header[..],
+            &[0x20; 6][..],
+            &prefix[..],
+            &[0x41; 8][..],
+        ]
+        .concat();
+        assert_eq!(
+            Err(TapeError::MissingEof {
+                block: 1,
+                offset: 16,
+                context: Context::new(&bytes[16..])
+            }),
+            Tape::parse(&bytes).map(|_| ())
+        );
+
+        let bytes = [&prefix[..], &ascii_header[..], &[0x20; 2][..]].concat();
+        assert_eq!(
+            Err(TapeError::ShortHeader {
+                block: 0,
+                offset: 0,
+                context: Context::new(&bytes[0..])
+            }),
+            Tape::parse(&bytes).map(|_| ())
+        );
+        assert_eq!(
+            "header too short at offset 0x0 (block 0): \
+             1f a6 de ba cc 13 7d 74 ea ea ea ea ea ea ea ea",
+            Tape::parse(&bytes).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn should_read_malformed_tape_leniently() {
+        let prefix = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+        let bin_header = [0xd0; 10];
+        let ascii_header = [0xea; 10];
+        let bytes = [
+            &[0x01; 8][..],
+            &prefix[..],
+            &bin_header[..],
+            &[0x20; 6][..],
+            &prefix[..],
+            &ascii_header[..],
+            &[0x41; 6][..],
+            &prefix[..],
+            &[0x41; 8][..],
+            &prefix[..],
+            &bin_header[..],
+            &[0xe0, 0x42, 0x20, 0x20, 0x20, 0x20][..],
+            &prefix[..],
+            &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9, 0x00][..],
+            &prefix[..],
+            &bin_header[..],
+            &[0x43; 6][..],
+            &prefix[..],
+            &[0x00, 0x80, 0x00, 0x80][..],
+        ]
+        .concat();
+        let tape = Tape::from_bytes(&bytes);
+
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(
+            vec![
+                File::Header("".to_string(), FileType::Bin),
+                File::Ascii("AAAAAA".to_string(), vec![&[0x41; 8]]),
+                File::Bin(
+                    "\u{fffd}B".to_string(),
+                    0x8000,
+                    0x8000,
+                    0x8000,
+                    &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9, 0x00]
+                ),
+                File::Custom(&[&bin_header[..], &[0x43; 6][..]].concat()),
+                File::Custom(&[0x00, 0x80, 0x00, 0x80]),
+            ],
+            files
+        );
+        let problems: Vec<String> = tape
+            .problems()
+            .iter()
+            .map(|p| p.to_string().split(" at ").next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "unexpected data without block prefix",
+                "header without data block",
+                "ASCII file without EOF",
+                "binary data too short to contain addresses",
+            ],
+            problems
+        );
+
+        // A tape that ends in a header
+        let bytes = [&prefix[..], &ascii_header[..], &[0x41; 6][..]].concat();
+        let tape = Tape::from_bytes(&bytes);
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(
+            vec![File::Header("AAAAAA".to_string(), FileType::Ascii)],
+            files
+        );
+        assert_eq!(Some("AAAAAA.asc".to_string()), files[0].name());
+        assert_eq!(0, files[0].size());
+        assert_eq!(1, tape.problems().len());
+    }
+
+    /// Walks the given tape as `mcp list` and `mcp check` do, which must not panic, and
+    /// checks that it is written back as it was read
+    fn read_malformed_tape(bytes: &[u8], scan: Scan) -> Tape {
+        let tape = Tape::from_bytes_with(bytes, scan);
+        for file in tape.files() {
+            let _ = (file.name(), file.payload(), file.text_len());
+        }
+        let _ = (tape.entries(), tape.stats(), tape.problems());
+        let _ = Tape::parse_with_recovery(bytes);
+        if scan == Scan::Aligned {
+            assert_eq!(bytes, &tape.to_bytes()[..]);
+        }
+        tape
+    }
+
+    #[test]
+    fn should_read_fuzzed_tapes() {
+        let prefix = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+        let bin_header = [0xd0; 10];
+        let ascii_header = [0xea; 10];
+
+        // Short headers
+        for len in 0..6 {
+            let bytes = [&prefix[..], &bin_header[..], &[0x41; 6][..len]].concat();
+            let tape = read_malformed_tape(&bytes, Scan::Aligned);
+            let files: Vec<File> = tape.files().collect();
+            assert_eq!(vec![File::Custom(&bytes[8..])], files);
+            assert_eq!(1, tape.problems().len());
+        }
+
+        // A block prefix inside the payload of a binary file
+        let code = [&[0xaf][..], &prefix[..], &[0xc9][..]].concat();
+        let bytes = [
+            &prefix[..],
+            &bin_header[..],
+            &[0x41; 6][..],
+            &prefix[..],
+            &[0x00, 0x80, 0x09, 0x80, 0x00, 0x80][..],
+            &code[..],
+        ]
+        .concat();
+        for scan in [Scan::Aligned, Scan::Unaligned].iter().copied() {
+            let tape = read_malformed_tape(&bytes, scan);
+            let files: Vec<File> = tape.files().collect();
+            assert_eq!(1, files.len());
+            assert_eq!(&code[..], &files[0].payload()[..]);
+            assert_eq!(bytes, tape.to_bytes());
+        }
+
+        // Zero-length blocks
+        let bytes = [
+            &prefix[..],
+            &prefix[..],
+            &bin_header[..],
+            &[0x42; 6][..],
+            &prefix[..],
+            &prefix[..],
+            &ascii_header[..],
+            &[0x41; 6][..],
+            &prefix[..],
+            &prefix[..],
+            &[0x41, 0x1a, 0x1a, 0x1a, 0x1a, 0x1a, 0x1a, 0x1a][..],
+            &prefix[..],
+        ]
+        .concat();
+        let tape = read_malformed_tape(&bytes, Scan::Aligned);
+        assert_eq!(
+            vec![
+                File::Custom(&[]),
+                File::Custom(&bytes[16..32]),
+                File::Custom(&[]),
+                File::Ascii("AAAAAA".to_string(), vec![&[], &bytes[80..88]]),
+                File::Custom(&[]),
+            ],
+            tape.files().collect::<Vec<_>>()
+        );
+        let file = tape.files().nth(3).unwrap();
+        assert_eq!(b"A", &file.payload()[..]);
+        assert_eq!(1, tape.problems().len());
+    }
+
+    #[test]
+    fn should_parse_with_recovery() {
+        let prefix = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+        let bin_header = [0xd0; 10];
+        let ascii_header = [0xea; 10];
+        let bytes = [
+            &[0x01; 8][..],
+            &prefix[..],
+            &bin_header[..],
+            &[0x20; 6][..],
+            &prefix[..],
+            &ascii_header[..],
+            &[0x41; 6][..],
+            &prefix[..],
+            &[0x41; 8][..],
+            &prefix[..],
+            &bin_header[..],
+            &[0x42; 6][..],
+            &prefix[..],
+            &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0xc9, 0x00][..],
+        ]
+        .concat();
+        assert!(Tape::parse(&bytes).is_err());
+
+        let (tape, skipped) = Tape::parse_with_recovery(&bytes);
+        assert_eq!(
+            vec![
+                SkippedRegion {
+                    error: TapeError::MissingPrefix {
+                        offset: 0,
+                        context: Context::new(&bytes)
+                    },
+                    range: 0..8,
+                },
+                SkippedRegion {
+                    error: TapeError::MissingData {
+                        block: 0,
+                        offset: 8,
+                        context: Context::new(&bytes[8..])
+                    },
+                    range: 8..32,
+                },
+                SkippedRegion {
+                    error: TapeError::MissingEof {
+                        block: 1,
+                        offset: 32,
+                        context: Context::new(&bytes[32..])
+                    },
+                    range: 32..72,
+                },
+            ],
+            skipped
+        );
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(1, files.len());
+        assert_eq!(Some("BBBBBB.bin".to_string()), files[0].name());
+
+        let (tape, skipped) = Tape::parse_with_recovery(&bytes[72..]);
+        assert!(skipped.is_empty());
+        assert_eq!(2, tape.blocks().len());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn should_append_to_path() {
+        let dir = TempDir::new("mcp").unwrap();
+        let path = dir.path().join("foobar.cas");
+        let (fname, _) = file_name("foobar");
+
+        let mut first = Tape::new();
+        first
+            .append_bin(&fname, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0x01, 0x02])
+            .unwrap();
+        first.append_to_path(&path).unwrap();
+        let mut second = Tape::new();
+        second.append_ascii(&fname, b"10 END").unwrap();
+        second.append_to_path(&path).unwrap();
+
+        let tape = Tape::from_file(&path).unwrap();
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(2, files.len());
+        assert_eq!("foobar.bin", files[0].name().unwrap());
+        assert_eq!("foobar.asc", files[1].name().unwrap());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn should_pad_blocks_appended_to_path() {
+        let dir = TempDir::new("mcp").unwrap();
+        let path = dir.path().join("foobar.cas");
+        // An ASCII header with 3 extra bytes, so its data block is not aligned
+        let mut bytes = PREFIX.to_vec();
+        bytes.extend_from_slice(&[0xea; 10]);
+        bytes.extend_from_slice(b"foobar\0\0\0");
+        bytes.extend_from_slice(&PREFIX);
+        bytes.extend_from_slice(&[0x1a; 256]);
+        let tape = Tape::from_bytes_with(&bytes, Scan::Unaligned);
+        assert_eq!(2, tape.blocks().len());
+
+        tape.append_to_path(&path).unwrap();
+        let written = fs::read(&path).unwrap();
+        assert_eq!(tape.to_bytes(), written);
+        let mut read = vec![];
+        tape.reader().read_to_end(&mut read).unwrap();
+        assert_eq!(read, written);
+        assert_eq!(2, Tape::from_file(&path).unwrap().blocks().len());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn should_rewrite_unaligned_path_to_append() {
+        let dir = TempDir::new("mcp").unwrap();
+        let path = dir.path().join("foobar.cas");
+        let bytes = [
+            0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74, 0x01, 0x02, 0x03,
+        ];
+        fs::write(&path, &bytes[..]).unwrap();
+
+        let mut tape = Tape::new();
+        tape.append_custom(&[0x01; 8]).unwrap();
+        tape.append_to_path(&path).unwrap();
+        let mut expected = Tape::from_bytes_with(&bytes, Scan::Unaligned);
+        expected.extend_from(&tape);
+        let written = fs::read(&path).unwrap();
+        assert_eq!(expected.to_bytes(), written);
+        assert_eq!(0, written.len() % 8);
+        assert_eq!(2, Tape::from_file(&path).unwrap().blocks().len());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn should_not_append_to_other_files() {
+        let dir = TempDir::new("mcp").unwrap();
+        let path = dir.path().join("foobar.cas");
+        let mut tape = Tape::new();
+        tape.append_custom(&[0x01; 8]).unwrap();
+        for bytes in [&[0x01; 8][..], &[0x01; 3][..]] {
+            fs::write(&path, bytes).unwrap();
+            let err = tape.append_to_path(&path).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            assert_eq!(bytes, &fs::read(&path).unwrap()[..]);
+        }
+    }
+
     fn should_add_bin_file_prop(bytes: Vec<u8>) -> TestResult {
         if Tape::validate_bin(&bytes[..]).is_err() {
             return TestResult::discard();
         }
         let mut tape = Tape::new();
-        let (fname, _) = file_name(&"foobar");
+        let (fname, _) = file_name("foobar");
         let padding = tape.append_bin(&fname, &bytes[..]).unwrap();
 
         let files = Vec::from_iter(tape.files());
@@ -688,7 +3420,7 @@ mod test {
         );
         require_prop!(
             "block content is as expected",
-            &tape.blocks()[1].data_without_prefix()[0..bytes.len()] == &bytes[..]
+            tape.blocks()[1].data_without_prefix()[0..bytes.len()] == bytes[..]
         );
         require_prop!(
             "padding bytes as expected",
@@ -697,6 +3429,43 @@ mod test {
         TestResult::from_bool(true)
     }
 
+    #[test]
+    fn should_read_and_seek_serialized_tape() {
+        let mut tape = Tape::new();
+        tape.append_bin(
+            &file_name("game").0,
+            &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xc9, 0x00],
+        )
+        .unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        let bytes: Vec<u8> = tape
+            .blocks()
+            .iter()
+            .flat_map(|b| b.data().to_vec())
+            .collect();
+
+        let mut reader = tape.reader();
+        assert_eq!(bytes.len() as u64, reader.len());
+        let mut all = vec![];
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(bytes, all);
+
+        let mut buf = [0; 12];
+        assert_eq!(20, reader.seek(SeekFrom::Start(20)).unwrap());
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&bytes[20..32], &buf[..]);
+        assert_eq!(
+            bytes.len() as u64 - 4,
+            reader.seek(SeekFrom::End(-4)).unwrap()
+        );
+        assert_eq!(4, reader.read(&mut buf).unwrap());
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+        assert!(reader.seek(SeekFrom::Current(-100)).is_err());
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+        assert_eq!(0, Tape::new().reader().read(&mut buf).unwrap());
+    }
+
     #[test]
     fn should_add_bin_file() {
         quickcheck(should_add_bin_file_prop as fn(Vec<u8>) -> TestResult);
@@ -707,7 +3476,7 @@ mod test {
             return TestResult::discard();
         }
         let mut tape = Tape::new();
-        let (fname, _) = file_name(&"foobar");
+        let (fname, _) = file_name("foobar");
         let padding = tape.append_basic(&fname, &bytes[..]).unwrap();
 
         let files = Vec::from_iter(tape.files());
@@ -719,7 +3488,7 @@ mod test {
         );
         require_prop!(
             "block content is as expected",
-            &tape.blocks()[1].data_without_prefix()[0..bytes.len()] == &bytes[..]
+            tape.blocks()[1].data_without_prefix()[0..bytes.len()] == bytes[..]
         );
         require_prop!(
             "padding bytes as expected",
@@ -735,7 +3504,7 @@ mod test {
 
     fn should_add_ascii_file_prop(text: String) -> TestResult {
         let mut tape = Tape::new();
-        let (fname, _) = file_name(&"foobar");
+        let (fname, _) = file_name("foobar");
         tape.append_ascii(&fname, text.as_bytes()).unwrap();
 
         let files = Vec::from_iter(tape.files());
@@ -746,6 +3515,8 @@ mod test {
             files[0].name().unwrap() == "foobar.asc"
         );
 
+        // The text ends at the first EOF byte
+        let text = text.split('\u{1a}').next().unwrap_or_default();
         let chunks = Vec::from_iter(text.as_bytes().chunks(256));
         for i in 0..chunks.len() {
             let block_data = tape.blocks[i + 1].data_without_prefix();
@@ -775,4 +3546,37 @@ mod test {
     fn should_add_ascii_file() {
         quickcheck(should_add_ascii_file_prop as fn(String) -> TestResult);
     }
+
+    #[test]
+    fn should_stop_ascii_files_at_eof() {
+        let (name, _) = file_name("text");
+        for len in [0, 1, 255, 256, 512] {
+            let text = vec![b'A'; len];
+            let mut tape = Tape::new();
+            tape.append_ascii(&name, &text).unwrap();
+            let files = Vec::from_iter(tape.files());
+            assert_eq!(1, files.len(), "{} bytes", len);
+            assert_eq!(Some(len), files[0].text_len());
+            assert_eq!(&text[..], &files[0].payload()[..]);
+            assert_eq!(len / 256 + 2, tape.blocks().len());
+            assert!(tape.problems().is_empty());
+        }
+
+        // Text after an EOF byte is left out, so no chunk is taken as a custom file
+        let mut text = vec![b'A'; 600];
+        text[300] = 0x1a;
+        let mut tape = Tape::new();
+        assert_eq!(212, tape.append_ascii(&name, &text).unwrap());
+        let files = Vec::from_iter(tape.files());
+        assert_eq!(1, files.len());
+        assert_eq!(Some(300), files[0].text_len());
+        assert_eq!(&text[..300], &files[0].payload()[..]);
+
+        // An EOF byte in the middle of a chunk of a dump ends the file there
+        let chunk = [&[b'B'; 10][..], &[0x1a][..], &[b'C'; 245][..]].concat();
+        let file = File::Ascii("TEXT".to_string(), vec![&chunk, &[0x1a; 256]]);
+        assert_eq!(Some(10), file.text_len());
+        assert_eq!(&[b'B'; 10][..], &file.payload()[..]);
+        assert_eq!(None, File::Custom(&chunk).text_len());
+    }
 }
@@ -0,0 +1,105 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::str::FromStr;
+
+/// A line ending convention for text files
+///
+/// MSX Basic stores ASCII programs with `CrLf` line endings, while modern systems
+/// typically use `Lf` (UNIX) or `CrLf` (Windows).
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Cr,
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Returns the bytes that represent this line ending.
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Cr => b"\r",
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<LineEnding> {
+        match s.to_lowercase().as_str() {
+            "cr" => Ok(LineEnding::Cr),
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::CrLf),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown line ending {:?} (expected cr, lf or crlf)", s),
+            )),
+        }
+    }
+}
+
+/// Converts the line endings of the given text to `eol`
+///
+/// Any of `CR`, `LF` or `CRLF` sequences found in `text` is considered a line ending and
+/// replaced by `eol`. Other bytes are copied as they are.
+///
+pub fn convert_line_endings(text: &[u8], eol: LineEnding) -> Vec<u8> {
+    let mut result = Vec::with_capacity(text.len() + text.len() / 16);
+    let mut bytes = text.iter().peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\r' => {
+                bytes.next_if_eq(&&b'\n');
+                result.extend_from_slice(eol.as_bytes());
+            }
+            b'\n' => result.extend_from_slice(eol.as_bytes()),
+            _ => result.push(*byte),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_parse_line_ending() {
+        assert_eq!(LineEnding::Cr, "cr".parse().unwrap());
+        assert_eq!(LineEnding::Lf, "LF".parse().unwrap());
+        assert_eq!(LineEnding::CrLf, "crlf".parse().unwrap());
+        assert!("foo".parse::<LineEnding>().is_err());
+    }
+
+    #[test]
+    fn should_convert_line_endings() {
+        let text = b"10 CLS\r\n20 PRINT\r30 END\nEOF";
+        assert_eq!(
+            b"10 CLS\n20 PRINT\n30 END\nEOF".to_vec(),
+            convert_line_endings(text, LineEnding::Lf)
+        );
+        assert_eq!(
+            b"10 CLS\r\n20 PRINT\r\n30 END\r\nEOF".to_vec(),
+            convert_line_endings(text, LineEnding::CrLf)
+        );
+        assert_eq!(
+            b"10 CLS\r20 PRINT\r30 END\rEOF".to_vec(),
+            convert_line_endings(text, LineEnding::Cr)
+        );
+        assert_eq!(
+            b"\n\n".to_vec(),
+            convert_line_endings(b"\n\r", LineEnding::Lf)
+        );
+    }
+}
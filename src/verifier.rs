@@ -0,0 +1,207 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Generation of a Basic program that verifies a tape on the MSX itself
+//!
+//! The verifier is appended at the end of the tape. Once loaded with `CLOAD` and run,
+//! it asks to rewind the tape and reads back every file it can check, comparing the sum
+//! of its bytes with the one stored in its `DATA` table:
+//!
+//! * Binary files are loaded with `BLOAD` into a buffer at `0x9000`, relocated with the
+//!   offset argument so they never overwrite the verifier. Files larger than the buffer
+//!   (16KB) are not checked.
+//! * ASCII files are read character by character until their EOF.
+//! * Tokenized Basic files would replace the verifier when loaded, so they are listed but
+//!   not checked. Custom files have no name to look for, so they are ignored.
+
+use std::io;
+
+use crate::basic;
+use crate::tape::{self, File, Tape};
+
+/// The name of the verifier program in the tape
+pub const NAME: &str = "VERIFY";
+
+/// The address of the buffer binary files are loaded into
+const BUFFER: u16 = 0x9000;
+
+/// The size of the buffer binary files are loaded into
+const BUFFER_LEN: usize = 0x4000;
+
+/// The maximum size of the verifier, so it does not reach the end of the memory
+/// reserved for Basic with `CLEAR`
+const MAX_PROGRAM_LEN: usize = 0x0c00;
+
+/// How the verifier handles a file of the tape
+#[derive(Clone, Debug, PartialEq)]
+pub enum Check {
+    /// The file is checked against the sum of its bytes
+    Sum(String, u32),
+    /// The file is listed but cannot be checked, for the given reason
+    Skipped(String, &'static str),
+    /// The file is not listed (custom files)
+    Ignored,
+}
+
+/// Returns how the verifier handles each file of the given tape
+pub fn checks(tape: &Tape) -> Vec<Check> {
+    tape.files().map(|file| check_of(&file)).collect()
+}
+
+fn check_of(file: &File) -> Check {
+    let name = match file {
+        File::Bin(name, ..)
+        | File::Basic(name, _)
+        | File::Ascii(name, _)
+        | File::Header(name, _) => name.trim_end().to_string(),
+        File::Custom(_) => return Check::Ignored,
+    };
+    if name.contains('"') || !name.is_ascii() {
+        return Check::Skipped(name, "NAME");
+    }
+    match file {
+        File::Bin(_, begin, end, ..) => {
+            let len = (end + 1).saturating_sub(*begin);
+            if len > BUFFER_LEN {
+                return Check::Skipped(name, "TOO BIG");
+            }
+            let program = file.payload();
+            Check::Sum(name, sum(&program[..len.min(program.len())]))
+        }
+        File::Ascii(..) => Check::Sum(name, sum(&file.payload())),
+        File::Header(..) => Check::Skipped(name, "NO DATA"),
+        _ => Check::Skipped(name, "BASIC"),
+    }
+}
+
+fn sum(data: &[u8]) -> u32 {
+    data.iter().map(|b| *b as u32).sum()
+}
+
+/// Returns the Basic source of the verifier of the given tape
+pub fn source(tape: &Tape) -> Vec<u8> {
+    let mut source = format!(
+        "10 CLEAR 200,&H{:X}:KEY OFF:F=0\r\n\
+         20 PRINT \"TAPE VERIFIER\":PRINT \"REWIND THE TAPE, PRESS PLAY\"\r\n\
+         30 PRINT \"AND PRESS ANY KEY\":A$=INPUT$(1)\r\n\
+         40 READ T$,N$,O,L,S:IF T$=\"\" THEN 100\r\n\
+         50 PRINT N$;\" \";:C=0:IF T$=\"B\" THEN 60 ELSE IF T$=\"A\" THEN 70 ELSE PRINT T$:GOTO 40\r\n\
+         60 BLOAD \"CAS:\"+N$,O:FOR I=&H{:X} TO &H{:X}+L-1:C=C+PEEK(I):NEXT:GOTO 90\r\n\
+         70 OPEN \"CAS:\"+N$ FOR INPUT AS #1\r\n\
+         80 IF EOF(1) THEN CLOSE #1 ELSE C=C+ASC(INPUT$(1,#1)):GOTO 80\r\n\
+         90 IF C=S THEN PRINT \"OK\" ELSE PRINT \"FAIL\":F=F+1\r\n\
+         95 GOTO 40\r\n\
+         100 PRINT:IF F=0 THEN PRINT \"ALL FILES OK\" ELSE PRINT F;\"FILES FAILED\"\r\n",
+        BUFFER - 1,
+        BUFFER,
+        BUFFER
+    );
+    let mut line = 1000;
+    for (check, file) in checks(tape).iter().zip(tape.files()) {
+        let entry = match (check, file) {
+            (Check::Sum(name, sum), File::Bin(_, begin, end, ..)) => {
+                let offset = BUFFER.wrapping_sub(begin as u16);
+                let len = (end + 1).saturating_sub(begin);
+                format!("B,\"{}\",&H{:X},{},{}", name, offset, len, sum)
+            }
+            (Check::Sum(name, sum), _) => format!("A,\"{}\",0,0,{}", name, sum),
+            (Check::Skipped(name, reason), _) => {
+                let name = name.replace('"', "'");
+                format!("\"NOT CHECKED ({})\",\"{}\",0,0,0", reason, name)
+            }
+            (Check::Ignored, _) => continue,
+        };
+        source.push_str(&format!("{} DATA {}\r\n", line, entry));
+        line += 10;
+    }
+    source.push_str(&format!("{} DATA \"\",\"\",0,0,0\r\n", line));
+    source.into_bytes()
+}
+
+/// Returns the tokenized verifier of the given tape.
+///
+/// Fails with an error of kind `InvalidInput` if the tape has too many files to fit
+/// their `DATA` table in memory.
+///
+pub fn program(tape: &Tape) -> io::Result<Vec<u8>> {
+    let program = basic::tokenize(&source(tape)).map_err(|errors| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid verifier program: {}", errors[0]),
+        )
+    })?;
+    if program.len() > MAX_PROGRAM_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the tape has too many files for the verifier ({} bytes, at most {} allowed)",
+                program.len(),
+                MAX_PROGRAM_LEN
+            ),
+        ));
+    }
+    Ok(program)
+}
+
+/// Append the verifier of the files of the given tape to it.
+///
+/// The verifier is appended as a tokenized Basic file named `NAME`. Returns the checks
+/// it performs.
+///
+pub fn append(tape: &mut Tape) -> io::Result<Vec<Check>> {
+    let checks = checks(tape);
+    let program = program(tape)?;
+    let (name, _) = tape::file_name(NAME);
+    tape.append_basic(&name, &program)?;
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_append_verifier() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0xc0, 0x01, 0xc0, 0x00, 0xc0, 0x10, 0x20])
+            .unwrap();
+        tape.append_custom(&[1, 2, 3]).unwrap();
+        let (name, _) = tape::file_name("text");
+        tape.append_ascii(&name, b"10 END\r\n").unwrap();
+        let (name, _) = tape::file_name("prog");
+        tape.append_basic(&name, &[0x00, 0x00]).unwrap();
+        let (name, _) = tape::file_name("big");
+        let mut big = vec![0x00, 0x80, 0x00, 0xc0, 0x00, 0x80];
+        big.resize(6 + 0x4001, 0);
+        tape.append_bin(&name, &big).unwrap();
+
+        let checks = append(&mut tape).unwrap();
+        assert_eq!(
+            vec![
+                Check::Sum("game".to_string(), 0x30),
+                Check::Ignored,
+                Check::Sum("text".to_string(), 367),
+                Check::Skipped("prog".to_string(), "BASIC"),
+                Check::Skipped("big".to_string(), "TOO BIG"),
+            ],
+            checks
+        );
+
+        let files: Vec<File> = tape.files().collect();
+        assert_eq!(6, files.len());
+        assert_eq!(Some("VERIFY.bas".to_string()), files[5].name());
+        let listing = basic::detokenize(&files[5].raw_data()).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        assert!(listing.contains("1000 DATA B,\"game\",&HD000,2,48\n"));
+        assert!(listing.contains("1010 DATA A,\"text\",0,0,367\n"));
+        assert!(listing.contains("1020 DATA \"NOT CHECKED (BASIC)\",\"prog\",0,0,0\n"));
+        assert!(listing.contains("1040 DATA \"\",\"\",0,0,0\n"));
+    }
+}
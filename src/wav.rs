@@ -7,17 +7,189 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::f32;
+use std::fmt;
 use std::io;
 use std::io::Write;
 use std::iter::FromIterator;
+use std::str::FromStr;
+use std::time::Duration;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 
+use crate::progress::{self, Progress};
+use crate::tape::{Block, Tape};
+
 const SHORT_PULSE: u32 = 2400;
 const LONG_PULSE: u32 = 1200;
 
-const SHORT_HEADER: u32 = 4000;
-const LONG_HEADER: u32 = 16000;
+pub(crate) const SHORT_HEADER: u32 = 4000;
+pub(crate) const LONG_HEADER: u32 = 16000;
+
+/// The speeds tapes can be encoded at, in bauds
+///
+/// The MSX BIOS writes tapes at 1200 or 2400 bauds. It measures the speed of every block
+/// from its header, so many machines also read the turbo speeds of 3600 and 4800 bauds
+/// used by CasLink and other tools, although not every cassette interface copes with them.
+pub const SPEEDS: [u32; 4] = [1200, 2400, 3600, 4800];
+
+/// The timing parameters used to encode tapes as audio
+///
+/// Every block is preceded by a silence (the gap) and a header of short pulses, both
+/// longer for file headers. Tapes are encoded at `bauds`, except for the files before
+/// `turbo_from`, which are encoded at 1200 bauds so that a loader among them can read the
+/// rest at a turbo speed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Profile {
+    pub bauds: u32,
+    pub sample_rate: u32,
+    /// The silence before blocks that are not file headers, in milliseconds
+    pub short_gap_ms: u32,
+    /// The silence before file header blocks, in milliseconds
+    pub long_gap_ms: u32,
+    /// The index of the first file encoded at `bauds`
+    pub turbo_from: usize,
+}
+
+impl Default for Profile {
+    /// 1200 bauds, 43200 samples per second, and gaps of 1 and 2 seconds
+    fn default() -> Profile {
+        Profile {
+            bauds: 1200,
+            sample_rate: 43200,
+            short_gap_ms: 1000,
+            long_gap_ms: 2000,
+            turbo_from: 0,
+        }
+    }
+}
+
+impl Profile {
+    /// Returns the default profile at the given speed
+    ///
+    /// The sample rate is 43200 samples per second, or 48000 for speeds whose pulses do
+    /// not take a whole number of samples at 43200 (i.e., 4800 bauds).
+    pub fn with_bauds(bauds: u32) -> Profile {
+        let profile = Profile {
+            bauds,
+            ..Profile::default()
+        };
+        if profile.sample_rate.is_multiple_of(2 * bauds) {
+            profile
+        } else {
+            Profile {
+                sample_rate: 48000,
+                ..profile
+            }
+        }
+    }
+
+    /// Check that tapes can be encoded with this profile
+    ///
+    /// Fails with an error of kind `InvalidInput` if the speed is not one of `SPEEDS` or
+    /// its pulses do not take a whole number of samples.
+    pub fn validate(&self) -> io::Result<()> {
+        if !SPEEDS.contains(&self.bauds) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported speed of {} bauds", self.bauds),
+            ));
+        }
+        if !self.sample_rate.is_multiple_of(2 * self.bauds) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the sample rate must be a multiple of {} to encode at {} bauds",
+                    2 * self.bauds,
+                    self.bauds
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the speed of each block of the given tape
+    pub fn block_bauds(&self, tape: &Tape) -> Vec<u32> {
+        let mut bauds = vec![self.bauds; tape.blocks().len()];
+        for (index, (blocks, _)) in tape.file_extents().into_iter().enumerate() {
+            if index < self.turbo_from {
+                bauds[blocks].fill(1200);
+            }
+        }
+        bauds
+    }
+
+    /// Returns this profile at the given speed
+    fn at(&self, bauds: u32) -> Profile {
+        Profile { bauds, ..*self }
+    }
+
+    /// Returns the number of samples of a pulse of the given frequency
+    fn pulse_len(&self, freq: u32) -> u64 {
+        (self.sample_rate / (self.bauds * (freq / 1200))) as u64
+    }
+
+    fn header_len(&self, pulses: u32) -> u64 {
+        (pulses * self.bauds / 1200) as u64 * self.pulse_len(SHORT_PULSE)
+    }
+
+    fn gap_len(&self, ms: u32) -> u64 {
+        self.sample_rate as u64 * ms as u64 / 1000
+    }
+
+    fn byte_len(&self, byte: u8) -> u64 {
+        let ones = byte.count_ones() as u64;
+        let (short, long) = (self.pulse_len(SHORT_PULSE), self.pulse_len(LONG_PULSE));
+        long + ones * 2 * short + (8 - ones) * long + 4 * short
+    }
+
+    /// Returns the duration of the given number of samples, rounded up to the nanosecond
+    fn duration_of(&self, samples: u64) -> Duration {
+        let rate = self.sample_rate as u64;
+        Duration::from_nanos((samples * 1_000_000_000).div_ceil(rate))
+    }
+
+    fn samples_at(&self, time: Duration) -> u64 {
+        (time.as_nanos() * self.sample_rate as u128 / 1_000_000_000) as u64
+    }
+}
+
+/// A compact cassette, identified by its total playing time in minutes (e.g. `C60`)
+///
+/// Both sides of a cassette play for half its total time, so a `C60` holds up to 30
+/// minutes of audio per side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cassette {
+    pub minutes: u32,
+}
+
+impl Cassette {
+    /// Returns the playing time of one side of the cassette
+    pub fn side_duration(&self) -> Duration {
+        Duration::from_secs(self.minutes as u64 * 30)
+    }
+}
+
+impl fmt::Display for Cassette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "C{}", self.minutes)
+    }
+}
+
+impl FromStr for Cassette {
+    type Err = io::Error;
+
+    /// Parse a cassette length such as `C60` (or just `60`)
+    fn from_str(s: &str) -> io::Result<Cassette> {
+        let digits = s.strip_prefix(|c| c == 'C' || c == 'c').unwrap_or(s);
+        match digits.parse::<u32>() {
+            Ok(minutes) if minutes > 0 => Ok(Cassette { minutes }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid cassette length {:?} (expected C60, C90, etc.)", s),
+            )),
+        }
+    }
+}
 
 /// An object capable to export binary data in WAV format
 ///
@@ -26,183 +198,509 @@ const LONG_HEADER: u32 = 16000;
 /// the `export()` method to generate the corresponding WAV header and dump
 /// the content into a valid WAV file.
 pub struct Exporter {
-	bauds: u32,
-	sample_rate: u32,
-	buffer: Vec<u8>,
+    profile: Profile,
+    buffer: Vec<u8>,
+}
+
+impl Default for Exporter {
+    fn default() -> Exporter {
+        Exporter::new()
+    }
 }
 
 impl Exporter {
+    /// Create a new exporter using default settings
+    ///
+    /// Default settins are 1200 bauds and 43200 samples per second.
+    pub fn new() -> Exporter {
+        Exporter::with_profile(Profile::default())
+    }
+
+    /// Create a new exporter using the given timing profile
+    pub fn with_profile(profile: Profile) -> Exporter {
+        Exporter {
+            profile,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Export the encoded data to the given `Write` instance
+    ///
+    /// This method dumps the encoded data into the given `Write` instance. Before
+    /// calling this method, you must use the `write_X()` functions to encode
+    /// some data.
+    pub fn export<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.write_wave(w)?;
+        w.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    /// Write a short header to the internal buffer
+    pub fn write_short_header(&mut self) -> io::Result<usize> {
+        self.write_header(SHORT_HEADER)
+    }
+
+    /// Write a long header to the internal buffer
+    pub fn write_long_header(&mut self) -> io::Result<usize> {
+        self.write_header(LONG_HEADER)
+    }
+
+    /// Write a header comprised by the given amount of pulses to the internal buffer
+    pub fn write_header(&mut self, pulses: u32) -> io::Result<usize> {
+        let to = pulses * self.profile.bauds / 1200;
+        let mut nbytes = 0;
+        for _ in 0..to {
+            nbytes += self.write_pulse(SHORT_PULSE)?;
+        }
+        Ok(nbytes)
+    }
+
+    /// Write a short silence (1 second by default) to the internal buffer
+    pub fn write_short_silence(&mut self) -> io::Result<usize> {
+        let pulses = self.profile.gap_len(self.profile.short_gap_ms) as u32;
+        self.write_silence(pulses)
+    }
+
+    /// Write a long silence (2 seconds by default) to the internal buffer
+    pub fn write_long_silence(&mut self) -> io::Result<usize> {
+        let pulses = self.profile.gap_len(self.profile.long_gap_ms) as u32;
+        self.write_silence(pulses)
+    }
+
+    /// Write a silence comprised by the given amount of pulses to the internal buffer
+    pub fn write_silence(&mut self, pulses: u32) -> io::Result<usize> {
+        let mut nbytes = 0;
+        for _ in 0..pulses {
+            nbytes += self.buffer.write(&[0x80])?;
+        }
+        Ok(nbytes)
+    }
+
+    /// Write a silence of the given duration to the internal buffer
+    ///
+    /// Used to separate programs when several tapes are recorded one after another.
+    pub fn write_pause(&mut self, duration: Duration) -> io::Result<usize> {
+        let samples = self.profile.samples_at(duration) as u32;
+        self.write_silence(samples)
+    }
+
+    /// Write a tape block to the internal buffer at the given speed, instead of that of
+    /// the profile
+    pub fn write_block_at(&mut self, block: &Block, bauds: u32) -> io::Result<usize> {
+        let profile = self.profile;
+        self.profile = profile.at(bauds);
+        let result = self.write_block(block);
+        self.profile = profile;
+        result
+    }
+
+    /// Write a tape block to the internal buffer
+    ///
+    /// File header blocks are preceded by a long silence and a long header, while
+    /// any other block is preceded by a short silence and a short header.
+    pub fn write_block(&mut self, block: &Block) -> io::Result<usize> {
+        let mut nbytes = 0;
+        if block.is_file_header() {
+            nbytes += self.write_long_silence()?;
+            nbytes += self.write_long_header()?;
+        } else {
+            nbytes += self.write_short_silence()?;
+            nbytes += self.write_short_header()?;
+        }
+        nbytes += self.write_data(block.data_without_prefix())?;
+        Ok(nbytes)
+    }
+
+    /// Write all the blocks of a tape to the internal buffer
+    ///
+    /// Each block is written at the speed given by `Profile::block_bauds()`. The progress
+    /// is reported as the number of blocks written out of the total. If the
+    /// observer is cancelled, the blocks written so far are kept and an error of kind
+    /// `Interrupted` is returned.
+    pub fn write_tape(&mut self, tape: &Tape, progress: &mut dyn Progress) -> io::Result<usize> {
+        let total = tape.blocks().len() as u64;
+        let mut nbytes = 0;
+        let bauds = self.profile.block_bauds(tape);
+        for (i, block) in tape.blocks().iter().enumerate() {
+            nbytes += self.write_block_at(block, bauds[i])?;
+            progress.update(i as u64 + 1, Some(total));
+            if progress.is_cancelled() {
+                return Err(progress::cancelled());
+            }
+        }
+        Ok(nbytes)
+    }
+
+    /// Returns the sample rate used to encode the data
+    pub fn sample_rate(&self) -> u32 {
+        self.profile.sample_rate
+    }
+
+    /// Returns the playing time of the audio encoded so far
+    pub fn elapsed(&self) -> Duration {
+        self.profile.duration_of(self.buffer.len() as u64)
+    }
+
+    /// Returns the 8-bit unsigned PCM samples encoded so far
+    pub fn samples(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Write 8-bit unsigned PCM samples to the internal buffer as they are
+    ///
+    /// This is used to save audio that is not encoded by the exporter, such as a recording
+    /// of a tape, along with the WAV header.
+    ///
+    pub fn write_samples(&mut self, samples: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(samples);
+        Ok(samples.len())
+    }
+
+    /// Write binary data to the internal buffer
+    pub fn write_data(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut nbytes = 0;
+        for byte in data {
+            nbytes += self.write_byte(*byte)?;
+        }
+        Ok(nbytes)
+    }
+
+    fn write_wave<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let data_len = self.buffer.len() as u32;
+        let file_len = data_len + 44;
+
+        // RIFF chunk start
+        write!(w, "RIFF")?;
+
+        // RIFF chunk length (size of overall file)
+        w.write_u32::<LittleEndian>(file_len)?;
+
+        // WAVE chunk start
+        write!(w, "WAVE")?;
+
+        // Format chunk start
+        write!(w, "fmt ")?;
+
+        // Format chunk length
+        w.write_u32::<LittleEndian>(16)?;
+
+        // Type of format (PCM)
+        w.write_u16::<LittleEndian>(1)?;
+
+        // Number of channels
+        w.write_u16::<LittleEndian>(1)?;
+
+        // Sample rate
+        w.write_u32::<LittleEndian>(self.profile.sample_rate)?;
+
+        // Sample rate * bits per sample * channels / 8
+        w.write_u32::<LittleEndian>(self.profile.sample_rate)?;
+
+        // Bits per sample * channels
+        w.write_u16::<LittleEndian>(8)?;
+
+        // Bits per sample
+        w.write_u16::<LittleEndian>(8)?;
+
+        // Data chunk start
+        write!(w, "data")?;
+
+        // Data chunk length
+        w.write_u32::<LittleEndian>(data_len)?;
+
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<usize> {
+        let mut nbytes = 0;
+        nbytes += self.write_pulse(LONG_PULSE)?;
+        let mut bits = byte;
+        for _ in 0..8 {
+            if bits & 0x01 > 0 {
+                nbytes += self.write_pulse(SHORT_PULSE)?;
+                nbytes += self.write_pulse(SHORT_PULSE)?;
+            } else {
+                nbytes += self.write_pulse(LONG_PULSE)?;
+            }
+            bits >>= 1;
+        }
+        for _ in 0..4 {
+            nbytes += self.write_pulse(SHORT_PULSE)?;
+        }
+        Ok(nbytes)
+    }
+
+    fn write_pulse(&mut self, freq: u32) -> io::Result<usize> {
+        let len = self.profile.pulse_len(freq) as u32;
+        let scale = 2.0 * f32::consts::PI / len as f32;
+        let func = |x: f32| (f32::sin(scale * x) * 127.0) as i8 as u8 ^ 0x80;
+        let bytes = Vec::from_iter((0..len).map(|x| func(x as f32)));
+        self.buffer.write(&bytes[..])
+    }
+}
+
+/// A position in the audio encoding of a tape
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    /// The index of the block being played
+    pub block: usize,
+    /// The index of the file the block belongs to
+    pub file: usize,
+    /// The offset in the block data (excluding the prefix bytes) of the byte being played,
+    /// or `None` if the silence or header that precede the data are being played
+    pub byte: Option<usize>,
+}
+
+/// The samples taken by the encoding of a block: its gap and header, and its data
+#[derive(Clone, Debug)]
+struct Span {
+    start: u64,
+    data: u64,
+    end: u64,
+    bauds: u32,
+}
+
+/// A map between the time offsets of the audio encoding of a tape and its contents
+///
+/// The timeline follows the encoding of `Exporter` with the same `Profile`, so it tells
+/// what is loading at any time of the exported audio and where to seek to play a given
+/// block or file.
+pub struct Timeline<'a> {
+    tape: &'a Tape,
+    profile: Profile,
+    spans: Vec<Span>,
+    files: Vec<usize>,
+}
 
-	/// Create a new exporter using default settings
-	///
-	/// Default settins are 1200 bauds and 43200 samples per second.
-	pub fn new() -> Exporter {
-		Exporter {
-			bauds: 1200,
-			sample_rate: 43200,
-			buffer: Vec::new(),
-		}
-	}
-
-	/// Export the encoded data to the given `Write` instance
-	///
-	/// This method dumps the encoded data into the given `Write` instance. Before
-	/// calling this method, you must use the `write_X()` functions to encode
-	/// some data.
-	pub fn export<W: Write>(&self, w: &mut W) -> io::Result<()> {
-		self.write_wave(w)?;
-		w.write(&*self.buffer)?;
-		Ok(())
-	}
-
-	/// Write a short header to the internal buffer
-	pub fn write_short_header(&mut self) -> io::Result<usize> {
-		self.write_header(SHORT_HEADER)
-	}
-
-	/// Write a long header to the internal buffer
-	pub fn write_long_header(&mut self) -> io::Result<usize> {
-		self.write_header(LONG_HEADER)
-	}
-
-	/// Write a header comprised by the given amount of pulses to the internal buffer
-	pub fn write_header(&mut self, pulses: u32) -> io::Result<usize> {
-		let to = pulses * self.bauds / 1200;
-		let mut nbytes = 0;
-		for _ in 0..to {
-			nbytes += self.write_pulse(SHORT_PULSE)?;
-		}
-		Ok(nbytes)
-	}
-
-	/// Write a short silence (1 second) to the internal buffer
-	pub fn write_short_silence(&mut self) -> io::Result<usize> {
-		let pulses = self.sample_rate;
-		self.write_silence(pulses)
-	}
-
-	/// Write a long silence (2 seconds) to the internal buffer
-	pub fn write_long_silence(&mut self) -> io::Result<usize> {
-		let pulses = self.sample_rate * 2;
-		self.write_silence(pulses)
-	}
-
-	/// Write a silence comprised by the given amount of pulses to the internal buffer
-	pub fn write_silence(&mut self, pulses: u32) ->  io::Result<usize> {
-		let mut nbytes = 0;
-		for _ in 0..pulses {
-			nbytes += self.buffer.write(&[0x80])?;
-		}
-		Ok(nbytes)
-	}
-
-	/// Write binary data to the internal buffer
-	pub fn write_data(&mut self, data: &[u8]) -> io::Result<usize> {
-		let mut nbytes = 0;
-		for byte in data {
-			nbytes += self.write_byte(*byte)?;
-		}
-		Ok(nbytes)
-	}
-
-	fn write_wave<W: Write>(&self, w: &mut W) -> io::Result<()> {
-		let data_len = self.buffer.len() as u32;
-		let file_len = data_len + 44;
-
-		// RIFF chunk start
-		write!(w, "RIFF")?;
-
-		// RIFF chunk length (size of overall file)
-		w.write_u32::<LittleEndian>(file_len)?;
-
-		// WAVE chunk start
-		write!(w, "WAVE")?;
-
-		// Format chunk start
-		write!(w, "fmt ")?;
-
-		// Format chunk length
-		w.write_u32::<LittleEndian>(16)?;
-
-		// Type of format (PCM)
-		w.write_u16::<LittleEndian>(1)?;
-
-		// Number of channels
-		w.write_u16::<LittleEndian>(1)?;
-
-		// Sample rate
-		w.write_u32::<LittleEndian>(self.sample_rate)?;
-
-		// Sample rate * bits per sample * channels / 8
-		w.write_u32::<LittleEndian>(self.sample_rate)?;
-
-		// Bits per sample * channels
-		w.write_u16::<LittleEndian>(8)?;
-
-		// Bits per sample
-		w.write_u16::<LittleEndian>(8)?;
-
-		// Data chunk start
-		write!(w, "data")?;
-
-		// Data chunk length
-		w.write_u32::<LittleEndian>(data_len)?;
-
-		Ok(())
-	}
-
-	fn write_byte(&mut self, byte: u8) -> io::Result<usize> {
-		let mut nbytes = 0;
-		nbytes += self.write_pulse(LONG_PULSE)?;
-		let mut bits = byte;
-		for _ in 0..8 {
-			if bits & 0x01 > 0 {
-				nbytes += self.write_pulse(SHORT_PULSE)?;
-				nbytes += self.write_pulse(SHORT_PULSE)?;
-			} else {
-				nbytes += self.write_pulse(LONG_PULSE)?;
-			}
-			bits = bits >> 1;
-		}
-		for _ in 0..4 {
-			nbytes += self.write_pulse(SHORT_PULSE)?;
-		}
-		Ok(nbytes)
-	}
-
-	fn write_pulse(&mut self, freq: u32) -> io::Result<usize> {
-		let len = self.sample_rate / (self.bauds * (freq / 1200));
-		let scale = 2.0 * f32::consts::PI  / len as f32;
-		let func = |x: f32| (f32::sin(scale * x) * 127.0) as i8 as u8 ^ 0x80;
-		let bytes = Vec::from_iter((0..len).map(|x| func(x as f32)));
-		self.buffer.write(&bytes[..])
-	}
+impl<'a> Timeline<'a> {
+    /// Create the timeline of the given tape encoded with the given profile
+    pub fn new(tape: &'a Tape, profile: Profile) -> Timeline<'a> {
+        let mut spans = Vec::with_capacity(tape.blocks().len());
+        let mut start = 0;
+        for (block, bauds) in tape.blocks().iter().zip(profile.block_bauds(tape)) {
+            let profile = profile.at(bauds);
+            let lead = if block.is_file_header() {
+                profile.gap_len(profile.long_gap_ms) + profile.header_len(LONG_HEADER)
+            } else {
+                profile.gap_len(profile.short_gap_ms) + profile.header_len(SHORT_HEADER)
+            };
+            let data = start + lead;
+            let end = data
+                + block
+                    .data_without_prefix()
+                    .iter()
+                    .map(|b| profile.byte_len(*b))
+                    .sum::<u64>();
+            spans.push(Span {
+                start,
+                data,
+                end,
+                bauds,
+            });
+            start = end;
+        }
+        let mut files = vec![];
+        for (index, (blocks, _)) in tape.file_extents().into_iter().enumerate() {
+            files.extend(blocks.map(|_| index));
+        }
+        files.resize(spans.len(), files.last().copied().unwrap_or(0));
+        Timeline {
+            tape,
+            profile,
+            spans,
+            files,
+        }
+    }
+
+    /// Returns the duration of the whole encoded tape
+    pub fn duration(&self) -> Duration {
+        self.profile
+            .duration_of(self.spans.last().map_or(0, |s| s.end))
+    }
+
+    /// Returns what is being played at the given time, or `None` past the end of the tape
+    pub fn position_at(&self, time: Duration) -> Option<Position> {
+        let sample = self.profile.samples_at(time);
+        let block = self.spans.partition_point(|s| s.end <= sample);
+        let span = self.spans.get(block)?;
+        let byte = if sample < span.data {
+            None
+        } else {
+            let mut offset = span.data;
+            let data = self.tape.blocks()[block].data_without_prefix();
+            let profile = self.profile.at(span.bauds);
+            data.iter().position(|b| {
+                offset += profile.byte_len(*b);
+                offset > sample
+            })
+        };
+        Some(Position {
+            block,
+            file: self.files[block],
+            byte,
+        })
+    }
+
+    /// Returns the time the given block starts to play (including its gap), if it exists
+    pub fn time_of_block(&self, block: usize) -> Option<Duration> {
+        self.spans
+            .get(block)
+            .map(|s| self.profile.duration_of(s.start))
+    }
+
+    /// Returns the time the given file starts to play, if it exists
+    pub fn time_of_file(&self, file: usize) -> Option<Duration> {
+        let block = self.files.iter().position(|f| *f == file)?;
+        self.time_of_block(block)
+    }
 }
 
 #[cfg(test)]
 mod test {
 
-	use byteorder::{ByteOrder, LittleEndian};
-
-	use super::*;
-
-	#[test]
-	fn should_export_empty_data() {
-		let exporter = Exporter::new();
-		let mut output: Vec<u8> = Vec::new();
-		exporter.export(&mut output).ok();
-		assert_eq!("RIFF".as_bytes(), &output[0..4]);
-		assert_eq!(44, LittleEndian::read_u32(&output[4..8]));
-		assert_eq!("WAVE".as_bytes(), &output[8..12]);
-		assert_eq!("fmt ".as_bytes(), &output[12..16]);
-		assert_eq!(16, LittleEndian::read_u32(&output[16..20]));
-		assert_eq!(1, LittleEndian::read_u16(&output[20..22]));
-		assert_eq!(1, LittleEndian::read_u16(&output[22..24]));
-		assert_eq!(43200, LittleEndian::read_u32(&output[24..28]));
-		assert_eq!(43200, LittleEndian::read_u32(&output[28..32]));
-		assert_eq!(8, LittleEndian::read_u16(&output[32..34]));
-		assert_eq!(8, LittleEndian::read_u16(&output[34..36]));
-		assert_eq!("data".as_bytes(), &output[36..40]);
-		assert_eq!(0, LittleEndian::read_u32(&output[40..44]));
-	}
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use super::*;
+    use crate::tape;
+
+    #[test]
+    fn should_export_empty_data() {
+        let exporter = Exporter::new();
+        let mut output: Vec<u8> = Vec::new();
+        exporter.export(&mut output).ok();
+        assert_eq!("RIFF".as_bytes(), &output[0..4]);
+        assert_eq!(44, LittleEndian::read_u32(&output[4..8]));
+        assert_eq!("WAVE".as_bytes(), &output[8..12]);
+        assert_eq!("fmt ".as_bytes(), &output[12..16]);
+        assert_eq!(16, LittleEndian::read_u32(&output[16..20]));
+        assert_eq!(1, LittleEndian::read_u16(&output[20..22]));
+        assert_eq!(1, LittleEndian::read_u16(&output[22..24]));
+        assert_eq!(43200, LittleEndian::read_u32(&output[24..28]));
+        assert_eq!(43200, LittleEndian::read_u32(&output[28..32]));
+        assert_eq!(8, LittleEndian::read_u16(&output[32..34]));
+        assert_eq!(8, LittleEndian::read_u16(&output[34..36]));
+        assert_eq!("data".as_bytes(), &output[36..40]);
+        assert_eq!(0, LittleEndian::read_u32(&output[40..44]));
+    }
+
+    #[test]
+    fn should_write_pauses() {
+        let mut exporter = Exporter::new();
+        assert_eq!(
+            43200 * 3 / 2,
+            exporter.write_pause(Duration::from_millis(1500)).unwrap()
+        );
+        assert_eq!(Duration::from_millis(1500), exporter.elapsed());
+        assert!(exporter.samples().iter().all(|s| *s == 0x80));
+    }
+
+    #[test]
+    fn should_parse_cassettes() {
+        let c60: Cassette = "C60".parse().unwrap();
+        assert_eq!(Cassette { minutes: 60 }, c60);
+        assert_eq!(Duration::from_secs(30 * 60), c60.side_duration());
+        assert_eq!("C90", "c90".parse::<Cassette>().unwrap().to_string());
+        assert_eq!(Cassette { minutes: 46 }, "46".parse().unwrap());
+        assert!("C0".parse::<Cassette>().is_err());
+        assert!("long".parse::<Cassette>().is_err());
+    }
+
+    #[test]
+    fn should_map_playback_positions() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xff, 0x00])
+            .unwrap();
+        tape.append_custom(&[0x00; 8]).unwrap();
+        let profile = Profile {
+            bauds: 2400,
+            ..Profile::default()
+        };
+
+        let mut exporter = Exporter::with_profile(profile);
+        let mut ends = vec![];
+        for block in tape.blocks() {
+            exporter.write_block(block).unwrap();
+            ends.push(exporter.samples().len() as u64);
+        }
+        let timeline = Timeline::new(&tape, profile);
+        assert_eq!(
+            ends,
+            timeline.spans.iter().map(|s| s.end).collect::<Vec<_>>()
+        );
+        assert_eq!(profile.duration_of(ends[2]), timeline.duration());
+
+        assert_eq!(Some(Duration::ZERO), timeline.time_of_file(0));
+        assert_eq!(timeline.time_of_block(2), timeline.time_of_file(1));
+        assert_eq!(None, timeline.time_of_file(2));
+
+        let position = timeline.position_at(Duration::from_millis(500)).unwrap();
+        assert_eq!(
+            Position {
+                block: 0,
+                file: 0,
+                byte: None
+            },
+            position
+        );
+        let data = profile.duration_of(timeline.spans[1].data);
+        let position = timeline.position_at(data).unwrap();
+        assert_eq!(
+            Position {
+                block: 1,
+                file: 0,
+                byte: Some(0)
+            },
+            position
+        );
+        let seventh = data + profile.duration_of(6 * profile.byte_len(0x00));
+        assert_eq!(Some(6), timeline.position_at(seventh).unwrap().byte);
+        let position = timeline
+            .position_at(timeline.time_of_file(1).unwrap())
+            .unwrap();
+        assert_eq!(
+            Position {
+                block: 2,
+                file: 1,
+                byte: None
+            },
+            position
+        );
+        assert_eq!(None, timeline.position_at(timeline.duration()));
+    }
+
+    #[test]
+    fn should_encode_turbo_speeds() {
+        let mut tape = Tape::new();
+        let (name, _) = tape::file_name("loader");
+        tape.append_ascii(&name, b"10 BLOAD\"CAS:\",R\r\n").unwrap();
+        let (name, _) = tape::file_name("game");
+        tape.append_bin(&name, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xff, 0x00])
+            .unwrap();
+
+        let profile = Profile {
+            turbo_from: 1,
+            ..Profile::with_bauds(3600)
+        };
+        assert!(profile.validate().is_ok());
+        assert_eq!(vec![1200, 1200, 3600, 3600], profile.block_bauds(&tape));
+        let mut exporter = Exporter::with_profile(profile);
+        exporter.write_tape(&tape, &mut progress::ignore()).unwrap();
+        let timeline = Timeline::new(&tape, profile);
+        assert_eq!(exporter.elapsed(), timeline.duration());
+        // Every bit of the game takes 12 samples instead of 36
+        let game = timeline.spans[3].end - timeline.spans[3].data;
+        assert_eq!(8 * 11 * 12, game);
+
+        assert_eq!(48000, Profile::with_bauds(4800).sample_rate);
+        assert!(Profile::with_bauds(4800).validate().is_ok());
+        let e = Profile {
+            bauds: 4800,
+            ..Profile::default()
+        }
+        .validate()
+        .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+        assert!(Profile::with_bauds(9600).validate().is_err());
+    }
 }
@@ -0,0 +1,481 @@
+//
+// MSX CAS Packager
+// Copyright (c) 2015 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A tiny Z80 assembler for the loader stubs generated by the crate
+//!
+//! Loader stubs are assembled from parameterized templates, so their origin, copy
+//! ranges and jump targets can be adjusted instead of embedding pre-built byte arrays.
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::io;
+
+/// An 8-bit register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reg8 {
+    B = 0,
+    C = 1,
+    D = 2,
+    E = 3,
+    H = 4,
+    L = 5,
+    A = 7,
+}
+
+/// A 16-bit register pair
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reg16 {
+    BC = 0,
+    DE = 1,
+    HL = 2,
+    SP = 3,
+}
+
+/// A condition of jumps and returns
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cond {
+    NZ = 0,
+    Z = 1,
+    NC = 2,
+    C = 3,
+}
+
+/// A reference to a label to be resolved when the code is assembled
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fixup {
+    Absolute,
+    Relative,
+}
+
+/// An assembler of Z80 machine code
+///
+/// Instructions are appended in order by calling the method named after them. Jumps
+/// and loads may refer to labels defined before or after the instruction. The labels
+/// are resolved by `assemble()`, which returns the resulting machine code.
+///
+pub struct Assembler {
+    org: u16,
+    code: Vec<u8>,
+    labels: HashMap<String, u16>,
+    fixups: Vec<(usize, String, Fixup)>,
+}
+
+impl Assembler {
+    /// Create a new assembler for code located at the given address.
+    pub fn new(org: u16) -> Assembler {
+        Assembler {
+            org,
+            code: vec![],
+            labels: HashMap::new(),
+            fixups: vec![],
+        }
+    }
+
+    /// Returns the address of the next instruction.
+    pub fn here(&self) -> u16 {
+        self.org.wrapping_add(self.code.len() as u16)
+    }
+
+    /// Define a label at the address of the next instruction.
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        let here = self.here();
+        self.labels.insert(name.to_string(), here);
+        self
+    }
+
+    /// Append raw bytes.
+    pub fn db(&mut self, bytes: &[u8]) -> &mut Self {
+        self.code.extend_from_slice(bytes);
+        self
+    }
+
+    /// Append a 16-bit little endian word.
+    pub fn dw(&mut self, word: u16) -> &mut Self {
+        self.db(&word.to_le_bytes())
+    }
+
+    fn dw_label(&mut self, label: &str) -> &mut Self {
+        self.fixups
+            .push((self.code.len(), label.to_string(), Fixup::Absolute));
+        self.dw(0)
+    }
+
+    fn rel_label(&mut self, opcode: u8, label: &str) -> &mut Self {
+        self.code.push(opcode);
+        self.fixups
+            .push((self.code.len(), label.to_string(), Fixup::Relative));
+        self.db(&[0])
+    }
+
+    /// `LD r,n`
+    pub fn ld_r_n(&mut self, r: Reg8, n: u8) -> &mut Self {
+        self.db(&[0x06 | (r as u8) << 3, n])
+    }
+
+    /// `LD r,r'`
+    pub fn ld_r_r(&mut self, dst: Reg8, src: Reg8) -> &mut Self {
+        self.db(&[0x40 | (dst as u8) << 3 | src as u8])
+    }
+
+    /// `LD rr,nn`
+    pub fn ld_rr_nn(&mut self, rr: Reg16, nn: u16) -> &mut Self {
+        self.db(&[0x01 | (rr as u8) << 4]).dw(nn)
+    }
+
+    /// `LD rr,label`
+    pub fn ld_rr_label(&mut self, rr: Reg16, label: &str) -> &mut Self {
+        self.db(&[0x01 | (rr as u8) << 4]).dw_label(label)
+    }
+
+    /// `LD A,(nn)`
+    pub fn ld_a_mem(&mut self, addr: u16) -> &mut Self {
+        self.db(&[0x3a]).dw(addr)
+    }
+
+    /// `LD (nn),A`
+    pub fn ld_mem_a(&mut self, addr: u16) -> &mut Self {
+        self.db(&[0x32]).dw(addr)
+    }
+
+    /// `LD HL,(nn)`
+    pub fn ld_hl_mem(&mut self, addr: u16) -> &mut Self {
+        self.db(&[0x2a]).dw(addr)
+    }
+
+    /// `LD (nn),HL`
+    pub fn ld_mem_hl(&mut self, addr: u16) -> &mut Self {
+        self.db(&[0x22]).dw(addr)
+    }
+
+    /// `LD A,(HL)`
+    pub fn ld_a_hl(&mut self) -> &mut Self {
+        self.db(&[0x7e])
+    }
+
+    /// `LD (HL),A`
+    pub fn ld_hl_a(&mut self) -> &mut Self {
+        self.db(&[0x77])
+    }
+
+    /// `XOR A`
+    pub fn xor_a(&mut self) -> &mut Self {
+        self.db(&[0xaf])
+    }
+
+    /// `AND n`
+    pub fn and_n(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xe6, n])
+    }
+
+    /// `OR r`
+    pub fn or_r(&mut self, r: Reg8) -> &mut Self {
+        self.db(&[0xb0 | r as u8])
+    }
+
+    /// `CPL`
+    pub fn cpl(&mut self) -> &mut Self {
+        self.db(&[0x2f])
+    }
+
+    /// `RLCA`
+    pub fn rlca(&mut self) -> &mut Self {
+        self.db(&[0x07])
+    }
+
+    /// `RRCA`
+    pub fn rrca(&mut self) -> &mut Self {
+        self.db(&[0x0f])
+    }
+
+    /// `INC rr`
+    pub fn inc_rr(&mut self, rr: Reg16) -> &mut Self {
+        self.db(&[0x03 | (rr as u8) << 4])
+    }
+
+    /// `DEC rr`
+    pub fn dec_rr(&mut self, rr: Reg16) -> &mut Self {
+        self.db(&[0x0b | (rr as u8) << 4])
+    }
+
+    /// `ADD HL,rr`
+    pub fn add_hl_rr(&mut self, rr: Reg16) -> &mut Self {
+        self.db(&[0x09 | (rr as u8) << 4])
+    }
+
+    /// `PUSH rr` (`SP` stands for `AF`)
+    pub fn push(&mut self, rr: Reg16) -> &mut Self {
+        self.db(&[0xc5 | (rr as u8) << 4])
+    }
+
+    /// `POP rr` (`SP` stands for `AF`)
+    pub fn pop(&mut self, rr: Reg16) -> &mut Self {
+        self.db(&[0xc1 | (rr as u8) << 4])
+    }
+
+    /// `LDIR`
+    pub fn ldir(&mut self) -> &mut Self {
+        self.db(&[0xed, 0xb0])
+    }
+
+    /// `DI`
+    pub fn di(&mut self) -> &mut Self {
+        self.db(&[0xf3])
+    }
+
+    /// `EI`
+    pub fn ei(&mut self) -> &mut Self {
+        self.db(&[0xfb])
+    }
+
+    /// `OUT (n),A`
+    pub fn out_n_a(&mut self, port: u8) -> &mut Self {
+        self.db(&[0xd3, port])
+    }
+
+    /// `IN A,(n)`
+    pub fn in_a_n(&mut self, port: u8) -> &mut Self {
+        self.db(&[0xdb, port])
+    }
+
+    /// `JP nn`
+    pub fn jp(&mut self, addr: u16) -> &mut Self {
+        self.db(&[0xc3]).dw(addr)
+    }
+
+    /// `JP label`
+    pub fn jp_label(&mut self, label: &str) -> &mut Self {
+        self.db(&[0xc3]).dw_label(label)
+    }
+
+    /// `JP cc,label`
+    pub fn jp_cc_label(&mut self, cc: Cond, label: &str) -> &mut Self {
+        self.db(&[0xc2 | (cc as u8) << 3]).dw_label(label)
+    }
+
+    /// `JP (HL)`
+    pub fn jp_hl(&mut self) -> &mut Self {
+        self.db(&[0xe9])
+    }
+
+    /// `JR label`
+    pub fn jr_label(&mut self, label: &str) -> &mut Self {
+        self.rel_label(0x18, label)
+    }
+
+    /// `JR cc,label` (only `NZ`, `Z`, `NC` and `C` exist)
+    pub fn jr_cc_label(&mut self, cc: Cond, label: &str) -> &mut Self {
+        self.rel_label(0x20 | (cc as u8) << 3, label)
+    }
+
+    /// `DJNZ label`
+    pub fn djnz_label(&mut self, label: &str) -> &mut Self {
+        self.rel_label(0x10, label)
+    }
+
+    /// `CALL nn`
+    pub fn call(&mut self, addr: u16) -> &mut Self {
+        self.db(&[0xcd]).dw(addr)
+    }
+
+    /// `CALL label`
+    pub fn call_label(&mut self, label: &str) -> &mut Self {
+        self.db(&[0xcd]).dw_label(label)
+    }
+
+    /// `RET`
+    pub fn ret(&mut self) -> &mut Self {
+        self.db(&[0xc9])
+    }
+
+    /// `RET cc`
+    pub fn ret_cc(&mut self, cc: Cond) -> &mut Self {
+        self.db(&[0xc0 | (cc as u8) << 3])
+    }
+
+    /// Resolve the labels and return the assembled machine code.
+    ///
+    /// An error of kind `InvalidInput` is returned if a label is not defined or a
+    /// relative jump is out of range.
+    ///
+    pub fn assemble(&self) -> io::Result<Vec<u8>> {
+        let mut code = self.code.clone();
+        for (pos, label, fixup) in &self.fixups {
+            let target = *self.labels.get(label).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("undefined label {:?}", label),
+                )
+            })?;
+            match fixup {
+                Fixup::Absolute => code[*pos..*pos + 2].copy_from_slice(&target.to_le_bytes()),
+                Fixup::Relative => {
+                    let next = self.org as i32 + *pos as i32 + 1;
+                    let offset = target as i32 - next;
+                    if !(-128..=127).contains(&offset) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("relative jump to {:?} out of range", label),
+                        ));
+                    }
+                    code[*pos] = offset as i8 as u8;
+                }
+            }
+        }
+        Ok(code)
+    }
+}
+
+/// A memory range to be copied by a loader stub
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transfer {
+    pub src: u16,
+    pub dst: u16,
+    pub len: u16,
+}
+
+/// Assemble a stub that copies the given ranges with interrupts disabled and jumps to `exec`
+///
+/// This is the core of the ROM loaders: the ROM pages are loaded into free RAM and then
+/// moved to their final location, where they would overlap the BASIC work area during
+/// the load.
+///
+pub fn copy_and_jump(org: u16, copies: &[Transfer], exec: u16) -> io::Result<Vec<u8>> {
+    let mut asm = Assembler::new(org);
+    asm.di();
+    for copy in copies.iter().filter(|c| c.len > 0) {
+        asm.ld_rr_nn(Reg16::HL, copy.src)
+            .ld_rr_nn(Reg16::DE, copy.dst)
+            .ld_rr_nn(Reg16::BC, copy.len)
+            .ldir();
+    }
+    asm.ei().jp(exec);
+    asm.assemble()
+}
+
+/// Assemble a stub that calls each of the `targets` in order and returns to the caller
+///
+/// This chains several initialization routines (e.g., a depacker followed by the
+/// program entry point) from a single execution address.
+///
+pub fn chain(org: u16, targets: &[u16]) -> io::Result<Vec<u8>> {
+    let mut asm = Assembler::new(org);
+    for target in targets {
+        asm.call(*target);
+    }
+    asm.ret();
+    asm.assemble()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_encode_instructions() {
+        let mut asm = Assembler::new(0xc000);
+        asm.ld_r_n(Reg8::A, 0x12)
+            .ld_r_r(Reg8::B, Reg8::A)
+            .ld_rr_nn(Reg16::HL, 0x1234)
+            .ld_mem_a(0xf000)
+            .ld_hl_mem(0xf001)
+            .push(Reg16::SP)
+            .pop(Reg16::DE)
+            .out_n_a(0xa8)
+            .jp_hl()
+            .ret();
+        assert_eq!(
+            vec![
+                0x3e, 0x12, 0x47, 0x21, 0x34, 0x12, 0x32, 0x00, 0xf0, 0x2a, 0x01, 0xf0, 0xf5, 0xd1,
+                0xd3, 0xa8, 0xe9, 0xc9
+            ],
+            asm.assemble().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_encode_logic_and_conditions() {
+        let mut asm = Assembler::new(0xc000);
+        asm.label("top")
+            .and_n(0xc0)
+            .or_r(Reg8::D)
+            .cpl()
+            .rlca()
+            .rrca()
+            .inc_rr(Reg16::HL)
+            .dec_rr(Reg16::BC)
+            .add_hl_rr(Reg16::BC)
+            .ld_a_hl()
+            .ld_hl_a()
+            .ret_cc(Cond::C)
+            .jr_cc_label(Cond::NZ, "top")
+            .jp_cc_label(Cond::C, "top");
+        assert_eq!(
+            vec![
+                0xe6, 0xc0, 0xb2, 0x2f, 0x07, 0x0f, 0x23, 0x0b, 0x09, 0x7e, 0x77, 0xd8, 0x20, 0xf2,
+                0xda, 0x00, 0xc0
+            ],
+            asm.assemble().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_resolve_labels() {
+        let mut asm = Assembler::new(0x9000);
+        asm.ld_r_n(Reg8::B, 4)
+            .label("loop")
+            .djnz_label("loop")
+            .jr_label("end")
+            .call_label("end")
+            .label("end")
+            .jp_label("loop");
+        assert_eq!(
+            vec![0x06, 0x04, 0x10, 0xfe, 0x18, 0x03, 0xcd, 0x09, 0x90, 0xc3, 0x02, 0x90],
+            asm.assemble().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_fail_on_bad_labels() {
+        let mut asm = Assembler::new(0);
+        asm.jp_label("nowhere");
+        assert!(asm.assemble().is_err());
+
+        let mut asm = Assembler::new(0);
+        asm.jr_label("far").db(&[0; 200]).label("far");
+        assert!(asm.assemble().is_err());
+    }
+
+    #[test]
+    fn should_assemble_templates() {
+        let copies = [
+            Transfer {
+                src: 0x9000,
+                dst: 0x4000,
+                len: 0x2000,
+            },
+            Transfer {
+                src: 0,
+                dst: 0,
+                len: 0,
+            },
+        ];
+        assert_eq!(
+            vec![
+                0xf3, 0x21, 0x00, 0x90, 0x11, 0x00, 0x40, 0x01, 0x00, 0x20, 0xed, 0xb0, 0xfb, 0xc3,
+                0x10, 0x40
+            ],
+            copy_and_jump(0xc000, &copies, 0x4010).unwrap()
+        );
+        assert_eq!(
+            vec![0xcd, 0x00, 0xd0, 0xcd, 0x10, 0x40, 0xc9],
+            chain(0xc000, &[0xd000, 0x4010]).unwrap()
+        );
+    }
+}
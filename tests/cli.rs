@@ -0,0 +1,80 @@
+extern crate mcp;
+extern crate tempdir;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use mcp::tape::file_name;
+use mcp::Tape;
+use tempdir::TempDir;
+
+/// Run the `mcp` binary in the given directory, failing the test if it fails
+fn mcp(dir: &Path, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_mcp"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "mcp {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Write a tape with a binary file named `game` followed by two custom files
+fn write_tape(dir: &Path) {
+    let mut tape = Tape::new();
+    let (name, _) = file_name("game");
+    tape.append_bin(&name, &[0x00, 0x80, 0x01, 0x80, 0x00, 0x80, 0xc9, 0x00])
+        .unwrap();
+    tape.append_custom(&[0x01; 8]).unwrap();
+    tape.append_custom(&[0x02; 8]).unwrap();
+    fs::write(dir.join("tape.cas"), tape.to_bytes()).unwrap();
+}
+
+#[test]
+fn should_name_custom_files_from_zero() {
+    let dir = TempDir::new("mcp").unwrap();
+    write_tape(dir.path());
+
+    mcp(dir.path(), &["extract", "tape.cas", "-o", "out"]);
+    let out = dir.path().join("out");
+    assert_eq!(vec![0x01; 8], fs::read(out.join("custom.000")).unwrap());
+    assert_eq!(vec![0x02; 8], fs::read(out.join("custom.001")).unwrap());
+    assert!(!out.join("custom.002").exists());
+}
+
+#[test]
+fn should_extract_by_index() {
+    let dir = TempDir::new("mcp").unwrap();
+    write_tape(dir.path());
+
+    mcp(
+        dir.path(),
+        &["extract", "--index", "2", "tape.cas", "-o", "out"],
+    );
+    let names: Vec<_> = fs::read_dir(dir.path().join("out"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(vec!["custom.001"], names);
+}
+
+#[test]
+fn should_cat_selected_files() {
+    let dir = TempDir::new("mcp").unwrap();
+    write_tape(dir.path());
+
+    assert_eq!(
+        vec![0x01; 8],
+        mcp(dir.path(), &["cat", "tape.cas", "1"]).stdout
+    );
+    let output = mcp(dir.path(), &["cat", "tape.cas", "custom.001"]);
+    assert_eq!(vec![0x02; 8], output.stdout);
+    let output = mcp(dir.path(), &["cat", "tape.cas", "custom.*"]);
+    assert_eq!([[0x01; 8], [0x02; 8]].concat(), output.stdout);
+}